@@ -0,0 +1,64 @@
+//! Compare writing a large trace with `Database` and `ZeroCopyDatabase`.
+//!
+//! Run with `cargo bench --features zerocopy -- [events]`, 10 million
+//! events by default. The databases are written to the temporary directory,
+//! or to `$REPROZIP_BENCH_DIR` to measure a specific storage.
+
+extern crate reprozip;
+extern crate slog;
+
+use std::env;
+use std::fs::remove_file;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reprozip::Error;
+use reprozip::database::{Database, DatabaseOps, FileOp};
+use reprozip::database::zerocopy::ZeroCopyDatabase;
+
+const PROCESSES: u64 = 100;
+
+/// Record `events` distinct file accesses, spread over processes, and
+/// commit.
+fn write_trace<D: DatabaseOps>(
+    mut database: D,
+    events: u64,
+) -> Result<Duration, Error> {
+    let start = Instant::now();
+    let mut processes = Vec::new();
+    for _ in 0..PROCESSES {
+        processes.push(database.add_process(None, Path::new("/work"),
+                                            false)?);
+    }
+    for i in 0..events {
+        let path = PathBuf::from(format!("/data/{}/{}", i % 1000, i));
+        database.add_file_open(processes[(i % PROCESSES) as usize], &path,
+                               FileOp::READ, false)?;
+    }
+    database.commit()?;
+    Ok(start.elapsed())
+}
+
+fn main() -> Result<(), Error> {
+    let events = env::args().skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.parse().expect("invalid number of events"))
+        .unwrap_or(10_000_000);
+    let dir = env::var_os("REPROZIP_BENCH_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let logger = slog::Logger::root(slog::Discard, slog::o!());
+    let path = dir.join("reprozip-bench.sqlite");
+
+    let database = Database::new(&path, logger.clone())?;
+    let default = write_trace(database, events)?;
+    remove_file(&path)?;
+    let database = ZeroCopyDatabase::new(&path, logger)?;
+    let zerocopy = write_trace(database, events)?;
+    remove_file(&path)?;
+
+    println!("{} events", events);
+    println!("Database:         {:.3}s", default.as_secs_f64());
+    println!("ZeroCopyDatabase: {:.3}s", zerocopy.as_secs_f64());
+    Ok(())
+}
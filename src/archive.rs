@@ -0,0 +1,422 @@
+//! Writing packs, the archives holding a trace and the files it read.
+//!
+//! An `.rpz` pack has the layout of ReproZip: the trace under `METADATA/` and
+//! the files in a nested `DATA.tar.gz`. The tarballs have the layout of an
+//! unpacked pack instead, see `replay`, so they can be extracted and
+//! replayed as they are; `.tar.zst` is faster to write and read, `.tar.gz`
+//! can be opened anywhere.
+
+use std::fs::{File, metadata, read, remove_file};
+use std::io::{Read, Write, copy};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, Header};
+
+use crate::Error;
+use crate::database::{Database, DatabaseOps};
+use crate::repro_hash::input_files;
+
+/// The version file of an `.rpz` pack, for ReproZip to recognize it.
+const RPZ_VERSION: &[u8] = b"REPROZIP VERSION 2\n";
+
+/// Compression level for `.tar.zst`, the default of the `zstd` tool.
+const ZSTD_LEVEL: i32 = 3;
+
+/// The names of the trace database in the packs, see `add_database()`.
+const DATABASE_NAMES: &[&str] = &["METADATA/trace.sqlite3", "trace.sqlite"];
+
+/// The format of a pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Rpz,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// The usual extension of packs in this format.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ArchiveFormat::Rpz => "rpz",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ArchiveFormat, Error> {
+        match s {
+            "rpz" => Ok(ArchiveFormat::Rpz),
+            "tar-gz" => Ok(ArchiveFormat::TarGz),
+            "tar-zst" => Ok(ArchiveFormat::TarZst),
+            _ => Err(Error::Internal(format!("Unknown archive format {}", s))),
+        }
+    }
+}
+
+/// Writes a pack in some format.
+pub trait Archiver {
+    /// Add a file, given its original absolute path.
+    ///
+    /// The permissions and modification time are those of the file at that
+    /// path, if it is still there.
+    fn add_file(&mut self, path: &Path, content: &[u8]) -> Result<(), Error>;
+
+    /// Add the trace database.
+    fn add_database(&mut self, db_path: &Path) -> Result<(), Error>;
+
+    /// Write the end of the archive.
+    fn finish(self) -> Result<(), Error>;
+}
+
+/// Append a file to a tarball under `name`, with the metadata of `path`.
+fn append_file<W: Write>(
+    builder: &mut Builder<W>,
+    name: &Path,
+    path: &Path,
+    content: &[u8],
+) -> Result<(), Error> {
+    let mut header = Header::new_gnu();
+    header.set_size(content.len() as u64);
+    match metadata(path) {
+        Ok(stat) => {
+            header.set_mode(stat.permissions().mode() & 0o7777);
+            let mtime = stat.modified().ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            header.set_mtime(mtime);
+        }
+        Err(_) => header.set_mode(0o644),
+    }
+    builder.append_data(&mut header, name, content)?;
+    Ok(())
+}
+
+/// The name of a file in an archive, under `dir`.
+fn member_name(dir: &str, path: &Path) -> PathBuf {
+    Path::new(dir).join(path.strip_prefix("/").unwrap_or(path))
+}
+
+/// Writes an `.rpz` pack, as ReproZip does.
+///
+/// The files go to a temporary `DATA.tar.gz` next to the pack, which is
+/// added to it at the end.
+pub struct RpzArchiver {
+    builder: Builder<File>,
+    data: Builder<GzEncoder<File>>,
+    data_path: PathBuf,
+}
+
+impl RpzArchiver {
+    pub fn new(path: &Path) -> Result<RpzArchiver, Error> {
+        let mut data_path = path.as_os_str().to_owned();
+        data_path.push(".data.tmp");
+        let data_path = PathBuf::from(data_path);
+        let data = GzEncoder::new(File::create(&data_path)?,
+                                  Compression::default());
+        let mut builder = Builder::new(File::create(path)?);
+        let mut header = Header::new_gnu();
+        header.set_size(RPZ_VERSION.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "METADATA/version", RPZ_VERSION)?;
+        Ok(RpzArchiver { builder, data: Builder::new(data), data_path })
+    }
+}
+
+impl Archiver for RpzArchiver {
+    fn add_file(&mut self, path: &Path, content: &[u8]) -> Result<(), Error> {
+        append_file(&mut self.data, &member_name("DATA", path), path, content)
+    }
+
+    fn add_database(&mut self, db_path: &Path) -> Result<(), Error> {
+        self.builder.append_path_with_name(db_path,
+                                           "METADATA/trace.sqlite3")?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        let RpzArchiver { mut builder, data, data_path } = self;
+        data.into_inner()?.finish()?;
+        let result = builder
+            .append_path_with_name(&data_path, "DATA.tar.gz")
+            .and_then(|()| builder.into_inner())
+            .map(|_| ());
+        remove_file(&data_path)?;
+        Ok(result?)
+    }
+}
+
+/// Writes a pack as a `.tar.gz`.
+pub struct TarGzArchiver {
+    builder: Builder<GzEncoder<File>>,
+}
+
+impl TarGzArchiver {
+    pub fn new(path: &Path) -> Result<TarGzArchiver, Error> {
+        let file = GzEncoder::new(File::create(path)?, Compression::default());
+        Ok(TarGzArchiver { builder: Builder::new(file) })
+    }
+}
+
+impl Archiver for TarGzArchiver {
+    fn add_file(&mut self, path: &Path, content: &[u8]) -> Result<(), Error> {
+        append_file(&mut self.builder, &member_name("files", path), path,
+                    content)
+    }
+
+    fn add_database(&mut self, db_path: &Path) -> Result<(), Error> {
+        self.builder.append_path_with_name(db_path, "trace.sqlite")?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        self.builder.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes a pack as a `.tar.zst`.
+pub struct TarZstArchiver {
+    builder: Builder<zstd::Encoder<'static, File>>,
+}
+
+impl TarZstArchiver {
+    pub fn new(path: &Path) -> Result<TarZstArchiver, Error> {
+        let file = zstd::Encoder::new(File::create(path)?, ZSTD_LEVEL)?;
+        Ok(TarZstArchiver { builder: Builder::new(file) })
+    }
+}
+
+impl Archiver for TarZstArchiver {
+    fn add_file(&mut self, path: &Path, content: &[u8]) -> Result<(), Error> {
+        append_file(&mut self.builder, &member_name("files", path), path,
+                    content)
+    }
+
+    fn add_database(&mut self, db_path: &Path) -> Result<(), Error> {
+        self.builder.append_path_with_name(db_path, "trace.sqlite")?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        self.builder.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+/// Pack a trace and the files it read, returning how many files were
+/// packed.
+///
+/// The hashes of the packed files are recorded in the trace, so the pack
+/// can be checked with `ReplayEnvironment::verify_pack()`. Files that can't
+/// be read anymore, such as temporary files, are logged and left out.
+pub fn pack(
+    database_path: &Path,
+    format: ArchiveFormat,
+    output: &Path,
+    logger: &slog::Logger,
+) -> Result<usize, Error> {
+    let files = input_files(&Database::open(database_path, logger.clone())?)?;
+    match format {
+        ArchiveFormat::Rpz => {
+            write_pack(RpzArchiver::new(output)?, database_path, &files,
+                       logger)
+        }
+        ArchiveFormat::TarGz => {
+            write_pack(TarGzArchiver::new(output)?, database_path, &files,
+                       logger)
+        }
+        ArchiveFormat::TarZst => {
+            write_pack(TarZstArchiver::new(output)?, database_path, &files,
+                       logger)
+        }
+    }
+}
+
+fn write_pack<'a, A: Archiver>(
+    mut archiver: A,
+    database_path: &Path,
+    files: impl IntoIterator<Item = &'a PathBuf>,
+    logger: &slog::Logger,
+) -> Result<usize, Error> {
+    let mut hashes = Vec::new();
+    for path in files {
+        match read(path) {
+            Ok(content) => {
+                archiver.add_file(path, &content)?;
+                hashes.push((path, Sha256::digest(&content).into()));
+            }
+            Err(e) => {
+                warn!(logger, "Can't pack file: {}", e;
+                      "path" => %path.display());
+            }
+        }
+    }
+
+    // Record the hashes of the packed files in the trace, for
+    // `ReplayEnvironment::verify()`
+    let mut database = Database::open_for_update(database_path,
+                                                 logger.clone())?;
+    for &(path, hash) in &hashes {
+        database.add_file_hash(path, hash)?;
+    }
+    database.commit()?;
+
+    archiver.add_database(database_path)?;
+    archiver.finish()?;
+    Ok(hashes.len())
+}
+
+/// Extract the trace database from a pack, returning false if the file is
+/// not a pack.
+///
+/// The format is recognized from the content: a gzip or zstd stream for
+/// the tarballs, a tar file for `.rpz`.
+pub fn extract_database(pack: &Path, output: &Path) -> Result<bool, Error> {
+    let mut magic = [0u8; 262];
+    let mut file = File::open(pack)?;
+    let len = file.read(&mut magic)?;
+    let file = File::open(pack)?;
+    let reader: Box<dyn Read> = match &magic[..len] {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(file)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(zstd::Decoder::new(file)?),
+        m if m.len() == 262 && &m[257..] == b"ustar" => Box::new(file),
+        _ => return Ok(false),
+    };
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        if DATABASE_NAMES.iter().any(|n| name == Path::new(n)) {
+            copy(&mut entry, &mut File::create(output)?)?;
+            return Ok(true);
+        }
+    }
+    Err(Error::Internal(format!(
+        "No trace database in pack {}", pack.display(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, read, remove_dir_all, remove_file, write};
+    use std::io::Read;
+    use std::path::Path;
+
+    use flate2::read::GzDecoder;
+    use tar::Archive;
+
+    use crate::database::{Database, DatabaseOps, FileOp};
+    use crate::replay::ReplayEnvironment;
+    use crate::testing::TempDatabase;
+
+    use super::{
+        ArchiveFormat, Archiver, RpzArchiver, TarGzArchiver, TarZstArchiver,
+        extract_database, pack,
+    };
+
+    #[test]
+    fn test_tar_zst() {
+        let path = temp_dir().join(format!(
+            "reprozip-test-archive-{}.tar.zst", std::process::id(),
+        ));
+        let mut archiver = TarZstArchiver::new(&path).unwrap();
+        archiver.add_file(Path::new("/data/input"), b"content").unwrap();
+        archiver.finish().unwrap();
+
+        let file = zstd::Decoder::new(File::open(&path).unwrap()).unwrap();
+        let mut archive = Archive::new(file);
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        assert_eq!(entry.path().unwrap(), Path::new("files/data/input"));
+        assert_eq!(entry.header().mode().unwrap(), 0o644);
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "content");
+        drop(entry);
+        assert!(entries.next().is_none());
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_database() {
+        let path = |name: &str| temp_dir().join(format!(
+            "reprozip-test-extract-{}-{}", std::process::id(), name,
+        ));
+        let (database, extracted) = (path("trace"), path("extracted"));
+        write(&database, b"trace").unwrap();
+
+        let rpz = path("pack.rpz");
+        let mut archiver = RpzArchiver::new(&rpz).unwrap();
+        archiver.add_database(&database).unwrap();
+        archiver.add_file(Path::new("/data/input"), b"content").unwrap();
+        archiver.finish().unwrap();
+        let tgz = path("pack.tar.gz");
+        let mut archiver = TarGzArchiver::new(&tgz).unwrap();
+        archiver.add_file(Path::new("/data/input"), b"content").unwrap();
+        archiver.add_database(&database).unwrap();
+        archiver.finish().unwrap();
+        for pack in &[&rpz, &tgz] {
+            assert!(extract_database(pack, &extracted).unwrap());
+            assert_eq!(read(&extracted).unwrap(), b"trace");
+            remove_file(&extracted).unwrap();
+            remove_file(pack).unwrap();
+        }
+
+        // Not a pack
+        assert!(!extract_database(&database, &extracted).unwrap());
+        remove_file(&database).unwrap();
+    }
+
+    #[test]
+    fn test_pack_hashes() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = |name: &str| temp_dir().join(format!(
+            "reprozip-test-pack-{}-{}", std::process::id(), name,
+        ));
+        let database_path = TempDatabase::new("pack");
+        let (input, output, unpacked) =
+            (path("input"), path("pack.tar.gz"), path("unpacked"));
+        write(&input, b"content").unwrap();
+        let mut database = Database::new(&database_path, logger.clone())
+            .unwrap();
+        let root = database.add_process(None, Path::new("/"), false).unwrap();
+        database.add_file_open(root, &input, FileOp::READ, false).unwrap();
+        database.commit().unwrap();
+
+        assert_eq!(
+            pack(database_path.path(), ArchiveFormat::TarGz, &output,
+                 &logger).unwrap(),
+            1,
+        );
+        let file = GzDecoder::new(File::open(&output).unwrap());
+        Archive::new(file).unpack(&unpacked).unwrap();
+        let database = Database::open(&database_path, logger).unwrap();
+        assert_eq!(database.file_hashes().unwrap().len(), 1);
+        assert!(ReplayEnvironment::verify_pack(&unpacked).unwrap().is_empty());
+
+        // Changing the packed file is caught
+        let packed = ReplayEnvironment::new(&unpacked).file_path(&input);
+        write(&packed, b"changed").unwrap();
+        let mismatches = ReplayEnvironment::verify_pack(&unpacked).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].critical);
+
+        remove_dir_all(&unpacked).unwrap();
+        for path in &[&input, &output] {
+            remove_file(path).unwrap();
+        }
+    }
+}
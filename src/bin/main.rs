@@ -1,17 +1,56 @@
 extern crate clap;
+extern crate nix;
+extern crate sha2;
 #[macro_use] extern crate slog;
 extern crate slog_async;
 extern crate slog_term;
+extern crate termcolor;
 
 extern crate reprozip;
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::fmt::Display;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
 
 use clap::{App, Arg, SubCommand};
+use nix::unistd::{ForkResult, Pid, dup2, fork, setsid};
+use sha2::{Digest, Sha256};
 use slog::Drain;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream,
+                StandardStreamLock, WriteColor};
 
-use reprozip::{Error, ExitStatus, Tracer};
+use reprozip::{Backend, Error, ExitStatus, TraceConfig, Tracer};
+use reprozip::archive::{self, ArchiveFormat};
+use reprozip::bundle::docker::DockerBundler;
+use reprozip::clean::{CleanConfig, remove_artifacts};
+use reprozip::database::{
+    CwdUse, Database, DatabaseOps, ProcessId, landlock_access_names, vacuum,
+};
+use reprozip::database::journal::JournalDatabase;
+use reprozip::database::schema;
+use reprozip::dependency_graph::DependencyGraph;
+use reprozip::fanotify_backend::FanotifyTracer;
+use reprozip::fuzz::FuzzOrchestrator;
+use reprozip::portability::{PortabilityChecker, PortabilityWarning};
+use reprozip::process_tree::ProcessTree;
+use reprozip::repro_hash;
+use reprozip::report::ReproducibilityReport;
+use reprozip::seccomp::database_seccomp_profile;
+use reprozip::time_override::TimeOverride;
+#[cfg(feature = "cloud")]
+use reprozip::download::Downloader;
+#[cfg(feature = "cloud")]
+use reprozip::upload::{Checksum, Uploader, hex};
 
 struct LogLevelFilter<D> {
     drain: D,
@@ -38,9 +77,139 @@ where
     }
 }
 
+/// Which colors to use on a stream, `auto` using them only on terminals.
+fn color_choice(choice: &str, is_terminal: bool) -> ColorChoice {
+    match choice {
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        _ if is_terminal => ColorChoice::Auto,
+        _ => ColorChoice::Never,
+    }
+}
+
+/// Prints the messages for the user, in color if enabled: errors and
+/// warnings to stderr, successes to stdout.
+#[derive(Clone, Copy)]
+struct Console {
+    stdout: ColorChoice,
+    stderr: ColorChoice,
+}
+
+impl Console {
+    fn new(choice: &str) -> Console {
+        Console {
+            stdout: color_choice(choice, io::stdout().is_terminal()),
+            stderr: color_choice(choice, io::stderr().is_terminal()),
+        }
+    }
+
+    fn print(
+        mut stream: StandardStream,
+        color: Color,
+        label: Option<&str>,
+        message: &dyn Display,
+    ) {
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(color));
+        // Nothing to do if the terminal is gone
+        let _ = match label {
+            Some(label) => {
+                spec.set_bold(true);
+                stream.set_color(&spec)
+                    .and_then(|()| write!(stream, "{}:", label))
+                    .and_then(|()| stream.reset())
+                    .and_then(|()| writeln!(stream, " {}", message))
+            }
+            None => {
+                stream.set_color(&spec)
+                    .and_then(|()| write!(stream, "{}", message))
+                    .and_then(|()| stream.reset())
+                    .and_then(|()| writeln!(stream))
+            }
+        };
+    }
+
+    fn error<M: Display>(&self, message: M) {
+        Console::print(StandardStream::stderr(self.stderr), Color::Red,
+                       Some("Error"), &message);
+    }
+
+    fn warning<M: Display>(&self, message: M) {
+        Console::print(StandardStream::stderr(self.stderr), Color::Yellow,
+                       Some("Warning"), &message);
+    }
+
+    fn success<M: Display>(&self, message: M) {
+        Console::print(StandardStream::stdout(self.stdout), Color::Green,
+                       None, &message);
+    }
+}
+
+/// Formats log records for `slog_term::FullFormat` with termcolor, coloring
+/// errors and warnings.
+struct ColorDecorator {
+    stream: StandardStream,
+}
+
+impl slog_term::Decorator for ColorDecorator {
+    fn with_record<F>(
+        &self,
+        record: &slog::Record,
+        _logger_values: &slog::OwnedKVList,
+        f: F,
+    ) -> io::Result<()>
+    where
+        F: FnOnce(&mut dyn slog_term::RecordDecorator) -> io::Result<()>,
+    {
+        let color = match record.level() {
+            slog::Level::Critical | slog::Level::Error => Some(Color::Red),
+            slog::Level::Warning => Some(Color::Yellow),
+            _ => None,
+        };
+        let mut record = ColorRecord { stream: self.stream.lock(), color };
+        f(&mut record)?;
+        record.stream.reset()
+    }
+}
+
+struct ColorRecord<'a> {
+    stream: StandardStreamLock<'a>,
+    /// The color of the level and message
+    color: Option<Color>,
+}
+
+impl Write for ColorRecord<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl slog_term::RecordDecorator for ColorRecord<'_> {
+    fn reset(&mut self) -> io::Result<()> {
+        self.stream.reset()
+    }
+
+    fn start_level(&mut self) -> io::Result<()> {
+        self.stream.set_color(ColorSpec::new().set_fg(self.color)
+                                              .set_bold(true))
+    }
+
+    fn start_msg(&mut self) -> io::Result<()> {
+        self.stream.set_color(ColorSpec::new().set_fg(self.color))
+    }
+
+    fn start_key(&mut self) -> io::Result<()> {
+        self.stream.set_color(ColorSpec::new().set_bold(true))
+    }
+}
+
 fn main() {
     // Parse command line
-    let mut cli = App::new("reprozip")
+    let cli = App::new("reprozip")
         .bin_name("reprozip")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -51,13 +220,429 @@ fn main() {
              // broken, https://github.com/clap-rs/clap/issues/1356
              //.global(true)
              .multiple(true))
+        .arg(Arg::with_name("color")
+             .long("color")
+             .help("Whether to use colors in the output")
+             .takes_value(true)
+             .possible_values(&["auto", "always", "never"])
+             .default_value("auto"))
+        .arg(Arg::with_name("no-color")
+             .long("no-color")
+             .help("Don't use colors, same as --color never")
+             .overrides_with("color"))
         .subcommand(SubCommand::with_name("trace")
                     .about("Execute a program and generate a trace")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database to write the trace to")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("suppress-syslog")
+                         .long("suppress-syslog")
+                         .help("Make syslog() fail instead of accessing the \
+                                kernel log"))
+                    .arg(Arg::with_name("emulate-perf")
+                         .long("emulate-perf")
+                         .help("Make perf_event_open() fail, as if \
+                                performance counters were not available"))
+                    .arg(Arg::with_name("block-bpf")
+                         .long("block-bpf")
+                         .help("Make bpf() fail when the program loads an \
+                                eBPF program"))
+                    .arg(Arg::with_name("emulate-single-cpu")
+                         .long("emulate-single-cpu")
+                         .help("Pretend to be on CPU 0 and ignore attempts \
+                                to pin the program to specific CPUs"))
+                    .arg(Arg::with_name("max-file-size")
+                         .long("max-file-size")
+                         .help("Don't record files written by the program \
+                                that are bigger than this many bytes")
+                         .takes_value(true))
+                    .arg(Arg::with_name("fake-time")
+                         .long("fake-time")
+                         .help("Make the program see this time, in seconds \
+                                since the Unix epoch, when it starts \
+                                (requires a C compiler)")
+                         .takes_value(true))
+                    .arg(Arg::with_name("time-speed")
+                         .long("time-speed")
+                         .help("How fast the fake time passes, relative to \
+                                real time")
+                         .takes_value(true)
+                         .requires("fake-time"))
+                    .arg(Arg::with_name("deterministic-random")
+                         .long("deterministic-random")
+                         .help("Give the program random bytes generated \
+                                from this seed, any string, from getrandom() \
+                                and /dev/urandom")
+                         .takes_value(true)
+                         .value_name("seed"))
+                    .arg(Arg::with_name("maps-interval")
+                         .long("maps-interval")
+                         .help("Look for files mapped in memory every this \
+                                many syscalls, 0 to disable")
+                         .takes_value(true)
+                         .default_value("100"))
+                    .arg(Arg::with_name("backend")
+                         .long("backend")
+                         .help("How to watch the program; fanotify is \
+                                faster but requires root, only records \
+                                file accesses, and takes no other option")
+                         .takes_value(true)
+                         .possible_values(&["ptrace", "fanotify"])
+                         .default_value("ptrace"))
+                    .arg(Arg::with_name("journal")
+                         .long("journal")
+                         .help("Also write events to a journal file, so the \
+                                trace can be recovered if the tracer \
+                                crashes"))
+                    .arg(Arg::with_name("ignore-executable")
+                         .long("ignore-executable")
+                         .help("Don't record the files accessed by processes \
+                                running this program, given by file name")
+                         .takes_value(true)
+                         .number_of_values(1)
+                         .multiple(true))
+                    .arg(Arg::with_name("verify-fd-table")
+                         .long("verify-fd-table")
+                         .help("Check the tracked file descriptors against \
+                                the kernel, for debugging"))
+                    .arg(Arg::with_name("background")
+                         .long("background")
+                         .visible_alias("daemon")
+                         .help("Detach from the terminal and trace in the \
+                                background, e.g. a service")
+                         .requires("log"))
+                    .arg(Arg::with_name("log")
+                         .long("log")
+                         .help("With --background, write the output and \
+                                messages to this file")
+                         .takes_value(true))
+                    .arg(Arg::with_name("pid-file")
+                         .long("pid-file")
+                         .help("Write the PID of the command to this file \
+                                while it is traced")
+                         .takes_value(true))
+                    .arg(Arg::with_name("max-events")
+                         .long("max-events")
+                         .help("Abort the trace after this many ptrace \
+                                events")
+                         .takes_value(true))
+                    .arg(Arg::with_name("max-processes")
+                         .long("max-processes")
+                         .help("Abort the trace after this many processes \
+                                and threads were created")
+                         .takes_value(true))
+                    .arg(Arg::with_name("max-depth")
+                         .long("max-depth")
+                         .help("Make fork() fail in processes this many \
+                                levels below the first one")
+                         .takes_value(true))
+                    .arg(Arg::with_name("rootdir")
+                         .long("rootdir")
+                         .help("Record paths relative to this directory, \
+                                e.g. a container's root filesystem")
+                         .takes_value(true))
+                    .arg(Arg::with_name("seize")
+                         .long("seize")
+                         .help("Attach with PTRACE_SEIZE rather than having \
+                                the program stop itself"))
+                    .arg(Arg::with_name("inject-ld-preload")
+                         .long("inject-ld-preload")
+                         .help("Add this library to LD_PRELOAD for the \
+                                program, e.g. a sanitizer runtime")
+                         .takes_value(true)
+                         .number_of_values(1)
+                         .multiple(true))
+                    .arg(Arg::with_name("record-checksums-at-access")
+                         .long("record-checksums-at-access")
+                         .help("Hash the files read when they are first \
+                                opened, rather than with repro-hash"))
+                    .arg(Arg::with_name("record-mac-labels")
+                         .long("record-mac-labels")
+                         .help("Record the SELinux context of the files \
+                                opened, to restore it on replay"))
+                    .arg(Arg::with_name("capture-output")
+                         .long("capture-output")
+                         .help("Store the program's stdout and stderr in \
+                                the trace"))
+                    .arg(Arg::with_name("max-output-bytes")
+                         .long("max-output-bytes")
+                         .help("Most bytes of stdout and of stderr stored \
+                                with --capture-output [default: 10 MiB]")
+                         .takes_value(true)
+                         .requires("capture-output"))
+                    .arg(Arg::with_name("progress")
+                         .long("progress")
+                         .help("Print the number of events handled every \
+                                second"))
+                    .arg(Arg::with_name("auto-vacuum")
+                         .long("auto-vacuum")
+                         .help("Compact the database at the end of the \
+                                trace"))
+                    .arg(Arg::with_name("trace-children-only")
+                         .long("trace-children-only")
+                         .help("Don't record the files accessed by the \
+                                command itself, only by the processes it \
+                                starts"))
+                    .arg(Arg::with_name("strace-output")
+                         .long("strace-output")
+                         .help("Also write a strace-like log of the syscalls \
+                                to this file")
+                         .takes_value(true))
+                    .arg(Arg::with_name("syscall-log")
+                         .long("syscall-log")
+                         .help("Also write the syscalls to this file as CSV")
+                         .takes_value(true))
+                    .arg(Arg::with_name("diagnostic-recording")
+                         .long("diagnostic-recording")
+                         .help("Record the ptrace events to this file, to \
+                                debug the tracer with replay-diagnostic")
+                         .takes_value(true))
+                    .arg(Arg::with_name("format-version")
+                         .long("format-version")
+                         .help("Version of the database schema to write, 1 \
+                                for tools that only know about processes \
+                                and files")
+                         .takes_value(true)
+                         .possible_values(&["1", "2"]))
+                    .arg(Arg::with_name("attach")
+                         .long("attach")
+                         .help("Trace a running process instead of a \
+                                command, until it exits")
+                         .takes_value(true)
+                         .conflicts_with_all(&["rr4cmds", "fake-time",
+                                               "capture-output", "pid-file",
+                                               "inject-ld-preload"]))
                     .arg(Arg::with_name("rr4cmds")
                          .help("Command to run")
+                         .required_unless("attach")
+                         .takes_value(true)
+                         .multiple(true)))
+        .subcommand(SubCommand::with_name("fuzz")
+                    .about("Trace a program on each input of a fuzzing \
+                            corpus, into separate databases")
+                    .arg(Arg::with_name("db-template")
+                         .long("db-template")
+                         .help("Name of the databases, which get the name of \
+                                the input added before the extension")
+                         .takes_value(true)
+                         .default_value("trace.sqlite"))
+                    .arg(Arg::with_name("input-dir")
+                         .long("input-dir")
+                         .help("Directory of the input files")
+                         .takes_value(true)
+                         .required(true))
+                    .arg(Arg::with_name("command")
+                         .help("Command to run, with @@ for the input file")
                          .required(true)
                          .takes_value(true)
-                         .multiple(true)));
+                         .multiple(true)))
+        .subcommand(SubCommand::with_name("bundle-docker")
+                    .about("Create a Docker image from a trace")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .help("Directory to write the build context to")
+                         .takes_value(true)
+                         .default_value("docker-bundle"))
+                    .arg(Arg::with_name("base-image")
+                         .long("base-image")
+                         .help("Image to start from")
+                         .takes_value(true)
+                         .default_value("scratch"))
+                    .arg(Arg::with_name("build")
+                         .long("build")
+                         .help("Run 'docker build' on the result"))
+                    .arg(Arg::with_name("tag")
+                         .long("tag")
+                         .short("t")
+                         .help("Name of the image to build")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("generate-seccomp")
+                    .about("Generate a seccomp profile allowing only the \
+                            syscalls used in a trace")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .short("o")
+                         .help("File to write the JSON profile to")
+                         .takes_value(true)
+                         .default_value("seccomp.json")))
+        .subcommand(SubCommand::with_name("info")
+                    .about("Show information about a trace")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .help("Show a summary, or export the tables as CSV \
+                                files")
+                         .takes_value(true)
+                         .possible_values(&["text", "csv"])
+                         .default_value("text"))
+                    .arg(Arg::with_name("show-output")
+                         .long("show-output")
+                         .help("Print the output captured with \
+                                --capture-output"))
+                    .arg(Arg::with_name("report")
+                         .long("report")
+                         .help("Only show a summary of the reproducibility \
+                                risks"))
+                    .arg(Arg::with_name("output-dir")
+                         .long("output-dir")
+                         .help("Directory to write the CSV files to")
+                         .takes_value(true)
+                         .required_if("format", "csv")))
+        .subcommand(SubCommand::with_name("vacuum")
+                    .about("Compact a trace database")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db")))
+        .subcommand(SubCommand::with_name("merge")
+                    .about("Combine two traces, such as made on different \
+                            machines, into a new database")
+                    .arg(Arg::with_name("base")
+                         .long("base")
+                         .help("Database containing the main trace")
+                         .takes_value(true)
+                         .required(true))
+                    .arg(Arg::with_name("overlay")
+                         .long("overlay")
+                         .help("Database containing the trace to add")
+                         .takes_value(true)
+                         .required(true))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .help("Where to write the combined trace")
+                         .takes_value(true)
+                         .required(true))
+                    .arg(Arg::with_name("parent")
+                         .long("parent")
+                         .help("The process of the base trace that runs the \
+                                first process of the overlay")
+                         .takes_value(true)
+                         .default_value("0")))
+        .subcommand(SubCommand::with_name("clean")
+                    .about("Remove a trace database and the packs in the \
+                            current directory")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("yes")
+                         .long("yes")
+                         .short("y")
+                         .help("Don't ask for confirmation"))
+                    .arg(Arg::with_name("dry-run")
+                         .long("dry-run")
+                         .help("Only list the files that would be removed")))
+        .subcommand(SubCommand::with_name("validate")
+                    .about("Check whether the files used by a trace can be \
+                            packed and used on another machine")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db")))
+        .subcommand(SubCommand::with_name("critical-path")
+                    .about("Show the longest chain of processes each using \
+                            a file written by the previous one")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db")))
+        .subcommand(SubCommand::with_name("repro-hash")
+                    .about("Record the SHA-256 of the files read by a trace, \
+                            so that validate can detect changes")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("output")
+                         .long("output")
+                         .help("Also write the hashes to this JSON file, or \
+                                - for the standard output")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("replay-diagnostic")
+                    .about("Run the tracer on the events of a diagnostic \
+                            recording, to debug it")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database to create with the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("recording")
+                         .help("The recording, from trace \
+                                --diagnostic-recording")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("pack")
+                    .about("Create a pack from a trace, holding the files it \
+                            read")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("format")
+                         .long("format")
+                         .help("Archive format, tar-gz can be extracted \
+                                anywhere and tar-zst is faster")
+                         .takes_value(true)
+                         .possible_values(&["rpz", "tar-gz", "tar-zst"])
+                         .default_value("rpz"))
+                    .arg(Arg::with_name("pack")
+                         .help("The pack to create, experiment.rpz or \
+                                experiment.tar.gz or experiment.tar.zst by \
+                                default")));
+    #[cfg(feature = "cloud")]
+    let cli = cli
+        .subcommand(SubCommand::with_name("upload")
+                    .about("Upload a pack to object storage")
+                    .arg(Arg::with_name("database")
+                         .long("db")
+                         .help("Database containing the trace, to record the \
+                                upload in")
+                         .takes_value(true)
+                         .default_value("/tmp/db"))
+                    .arg(Arg::with_name("checksum")
+                         .long("checksum")
+                         .help("Download the pack again and compare its \
+                                hash")
+                         .takes_value(true)
+                         .possible_values(&["sha256"]))
+                    .arg(Arg::with_name("pack")
+                         .help("The pack to upload")
+                         .required(true))
+                    .arg(Arg::with_name("url")
+                         .help("Where to upload it, e.g. \
+                                s3://bucket/experiments/")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("download")
+                    .about("Download a pack from object storage into the \
+                            current directory, checking it against its \
+                            .sha256 file")
+                    .arg(Arg::with_name("url")
+                         .help("The pack to download, e.g. \
+                                s3://bucket/experiments/experiment.rpz")
+                         .required(true)));
+    let mut cli = cli;
     let matches = match cli.get_matches_from_safe_borrow(std::env::args_os()) {
         Ok(m) => m,
         Err(e) => {
@@ -66,9 +651,30 @@ fn main() {
         }
     };
 
+    // Before the logging thread is started, as only the forking thread
+    // remains in the child
+    if let Some(s_matches) = matches.subcommand_matches("trace") {
+        if s_matches.is_present("background") {
+            let log = Path::new(s_matches.value_of_os("log").unwrap());
+            if let Err(err) = daemonize(log) {
+                eprintln!("Can't run in the background: {}", err);
+                exit(1);
+            }
+        }
+    }
+
+    let color = if matches.is_present("no-color") {
+        "never"
+    } else {
+        matches.value_of("color").unwrap()
+    };
+    let console = Console::new(color);
+
     // Set up logging to terminal
     let logger = {
-        let decorator = slog_term::TermDecorator::new().build();
+        let decorator = ColorDecorator {
+            stream: StandardStream::stderr(console.stderr),
+        };
         let drain = slog_term::FullFormat::new(decorator).build().fuse();
         let level = match matches.occurrences_of("verbose") {
             0 => slog::Level::Warning,
@@ -87,30 +693,316 @@ fn main() {
     match matches.subcommand_name() {
         Some("trace") => {
             let s_matches = matches.subcommand_matches("trace").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
             let cmd = s_matches
                 .values_of_os("rr4cmds")
-                .expect("No value for 'command'")
-                .into_iter()
-                .map(OsStrExt::as_bytes)
-                .collect();
-            match run_trace(logger, cmd) {
+                .map(|cmd| cmd.map(OsStrExt::as_bytes).collect())
+                .unwrap_or_default();
+            let attach = s_matches.value_of("attach").map(|pid| {
+                Pid::from_raw(pid.parse().unwrap_or_else(|_| {
+                    eprintln!("Invalid value for --attach");
+                    exit(2);
+                }))
+            });
+            let config = TraceConfig {
+                suppress_syslog: s_matches.is_present("suppress-syslog"),
+                emulate_perf: s_matches.is_present("emulate-perf"),
+                block_bpf: s_matches.is_present("block-bpf"),
+                max_file_size: match s_matches.value_of("max-file-size") {
+                    Some(size) => match size.parse() {
+                        Ok(size) => Some(size),
+                        Err(_) => {
+                            eprintln!("Invalid value for --max-file-size");
+                            exit(2);
+                        }
+                    },
+                    None => None,
+                },
+                emulate_single_cpu: s_matches.is_present("emulate-single-cpu"),
+                time_override: match s_matches.value_of("fake-time") {
+                    Some(start) => {
+                        let start: f64 = start.parse().unwrap_or_else(|_| {
+                            eprintln!("Invalid value for --fake-time");
+                            exit(2);
+                        });
+                        let speed = match s_matches.value_of("time-speed") {
+                            Some(speed) => speed.parse().unwrap_or_else(|_| {
+                                eprintln!("Invalid value for --time-speed");
+                                exit(2);
+                            }),
+                            None => 1.0,
+                        };
+                        let start = UNIX_EPOCH + Duration::from_secs_f64(start);
+                        Some(TimeOverride::new(start).speed(speed))
+                    }
+                    None => None,
+                },
+                deterministic_random: s_matches
+                    .value_of("deterministic-random")
+                    .map(|seed| Sha256::digest(seed.as_bytes()).into()),
+                maps_scan_interval: match s_matches
+                    .value_of("maps-interval").unwrap().parse()
+                {
+                    Ok(interval) => interval,
+                    Err(_) => {
+                        eprintln!("Invalid value for --maps-interval");
+                        exit(2);
+                    }
+                },
+                backend: match s_matches.value_of("backend") {
+                    Some("fanotify") => Backend::Fanotify,
+                    _ => Backend::Ptrace,
+                },
+                strace_output: s_matches.value_of_os("strace-output")
+                    .map(PathBuf::from),
+                syscall_log: s_matches.value_of_os("syscall-log")
+                    .map(PathBuf::from),
+                skip_root: s_matches.is_present("trace-children-only"),
+                auto_vacuum: s_matches.is_present("auto-vacuum"),
+                ignored_executables: s_matches
+                    .values_of("ignore-executable")
+                    .map(|names| names.map(str::to_owned).collect())
+                    .unwrap_or_default(),
+                verify_fd_table: s_matches.is_present("verify-fd-table"),
+                pid_file: s_matches.value_of_os("pid-file").map(PathBuf::from),
+                max_events: s_matches.value_of("max-events").map(|max| {
+                    max.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value for --max-events");
+                        exit(2);
+                    })
+                }),
+                max_processes: s_matches.value_of("max-processes").map(|max| {
+                    max.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value for --max-processes");
+                        exit(2);
+                    })
+                }),
+                max_process_depth: s_matches.value_of("max-depth").map(|max| {
+                    max.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value for --max-depth");
+                        exit(2);
+                    })
+                }),
+                root_prefix: s_matches.value_of_os("rootdir")
+                    .map(PathBuf::from),
+                capture_output: s_matches.is_present("capture-output"),
+                max_output_bytes: match s_matches.value_of("max-output-bytes")
+                {
+                    Some(max) => max.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid value for --max-output-bytes");
+                        exit(2);
+                    }),
+                    None => TraceConfig::default().max_output_bytes,
+                },
+                use_seize: s_matches.is_present("seize"),
+                inject_ld_preload: s_matches
+                    .values_of_os("inject-ld-preload")
+                    .map(|paths| paths.map(PathBuf::from).collect())
+                    .unwrap_or_default(),
+                record_checksums: s_matches
+                    .is_present("record-checksums-at-access"),
+                record_mac_labels: s_matches.is_present("record-mac-labels"),
+                diagnostic_recording: s_matches
+                    .value_of_os("diagnostic-recording")
+                    .map(PathBuf::from),
+                schema_version: s_matches.value_of("format-version")
+                    .map_or(schema::LATEST_VERSION, |v| v.parse().unwrap()),
+            };
+            let journal = s_matches.is_present("journal");
+            let progress = s_matches.is_present("progress");
+            match run_trace(logger, database, config, journal, progress,
+                            attach, cmd) {
                 Ok(ExitStatus::Return(0)) => {}
                 Ok(ExitStatus::Return(status)) => {
-                    eprintln!(
-                        "Warning: program returned non-zero exit status {}",
-                        status
-                    );
+                    console.warning(format!(
+                        "program returned non-zero exit status {}",
+                        status,
+                    ));
                 }
                 Ok(ExitStatus::Signal(sig)) => {
-                    eprintln!("Warning: program was terminated by signal {:?}",
-                              sig);
+                    console.warning(format!(
+                        "program was terminated by signal {:?}",
+                        sig,
+                    ));
                 }
                 Err(err) => {
-                    eprintln!("Error: {}", err);
+                    console.error(err);
                     exit(1);
                 }
             }
         }
+        Some("info") => {
+            let s_matches = matches.subcommand_matches("info").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            let result = match s_matches.value_of_os("output-dir") {
+                Some(output) if s_matches.value_of("format") == Some("csv") => {
+                    Database::open(database, logger).and_then(|database| {
+                        database.export_csv(Path::new(output))
+                    })
+                }
+                _ if s_matches.is_present("report") => {
+                    Database::open(database, logger).and_then(|database| {
+                        print!("{}", ReproducibilityReport::new(&database)?);
+                        Ok(())
+                    })
+                }
+                _ => run_info(logger, console, database,
+                              s_matches.is_present("show-output")),
+            };
+            if let Err(err) = result {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("bundle-docker") => {
+            let s_matches =
+                matches.subcommand_matches("bundle-docker").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            if let Err(err) = run_bundle_docker(logger, database, s_matches) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("generate-seccomp") => {
+            let s_matches =
+                matches.subcommand_matches("generate-seccomp").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            let output = s_matches.value_of_os("output").unwrap();
+            if let Err(err) = run_generate_seccomp(logger, database, output) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("vacuum") => {
+            let s_matches = matches.subcommand_matches("vacuum").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            if let Err(err) = run_vacuum(logger, console, database) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("fuzz") => {
+            let s_matches = matches.subcommand_matches("fuzz").unwrap();
+            let command = s_matches.values_of_os("command").unwrap()
+                .map(|arg| arg.as_bytes().to_owned())
+                .collect();
+            if let Err(err) = run_fuzz(
+                logger,
+                console,
+                s_matches.value_of_os("db-template").unwrap(),
+                s_matches.value_of_os("input-dir").unwrap(),
+                command,
+            ) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("merge") => {
+            let s_matches = matches.subcommand_matches("merge").unwrap();
+            let parent = s_matches.value_of("parent").unwrap().parse()
+                .unwrap_or_else(|_| {
+                    eprintln!("Invalid value for --parent");
+                    exit(2);
+                });
+            if let Err(err) = run_merge(
+                logger,
+                console,
+                s_matches.value_of_os("base").unwrap(),
+                s_matches.value_of_os("overlay").unwrap(),
+                s_matches.value_of_os("output").unwrap(),
+                ProcessId::from_number(parent),
+            ) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("clean") => {
+            let s_matches = matches.subcommand_matches("clean").unwrap();
+            let config = CleanConfig {
+                database: PathBuf::from(
+                    s_matches.value_of_os("database").unwrap(),
+                ),
+                directory: PathBuf::from("."),
+            };
+            if let Err(err) = run_clean(
+                &config,
+                s_matches.is_present("yes"),
+                s_matches.is_present("dry-run"),
+            ) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("validate") => {
+            let s_matches = matches.subcommand_matches("validate").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            match run_validate(logger, console, database) {
+                Ok(true) => {}
+                Ok(false) => exit(1),
+                Err(err) => {
+                    console.error(err);
+                    exit(1);
+                }
+            }
+        }
+        Some("critical-path") => {
+            let s_matches =
+                matches.subcommand_matches("critical-path").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            if let Err(err) = run_critical_path(logger, database) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("repro-hash") => {
+            let s_matches = matches.subcommand_matches("repro-hash").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            let output = s_matches.value_of_os("output");
+            if let Err(err) = run_repro_hash(logger, console, database,
+                                             output)
+            {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("replay-diagnostic") => {
+            let s_matches =
+                matches.subcommand_matches("replay-diagnostic").unwrap();
+            let database = s_matches.value_of_os("database").unwrap();
+            let recording = Path::new(
+                s_matches.value_of_os("recording").unwrap(),
+            );
+            let result = Tracer::with_logger(database, logger)
+                .and_then(|tracer| tracer.replay_diagnostic(recording));
+            if let Err(err) = result {
+                console.error(err);
+                exit(1);
+            }
+        }
+        Some("pack") => {
+            let s_matches = matches.subcommand_matches("pack").unwrap();
+            if let Err(err) = run_pack(logger, console, s_matches) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        #[cfg(feature = "cloud")]
+        Some("upload") => {
+            let s_matches = matches.subcommand_matches("upload").unwrap();
+            if let Err(err) = run_upload(logger, console, s_matches) {
+                console.error(err);
+                exit(1);
+            }
+        }
+        #[cfg(feature = "cloud")]
+        Some("download") => {
+            let s_matches = matches.subcommand_matches("download").unwrap();
+            let url = s_matches.value_of("url").unwrap();
+            if let Err(err) = run_download(console, url) {
+                console.error(err);
+                exit(1);
+            }
+        }
         _ => {
             cli.print_help().expect("Can't print help");
             std::process::exit(2);
@@ -118,9 +1010,697 @@ fn main() {
     }
 }
 
+/// Keep running in the background, detached from the terminal, with output
+/// going to a log file.
+///
+/// The process forks twice, the intermediate one creating a new session, so
+/// that the one left is not a session leader and can't get a terminal back.
+/// The original process exits with 0 right away.
+fn daemonize(log: &Path) -> Result<(), Error> {
+    // Opened first, so errors are seen in the foreground
+    let log = OpenOptions::new().create(true).append(true).open(log)?;
+    let null = File::open("/dev/null")?;
+    if let ForkResult::Parent { .. } = fork()? {
+        exit(0);
+    }
+    setsid()?;
+    if let ForkResult::Parent { .. } = fork()? {
+        exit(0);
+    }
+    dup2(null.as_raw_fd(), io::stdin().as_raw_fd())?;
+    dup2(log.as_raw_fd(), io::stdout().as_raw_fd())?;
+    dup2(log.as_raw_fd(), io::stderr().as_raw_fd())?;
+    Ok(())
+}
+
 fn run_trace(
     logger: slog::Logger,
+    database: &OsStr,
+    config: TraceConfig,
+    journal: bool,
+    progress: bool,
+    attach: Option<Pid>,
     command: Vec<&[u8]>,
 ) -> Result<ExitStatus, Error> {
-    Tracer::with_logger("/tmp/db", logger)?.trace(&command)
+    let status = match config.backend {
+        Backend::Ptrace if journal => {
+            let database = JournalDatabase::new(
+                Database::with_schema_version(database, config.schema_version,
+                                              logger.clone())?,
+                config.schema_version,
+            )?;
+            let tracer = Tracer::with_database(database, logger, config);
+            let _progress = progress.then(|| report_progress(&tracer));
+            match attach {
+                Some(pid) => tracer.attach_stable(pid)?,
+                None => tracer.trace(&command)?.0,
+            }
+        }
+        Backend::Ptrace => {
+            let tracer = Tracer::with_config(database, logger, config)?;
+            let _progress = progress.then(|| report_progress(&tracer));
+            match attach {
+                Some(pid) => tracer.attach_stable(pid)?,
+                None => tracer.trace(&command)?.0,
+            }
+        }
+        Backend::Fanotify if attach.is_some() => {
+            return Err(Error::Internal(
+                "Can't attach to a process with fanotify".into(),
+            ));
+        }
+        Backend::Fanotify => {
+            // The other options all act on the ptrace tracer
+            let default = TraceConfig {
+                backend: Backend::Fanotify,
+                ..TraceConfig::default()
+            };
+            if journal || progress || config != default {
+                return Err(Error::Internal(
+                    "The fanotify backend only records file accesses, it \
+                     can't be used with the other options of trace".into(),
+                ));
+            }
+            FanotifyTracer::with_logger(database, logger)?.trace(&command)?.0
+        }
+    };
+    Ok(status)
+}
+
+/// Print the number of events handled by a tracer every second, until the
+/// returned sender is dropped.
+fn report_progress<D: DatabaseOps>(tracer: &Tracer<D>) -> mpsc::Sender<()> {
+    let counter = tracer.event_counter();
+    let (stop, stopped) = mpsc::channel();
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) =
+            stopped.recv_timeout(Duration::from_secs(1))
+        {
+            eprintln!("{} events", counter.load(Ordering::Relaxed));
+        }
+    });
+    stop
+}
+
+/// Format a number with commas between groups of thousands, e.g. `12,341`.
+fn thousands(number: u64) -> String {
+    let digits = number.to_string();
+    let mut result = String::with_capacity(digits.len() * 4 / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            result.push(',');
+        }
+        result.push(digit);
+    }
+    result
+}
+
+fn run_info(
+    logger: slog::Logger,
+    console: Console,
+    database: &OsStr,
+    show_output: bool,
+) -> Result<(), Error> {
+    let database = Database::open(database, logger)?;
+    let executions = database.executions()?;
+
+    println!("Trace: {} processes, {} file opens, {} unique files, {} total \
+              events",
+             thousands(database.process_count()?),
+             thousands(database.file_open_count()?),
+             thousands(database.unique_file_count()?),
+             thousands(database.total_events()?));
+
+    let score = database.reproducibility_score()?;
+    println!("Reproducibility score: {:.2}", score.score);
+    for reason in &score.reasons {
+        println!("    {}", reason);
+    }
+
+    println!("Processes:");
+    let tree = ProcessTree::from_database(&database)?;
+    for node in tree.dfs() {
+        let process = &node.info;
+        let depth = tree.ancestors(process.id).len();
+        let kind = if process.is_thread { "thread" } else { "process" };
+        let program = process.argv.as_ref().and_then(|argv| argv.first());
+        match program {
+            Some(program) => println!(
+                "    {:indent$}{} {}: {}", "", kind, process.id.number(),
+                String::from_utf8_lossy(program), indent = 2 * depth,
+            ),
+            None => println!(
+                "    {:indent$}{} {}", "", kind, process.id.number(),
+                indent = 2 * depth,
+            ),
+        }
+    }
+
+    println!("Executions:");
+    for exec in &executions {
+        let argv: Vec<_> = exec.argv.iter()
+            .map(|a| String::from_utf8_lossy(a))
+            .collect();
+        println!("    {} (process {}): {:?}",
+                 exec.path.display(), exec.process.number(), argv);
+    }
+
+    let setuid: Vec<_> = executions.iter()
+        .filter(|exec| exec.requires_setuid)
+        .collect();
+    if !setuid.is_empty() {
+        console.warning("These setuid programs ran without their \
+                         privileges, so they were not fully traced:");
+        for exec in &setuid {
+            eprintln!("    {} (process {})", exec.path.display(),
+                      exec.process.number());
+        }
+    }
+
+    let mut repeated: Vec<_> = database.file_opens()?
+        .into_iter()
+        .filter(|open| open.access_count > 1)
+        .collect();
+    repeated.sort_by_key(|open| std::cmp::Reverse(open.access_count));
+    if !repeated.is_empty() {
+        println!("Most repeated file accesses:");
+        for open in repeated.iter().take(10) {
+            println!("    {} (process {}, {:?}): {} times",
+                     open.path.display(), open.process.number(), open.mode,
+                     open.access_count);
+        }
+    }
+
+    let mut preloaded: Vec<_> = executions.iter()
+        .flat_map(|e| e.ld_preload_libraries())
+        .collect();
+    preloaded.sort();
+    preloaded.dedup();
+    if !preloaded.is_empty() {
+        console.warning("LD_PRELOAD libraries detected");
+        for library in &preloaded {
+            eprintln!("    {}", library.display());
+        }
+    }
+    if database.flag("has_kernel_log")? {
+        console.warning("This trace accesses the kernel log, which is \
+                         not reproducible");
+    }
+    if database.flag("has_perf_events")? {
+        console.warning("This trace uses hardware performance counters, \
+                         which are not reproducible across machines");
+    }
+    if database.flag("has_bpf")? {
+        console.warning("This trace loads eBPF programs, which require \
+                         kernel support at the same version");
+    }
+    let cache_queries = database.cache_stat_queries()?;
+    if !cache_queries.is_empty() {
+        let processes: BTreeSet<u32> = cache_queries.iter()
+            .map(|query| query.process.number())
+            .collect();
+        console.warning(format!("{} processes queried the page cache state \
+                                 of files, the program might depend on the \
+                                 memory available", processes.len()));
+    }
+    if database.flag("has_cross_process_memory")? {
+        console.warning("This trace reads or writes the memory of other \
+                         processes directly, which may not be reproducible");
+    }
+    if database.flag("has_quota_dependency")? {
+        console.warning("This trace uses disk quotas; replay on a system \
+                         without matching quotas may fail");
+    }
+
+    println!("Process times (wall, CPU):");
+    for id in database.process_ids()? {
+        let format = |time: Option<Duration>| match time {
+            Some(time) => format!("{:.3}s", time.as_secs_f64()),
+            None => "?".to_owned(),
+        };
+        let pids = match database.process_pids(id)? {
+            Some((host, namespace)) if host != namespace => {
+                format!(" (pid {}, {} in its namespace)", host, namespace)
+            }
+            Some((host, _)) => format!(" (pid {})", host),
+            None => String::new(),
+        };
+        println!("    process {}{}: {}, {}", id.number(), pids,
+                 format(database.process_duration(id)?),
+                 format(database.cpu_time(id)?));
+    }
+
+    let namespaces = database.namespace_changes()?;
+    if !namespaces.is_empty() {
+        console.warning("Some processes run in other namespaces than the \
+                         first one, replaying them requires setting those \
+                         up:");
+        for (id, types) in &namespaces {
+            eprintln!("    process {}: {}", id.number(), types.join(", "));
+        }
+    }
+
+    for policy in &database.landlock_policies()? {
+        println!("Process {} used Landlock for sandboxing",
+                 policy.process.number());
+        if policy.handled_access != 0 {
+            println!("    restricted: {}",
+                     landlock_access_names(policy.handled_access).join(", "));
+        }
+        for (path, access) in &policy.rules {
+            println!("    allowed beneath {}: {}", path.display(),
+                     landlock_access_names(*access).join(", "));
+        }
+    }
+
+    let polls = database.poll_durations()?;
+    if !polls.is_empty() {
+        println!("Time blocked waiting on files:");
+        for (id, path, duration) in &polls {
+            println!("    process {} blocked on {} for {:.3}s",
+                     id.number(), path.display(), *duration as f64 / 1e9);
+        }
+    }
+
+    let mut sleeps = Vec::new();
+    for id in database.process_ids()? {
+        let sleep = database.total_sleep_ns(id)?;
+        if sleep > 0 {
+            sleeps.push((id, sleep));
+        }
+    }
+    if !sleeps.is_empty() {
+        let total: u64 = sleeps.iter().map(|&(_, s)| s).sum();
+        println!("Total sleep time: {:.3}s", total as f64 / 1e9);
+        for (id, sleep) in &sleeps {
+            println!("    process {}: {:.3}s",
+                     id.number(), *sleep as f64 / 1e9);
+        }
+        // Processes can sleep concurrently, so compare the longest one
+        let longest = sleeps.iter().map(|&(_, s)| s).max().unwrap();
+        if let Some(wall_time) = database.wall_time_ns()? {
+            if longest > wall_time / 10 {
+                console.warning("This trace spent more than 10% of its \
+                                 time sleeping, the program expects real \
+                                 time to pass");
+            }
+        }
+    }
+    if database.flag("has_jit_code")? {
+        console.warning("This trace runs JIT-compiled or self-modifying \
+                         code");
+    }
+    if database.flag("has_cpu_affinity")? {
+        console.warning("This trace pins itself to specific CPUs, which \
+                         might not exist on other machines");
+    }
+    if database.flag("has_seccomp_filter")? {
+        console.warning("The trace was aborted because the program \
+                         installed a seccomp filter; it is incomplete");
+    }
+    if database.flag("has_timerfd")? {
+        console.warning("This trace uses real-time timers; replay may \
+                         behave differently if the wall clock differs \
+                         significantly");
+    }
+    let timers = database.timers()?;
+    if !timers.is_empty() {
+        println!("Timers:");
+        for timer in &timers {
+            let created = if timer.created { "created, " } else { "" };
+            println!("    process {}, timer {}: {}queried {} times",
+                     timer.process.number(), timer.timer_fd, created,
+                     timer.queries);
+        }
+        if database.flag("timer_fired")? {
+            println!("    A timer fired during the trace");
+        } else {
+            println!("    No timer fired during the trace");
+        }
+    }
+    if let Some(status) = database.crash_status()? {
+        console.warning(format!("The program crashed in this trace ({})",
+                                status));
+    }
+    if let Some(url) = database.upload_url()? {
+        println!("Uploaded to: {}", url);
+    }
+    if show_output {
+        let outputs = database.process_outputs()?;
+        if outputs.is_empty() {
+            println!("No output captured, trace with --capture-output");
+        }
+        for output in &outputs {
+            for (name, data) in &[("stdout", &output.stdout),
+                                  ("stderr", &output.stderr)] {
+                println!("Process {} {} ({} bytes):", output.process.number(),
+                         name, data.len());
+                print_output(data);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Most bytes of each captured stream printed by `info --show-output`.
+const SHOW_OUTPUT_BYTES: usize = 4096;
+
+/// Print captured output, indented, truncating it if it's long.
+fn print_output(data: &[u8]) {
+    let shown = &data[..data.len().min(SHOW_OUTPUT_BYTES)];
+    for line in String::from_utf8_lossy(shown).lines() {
+        println!("    {}", line);
+    }
+    if data.len() > shown.len() {
+        println!("    ... ({} more bytes)", data.len() - shown.len());
+    }
+}
+
+fn run_bundle_docker(
+    logger: slog::Logger,
+    database: &OsStr,
+    matches: &clap::ArgMatches,
+) -> Result<(), Error> {
+    let database = Database::open(database, logger)?;
+    let bundle = DockerBundler::new(&database)
+        .base_image(matches.value_of("base-image").unwrap())
+        .write(matches.value_of_os("output").unwrap())?;
+    println!("Wrote Docker build context to {}", bundle.directory.display());
+    if !bundle.bind_mounts.is_empty() {
+        println!("The following files need to be bind-mounted:");
+        for path in &bundle.bind_mounts {
+            println!("    -v {0}:{0}:ro", path.display());
+        }
+    }
+    if matches.is_present("build") {
+        bundle.build(matches.value_of("tag"))?;
+    }
+    Ok(())
+}
+
+fn run_generate_seccomp(
+    logger: slog::Logger,
+    database: &OsStr,
+    output: &OsStr,
+) -> Result<(), Error> {
+    let database = Database::open(database, logger)?;
+    let profile = database_seccomp_profile(&database)?;
+    std::fs::write(output, profile)?;
+    Ok(())
+}
+
+fn run_vacuum(
+    logger: slog::Logger,
+    console: Console,
+    database: &OsStr,
+) -> Result<(), Error> {
+    let before = std::fs::metadata(database)?.len();
+    let mut trace = Database::open_for_update(database, logger)?;
+    let duplicates = trace.deduplicate_file_opens()?;
+    trace.commit()?;
+    vacuum(database)?;
+    let after = std::fs::metadata(database)?.len();
+    console.success(format!("Merged {} duplicate file accesses",
+                            duplicates));
+    console.success(format!("Database size: {} bytes before, {} bytes after",
+                            before, after));
+    Ok(())
+}
+
+fn run_fuzz(
+    logger: slog::Logger,
+    console: Console,
+    db_template: &OsStr,
+    input_dir: &OsStr,
+    command: Vec<Vec<u8>>,
+) -> Result<(), Error> {
+    let results = FuzzOrchestrator::new(db_template, command, logger)?
+        .run(Path::new(input_dir))?;
+    let mut crashes = 0;
+    for result in &results {
+        if result.crashed {
+            crashes += 1;
+            console.warning(format!("{} crashed ({:?}), trace in {}",
+                                    result.input.display(), result.status,
+                                    result.database.display()));
+        }
+    }
+    console.success(format!("Traced {} inputs, {} crashes", results.len(),
+                            crashes));
+    Ok(())
+}
+
+fn run_merge(
+    logger: slog::Logger,
+    console: Console,
+    base: &OsStr,
+    overlay: &OsStr,
+    output: &OsStr,
+    parent: ProcessId,
+) -> Result<(), Error> {
+    // Open them first, which fails if one is still being traced
+    Database::open(base, logger.clone())?;
+    let overlay = Database::open(overlay, logger.clone())?;
+    std::fs::copy(base, output)?;
+    let merged = Database::open_for_update(output, logger).and_then(|mut db| {
+        db.merge_from(overlay, parent)?;
+        db.commit()
+    });
+    if let Err(e) = merged {
+        // Don't leave a copy of the base trace looking like the result
+        std::fs::remove_file(output)?;
+        return Err(e);
+    }
+    console.success(format!("Wrote combined trace to {}",
+                            Path::new(output).display()));
+    Ok(())
+}
+
+/// Report the files of a trace that are not portable, the working
+/// directories that were not tracked correctly, and the input files that
+/// changed since `repro-hash`, returning whether there were none.
+fn run_validate(
+    logger: slog::Logger,
+    console: Console,
+    database: &OsStr,
+) -> Result<bool, Error> {
+    let database = Database::open(database, logger)?;
+    // Check against the machine the trace was made on if we know its mounts
+    let mounts = database.mount_points()?;
+    let checker = if mounts.is_empty() {
+        PortabilityChecker::new()?
+    } else {
+        PortabilityChecker::with_mounts(mounts)
+    };
+    let paths: BTreeSet<PathBuf> = database.executions()?.into_iter()
+        .map(|execution| execution.path)
+        .chain(database.file_opens()?.into_iter().map(|open| open.path))
+        .collect();
+    let mut issues = 0;
+    let mut network = BTreeMap::new();
+    for path in &paths {
+        if let Some(warning) = checker.check_path(path) {
+            println!("{}: {}", path.display(), warning);
+            issues += 1;
+            if warning == PortabilityWarning::NetworkMount {
+                if let Some(mount) = checker.mount(path) {
+                    *network.entry(mount).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    if issues == 0 {
+        console.success(format!("All {} files are portable", paths.len()));
+    } else {
+        console.warning(format!("{} of {} files are not portable", issues,
+                                paths.len()));
+    }
+    if !network.is_empty() {
+        console.warning("Some files are on network mounts, which can't be \
+                         packed reliably:");
+        for (mount, count) in &network {
+            eprintln!("    {} files on {} ({} from {})", count,
+                      mount.mount_point.display(), mount.fs_type,
+                      mount.device);
+        }
+    }
+    let inconsistencies = database.validate_cwd_consistency()?;
+    if !inconsistencies.is_empty() {
+        console.warning("Some working directories were never changed to, \
+                         the paths relative to them might be wrong:");
+        for inconsistency in &inconsistencies {
+            let used_by = match inconsistency.used_by {
+                CwdUse::Execution => "executed a program",
+                CwdUse::NewProcess => "started",
+            };
+            eprintln!("    process {} {} in {}",
+                      inconsistency.process.number(), used_by,
+                      inconsistency.working_dir.display());
+        }
+    }
+    let changed = repro_hash::changed_files(&database)?;
+    if !changed.is_empty() {
+        console.warning(format!("{} input files changed since they were \
+                                 hashed:", changed.len()));
+        for path in &changed {
+            eprintln!("    {}", path.display());
+        }
+    }
+    Ok(issues == 0 && inconsistencies.is_empty() && changed.is_empty())
+}
+
+fn run_repro_hash(
+    logger: slog::Logger,
+    console: Console,
+    database: &OsStr,
+    output: Option<&OsStr>,
+) -> Result<(), Error> {
+    let mut database = Database::open_for_update(database, logger.clone())?;
+    let hashes = repro_hash::hash_inputs(&database, &logger)?;
+    for (path, hash) in &hashes {
+        database.add_file_hash(path, *hash)?;
+    }
+    database.commit()?;
+    match output {
+        Some(output) if output == "-" => {
+            repro_hash::write_json(&hashes, io::stdout().lock())?;
+        }
+        Some(output) => {
+            let file = std::fs::File::create(output)?;
+            repro_hash::write_json(&hashes, io::BufWriter::new(file))?;
+        }
+        None => {}
+    }
+    console.success(format!("Recorded the hashes of {} files",
+                            hashes.len()));
+    Ok(())
+}
+
+fn run_pack(
+    logger: slog::Logger,
+    console: Console,
+    matches: &clap::ArgMatches,
+) -> Result<(), Error> {
+    let database = Path::new(matches.value_of_os("database").unwrap());
+    let format = matches.value_of("format").unwrap()
+        .parse::<ArchiveFormat>()?;
+    let output = match matches.value_of_os("pack") {
+        Some(pack) => PathBuf::from(pack),
+        None => PathBuf::from(format!("experiment.{}", format.extension())),
+    };
+    let count = archive::pack(database, format, &output, &logger)?;
+    console.success(format!("Packed {} files into {}", count,
+                            output.display()));
+    Ok(())
+}
+
+fn run_critical_path(
+    logger: slog::Logger,
+    database: &OsStr,
+) -> Result<(), Error> {
+    let database = Database::open(database, logger)?;
+    let chain = DependencyGraph::from_database(&database)?.critical_path();
+    if chain.is_empty() {
+        println!("No files were written");
+        return Ok(());
+    }
+    // Show each process as the last program it executed
+    let programs: HashMap<ProcessId, PathBuf> = database.executions()?
+        .into_iter()
+        .map(|exec| (exec.process, exec.path))
+        .collect();
+    println!("Critical path, {} steps:", chain.len());
+    for (i, (process, path)) in chain.iter().enumerate() {
+        let program = match programs.get(process) {
+            Some(program) => format!(" ({})", program.display()),
+            None => String::new(),
+        };
+        let verb = if i == 0 { "wrote" } else { "read it and wrote" };
+        println!("    process {}{} {} {}", process.number(), program, verb,
+                 path.display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cloud")]
+fn run_upload(
+    logger: slog::Logger,
+    console: Console,
+    matches: &clap::ArgMatches,
+) -> Result<(), Error> {
+    let pack = Path::new(matches.value_of_os("pack").unwrap());
+    let url = matches.value_of("url").unwrap();
+    let checksum = match matches.value_of("checksum") {
+        Some(checksum) => Some(checksum.parse::<Checksum>()?),
+        None => None,
+    };
+    let upload = Uploader::new(url)?.upload(pack, checksum)?;
+    console.success(format!("Uploaded {} ({} bytes) to {}", pack.display(),
+                            upload.size, upload.url));
+    let sha256 = upload.sha256.map(|hash| hex(&hash));
+    if let Some(sha256) = &sha256 {
+        console.success(format!("Verified, SHA-256 {}", sha256));
+    }
+
+    let database = matches.value_of_os("database").unwrap();
+    if Path::new(database).exists() {
+        let mut trace = Database::open_for_update(database, logger)?;
+        trace.set_upload(upload.url.as_str(), sha256.as_deref())?;
+        trace.commit()?;
+    } else {
+        warn!(logger, "No trace database, not recording the upload";
+              "path" => %Path::new(database).display());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "cloud")]
+fn run_download(console: Console, url: &str) -> Result<(), Error> {
+    let download = Downloader::new(url)?.download(Path::new("."))?;
+    if download.resumed_from > 0 {
+        println!("Resumed download after {} bytes", download.resumed_from);
+    }
+    console.success(format!("Downloaded {} ({} bytes)",
+                            download.path.display(), download.size));
+    if download.verified {
+        console.success(format!("Verified, SHA-256 {}",
+                                hex(&download.sha256)));
+    } else {
+        console.warning("No checksum uploaded with the pack, it was not \
+                         verified");
+    }
+    if !download.integrity_checked {
+        console.warning("The download is neither a pack nor a trace, it \
+                         was not checked for corruption");
+    }
+    Ok(())
+}
+
+fn run_clean(
+    config: &CleanConfig,
+    yes: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let paths = config.artifacts()?;
+    if paths.is_empty() {
+        println!("Nothing to remove");
+        return Ok(());
+    }
+    println!("The following files will be removed:");
+    for path in &paths {
+        println!("    {}", path.display());
+    }
+    if dry_run {
+        return Ok(());
+    } else if !yes {
+        print!("Continue? [y/N] ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+    remove_artifacts(&paths)
 }
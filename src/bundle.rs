@@ -0,0 +1,3 @@
+//! Turning a trace into something that can run the experiment elsewhere.
+
+pub mod docker;
@@ -0,0 +1,170 @@
+//! Generation of a Docker image from a trace.
+//!
+//! The files read by the experiment are copied into a build context next to
+//! a `Dockerfile`, which starts from a minimal base image and recreates the
+//! environment, working directory and command of the first execution.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{Error, xattr};
+use crate::database::{Database, FileOp};
+
+/// Prefixes of the system locations whose files go into the image.
+///
+/// Files elsewhere are user data, and are bind-mounted when running the
+/// container instead.
+const SYSTEM_PREFIXES: &[&str] = &["/usr", "/lib", "/lib32", "/lib64", "/bin",
+                                   "/sbin"];
+
+fn is_system_path(path: &Path) -> bool {
+    SYSTEM_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Quote a string for use in a Dockerfile, as in a JSON string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(quoted, "\\u{:04x}", c as u32).unwrap();
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The result of bundling, a build context ready for `docker build`.
+pub struct DockerBundle {
+    pub directory: PathBuf,
+    /// Files that need to be bind-mounted into the container
+    pub bind_mounts: Vec<PathBuf>,
+}
+
+impl DockerBundle {
+    /// Run `docker build` on the generated context.
+    pub fn build(&self, tag: Option<&str>) -> Result<(), Error> {
+        let mut cmd = Command::new("docker");
+        cmd.arg("build");
+        if let Some(tag) = tag {
+            cmd.arg("-t").arg(tag);
+        }
+        cmd.arg(&self.directory);
+        let status = cmd.status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::Internal(format!("docker build failed: {}", status)))
+        }
+    }
+}
+
+/// Builder for a Docker build context from a trace.
+pub struct DockerBundler<'a> {
+    database: &'a Database,
+    base_image: String,
+}
+
+impl<'a> DockerBundler<'a> {
+    pub fn new(database: &'a Database) -> DockerBundler<'a> {
+        DockerBundler {
+            database,
+            base_image: "scratch".into(),
+        }
+    }
+
+    /// Set the image to start from, `scratch` by default.
+    pub fn base_image<S: Into<String>>(mut self, image: S) -> Self {
+        self.base_image = image.into();
+        self
+    }
+
+    /// Write the `Dockerfile` and the files into the given directory.
+    pub fn write<P: AsRef<Path>>(
+        &self,
+        directory: P,
+    ) -> Result<DockerBundle, Error> {
+        let directory = directory.as_ref();
+        let executions = self.database.executions()?;
+        let exec = match executions.first() {
+            Some(exec) => exec,
+            None => {
+                return Err(Error::Internal(
+                    "No program was executed in this trace".into(),
+                ))
+            }
+        };
+
+        // Sort the files read between the image and the bind mounts
+        let mut copied = BTreeSet::new();
+        let mut bind_mounts = BTreeSet::new();
+        for file_open in self.database.file_opens()? {
+            if file_open.is_directory
+                || !file_open.mode.contains(FileOp::READ)
+            {
+                continue;
+            }
+            if is_system_path(&file_open.path) {
+                copied.insert(file_open.path);
+            } else {
+                bind_mounts.insert(file_open.path);
+            }
+        }
+
+        let files = directory.join("files");
+        fs::create_dir_all(&files)?;
+        for path in &copied {
+            let dest = files.join(path.strip_prefix("/").unwrap());
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(path, &dest)
+                .and_then(|_| xattr::copy(path, &dest))
+                .map_err(|e| {
+                    Error::Internal(format!("Can't copy {}: {}",
+                                            path.display(), e))
+                })?;
+        }
+
+        let mut dockerfile = String::new();
+        writeln!(dockerfile, "FROM {}", self.base_image).unwrap();
+        writeln!(dockerfile, "COPY files/ /").unwrap();
+        for var in &exec.envp {
+            let var = String::from_utf8_lossy(var);
+            if let Some(eq) = var.find('=') {
+                // Escape variable substitution, done by Docker in ENV
+                let value = quote(&var[eq + 1..]).replace('$', "\\$");
+                writeln!(dockerfile, "ENV {}={}", &var[..eq], value).unwrap();
+            }
+        }
+        writeln!(dockerfile, "WORKDIR {}", exec.working_dir.display())
+            .unwrap();
+        for path in &bind_mounts {
+            writeln!(dockerfile, "# Bind-mount: {}", path.display())
+                .unwrap();
+        }
+        let args = exec.argv.iter().skip(1).map(|a| String::from_utf8_lossy(a));
+        let cmd: Vec<String> = Some(exec.path.to_string_lossy())
+            .into_iter()
+            .chain(args)
+            .map(|a| quote(&a))
+            .collect();
+        writeln!(dockerfile, "CMD [{}]", cmd.join(", ")).unwrap();
+        File::create(directory.join("Dockerfile"))?
+            .write_all(dockerfile.as_bytes())?;
+
+        Ok(DockerBundle {
+            directory: directory.to_owned(),
+            bind_mounts: bind_mounts.into_iter().collect(),
+        })
+    }
+}
@@ -0,0 +1,93 @@
+//! Hashing the files read by the traced program as it first opens them, see
+//! `TraceConfig::record_checksums`.
+//!
+//! Files are hashed by a pool of threads, so that the traced program is not
+//! held up while they are read. Each file is opened right away through the
+//! descriptor of the process, so the hash is of the file it opened even if
+//! the path is replaced afterwards.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::Error;
+use crate::database::DatabaseOps;
+use crate::repro_hash::hash_reader;
+
+/// The result of hashing a file, sent back by the pool.
+type HashResult = (PathBuf, io::Result<[u8; 32]>);
+
+/// Hashes files in the background, once per path.
+pub struct ChecksumRecorder {
+    pool: ThreadPool,
+    seen: HashSet<PathBuf>,
+    sender: Sender<HashResult>,
+    receiver: Receiver<HashResult>,
+    pending: usize,
+}
+
+impl ChecksumRecorder {
+    pub fn new() -> Result<ChecksumRecorder, Error> {
+        let pool = ThreadPoolBuilder::new()
+            .thread_name(|i| format!("reprozip-hash-{}", i))
+            .build()
+            .map_err(|e| {
+                Error::Internal(format!("Can't start hashing threads: {}", e))
+            })?;
+        let (sender, receiver) = mpsc::channel();
+        Ok(ChecksumRecorder {
+            pool,
+            seen: HashSet::new(),
+            sender,
+            receiver,
+            pending: 0,
+        })
+    }
+
+    /// Whether a file was already hashed, or is being.
+    pub fn has(&self, path: &Path) -> bool {
+        self.seen.contains(path)
+    }
+
+    /// Start hashing a file, opened from `path`.
+    pub fn record(&mut self, path: &Path, file: File) {
+        if !self.seen.insert(path.to_owned()) {
+            return;
+        }
+        self.pending += 1;
+        let path = path.to_owned();
+        let sender = self.sender.clone();
+        self.pool.spawn(move || {
+            // The receiver only goes away if the trace failed
+            sender.send((path, hash_reader(file))).ok();
+        });
+    }
+
+    /// Wait for the files to be hashed, and store the hashes.
+    ///
+    /// Files that couldn't be read are logged and left out.
+    pub fn finish(
+        self,
+        database: &mut dyn DatabaseOps,
+        logger: &slog::Logger,
+    ) -> Result<(), Error> {
+        info!(logger, "Waiting for {} files to be hashed", self.pending);
+        for _ in 0..self.pending {
+            let (path, result) = self.receiver.recv().map_err(|_| {
+                Error::Internal("Hashing thread panicked".into())
+            })?;
+            match result {
+                Ok(hash) => database.add_file_hash(&path, hash)?,
+                Err(e) => {
+                    warn!(logger, "Can't hash file: {}", e;
+                          "path" => %path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+}
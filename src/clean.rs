@@ -0,0 +1,62 @@
+//! Removal of the files left behind by tracing and packing.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Error;
+use crate::database::journal::journal_path;
+
+/// What `clean` should remove.
+pub struct CleanConfig {
+    /// The trace database
+    pub database: PathBuf,
+    /// The directory to look for packs in
+    pub directory: PathBuf,
+}
+
+impl CleanConfig {
+    /// List the files that exist and would be removed.
+    ///
+    /// This is the database with its journal and recovered trace, if any,
+    /// and the `.rpz` packs in the directory.
+    pub fn artifacts(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+        let mut recovered = self.database.as_os_str().to_owned();
+        recovered.push(".recovered");
+        for path in [
+            self.database.clone(),
+            journal_path(&self.database),
+            PathBuf::from(recovered),
+        ] {
+            if path.exists() {
+                paths.push(path);
+            }
+        }
+
+        let mut packs = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension() == Some(OsStr::new("rpz"))
+                && entry.file_type()?.is_file()
+                && !paths.contains(&path)
+            {
+                packs.push(path);
+            }
+        }
+        packs.sort();
+        paths.extend(packs);
+        Ok(paths)
+    }
+}
+
+/// Remove the given files, stopping at the first error.
+pub fn remove_artifacts(paths: &[PathBuf]) -> Result<(), Error> {
+    for path in paths {
+        fs::remove_file(path).map_err(|e| {
+            Error::Internal(format!("Can't remove {}: {}", path.display(), e))
+        })?;
+    }
+    Ok(())
+}
@@ -1,14 +1,55 @@
 //! This module is responsible for recording information in a SQLite database.
 
-use std::borrow::Cow;
-use std::path::Path;
+pub mod counting;
+pub mod journal;
+#[cfg(test)]
+pub mod mock;
+pub mod remap;
+pub mod schema;
+#[cfg(feature = "zerocopy")]
+pub mod zerocopy;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::remove_file;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::sys::signal::Signal;
+use rusqlite::{
+    Connection, OpenFlags, OptionalExtension, params, params_from_iter,
+};
+use rusqlite::types::Value;
 
 use crate::{Error, ExitStatus};
+use crate::portability::Mount;
+use crate::time_override::{TimeOverride, monotonic_ns};
+use self::journal::JournalDatabase;
+use self::schema::SchemaStrategy;
 
 /// The ID assigned to a process in the database.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct ProcessId(u32);
 
+impl ProcessId {
+    /// The identifier of the first process of a trace, which has no parent.
+    pub fn root() -> ProcessId {
+        ProcessId(0)
+    }
+
+    /// The number identifying this process in the database.
+    pub fn number(self) -> u32 {
+        self.0
+    }
+
+    /// The process with this number, the reverse of `number()`.
+    pub fn from_number(number: u32) -> ProcessId {
+        ProcessId(number)
+    }
+}
+
 bitflags! {
     /// Bit flags associated with a file access logged in the database.
     pub struct FileOp: u32 {
@@ -22,75 +63,2827 @@ bitflags! {
         const STAT  = 0b01000;
         /// The link itself is accessed, no dereference
         const LINK  = 0b10000;
+        /// The file is dropped from the page cache (`POSIX_FADV_DONTNEED`)
+        const CACHE_DROP = 0b100000;
     }
 }
 
-/// The database, where we record events about the traced program.
-pub struct Database {
-    logger: slog::Logger,
-    next_process: u32,
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Error {
+        Error::Internal(format!("database: {}", err))
+    }
 }
 
-impl Database {
-    pub fn new<D: AsRef<Path>>(
-        path: D,
-        logger: slog::Logger,
-    ) -> Result<Database, Error> {
-        Ok(Database { logger, next_process: 0 })
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Error {
+        Error::Internal(format!("CSV: {}", err))
+    }
+}
+
+/// The tables recording one event per row, counted by
+/// `Database::total_events()` along with the file accesses.
+const EVENT_TABLES: &[&str] = &[
+    "processes", "executions", "process_exits", "timer_events",
+    "timer_queries", "sleep_events", "poll_events", "fd_transfers",
+    "copy_ranges", "cpu_affinity_changes", "jit_regions", "quota_checks",
+    "xattr_reads", "xattr_writes", "pid_namespaces", "process_namespaces",
+    "signal_handlers", "landlock_policies", "bpf_operations", "cache_stats",
+    "page_fault_events", "cross_process_memory_accesses",
+];
+
+/// Compact a database file, reclaiming the space of deleted rows.
+///
+/// The trace must have been committed, and not be open for update.
+pub fn vacuum<D: AsRef<Path>>(path: D) -> Result<(), Error> {
+    let connection = Connection::open(path)?;
+    connection.query_row(
+        "PRAGMA wal_checkpoint(TRUNCATE)",
+        [],
+        |_| Ok(()),
+    )?;
+    connection.execute_batch("VACUUM")?;
+    Ok(())
+}
+
+/// Recover the journal left next to a database by a tracer that crashed, if
+/// any, into `<path>.recovered`.
+///
+/// This fails if the journal is that of a tracer still running.
+fn recover_leftover(path: &Path, logger: &slog::Logger) -> Result<(), Error> {
+    let leftover = journal::journal_path(path);
+    if leftover.exists() {
+        let mut recovered = path.as_os_str().to_owned();
+        recovered.push(".recovered");
+        let recovered = PathBuf::from(recovered);
+        JournalDatabase::recover(&leftover, &recovered)?;
+        warn!(logger, "Recovered trace from a crashed tracer";
+              "path" => %recovered.display());
+    }
+    Ok(())
+}
+
+/// The names of the tables in a database.
+fn table_names(connection: &Connection) -> Result<Vec<String>, Error> {
+    let mut stmt = connection.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table'",
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// The columns of a table, in order.
+fn table_columns(
+    connection: &Connection,
+    table: &str,
+) -> Result<Vec<String>, Error> {
+    let mut stmt = connection.prepare(&format!(
+        "PRAGMA table_info({})", table,
+    ))?;
+    let rows = stmt.query_map([], |row| row.get(1))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Whether a column holds process numbers, renumbered by
+/// `Database::merge_from()`.
+fn is_process_column(table: &str, column: &str) -> bool {
+    matches!(
+        (table, column),
+        ("processes", "id")
+        | ("processes", "parent")
+        | ("fd_transfers", "sender")
+        | ("fd_transfers", "receiver")
+        | ("landlock_policies", "ruleset_process")
+        | (_, "process_id")
+    )
+}
+
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+fn bytes_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(&bytes))
+}
+
+/// Encode a list of strings as a single NUL-separated blob.
+fn join_args(args: &[Vec<u8>]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    for arg in args {
+        blob.extend_from_slice(arg);
+        blob.push(0);
+    }
+    blob
+}
+
+fn split_args(blob: &[u8]) -> Vec<Vec<u8>> {
+    match blob.split_last() {
+        Some((0, blob)) => {
+            blob.split(|&b| b == 0).map(|s| s.to_owned()).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// A thread or process, as read back from the database.
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub id: ProcessId,
+    pub parent: Option<ProcessId>,
+    pub working_dir: PathBuf,
+    pub is_thread: bool,
+    /// Arguments of the first program executed by this process, if any
+    pub argv: Option<Vec<Vec<u8>>>,
+    /// How the process ended, if it was seen exiting
+    pub exit_status: Option<ExitStatus>,
+}
+
+/// A file access, as read back from the database.
+#[derive(Debug, Clone)]
+pub struct FileOpen {
+    pub process: ProcessId,
+    pub path: PathBuf,
+    pub mode: FileOp,
+    pub is_directory: bool,
+    /// How many times the process made this same access
+    pub access_count: u32,
+    /// How the process said it would read the file with `posix_fadvise()`,
+    /// "sequential" or "random"
+    pub access_pattern: Option<String>,
+}
+
+/// A program executed by a traced process, as read back from the database.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub process: ProcessId,
+    pub path: PathBuf,
+    pub argv: Vec<Vec<u8>>,
+    pub envp: Vec<Vec<u8>>,
+    pub working_dir: PathBuf,
+    /// Whether the program is setuid or setgid, and didn't get its
+    /// privileges because it was traced
+    pub requires_setuid: bool,
+}
+
+/// An extended attribute read by a traced process.
+#[derive(Debug, Clone)]
+pub struct XattrRead {
+    pub process: ProcessId,
+    pub path: PathBuf,
+    pub name: Vec<u8>,
+}
+
+/// The page cache state of a file, as returned by `cachestat()`, in pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStat {
+    pub nr_cache: u64,
+    pub nr_dirty: u64,
+    pub nr_writeback: u64,
+    pub nr_evicted: u64,
+}
+
+/// A query of the page cache state of a file by a traced process.
+#[derive(Debug, Clone)]
+pub struct CacheStatQuery {
+    pub process: ProcessId,
+    pub path: PathBuf,
+    pub stat: CacheStat,
+}
+
+/// A page fault reported to a process through a `userfaultfd()` descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageFault {
+    pub process: ProcessId,
+    pub address: u64,
+    pub flags: u64,
+    /// The thread that faulted, if the kernel gave it
+    pub thread_id: Option<u32>,
+}
+
+/// Data copied between two files with `copy_file_range()`.
+#[derive(Debug, Clone)]
+pub struct CopyRange {
+    pub process: ProcessId,
+    pub src_path: PathBuf,
+    pub dst_path: PathBuf,
+    /// The bytes copied by the first of the copies
+    pub bytes: u64,
+    /// How many times the process copied between these same files
+    pub copy_count: u32,
+}
+
+/// A range of another process's memory read or written with
+/// `process_vm_readv()` or `process_vm_writev()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossProcessMemoryAccess {
+    pub process: ProcessId,
+    /// The pid of the other process, as the program sees it
+    pub target_pid: i32,
+    pub is_write: bool,
+    pub address: u64,
+    pub length: u64,
+}
+
+/// Where a working directory was used, see `CwdInconsistency`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CwdUse {
+    /// A program was executed from it
+    Execution,
+    /// A process or thread was created in it
+    NewProcess,
+}
+
+/// A working directory used by a process that the recorded changes of
+/// directory don't explain, so the paths resolved against it are suspect.
+#[derive(Debug, Clone)]
+pub struct CwdInconsistency {
+    pub process: ProcessId,
+    pub working_dir: PathBuf,
+    pub used_by: CwdUse,
+}
+
+/// A timer used by a traced process, see `Database::timers()`.
+#[derive(Debug, Clone)]
+pub struct TimerUsage {
+    pub process: ProcessId,
+    /// The timerfd, or the ID of a POSIX timer
+    pub timer_fd: i32,
+    /// Whether the timer was created with `timerfd_create` during the trace
+    pub created: bool,
+    /// How many times its state was read
+    pub queries: u64,
+}
+
+/// What a process wrote, see `Database::process_outputs()`.
+#[derive(Debug, Clone)]
+pub struct ProcessOutput {
+    pub process: ProcessId,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// A Landlock ruleset enforced by a process, see
+/// `Database::landlock_policies()`.
+#[derive(Debug, Clone)]
+pub struct LandlockPolicy {
+    /// The process that restricted itself
+    pub process: ProcessId,
+    /// The filesystem access rights the ruleset restricts
+    pub handled_access: u64,
+    /// The rights allowed under each directory or on each file
+    pub rules: Vec<(PathBuf, u64)>,
+}
+
+/// How likely a trace is to replay identically, see
+/// `Database::reproducibility_score()`.
+#[derive(Debug, Clone)]
+pub struct ReproducibilityScore {
+    /// From 0.0, certainly different, to 1.0, nothing non-deterministic seen
+    pub score: f64,
+    /// What lowered the score
+    pub reasons: Vec<String>,
+}
+
+impl LandlockPolicy {
+    fn empty(process: ProcessId) -> LandlockPolicy {
+        LandlockPolicy { process, handled_access: 0, rules: Vec::new() }
+    }
+}
+
+/// Assembles the Landlock policies from the events, in order, see
+/// `DatabaseOps::add_landlock_event()`.
+#[derive(Default)]
+struct LandlockPolicies {
+    /// The rulesets being built, by creating process and descriptor
+    rulesets: HashMap<(u32, i32), LandlockPolicy>,
+    policies: Vec<LandlockPolicy>,
+}
+
+impl LandlockPolicies {
+    fn add(
+        &mut self,
+        process: ProcessId,
+        ruleset: (u32, i32),
+        event: &str,
+        access: u64,
+        path: Option<PathBuf>,
+    ) {
+        match (event, path) {
+            ("create", _) => {
+                let mut policy = LandlockPolicy::empty(process);
+                policy.handled_access = access;
+                self.rulesets.insert(ruleset, policy);
+            }
+            // The handled rights of rulesets created before the trace are
+            // not known
+            ("rule", Some(path)) => {
+                self.rulesets.entry(ruleset)
+                    .or_insert_with(|| LandlockPolicy::empty(process))
+                    .rules.push((path, access));
+            }
+            ("restrict", _) => {
+                let mut policy = self.rulesets.get(&ruleset).cloned()
+                    .unwrap_or_else(|| LandlockPolicy::empty(process));
+                policy.process = process;
+                self.policies.push(policy);
+            }
+            _ => {}
+        }
     }
+}
+
+impl Execution {
+    /// The libraries listed in the `LD_PRELOAD` variable of the environment.
+    ///
+    /// Paths with a slash are made absolute, names without one are left as
+    /// they are since the dynamic loader looks them up in its search path.
+    pub fn ld_preload_libraries(&self) -> Vec<PathBuf> {
+        ld_preload_libraries(&self.envp)
+            .into_iter()
+            .map(|lib| {
+                if lib.components().count() > 1 {
+                    self.working_dir.join(lib)
+                } else {
+                    lib
+                }
+            })
+            .collect()
+    }
+}
+
+/// Check an SQLite file for corruption, returning the problems found.
+pub fn integrity_check(path: &Path) -> Result<Vec<String>, Error> {
+    let connection = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?;
+    let mut stmt = connection.prepare("PRAGMA integrity_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut problems = Vec::new();
+    for row in rows {
+        let row = row?;
+        if row != "ok" {
+            problems.push(row);
+        }
+    }
+    Ok(problems)
+}
+
+/// Extract the libraries listed in `LD_PRELOAD` from an environment.
+///
+/// The dynamic loader accepts both colons and spaces as separators.
+pub fn ld_preload_libraries(envp: &[Vec<u8>]) -> Vec<PathBuf> {
+    envp.iter()
+        .filter_map(|var| var.strip_prefix(b"LD_PRELOAD="))
+        .flat_map(|value| value.split(|&b| b == b':' || b == b' '))
+        .filter(|lib| !lib.is_empty())
+        .map(|lib| bytes_path(lib.to_owned()))
+        .collect()
+}
+
+/// The Landlock filesystem access rights, in the order of their bits.
+const LANDLOCK_ACCESS_FS: &[&str] = &[
+    "execute", "write_file", "read_file", "read_dir", "remove_dir",
+    "remove_file", "make_char", "make_dir", "make_reg", "make_sock",
+    "make_fifo", "make_block", "make_sym", "refer", "truncate", "ioctl_dev",
+];
+
+/// Name the Landlock filesystem access rights set in a mask.
+///
+/// Rights unknown to this version are shown as their bit number.
+pub fn landlock_access_names(access: u64) -> Vec<String> {
+    (0..64)
+        .filter(|bit| access & (1 << bit) != 0)
+        .map(|bit| match LANDLOCK_ACCESS_FS.get(bit) {
+            Some(name) => (*name).to_owned(),
+            None => format!("bit{}", bit),
+        })
+        .collect()
+}
+
+/// The operations used to record a trace.
+///
+/// This is implemented by `Database`, and can be implemented by wrappers
+/// such as `counting::CountingDatabase`, so the `Tracer` can be used with
+/// them.
+pub trait DatabaseOps {
+    /// The path of the database file.
+    fn path(&self) -> &Path;
 
     /// Record the creation of a thread or process.
-    pub fn add_process(
+    fn add_process(
         &mut self,
         parent: Option<ProcessId>,
         working_dir: &Path,
         is_thread: bool,
-    ) -> Result<ProcessId, Error> {
-        // TODO
-        let proc = self.next_process;
-        self.next_process += 1;
-        let parent_str = parent
-            .map(|p| Cow::Owned(format!("{}", p.0)))
-            .unwrap_or(Cow::Borrowed("(none)"));
-        warn!(
-            self.logger,
-            "Adding process {} parent={} is_thread={} working_dir={}",
-            proc, parent_str, is_thread, working_dir.to_string_lossy()
-        );
-        Ok(ProcessId(proc))
-    }
+    ) -> Result<ProcessId, Error>;
 
     /// Record a file access.
-    pub fn add_file_open(
+    fn add_file_open(
         &mut self,
         id: ProcessId,
         path: &Path,
         mode: FileOp,
         is_directory: bool,
-    ) -> Result<(), Error> {
-        // TODO
-        warn!(
-            self.logger,
-            "Adding file open process={} path={} mode={:?}, is_directory={}",
-            id.0, path.to_string_lossy(), mode, is_directory,
-        );
-        Ok(())
-    }
+    ) -> Result<(), Error>;
+
+    /// Record how a process said it would read a file it opened, with
+    /// `posix_fadvise()`; `pattern` is "sequential" or "random".
+    ///
+    /// The pattern is set on the read of the file by that process, which is
+    /// recorded if there was none.
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error>;
+
+    /// Record the execution of a program.
+    ///
+    /// `requires_setuid` marks a setuid or setgid program that ran without
+    /// its privileges, see `Error::SetuidTraceRefused`.
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error>;
 
     /// Record the death of a thread or process.
-    pub fn process_exit(
+    fn process_exit(
         &mut self,
         id: ProcessId,
         status: ExitStatus,
-    ) -> Result<(), Error> {
-        // TODO
-        warn!(self.logger, "Adding process exit {} status={:?}", id.0, status);
-        Ok(())
-    }
+    ) -> Result<(), Error>;
+
+    /// Record the PID of a thread as seen by the tracer, and in its own PID
+    /// namespace.
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error>;
+
+    /// Record the creation of a new PID namespace by a process, with
+    /// `unshare()` or `clone()`.
+    ///
+    /// `namespace` is the inode number identifying it.
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error>;
+
+    /// Record a namespace a process is in, of type `ns_type` (e.g. `"mnt"`)
+    /// and identified by its inode number.
+    ///
+    /// `entered_by` is the syscall that moved the process into it, `None`
+    /// for those it started in.
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error>;
+
+    /// Record a call to `sigaction()`, registering how a signal is handled.
+    ///
+    /// `handler` is the address of the function, or `SIG_DFL` or `SIG_IGN`.
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error>;
+
+    /// Set the signal a thread is handling, or `None` once it returned from
+    /// the handler.
+    ///
+    /// The files it opens and processes it creates in the meantime are
+    /// marked with the signal.
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error>;
+
+    /// Record the CPU time used by a thread, in nanoseconds, measured when
+    /// it is about to exit.
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error>;
+
+    /// Record that a syscall was used by the traced program.
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error>;
+
+    /// Set a boolean flag in the trace's metadata.
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error>;
+
+    /// Record the creation of a timer with `timerfd_create`.
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error>;
+
+    /// Record the arming of a timer with `timerfd_settime`.
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error>;
+
+    /// Record a read of a timer's state with `timerfd_gettime`, or with
+    /// `timer_gettime`, in which case `timer_fd` is the timer's ID.
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error>;
+
+    /// Record a call to `nanosleep` or `clock_nanosleep`.
+    ///
+    /// `clockid` is `None` for `nanosleep`, which doesn't take one.
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error>;
+
+    /// Record a process waiting on a file with `poll()`, `select()` or
+    /// `epoll_wait()`.
+    ///
+    /// `timeout_ns` is `None` if it could wait forever, `duration_ns` is how
+    /// long it was blocked.
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error>;
+
+    /// Record a file descriptor received with `SCM_RIGHTS`.
+    ///
+    /// `sender` is the sending process and its descriptor, if known. `path`
+    /// is `None` if the descriptor is not a file.
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Record data copied from a file to another by the kernel, with
+    /// `copy_file_range`.
+    ///
+    /// Repeated copies by a process between the same files are counted
+    /// rather than recorded again.
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error>;
+
+    /// Record a step of setting up a Landlock sandbox.
+    ///
+    /// `ruleset` is the process that created the ruleset and the descriptor
+    /// it got, since the descriptor can be used by its children. `event` is
+    /// "create" with the handled access rights, "rule" with the rights
+    /// allowed beneath `path`, or "restrict" when the process enforces it.
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Record a call to `bpf()` loading an eBPF program or creating a map.
+    ///
+    /// `bpf_type` is the program type or the map type, depending on
+    /// `command`.
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error>;
+
+    /// Record a call to `cachestat()`, querying the page cache state of a
+    /// file.
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error>;
+
+    /// Record a page fault reported to a process by reading a
+    /// `userfaultfd()` descriptor.
+    ///
+    /// `flags` are the `UFFD_PAGEFAULT_FLAG_*` of the fault, and `thread_id`
+    /// the thread that faulted, if the kernel gave it.
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error>;
+
+    /// Record a range of another process's memory that a process read or
+    /// wrote directly, with `process_vm_readv()` or `process_vm_writev()`.
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error>;
+
+    /// Record how long the trace took, in nanoseconds of wall time.
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error>;
+
+    /// Record the fake time the program was shown.
+    fn set_time_override(&mut self, time: &TimeOverride) -> Result<(), Error>;
+
+    /// Record a call to `sched_setaffinity`, pinning a thread to some CPUs.
+    ///
+    /// `target_pid` is the thread whose affinity is set, 0 for the caller.
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Record a memory region made executable after being written to, from
+    /// `start` to `end` (excluded), holding generated code.
+    ///
+    /// `path` is the file the region was mapped from, if any.
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Record a call to `quotactl()`, querying or changing disk quotas.
+    ///
+    /// `command` and `quota_type` are the two halves of its `cmd` argument,
+    /// and `device` the block device of the filesystem, if one was given.
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error>;
+
+    /// Record the reading of an extended attribute.
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Record the setting of an extended attribute.
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Record what a process wrote to its stdout and stderr, see
+    /// `TraceConfig::capture_output`.
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Record the SHA-256 hash of a file's content.
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error>;
+
+    /// Record the SELinux context of a file, from its `security.selinux`
+    /// extended attribute.
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Record a mount point of the machine the trace was made on, from the
+    /// mount table read when tracing starts.
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error>;
 
     /// Commit the trace to disk.
-    pub fn commit(self) -> Result<(), Error> {
-        // TODO
-        Ok(())
+    fn commit(self) -> Result<(), Error> where Self: Sized;
+}
+
+/// The database, where we record events about the traced program.
+pub struct Database {
+    logger: slog::Logger,
+    path: PathBuf,
+    connection: Connection,
+    /// The version of the schema, see `schema`
+    schema_version: u32,
+    next_process: u32,
+    observed_syscalls: HashSet<u32>,
+    /// The signal each process is currently handling, if any
+    signal_contexts: HashMap<u32, i32>,
+    /// The row of each distinct file access and how many times it was made,
+    /// the counts are written on commit
+    file_open_counts: HashMap<(ProcessId, PathBuf, FileOp), (i64, u32)>,
+    /// The same for copies between two files
+    copy_range_counts: HashMap<(ProcessId, PathBuf, PathBuf), (i64, u32)>,
+}
+
+impl Database {
+    /// Create a new database, replacing any existing file at that path.
+    ///
+    /// If a journal was left there by a tracer that crashed, it is first
+    /// recovered into `<path>.recovered`, see `journal::JournalDatabase`.
+    pub fn new<D: AsRef<Path>>(
+        path: D,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        Database::with_schema_version(path, schema::LATEST_VERSION, logger)
+    }
+
+    /// Create a new database like `new()`, with an older version of the
+    /// schema if requested, see `schema`.
+    pub fn with_schema_version<D: AsRef<Path>>(
+        path: D,
+        version: u32,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        let schema = schema::strategy(version)?;
+        let path = path.as_ref();
+        recover_leftover(path, &logger)?;
+        Database::create(path, schema.as_ref(), logger)
+    }
+
+    /// Create a new database, without looking for a journal.
+    fn create(
+        path: &Path,
+        schema: &dyn SchemaStrategy,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        match remove_file(path) {
+            Ok(()) => {
+                info!(logger, "Replacing existing database";
+                      "path" => %path.display());
+            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(Error::Internal(format!(
+                    "Can't remove existing database: {}", e
+                )))
+            }
+        }
+        Database::with_connection(Connection::open(path)?, path, schema,
+                                  logger)
+    }
+
+    /// Create the tables in a new database, through an open connection.
+    ///
+    /// `path` is where the trace is meant to be, which might not be where
+    /// the connection writes, see `zerocopy::ZeroCopyDatabase`.
+    fn with_connection(
+        connection: Connection,
+        path: &Path,
+        schema: &dyn SchemaStrategy,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        // The version is written outside the transaction, so the tables
+        // can be read even if the trace is never committed
+        schema.create_tables(&connection)?;
+        schema::write_version(&connection, schema)?;
+        connection.execute_batch("BEGIN")?;
+        Ok(Database {
+            logger,
+            path: path.to_owned(),
+            connection,
+            schema_version: schema.version(),
+            next_process: 0,
+            observed_syscalls: HashSet::new(),
+            signal_contexts: HashMap::new(),
+            file_open_counts: HashMap::new(),
+            copy_range_counts: HashMap::new(),
+        })
+    }
+
+    /// Open an existing database, to read a previous trace.
+    ///
+    /// If the tracer crashed and left a journal, the trace is recovered from
+    /// it into `<path>.recovered` first, this database is left as is.
+    pub fn open<D: AsRef<Path>>(
+        path: D,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        Database::open_with_flags(path.as_ref(), logger,
+                                  OpenFlags::SQLITE_OPEN_READ_ONLY)
+    }
+
+    /// Open an existing database, to add to the trace with the
+    /// `DatabaseOps` methods.
+    ///
+    /// Nothing is written until `commit()`.
+    pub fn open_for_update<D: AsRef<Path>>(
+        path: D,
+        logger: slog::Logger,
+    ) -> Result<Database, Error> {
+        let database = Database::open_with_flags(
+            path.as_ref(),
+            logger,
+            OpenFlags::SQLITE_OPEN_READ_WRITE,
+        )?;
+        database.connection.execute_batch("BEGIN")?;
+        Ok(database)
+    }
+
+    fn open_with_flags(
+        path: &Path,
+        logger: slog::Logger,
+        flags: OpenFlags,
+    ) -> Result<Database, Error> {
+        recover_leftover(path, &logger)?;
+        let connection = Connection::open_with_flags(path, flags)?;
+        let schema_version = schema::read_version(&connection)?;
+        schema::strategy(schema_version)?
+            .create_missing_tables(&connection)?;
+        let next_process = connection.query_row(
+            "SELECT COALESCE(MAX(id) + 1, 0) FROM processes",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(Database {
+            logger,
+            path: path.to_owned(),
+            connection,
+            schema_version,
+            next_process,
+            observed_syscalls: HashSet::new(),
+            signal_contexts: HashMap::new(),
+            file_open_counts: HashMap::new(),
+            copy_range_counts: HashMap::new(),
+        })
+    }
+
+    /// Whether the tables have the columns added in version 2 of the schema.
+    /// With version 1, those are not written and read as empty.
+    fn has_v2_columns(&self) -> bool {
+        self.schema_version >= 2
+    }
+
+    /// A column added in version 2 of the schema, or the value it reads as
+    /// in version 1.
+    fn v2_column<'a>(&self, column: &'a str, v1: &'a str) -> &'a str {
+        if self.has_v2_columns() { column } else { v1 }
+    }
+
+    /// Get a boolean flag from the trace's metadata, false if unset.
+    pub fn flag(&self, name: &str) -> Result<bool, Error> {
+        let value = self.connection.query_row(
+            "SELECT value FROM metadata WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(value.unwrap_or(false))
+    }
+
+    /// Get all the boolean flags set in the trace's metadata, the `has_`
+    /// markers.
+    pub fn flags(&self) -> Result<BTreeMap<String, bool>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT name, value FROM metadata
+             WHERE name LIKE 'has\\_%' ESCAPE '\\' ORDER BY name",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the total time a process asked to sleep for, in nanoseconds.
+    pub fn total_sleep_ns(&self, id: ProcessId) -> Result<u64, Error> {
+        let total: i64 = self.connection.query_row(
+            "SELECT COALESCE(SUM(duration_ns), 0) FROM sleep_events
+             WHERE process_id = ?1",
+            params![id.0],
+            |row| row.get(0),
+        )?;
+        Ok(total as u64)
+    }
+
+    /// Check the working directories used against the changes of directory
+    /// that were recorded.
+    ///
+    /// Relative paths are resolved when recorded, so they can't be checked
+    /// themselves. Instead, the directories that programs were executed
+    /// from and that processes were created in must be ones their process
+    /// started in or changed to (`FileOp::WDIR` accesses), in any order
+    /// since repeated accesses are merged. Processes whose files were not
+    /// recorded are not checked.
+    pub fn validate_cwd_consistency(
+        &self,
+    ) -> Result<Vec<CwdInconsistency>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, parent, working_dir, is_thread FROM processes
+             ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u32>(0)?,
+                row.get::<_, Option<u32>>(1)?,
+                bytes_path(row.get(2)?),
+                row.get::<_, bool>(3)?,
+            ))
+        })?;
+        let processes: HashMap<u32, (Option<u32>, PathBuf, bool)> = rows
+            .map(|row| row.map(|(id, parent, wd, thread)| {
+                (id, (parent, wd, thread))
+            }))
+            .collect::<Result<_, _>>()?;
+
+        // Threads share the working directory of their process
+        let leader = |mut id: u32| {
+            while let Some(&(Some(parent), _, true)) = processes.get(&id) {
+                id = parent;
+            }
+            id
+        };
+        let mut history: HashMap<u32, HashSet<PathBuf>> = HashMap::new();
+        for (&id, (_, working_dir, is_thread)) in &processes {
+            if !is_thread {
+                history.entry(id).or_default().insert(working_dir.clone());
+            }
+        }
+        let mut recorded = HashSet::new();
+        for open in self.file_opens()? {
+            let group = leader(open.process.0);
+            recorded.insert(group);
+            if open.mode.contains(FileOp::WDIR) {
+                history.entry(group).or_default().insert(open.path);
+            }
+        }
+        let known = |id: u32, working_dir: &Path| {
+            let group = leader(id);
+            !recorded.contains(&group)
+                || history.get(&group)
+                    .map(|dirs| dirs.contains(working_dir))
+                    .unwrap_or(false)
+        };
+
+        let mut inconsistencies = Vec::new();
+        for (&id, (parent, working_dir, _)) in &processes {
+            if let Some(parent) = parent {
+                if !known(*parent, working_dir) {
+                    inconsistencies.push(CwdInconsistency {
+                        process: ProcessId(id),
+                        working_dir: working_dir.clone(),
+                        used_by: CwdUse::NewProcess,
+                    });
+                }
+            }
+        }
+        for exec in self.executions()? {
+            if !known(exec.process.0, &exec.working_dir) {
+                inconsistencies.push(CwdInconsistency {
+                    process: exec.process,
+                    working_dir: exec.working_dir,
+                    used_by: CwdUse::Execution,
+                });
+            }
+        }
+        inconsistencies.sort_by_key(|inconsistency| inconsistency.process.0);
+        Ok(inconsistencies)
+    }
+
+    /// Get the timers created or queried by each process.
+    pub fn timers(&self) -> Result<Vec<TimerUsage>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, timer_fd, SUM(created), SUM(queries)
+             FROM (
+                 SELECT process_id, timer_fd, 1 AS created, 0 AS queries
+                 FROM timer_events WHERE event = 'create'
+                 UNION ALL
+                 SELECT process_id, timer_fd, 0, 1 FROM timer_queries
+             )
+             GROUP BY process_id, timer_fd
+             ORDER BY process_id, timer_fd",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TimerUsage {
+                process: ProcessId(row.get(0)?),
+                timer_fd: row.get(1)?,
+                created: row.get::<_, i64>(2)? > 0,
+                queries: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the total time each process spent blocked waiting on each file,
+    /// in nanoseconds, longest first.
+    pub fn poll_durations(
+        &self,
+    ) -> Result<Vec<(ProcessId, PathBuf, u64)>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, path, SUM(duration_ns) AS total
+             FROM poll_events
+             GROUP BY process_id, path
+             ORDER BY total DESC",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                ProcessId(row.get(0)?),
+                bytes_path(row.get(1)?),
+                row.get::<_, i64>(2)? as u64,
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get how long a process ran, if it was seen exiting.
+    pub fn process_duration(
+        &self,
+        id: ProcessId,
+    ) -> Result<Option<Duration>, Error> {
+        if !self.has_v2_columns() {
+            return Ok(None);
+        }
+        let (start, end) = self.connection.query_row(
+            "SELECT start_ns, end_ns FROM processes WHERE id = ?1",
+            params![id.0],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?)),
+        )?;
+        Ok(end.map(|end| Duration::from_nanos((end - start).max(0) as u64)))
+    }
+
+    /// Get the PID of a thread as seen by the tracer and in its namespace,
+    /// if they were recorded.
+    pub fn process_pids(
+        &self,
+        id: ProcessId,
+    ) -> Result<Option<(i32, i32)>, Error> {
+        if !self.has_v2_columns() {
+            return Ok(None);
+        }
+        let pids = self.connection.query_row(
+            "SELECT host_pid, namespace_pid FROM processes WHERE id = ?1",
+            params![id.0],
+            |row| Ok((row.get::<_, Option<i32>>(0)?,
+                      row.get::<_, Option<i32>>(1)?)),
+        )?;
+        match pids {
+            (Some(host), Some(namespace)) => Ok(Some((host, namespace))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Get the processes that left the namespaces of the first process, or
+    /// changed namespaces, with the types of namespaces involved.
+    pub fn namespace_changes(
+        &self,
+    ) -> Result<Vec<(ProcessId, Vec<String>)>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT DISTINCT n.process_id, n.ns_type
+             FROM process_namespaces n
+             LEFT JOIN process_namespaces r
+                 ON r.process_id = 0 AND r.entered_by IS NULL
+                 AND r.ns_type = n.ns_type
+             WHERE n.ns_inode != r.ns_inode OR n.entered_by IS NOT NULL
+             ORDER BY n.process_id, n.ns_type",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((ProcessId(row.get(0)?), row.get::<_, String>(1)?))
+        })?;
+        let mut changes: Vec<(ProcessId, Vec<String>)> = Vec::new();
+        for row in rows {
+            let (id, ns_type) = row?;
+            match changes.last_mut() {
+                Some((last, types)) if *last == id => types.push(ns_type),
+                _ => changes.push((id, vec![ns_type])),
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Get the CPU time a thread used, if it was measured.
+    pub fn cpu_time(&self, id: ProcessId) -> Result<Option<Duration>, Error> {
+        if !self.has_v2_columns() {
+            return Ok(None);
+        }
+        let cpu_ns = self.connection.query_row(
+            "SELECT cpu_time_ns FROM processes WHERE id = ?1",
+            params![id.0],
+            |row| row.get::<_, Option<i64>>(0),
+        )?;
+        Ok(cpu_ns.map(|ns| Duration::from_nanos(ns as u64)))
+    }
+
+    /// Get how long the trace took, if it was recorded.
+    pub fn wall_time_ns(&self) -> Result<Option<u64>, Error> {
+        let value: Option<i64> = self.connection.query_row(
+            "SELECT value FROM metadata WHERE name = 'wall_time_ns'",
+            [],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(value.map(|v| v as u64))
+    }
+
+    /// Get the fake time the program was shown, if any.
+    pub fn time_override(&self) -> Result<Option<TimeOverride>, Error> {
+        let get = |name: &str| {
+            self.connection.query_row(
+                "SELECT value FROM metadata WHERE name = ?1",
+                params![name],
+                |row| row.get::<_, Value>(0),
+            ).optional()
+        };
+        let start = get("time_override_start_ns")?;
+        let speed = get("time_override_speed")?;
+        match (start, speed) {
+            (Some(Value::Integer(start)), Some(Value::Real(speed))) => {
+                Ok(Some(TimeOverride::from_start_ns(start, speed)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Get where the trace's pack was uploaded, if it was.
+    pub fn upload_url(&self) -> Result<Option<String>, Error> {
+        Ok(self.connection.query_row(
+            "SELECT value FROM metadata WHERE name = 'upload_url'",
+            [],
+            |row| row.get(0),
+        ).optional()?)
+    }
+
+    /// Record where the trace's pack was uploaded, with its SHA-256 in hex
+    /// if it was verified.
+    ///
+    /// The database must have been opened with `open_for_update()`, and
+    /// nothing is written until `commit()`.
+    pub fn set_upload(
+        &mut self,
+        url: &str,
+        sha256: Option<&str>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO metadata(name, value)
+             VALUES('upload_url', ?1)",
+            params![url],
+        )?;
+        match sha256 {
+            Some(sha256) => self.connection.execute(
+                "INSERT OR REPLACE INTO metadata(name, value)
+                 VALUES('upload_sha256', ?1)",
+                params![sha256],
+            )?,
+            None => self.connection.execute(
+                "DELETE FROM metadata WHERE name = 'upload_sha256'",
+                [],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Get how the program crashed, if the trace was marked as a crash by
+    /// `set_crash()`.
+    pub fn crash_status(&self) -> Result<Option<String>, Error> {
+        Ok(self.connection.query_row(
+            "SELECT value FROM metadata WHERE name = 'crash_status'",
+            [],
+            |row| row.get(0),
+        ).optional()?)
+    }
+
+    /// Mark the trace as that of a program that crashed, such as when
+    /// fuzzing, see `fuzz::FuzzOrchestrator`.
+    ///
+    /// The database must have been opened with `open_for_update()`, and
+    /// nothing is written until `commit()`.
+    pub fn set_crash(&mut self, status: ExitStatus) -> Result<(), Error> {
+        let description = match status {
+            ExitStatus::Return(code) => format!("exit code {}", code),
+            ExitStatus::Signal(signal) => format!("signal {:?}", signal),
+        };
+        self.connection.execute(
+            "INSERT OR REPLACE INTO metadata(name, value)
+             VALUES('crashed', 1), ('crash_status', ?1)",
+            params![description],
+        )?;
+        Ok(())
+    }
+
+    /// Run a query giving a single count, with a statement kept in the
+    /// connection's cache.
+    fn count(&self, query: &str) -> Result<u64, Error> {
+        let mut stmt = self.connection.prepare_cached(query)?;
+        let count: i64 = stmt.query_row([], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    /// The number of processes and threads.
+    pub fn process_count(&self) -> Result<u64, Error> {
+        self.count("SELECT COUNT(*) FROM processes")
+    }
+
+    /// The number of file accesses, including the repeated ones.
+    pub fn file_open_count(&self) -> Result<u64, Error> {
+        self.count(&format!(
+            "SELECT COALESCE(SUM({}), 0) FROM file_opens",
+            self.v2_column("access_count", "1"),
+        ))
+    }
+
+    /// The number of different paths accessed.
+    pub fn unique_file_count(&self) -> Result<u64, Error> {
+        self.count("SELECT COUNT(DISTINCT path) FROM file_opens")
+    }
+
+    /// The number of events of all kinds, each file access counting as one.
+    pub fn total_events(&self) -> Result<u64, Error> {
+        let mut total = self.file_open_count()?;
+        for table in EVENT_TABLES {
+            total += self.count(&format!("SELECT COUNT(*) FROM {}", table))?;
+        }
+        Ok(total)
+    }
+
+    /// Get the output captured from the processes, if the trace was made
+    /// with `TraceConfig::capture_output`.
+    pub fn process_outputs(&self) -> Result<Vec<ProcessOutput>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, stdout, stderr FROM process_outputs
+             ORDER BY process_id",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ProcessOutput {
+                process: ProcessId(row.get(0)?),
+                stdout: row.get(1)?,
+                stderr: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the Landlock rulesets that processes enforced on themselves.
+    ///
+    /// A ruleset is listed with the rules it had when each process enforced
+    /// it; rules added later only apply to later enforcements.
+    pub fn landlock_policies(&self) -> Result<Vec<LandlockPolicy>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, ruleset_process, ruleset_fd, event, access,
+                    path
+             FROM landlock_policies
+             ORDER BY rowid",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut policies = LandlockPolicies::default();
+        while let Some(row) = rows.next()? {
+            let process = ProcessId(row.get(0)?);
+            let ruleset = (row.get(1)?, row.get(2)?);
+            let event: String = row.get(3)?;
+            let access = row.get::<_, Option<i64>>(4)?.unwrap_or(0) as u64;
+            let path = row.get::<_, Option<Vec<u8>>>(5)?.map(bytes_path);
+            policies.add(process, ruleset, &event, access, path);
+        }
+        Ok(policies.policies)
+    }
+
+    /// Get the identifiers of all the processes, in order.
+    pub fn process_ids(&self) -> Result<Vec<ProcessId>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id FROM processes ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], |row| Ok(ProcessId(row.get(0)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Estimate how reproducible the trace is, from the non-determinism
+    /// markers that were recorded.
+    pub fn reproducibility_score(
+        &self,
+    ) -> Result<ReproducibilityScore, Error> {
+        // The score starts at 1.0, and each kind of marker seen takes off a
+        // fixed amount, no matter how many times it was seen. Markers that
+        // make the output differ on every run weigh more than those that
+        // only make it depend on the machine. The result is clamped to 0.0.
+        let syscalls = self.observed_syscalls()?;
+        let uses = |numbers: &[libc::c_long]| {
+            numbers.iter().any(|&n| syscalls.contains(&(n as u32)))
+        };
+        let random_files: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM file_opens WHERE path IN (?1, ?2)",
+            params![&b"/dev/random"[..], &b"/dev/urandom"[..]],
+            |row| row.get(0),
+        )?;
+        let sleeps: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM sleep_events",
+            [],
+            |row| row.get(0),
+        )?;
+        let time_fixed = self.time_override()?.is_some();
+        // time() only exists on x86_64, newer architectures use the vDSO
+        #[cfg(target_arch = "x86_64")]
+        let time_syscalls = [libc::SYS_time, libc::SYS_gettimeofday,
+                             libc::SYS_clock_gettime];
+        #[cfg(not(target_arch = "x86_64"))]
+        let time_syscalls = [libc::SYS_gettimeofday, libc::SYS_clock_gettime];
+
+        let markers = [
+            (
+                self.flag("has_seccomp_filter")?,
+                0.5,
+                "the trace was aborted because of a seccomp filter",
+            ),
+            (
+                (random_files > 0 || uses(&[libc::SYS_getrandom]))
+                    && !self.flag("deterministic_random")?,
+                0.2,
+                "reads random numbers",
+            ),
+            (
+                uses(&[libc::SYS_socket, libc::SYS_connect]),
+                0.2,
+                "uses the network",
+            ),
+            (
+                uses(&time_syscalls) && !time_fixed,
+                0.1,
+                "reads the wall-clock time",
+            ),
+            (
+                self.flag("has_perf_events")?,
+                0.15,
+                "uses hardware performance counters",
+            ),
+            (
+                self.flag("has_bpf")?,
+                0.15,
+                "loads eBPF programs",
+            ),
+            (
+                self.flag("has_cpu_affinity")?,
+                0.1,
+                "sets its CPU affinity",
+            ),
+            (
+                uses(&[libc::SYS_shmget, libc::SYS_semget, libc::SYS_msgget,
+                       libc::SYS_mq_open]),
+                0.1,
+                "uses System V or POSIX IPC",
+            ),
+            (
+                self.flag("has_timerfd")? || sleeps > 0,
+                0.05,
+                "depends on timers",
+            ),
+            (
+                self.flag("has_kernel_log")?,
+                0.05,
+                "reads the kernel log",
+            ),
+        ];
+        let mut score = 1.0;
+        let mut reasons = Vec::new();
+        for &(seen, deduction, reason) in &markers {
+            if seen {
+                score -= deduction;
+                reasons.push(format!("{} (-{:.2})", reason, deduction));
+            }
+        }
+        Ok(ReproducibilityScore {
+            score: f64::max(score, 0.0),
+            reasons,
+        })
+    }
+
+    /// Write the main tables as CSV files in a directory, for analysis in
+    /// other tools.
+    ///
+    /// This writes `processes.csv`, `file_opens.csv` and
+    /// `process_exits.csv`, with the same columns as the tables. Paths and
+    /// other binary values are decoded as UTF-8, lossily.
+    pub fn export_csv(&self, path: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(path)?;
+        for table in &["processes", "file_opens", "process_exits"] {
+            let mut writer =
+                csv::Writer::from_path(path.join(format!("{}.csv", table)))?;
+            let mut stmt = self.connection.prepare(&format!(
+                "SELECT * FROM {} ORDER BY rowid", table,
+            ))?;
+            writer.write_record(stmt.column_names())?;
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let mut record = Vec::with_capacity(columns);
+                for i in 0..columns {
+                    record.push(match row.get::<_, Value>(i)? {
+                        Value::Null => String::new(),
+                        Value::Integer(i) => i.to_string(),
+                        Value::Real(f) => f.to_string(),
+                        Value::Text(s) => s,
+                        Value::Blob(b) => String::from_utf8_lossy(&b).into(),
+                    });
+                }
+                writer.write_record(&record)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Merge the file accesses recorded more than once for the same process,
+    /// path and operation, returning the number of rows removed.
+    ///
+    /// The first row of each group is kept, and its access count becomes the
+    /// total of the group. The database must have been opened with
+    /// `open_for_update()`, and nothing is written until `commit()`.
+    pub fn deduplicate_file_opens(&mut self) -> Result<u64, Error> {
+        // Without access counts, the duplicates are only removed
+        if self.has_v2_columns() {
+            self.connection.execute(
+                "UPDATE file_opens SET access_count = (
+                     SELECT SUM(other.access_count) FROM file_opens AS other
+                     WHERE other.process_id = file_opens.process_id
+                         AND other.path = file_opens.path
+                         AND other.file_op = file_opens.file_op
+                 )
+                 WHERE rowid IN (
+                     SELECT MIN(rowid) FROM file_opens
+                     GROUP BY process_id, path, file_op
+                     HAVING COUNT(*) > 1
+                 )",
+                [],
+            )?;
+        }
+        let deleted = self.connection.execute(
+            "DELETE FROM file_opens WHERE rowid NOT IN (
+                 SELECT MIN(rowid) FROM file_opens
+                 GROUP BY process_id, path, file_op
+             )",
+            [],
+        )?;
+        Ok(deleted as u64)
+    }
+
+    /// Add the trace from another database to this one, as if its first
+    /// process had been created by `parent_in_self`.
+    ///
+    /// The processes of the other trace are renumbered to follow the ones
+    /// here, in every table. Syscalls, hashes and mount points are merged,
+    /// and flags set in either trace are set. The events the schema of this
+    /// trace has no table for are left out, with a warning. The database
+    /// must have been opened with `open_for_update()`, and nothing is
+    /// written until `commit()`.
+    pub fn merge_from(
+        &mut self,
+        other: Database,
+        parent_in_self: ProcessId,
+    ) -> Result<(), Error> {
+        if parent_in_self.0 >= self.next_process {
+            return Err(Error::Internal(format!(
+                "No process {} in the database", parent_in_self.0,
+            )));
+        }
+        let offset = i64::from(self.next_process);
+        let tables = table_names(&self.connection)?;
+        for table in table_names(&other.connection)? {
+            // Tables this trace doesn't have are left out, see `schema`
+            if !tables.contains(&table) {
+                let rows: i64 = other.connection.query_row(
+                    &format!("SELECT COUNT(*) FROM {}", table),
+                    [],
+                    |row| row.get(0),
+                )?;
+                if rows > 0 {
+                    warn!(self.logger, "Events left out of the merge, the \
+                                        schema of the trace doesn't have \
+                                        them";
+                          "table" => &table, "rows" => rows);
+                }
+                continue;
+            }
+            match table.as_str() {
+                "metadata" => {
+                    // Flags are set if set in either trace, other values are
+                    // kept from this one
+                    let mut stmt = other.connection.prepare(
+                        "SELECT name, value FROM metadata",
+                    )?;
+                    let mut rows = stmt.query([])?;
+                    while let Some(row) = rows.next()? {
+                        let name: String = row.get(0)?;
+                        let value: Value = row.get(1)?;
+                        if value == Value::Integer(1) {
+                            self.connection.execute(
+                                "UPDATE metadata SET value = 1
+                                 WHERE name = ?1 AND value = 0",
+                                params![name],
+                            )?;
+                        }
+                        self.connection.execute(
+                            "INSERT OR IGNORE INTO metadata(name, value)
+                             VALUES(?1, ?2)",
+                            params![name, value],
+                        )?;
+                    }
+                }
+                "mount_points" => {
+                    // The traces can be from different machines, keep the
+                    // mounts of both without repeating the ones they share
+                    let mut stmt = other.connection.prepare(
+                        "SELECT device, mount_point, filesystem_type,
+                                mount_options
+                         FROM mount_points ORDER BY rowid",
+                    )?;
+                    let mut rows = stmt.query([])?;
+                    while let Some(row) = rows.next()? {
+                        self.connection.execute(
+                            "INSERT INTO mount_points(device, mount_point,
+                                                      filesystem_type,
+                                                      mount_options)
+                             SELECT ?1, ?2, ?3, ?4 WHERE NOT EXISTS (
+                                 SELECT 1 FROM mount_points
+                                 WHERE device = ?1 AND mount_point = ?2
+                                     AND filesystem_type = ?3
+                                     AND mount_options = ?4
+                             )",
+                            params![row.get::<_, Value>(0)?,
+                                    row.get::<_, Value>(1)?,
+                                    row.get::<_, Value>(2)?,
+                                    row.get::<_, Value>(3)?],
+                        )?;
+                    }
+                }
+                _ => {
+                    // Columns this trace doesn't have are left out too
+                    let ours = table_columns(&self.connection, &table)?;
+                    let columns: Vec<String> =
+                        table_columns(&other.connection, &table)?
+                            .into_iter()
+                            .filter(|column| ours.contains(column))
+                            .collect();
+                    let conflict = match table.as_str() {
+                        "observed_syscalls" | "file_hashes"
+                        | "file_labels" => "OR IGNORE ",
+                        _ => "",
+                    };
+                    let mut insert = self.connection.prepare(&format!(
+                        "INSERT {}INTO {}({}) VALUES({})",
+                        conflict, table, columns.join(", "),
+                        vec!["?"; columns.len()].join(", "),
+                    ))?;
+                    let mut stmt = other.connection.prepare(&format!(
+                        "SELECT {} FROM {} ORDER BY rowid",
+                        columns.join(", "), table,
+                    ))?;
+                    let mut rows = stmt.query([])?;
+                    while let Some(row) = rows.next()? {
+                        let mut values = Vec::with_capacity(columns.len());
+                        for (i, column) in columns.iter().enumerate() {
+                            let value = match row.get(i)? {
+                                Value::Null if table == "processes"
+                                    && column == "parent" => {
+                                    Value::Integer(parent_in_self.0.into())
+                                }
+                                Value::Integer(id)
+                                    if is_process_column(&table, column) => {
+                                    Value::Integer(id + offset)
+                                }
+                                value => value,
+                            };
+                            values.push(value);
+                        }
+                        insert.execute(params_from_iter(values))?;
+                    }
+                }
+            }
+        }
+        self.next_process += other.next_process;
+        Ok(())
+    }
+
+    /// Get the numbers of all the syscalls used by the traced program.
+    pub fn observed_syscalls(&self) -> Result<Vec<u32>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT number FROM observed_syscalls ORDER BY number",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the program executions, in order.
+    pub fn executions(&self) -> Result<Vec<Execution>, Error> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT process_id, path, argv, envp, working_dir, {}
+             FROM executions ORDER BY rowid",
+            self.v2_column("requires_setuid", "0"),
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Execution {
+                process: ProcessId(row.get(0)?),
+                path: bytes_path(row.get(1)?),
+                argv: split_args(&row.get::<_, Vec<u8>>(2)?),
+                envp: split_args(&row.get::<_, Vec<u8>>(3)?),
+                working_dir: bytes_path(row.get(4)?),
+                requires_setuid: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the recorded hashes of files.
+    pub fn file_hashes(&self) -> Result<Vec<(PathBuf, [u8; 32])>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT path, hash FROM file_hashes ORDER BY path",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((bytes_path(row.get(0)?), row.get::<_, [u8; 32]>(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the recorded SELinux contexts of files.
+    pub fn file_labels(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT path, label FROM file_labels ORDER BY path",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((bytes_path(row.get(0)?), row.get(1)?))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get the mount table recorded when the trace started, in its order.
+    ///
+    /// This is empty for traces made before it was recorded.
+    pub fn mount_points(&self) -> Result<Vec<Mount>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT device, mount_point, filesystem_type, mount_options
+             FROM mount_points ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(Mount {
+                device: row.get(0)?,
+                mount_point: bytes_path(row.get(1)?),
+                fs_type: row.get(2)?,
+                options: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the queries of the page cache state, in order.
+    pub fn cache_stat_queries(&self) -> Result<Vec<CacheStatQuery>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, path, nr_cache, nr_dirty, nr_writeback,
+                    nr_evicted
+             FROM cache_stats ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(CacheStatQuery {
+                process: ProcessId(row.get(0)?),
+                path: bytes_path(row.get(1)?),
+                stat: CacheStat {
+                    nr_cache: row.get::<_, i64>(2)? as u64,
+                    nr_dirty: row.get::<_, i64>(3)? as u64,
+                    nr_writeback: row.get::<_, i64>(4)? as u64,
+                    nr_evicted: row.get::<_, i64>(5)? as u64,
+                },
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the page faults read from `userfaultfd()` descriptors, in
+    /// order.
+    pub fn page_faults(&self) -> Result<Vec<PageFault>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, address, flags, thread_id
+             FROM page_fault_events ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(PageFault {
+                process: ProcessId(row.get(0)?),
+                address: row.get::<_, i64>(1)? as u64,
+                flags: row.get::<_, i64>(2)? as u64,
+                thread_id: row.get(3)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the copies between files, in order.
+    pub fn copy_ranges(&self) -> Result<Vec<CopyRange>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, src_path, dst_path, bytes, copy_count
+             FROM copy_ranges ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(CopyRange {
+                process: ProcessId(row.get(0)?),
+                src_path: bytes_path(row.get(1)?),
+                dst_path: bytes_path(row.get(2)?),
+                bytes: row.get::<_, i64>(3)? as u64,
+                copy_count: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the accesses to the memory of other processes, in order.
+    pub fn cross_process_memory_accesses(
+        &self,
+    ) -> Result<Vec<CrossProcessMemoryAccess>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, target_pid, is_write, address, length
+             FROM cross_process_memory_accesses ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(CrossProcessMemoryAccess {
+                process: ProcessId(row.get(0)?),
+                target_pid: row.get(1)?,
+                is_write: row.get(2)?,
+                address: row.get::<_, i64>(3)? as u64,
+                length: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get all the extended attributes read, in order.
+    pub fn xattr_reads(&self) -> Result<Vec<XattrRead>, Error> {
+        let mut stmt = self.connection.prepare(
+            "SELECT process_id, path, name FROM xattr_reads ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(XattrRead {
+                process: ProcessId(row.get(0)?),
+                path: bytes_path(row.get(1)?),
+                name: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Get a single process from its identifier.
+    pub fn get_process(&self, id: ProcessId) -> Result<Process, Error> {
+        let (parent, working_dir, is_thread) = self.connection.query_row(
+            "SELECT parent, working_dir, is_thread FROM processes
+             WHERE id = ?1",
+            params![id.0],
+            |row| {
+                Ok((
+                    row.get::<_, Option<u32>>(0)?,
+                    bytes_path(row.get(1)?),
+                    row.get(2)?,
+                ))
+            },
+        ).optional()?.ok_or_else(|| {
+            Error::Internal(format!("No process {} in database", id.0))
+        })?;
+        let argv = self.connection.query_row(
+            "SELECT argv FROM executions WHERE process_id = ?1
+             ORDER BY rowid LIMIT 1",
+            params![id.0],
+            |row| row.get::<_, Vec<u8>>(0),
+        ).optional()?.map(|blob| split_args(&blob));
+        let exit = self.connection.query_row(
+            "SELECT exit_code, signal FROM process_exits
+             WHERE process_id = ?1",
+            params![id.0],
+            |row| Ok((row.get::<_, Option<i32>>(0)?,
+                      row.get::<_, Option<i32>>(1)?)),
+        ).optional()?;
+        let exit_status = match exit {
+            Some((Some(code), _)) => Some(ExitStatus::Return(code)),
+            Some((None, Some(signal))) => {
+                Signal::from_c_int(signal).ok().map(ExitStatus::Signal)
+            }
+            _ => None,
+        };
+        Ok(Process {
+            id,
+            parent: parent.map(ProcessId),
+            working_dir,
+            is_thread,
+            argv,
+            exit_status,
+        })
+    }
+
+    /// Get all the threads and processes, in the order they were created.
+    pub fn processes(&self) -> Result<Vec<Process>, Error> {
+        self.process_ids()?.into_iter()
+            .map(|id| self.get_process(id))
+            .collect()
+    }
+
+    /// Get all the file accesses, in order.
+    pub fn file_opens(&self) -> Result<Vec<FileOpen>, Error> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT process_id, path, file_op, is_directory, {}, {}
+             FROM file_opens ORDER BY rowid",
+            self.v2_column("access_count", "1"),
+            self.v2_column("access_pattern", "NULL"),
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(FileOpen {
+                process: ProcessId(row.get(0)?),
+                path: bytes_path(row.get(1)?),
+                mode: FileOp::from_bits_truncate(row.get(2)?),
+                is_directory: row.get(3)?,
+                access_count: row.get(4)?,
+                access_pattern: row.get(5)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+impl DatabaseOps for Database {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn add_process(
+        &mut self,
+        parent: Option<ProcessId>,
+        working_dir: &Path,
+        is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        let proc = self.next_process;
+        self.next_process += 1;
+        debug!(
+            self.logger,
+            "Adding process {} parent={:?} is_thread={} working_dir={}",
+            proc, parent.map(|p| p.0), is_thread, working_dir.display()
+        );
+        if self.has_v2_columns() {
+            self.connection.execute(
+                "INSERT INTO processes(id, parent, working_dir, is_thread,
+                                       start_ns, signal)
+                 VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+                params![proc, parent.map(|p| p.0), path_bytes(working_dir),
+                        is_thread, monotonic_ns(),
+                        parent.and_then(|p| self.signal_contexts.get(&p.0))],
+            )?;
+        } else {
+            self.connection.execute(
+                "INSERT INTO processes(id, parent, working_dir, is_thread)
+                 VALUES(?1, ?2, ?3, ?4)",
+                params![proc, parent.map(|p| p.0), path_bytes(working_dir),
+                        is_thread],
+            )?;
+        }
+        Ok(ProcessId(proc))
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        // Only the first of identical accesses gets a row
+        let key = (id, path.to_owned(), mode);
+        if let Some((_, count)) = self.file_open_counts.get_mut(&key) {
+            *count += 1;
+            return Ok(());
+        }
+        debug!(
+            self.logger,
+            "Adding file open process={} path={} mode={:?}, is_directory={}",
+            id.0, path.display(), mode, is_directory,
+        );
+        if self.has_v2_columns() {
+            self.connection.execute(
+                "INSERT INTO file_opens(process_id, path, file_op,
+                                        is_directory, signal)
+                 VALUES(?1, ?2, ?3, ?4, ?5)",
+                params![id.0, path_bytes(path), mode.bits(), is_directory,
+                        self.signal_contexts.get(&id.0)],
+            )?;
+        } else {
+            self.connection.execute(
+                "INSERT INTO file_opens(process_id, path, file_op,
+                                        is_directory)
+                 VALUES(?1, ?2, ?3, ?4)",
+                params![id.0, path_bytes(path), mode.bits(), is_directory],
+            )?;
+        }
+        let row = self.connection.last_insert_rowid();
+        self.file_open_counts.insert(key, (row, 1));
+        Ok(())
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        if !self.has_v2_columns() {
+            return Ok(());
+        }
+        // The pattern goes on the read of the file, which is added if the
+        // process didn't open it itself, such as an inherited descriptor
+        let key = (id, path.to_owned(), FileOp::READ);
+        if !self.file_open_counts.contains_key(&key) {
+            self.add_file_open(id, path, FileOp::READ, false)?;
+        }
+        let (row, _) = self.file_open_counts[&key];
+        self.connection.execute(
+            "UPDATE file_opens SET access_pattern = ?2 WHERE rowid = ?1",
+            params![row, pattern],
+        )?;
+        Ok(())
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        debug!(self.logger, "Adding exec process={} path={}",
+               id.0, path.display());
+        if self.has_v2_columns() {
+            self.connection.execute(
+                "INSERT INTO executions(process_id, path, argv, envp,
+                                        working_dir, requires_setuid)
+                 VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+                params![id.0, path_bytes(path), join_args(argv),
+                        join_args(envp), path_bytes(working_dir),
+                        requires_setuid],
+            )?;
+        } else {
+            self.connection.execute(
+                "INSERT INTO executions(process_id, path, argv, envp,
+                                        working_dir)
+                 VALUES(?1, ?2, ?3, ?4, ?5)",
+                params![id.0, path_bytes(path), join_args(argv),
+                        join_args(envp), path_bytes(working_dir)],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn process_exit(
+        &mut self,
+        id: ProcessId,
+        status: ExitStatus,
+    ) -> Result<(), Error> {
+        debug!(self.logger, "Adding process exit {} status={:?}",
+               id.0, status);
+        let (code, signal) = match status {
+            ExitStatus::Return(code) => (Some(code), None),
+            ExitStatus::Signal(sig) => (None, Some(sig as i32)),
+        };
+        self.connection.execute(
+            "INSERT INTO process_exits(process_id, exit_code, signal)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, code, signal],
+        )?;
+        if self.has_v2_columns() {
+            self.connection.execute(
+                "UPDATE processes SET end_ns = ?2 WHERE id = ?1",
+                params![id.0, monotonic_ns()],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error> {
+        if !self.has_v2_columns() {
+            return Ok(());
+        }
+        self.connection.execute(
+            "UPDATE processes SET host_pid = ?2, namespace_pid = ?3
+             WHERE id = ?1",
+            params![id.0, host_pid, namespace_pid],
+        )?;
+        Ok(())
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO pid_namespaces(process_id, namespace)
+             VALUES(?1, ?2)",
+            params![id.0, namespace as i64],
+        )?;
+        Ok(())
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO process_namespaces(process_id, ns_type, ns_inode,
+                                            entered_by)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, ns_type, inode as i64, entered_by],
+        )?;
+        Ok(())
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO signal_handlers(process_id, signum, handler, flags)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, signum, handler as i64, flags as i64],
+        )?;
+        Ok(())
+    }
+
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error> {
+        match signum {
+            Some(signum) => self.signal_contexts.insert(id.0, signum),
+            None => self.signal_contexts.remove(&id.0),
+        };
+        Ok(())
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error> {
+        if !self.has_v2_columns() {
+            return Ok(());
+        }
+        self.connection.execute(
+            "UPDATE processes SET cpu_time_ns = ?2 WHERE id = ?1",
+            params![id.0, cpu_ns as i64],
+        )?;
+        Ok(())
+    }
+
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error> {
+        if self.observed_syscalls.insert(number) {
+            self.connection.execute(
+                "INSERT INTO observed_syscalls(number) VALUES(?1)",
+                params![number],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO metadata(name, value) VALUES(?1, ?2)",
+            params![name, value],
+        )?;
+        Ok(())
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO timer_events(process_id, event, timer_fd, clockid,
+                                      flags)
+             VALUES(?1, 'create', ?2, ?3, ?4)",
+            params![id.0, timer_fd, clockid, flags],
+        )?;
+        Ok(())
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO timer_events(process_id, event, timer_fd, flags,
+                                      interval_ns, value_ns)
+             VALUES(?1, 'settime', ?2, ?3, ?4, ?5)",
+            params![id.0, timer_fd, flags, interval_ns, value_ns],
+        )?;
+        Ok(())
+    }
+
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO timer_queries(process_id, timer_fd, remaining_ns)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, timer_fd, remaining_ns],
+        )?;
+        Ok(())
+    }
+
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO sleep_events(process_id, duration_ns, clockid)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, duration_ns as i64, clockid],
+        )?;
+        Ok(())
+    }
+
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO poll_events(process_id, path, timeout_ns,
+                                     duration_ns)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, path_bytes(path), timeout_ns.map(|t| t as i64),
+                    duration_ns as i64],
+        )?;
+        Ok(())
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO fd_transfers(sender, sender_fd, receiver, receiver_fd,
+                                      path)
+             VALUES(?1, ?2, ?3, ?4, ?5)",
+            params![sender.map(|(id, _)| id.0), sender.map(|(_, fd)| fd),
+                    receiver.0, receiver_fd, path.map(path_bytes)],
+        )?;
+        Ok(())
+    }
+
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        // Only the first of the copies between the same files gets a row
+        let key = (id, src_path.to_owned(), dst_path.to_owned());
+        if let Some((_, count)) = self.copy_range_counts.get_mut(&key) {
+            *count += 1;
+            return Ok(());
+        }
+        self.connection.execute(
+            "INSERT INTO copy_ranges(process_id, src_path, dst_path, bytes)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, path_bytes(src_path), path_bytes(dst_path),
+                    bytes as i64],
+        )?;
+        let row = self.connection.last_insert_rowid();
+        self.copy_range_counts.insert(key, (row, 1));
+        Ok(())
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO landlock_policies(process_id, ruleset_process,
+                                           ruleset_fd, event, access, path)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.0, ruleset.0.0, ruleset.1, event,
+                    access.map(|a| a as i64), path.map(path_bytes)],
+        )?;
+        Ok(())
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO bpf_operations(process_id, command, bpf_type)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, command, bpf_type],
+        )?;
+        Ok(())
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO cache_stats(process_id, path, nr_cache, nr_dirty,
+                                     nr_writeback, nr_evicted)
+             VALUES(?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id.0, path_bytes(path), stat.nr_cache as i64,
+                    stat.nr_dirty as i64, stat.nr_writeback as i64,
+                    stat.nr_evicted as i64],
+        )?;
+        Ok(())
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO page_fault_events(process_id, address, flags,
+                                           thread_id)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, address as i64, flags as i64, thread_id],
+        )?;
+        Ok(())
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO cross_process_memory_accesses(process_id,
+                                                       target_pid, is_write,
+                                                       address, length)
+             VALUES(?1, ?2, ?3, ?4, ?5)",
+            params![id.0, target_pid, is_write, address as i64,
+                    length as i64],
+        )?;
+        Ok(())
+    }
+
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO metadata(name, value)
+             VALUES('wall_time_ns', ?1)",
+            params![duration as i64],
+        )?;
+        Ok(())
+    }
+
+    fn set_time_override(
+        &mut self,
+        time: &TimeOverride,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO metadata(name, value)
+             VALUES('time_override_start_ns', ?1),
+                   ('time_override_speed', ?2)",
+            params![time.start_ns(), time.speed],
+        )?;
+        Ok(())
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO cpu_affinity_changes(process_id, target_pid, mask)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, target_pid, mask],
+        )?;
+        Ok(())
+    }
+
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO jit_regions(process_id, start_address, end_address,
+                                     prot, path)
+             VALUES(?1, ?2, ?3, ?4, ?5)",
+            params![id.0, start as i64, end as i64, prot,
+                    path.map(path_bytes)],
+        )?;
+        Ok(())
+    }
+
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO quota_checks(process_id, command, quota_type, device,
+                                      quota_id)
+             VALUES(?1, ?2, ?3, ?4, ?5)",
+            params![id.0, command, quota_type, device.map(path_bytes),
+                    quota_id],
+        )?;
+        Ok(())
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error> {
+        debug!(self.logger, "Adding xattr read process={} path={} name={}",
+               id.0, path.display(), String::from_utf8_lossy(name));
+        self.connection.execute(
+            "INSERT INTO xattr_reads(process_id, path, name)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, path_bytes(path), name],
+        )?;
+        Ok(())
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        debug!(self.logger, "Adding xattr write process={} path={} name={}",
+               id.0, path.display(), String::from_utf8_lossy(name));
+        self.connection.execute(
+            "INSERT INTO xattr_writes(process_id, path, name, value)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![id.0, path_bytes(path), name, value],
+        )?;
+        Ok(())
+    }
+
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO file_hashes(path, hash) VALUES(?1, ?2)",
+            params![path_bytes(path), &hash[..]],
+        )?;
+        Ok(())
+    }
+
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO file_labels(path, label) VALUES(?1, ?2)",
+            params![path_bytes(path), label],
+        )?;
+        Ok(())
+    }
+
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO mount_points(device, mount_point, filesystem_type,
+                                      mount_options)
+             VALUES(?1, ?2, ?3, ?4)",
+            params![device, path_bytes(mount_point), fs_type, options],
+        )?;
+        Ok(())
+    }
+
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT INTO process_outputs(process_id, stdout, stderr)
+             VALUES(?1, ?2, ?3)",
+            params![id.0, stdout, stderr],
+        )?;
+        Ok(())
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        if self.has_v2_columns() {
+            let mut stmt = self.connection.prepare(
+                "UPDATE file_opens SET access_count = ?2 WHERE rowid = ?1",
+            )?;
+            for &(row, count) in self.file_open_counts.values() {
+                if count > 1 {
+                    stmt.execute(params![row, count])?;
+                }
+            }
+        }
+        {
+            let mut stmt = self.connection.prepare(
+                "UPDATE copy_ranges SET copy_count = ?2 WHERE rowid = ?1",
+            )?;
+            for &(row, count) in self.copy_range_counts.values() {
+                if count > 1 {
+                    stmt.execute(params![row, count])?;
+                }
+            }
+        }
+        self.connection.execute_batch("COMMIT")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use rusqlite::{Connection, params};
+
+    use crate::ExitStatus;
+    use crate::testing::TempDatabase;
+    use super::{Database, DatabaseOps, FileOp, ProcessId, table_columns};
+
+    #[test]
+    fn test_deduplicate_file_opens() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = TempDatabase::new("dedup");
+        let mut database = Database::new(&path, logger.clone()).unwrap();
+        let process = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        database.commit().unwrap();
+
+        // The tracer merges repeated accesses itself, so insert directly
+        {
+            let connection = Connection::open(&path).unwrap();
+            for _ in 0..100 {
+                connection.execute(
+                    "INSERT INTO file_opens(process_id, path, file_op,
+                                            is_directory)
+                     VALUES(?1, ?2, ?3, 0)",
+                    params![process.0, &b"/etc/passwd"[..],
+                            FileOp::READ.bits()],
+                ).unwrap();
+            }
+        }
+
+        let mut database = Database::open_for_update(&path, logger.clone())
+            .unwrap();
+        assert_eq!(database.deduplicate_file_opens().unwrap(), 99);
+        assert_eq!(database.deduplicate_file_opens().unwrap(), 0);
+        database.commit().unwrap();
+
+        let database = Database::open(&path, logger).unwrap();
+        let file_opens = database.file_opens().unwrap();
+        assert_eq!(file_opens.len(), 1);
+        assert_eq!(file_opens[0].access_count, 100);
+    }
+
+    #[test]
+    fn test_schema_v1() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = TempDatabase::new("schema");
+        let mut database = Database::with_schema_version(&path, 1,
+                                                         logger.clone())
+            .unwrap();
+        let process = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        database.set_pids(process, 100, 1).unwrap();
+        database.set_cpu_time(process, 1000).unwrap();
+        for _ in 0..2 {
+            database.add_file_open(process, Path::new("/in"), FileOp::READ,
+                                   false).unwrap();
+        }
+        database.set_access_pattern(process, Path::new("/in"), "SEQUENTIAL")
+            .unwrap();
+        database.add_exec(process, Path::new("/bin/su"), &[], &[],
+                          Path::new("/work"), true).unwrap();
+        database.add_copy_range(process, Path::new("/in"), Path::new("/out"),
+                                4096).unwrap();
+        database.process_exit(process, ExitStatus::Return(0)).unwrap();
+        database.commit().unwrap();
+
+        // Only the tables and columns of version 1 were created
+        {
+            let connection = Connection::open(&path).unwrap();
+            let tables: i64 = connection.query_row(
+                "SELECT COUNT(*) FROM sqlite_master
+                 WHERE name = 'copy_ranges'",
+                [],
+                |row| row.get(0),
+            ).unwrap();
+            assert_eq!(tables, 0);
+            let columns = |table| table_columns(&connection, table).unwrap();
+            assert_eq!(columns("processes"),
+                       ["id", "parent", "working_dir", "is_thread"]);
+            assert_eq!(columns("file_opens"),
+                       ["process_id", "path", "file_op", "is_directory"]);
+            assert_eq!(columns("executions"),
+                       ["process_id", "path", "argv", "envp",
+                        "working_dir"]);
+        }
+
+        let database = Database::open(&path, logger).unwrap();
+        assert_eq!(database.processes().unwrap().len(), 1);
+        assert_eq!(database.process_pids(process).unwrap(), None);
+        assert_eq!(database.cpu_time(process).unwrap(), None);
+        assert_eq!(database.process_duration(process).unwrap(), None);
+        let opens = database.file_opens().unwrap();
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].access_count, 1);
+        assert_eq!(opens[0].access_pattern, None);
+        assert!(!database.executions().unwrap()[0].requires_setuid);
+        assert!(database.copy_ranges().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_access_pattern() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = TempDatabase::new("pattern");
+        let mut database = Database::new(&path, logger.clone()).unwrap();
+        let process = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        database.add_file_open(process, Path::new("/in"), FileOp::WRITE,
+                               false).unwrap();
+        database.add_file_open(process, Path::new("/in"), FileOp::READ,
+                               false).unwrap();
+        database.set_access_pattern(process, Path::new("/in"), "random")
+            .unwrap();
+        // Inherited file descriptor, never opened
+        database.set_access_pattern(process, Path::new("/stdin"),
+                                    "sequential").unwrap();
+        database.commit().unwrap();
+
+        let database = Database::open(&path, logger).unwrap();
+        let opens: Vec<_> = database.file_opens().unwrap()
+            .into_iter()
+            .map(|o| (o.path, o.mode, o.access_pattern))
+            .collect();
+        assert_eq!(opens, [
+            (PathBuf::from("/in"), FileOp::WRITE, None),
+            (PathBuf::from("/in"), FileOp::READ, Some("random".to_owned())),
+            (PathBuf::from("/stdin"), FileOp::READ,
+             Some("sequential".to_owned())),
+        ]);
+    }
+
+    #[test]
+    fn test_copy_range_counts() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = TempDatabase::new("copy");
+        let mut database = Database::new(&path, logger.clone()).unwrap();
+        let process = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        for _ in 0..3 {
+            database.add_copy_range(process, Path::new("/in"),
+                                    Path::new("/out"), 4096).unwrap();
+        }
+        database.add_copy_range(process, Path::new("/in"),
+                                Path::new("/other"), 10).unwrap();
+        database.commit().unwrap();
+
+        let database = Database::open(&path, logger).unwrap();
+        let copies = database.copy_ranges().unwrap();
+        assert_eq!(copies.len(), 2);
+        assert_eq!(copies[0].dst_path, Path::new("/out"));
+        assert_eq!(copies[0].copy_count, 3);
+        assert_eq!(copies[1].dst_path, Path::new("/other"));
+        assert_eq!(copies[1].copy_count, 1);
+    }
+
+    #[test]
+    fn test_file_open_counts() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let path = TempDatabase::new("open-counts");
+        let mut database = Database::new(&path, logger.clone()).unwrap();
+        let process = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        for _ in 0..5 {
+            database.add_file_open(process, Path::new("/etc/passwd"),
+                                   FileOp::READ, false).unwrap();
+        }
+        // A different mode is a different access
+        database.add_file_open(process, Path::new("/etc/passwd"),
+                               FileOp::WRITE, false).unwrap();
+        database.commit().unwrap();
+
+        let database = Database::open(&path, logger).unwrap();
+        let opens = database.file_opens().unwrap();
+        assert_eq!(opens.len(), 2);
+        assert_eq!(opens[0].mode, FileOp::READ);
+        assert_eq!(opens[0].access_count, 5);
+        assert_eq!(opens[1].mode, FileOp::WRITE);
+        assert_eq!(opens[1].access_count, 1);
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let base_path = TempDatabase::new("merge-base");
+        let overlay_path = TempDatabase::new("merge-overlay");
+
+        let mut base = Database::new(&base_path, logger.clone()).unwrap();
+        let root = base.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        let child = base.add_process(Some(root), Path::new("/work"), false)
+            .unwrap();
+        base.add_file_open(child, Path::new("/etc/passwd"), FileOp::READ,
+                           false).unwrap();
+        base.set_flag("has_sockets", true).unwrap();
+        base.set_flag("has_ptrace", false).unwrap();
+        base.add_mount_point("/dev/sda1", Path::new("/"), "ext4", "rw")
+            .unwrap();
+        base.commit().unwrap();
+
+        let mut overlay = Database::new(&overlay_path, logger.clone())
+            .unwrap();
+        let root = overlay.add_process(None, Path::new("/tmp"), false)
+            .unwrap();
+        let child = overlay.add_process(Some(root), Path::new("/tmp"), false)
+            .unwrap();
+        overlay.add_file_open(root, Path::new("/etc/hosts"), FileOp::READ,
+                              false).unwrap();
+        overlay.add_file_open(child, Path::new("/tmp/out"), FileOp::WRITE,
+                              false).unwrap();
+        overlay.set_flag("has_sockets", false).unwrap();
+        overlay.set_flag("has_ptrace", true).unwrap();
+        overlay.set_flag("has_io_uring", true).unwrap();
+        overlay.add_mount_point("/dev/sda1", Path::new("/"), "ext4", "rw")
+            .unwrap();
+        overlay.add_mount_point("server:/export", Path::new("/data"), "nfs",
+                                "rw").unwrap();
+        overlay.commit().unwrap();
+
+        let mut merged = Database::open_for_update(&base_path, logger.clone())
+            .unwrap();
+        let overlay = Database::open(&overlay_path, logger.clone()).unwrap();
+        merged.merge_from(overlay, ProcessId(1)).unwrap();
+        merged.commit().unwrap();
+
+        let merged = Database::open(&base_path, logger).unwrap();
+        let parents: Vec<_> = merged.processes().unwrap().iter()
+            .map(|process| (process.id, process.parent))
+            .collect();
+        assert_eq!(parents, vec![
+            (ProcessId(0), None),
+            (ProcessId(1), Some(ProcessId(0))),
+            (ProcessId(2), Some(ProcessId(1))),
+            (ProcessId(3), Some(ProcessId(2))),
+        ]);
+        let opens: Vec<_> = merged.file_opens().unwrap().into_iter()
+            .map(|open| (open.process, open.path))
+            .collect();
+        assert_eq!(opens, vec![
+            (ProcessId(1), "/etc/passwd".into()),
+            (ProcessId(2), "/etc/hosts".into()),
+            (ProcessId(3), "/tmp/out".into()),
+        ]);
+        assert!(merged.flag("has_sockets").unwrap());
+        assert!(merged.flag("has_ptrace").unwrap());
+        assert!(merged.flag("has_io_uring").unwrap());
+        let mounts: Vec<_> = merged.mount_points().unwrap().into_iter()
+            .map(|mount| (mount.device, mount.mount_point))
+            .collect();
+        assert_eq!(mounts, vec![
+            ("/dev/sda1".to_owned(), "/".into()),
+            ("server:/export".to_owned(), "/data".into()),
+        ]);
+    }
+
+    #[test]
+    fn test_merge_older_schema() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let base_path = TempDatabase::new("merge-v1");
+        let overlay_path = TempDatabase::new("merge-v2");
+
+        let mut base = Database::with_schema_version(&base_path, 1,
+                                                     logger.clone())
+            .unwrap();
+        base.add_process(None, Path::new("/work"), false).unwrap();
+        base.commit().unwrap();
+
+        let mut overlay = Database::new(&overlay_path, logger.clone())
+            .unwrap();
+        let root = overlay.add_process(None, Path::new("/tmp"), false)
+            .unwrap();
+        overlay.add_file_open(root, Path::new("/in"), FileOp::READ, false)
+            .unwrap();
+        overlay.add_copy_range(root, Path::new("/in"), Path::new("/out"), 10)
+            .unwrap();
+        overlay.commit().unwrap();
+
+        // The columns and tables of version 2 are left out
+        let mut merged = Database::open_for_update(&base_path, logger.clone())
+            .unwrap();
+        let overlay = Database::open(&overlay_path, logger.clone()).unwrap();
+        merged.merge_from(overlay, ProcessId(0)).unwrap();
+        merged.commit().unwrap();
+
+        let merged = Database::open(&base_path, logger).unwrap();
+        assert_eq!(merged.processes().unwrap().len(), 2);
+        let opens = merged.file_opens().unwrap();
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].process, ProcessId(1));
+        assert!(merged.copy_ranges().unwrap().is_empty());
     }
 }
@@ -0,0 +1,581 @@
+//! A wrapper around a database counting the operations, for profiling.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Error, ExitStatus};
+use crate::time_override::TimeOverride;
+use super::{CacheStat, DatabaseOps, FileOp, ProcessId};
+
+/// Number of calls made to each operation of a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    pub processes: u64,
+    pub file_opens: u64,
+    pub access_patterns: u64,
+    pub executions: u64,
+    pub process_exits: u64,
+    pub syscalls: u64,
+    pub flags: u64,
+    pub timer_events: u64,
+    pub sleeps: u64,
+    pub poll_events: u64,
+    pub fd_transfers: u64,
+    pub copy_ranges: u64,
+    pub landlock_events: u64,
+    pub bpf_operations: u64,
+    pub cache_stats: u64,
+    pub page_faults: u64,
+    pub cross_process_memory: u64,
+    pub cpu_affinity: u64,
+    pub quota_checks: u64,
+    pub jit_regions: u64,
+    pub xattrs: u64,
+    pub signal_handlers: u64,
+    pub namespaces: u64,
+    pub file_hashes: u64,
+    pub file_labels: u64,
+    pub mount_points: u64,
+}
+
+impl DatabaseStats {
+    /// The total number of operations.
+    pub fn total(&self) -> u64 {
+        self.processes + self.file_opens + self.access_patterns
+            + self.executions
+            + self.process_exits + self.syscalls + self.flags
+            + self.timer_events + self.sleeps + self.poll_events
+            + self.fd_transfers + self.copy_ranges + self.landlock_events
+            + self.bpf_operations + self.cache_stats + self.page_faults
+            + self.cross_process_memory + self.cpu_affinity
+            + self.quota_checks + self.jit_regions + self.xattrs
+            + self.signal_handlers + self.namespaces + self.file_hashes
+            + self.file_labels + self.mount_points
+    }
+}
+
+/// The counters updated by a `CountingDatabase`.
+///
+/// They are shared, so they can still be read once the database has been
+/// consumed by the tracer.
+#[derive(Debug, Default)]
+pub struct DatabaseCounters {
+    processes: AtomicU64,
+    file_opens: AtomicU64,
+    access_patterns: AtomicU64,
+    executions: AtomicU64,
+    process_exits: AtomicU64,
+    syscalls: AtomicU64,
+    flags: AtomicU64,
+    timer_events: AtomicU64,
+    sleeps: AtomicU64,
+    poll_events: AtomicU64,
+    fd_transfers: AtomicU64,
+    copy_ranges: AtomicU64,
+    landlock_events: AtomicU64,
+    bpf_operations: AtomicU64,
+    cache_stats: AtomicU64,
+    page_faults: AtomicU64,
+    cross_process_memory: AtomicU64,
+    cpu_affinity: AtomicU64,
+    quota_checks: AtomicU64,
+    jit_regions: AtomicU64,
+    xattrs: AtomicU64,
+    signal_handlers: AtomicU64,
+    namespaces: AtomicU64,
+    file_hashes: AtomicU64,
+    file_labels: AtomicU64,
+    mount_points: AtomicU64,
+}
+
+impl DatabaseCounters {
+    /// Get the current totals.
+    pub fn report(&self) -> DatabaseStats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        DatabaseStats {
+            processes: get(&self.processes),
+            file_opens: get(&self.file_opens),
+            access_patterns: get(&self.access_patterns),
+            executions: get(&self.executions),
+            process_exits: get(&self.process_exits),
+            syscalls: get(&self.syscalls),
+            flags: get(&self.flags),
+            timer_events: get(&self.timer_events),
+            sleeps: get(&self.sleeps),
+            poll_events: get(&self.poll_events),
+            fd_transfers: get(&self.fd_transfers),
+            copy_ranges: get(&self.copy_ranges),
+            landlock_events: get(&self.landlock_events),
+            bpf_operations: get(&self.bpf_operations),
+            cache_stats: get(&self.cache_stats),
+            page_faults: get(&self.page_faults),
+            cross_process_memory: get(&self.cross_process_memory),
+            cpu_affinity: get(&self.cpu_affinity),
+            quota_checks: get(&self.quota_checks),
+            jit_regions: get(&self.jit_regions),
+            xattrs: get(&self.xattrs),
+            signal_handlers: get(&self.signal_handlers),
+            namespaces: get(&self.namespaces),
+            file_hashes: get(&self.file_hashes),
+            file_labels: get(&self.file_labels),
+            mount_points: get(&self.mount_points),
+        }
+    }
+}
+
+fn count(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Database delegating to another, counting the calls to each operation.
+///
+/// ```rust,no_run
+/// # use reprozip::Tracer;
+/// # use reprozip::database::Database;
+/// # use reprozip::database::counting::CountingDatabase;
+/// let logger = slog::Logger::root(slog::Discard, slog::o!());
+/// let database = Database::new("/tmp/db", logger.clone()).unwrap();
+/// let database = CountingDatabase::new(database);
+/// let counters = database.counters();
+/// Tracer::with_database(database, logger, Default::default())
+///     .trace(&["ls"])
+///     .unwrap();
+/// println!("{:?}", counters.report());
+/// ```
+pub struct CountingDatabase<D: DatabaseOps> {
+    inner: D,
+    counters: Arc<DatabaseCounters>,
+}
+
+impl<D: DatabaseOps> CountingDatabase<D> {
+    pub fn new(inner: D) -> CountingDatabase<D> {
+        CountingDatabase {
+            inner,
+            counters: Default::default(),
+        }
+    }
+
+    /// Get the totals so far.
+    pub fn report(&self) -> DatabaseStats {
+        self.counters.report()
+    }
+
+    /// Get the counters, which remain readable after the database is gone.
+    pub fn counters(&self) -> Arc<DatabaseCounters> {
+        self.counters.clone()
+    }
+
+    /// Get the wrapped database back.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+impl<D: DatabaseOps> DatabaseOps for CountingDatabase<D> {
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn add_process(
+        &mut self,
+        parent: Option<ProcessId>,
+        working_dir: &Path,
+        is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        count(&self.counters.processes);
+        self.inner.add_process(parent, working_dir, is_thread)
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        count(&self.counters.file_opens);
+        self.inner.add_file_open(id, path, mode, is_directory)
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        count(&self.counters.access_patterns);
+        self.inner.set_access_pattern(id, path, pattern)
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        count(&self.counters.executions);
+        self.inner.add_exec(id, path, argv, envp, working_dir,
+                            requires_setuid)
+    }
+
+    fn process_exit(
+        &mut self,
+        id: ProcessId,
+        status: ExitStatus,
+    ) -> Result<(), Error> {
+        count(&self.counters.process_exits);
+        self.inner.process_exit(id, status)
+    }
+
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error> {
+        count(&self.counters.syscalls);
+        self.inner.add_syscall(number)
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        count(&self.counters.flags);
+        self.inner.set_flag(name, value)
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error> {
+        count(&self.counters.timer_events);
+        self.inner.add_timerfd_create(id, timer_fd, clockid, flags)
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error> {
+        count(&self.counters.timer_events);
+        self.inner.add_timerfd_settime(id, timer_fd, flags, interval_ns,
+                                       value_ns)
+    }
+
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error> {
+        count(&self.counters.timer_events);
+        self.inner.add_timer_query(id, timer_fd, remaining_ns)
+    }
+
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        count(&self.counters.sleeps);
+        self.inner.add_sleep(id, duration_ns, clockid)
+    }
+
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        count(&self.counters.poll_events);
+        self.inner.add_poll_event(id, path, timeout_ns, duration_ns)
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        count(&self.counters.fd_transfers);
+        self.inner.add_fd_transfer(sender, receiver, receiver_fd, path)
+    }
+
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        count(&self.counters.copy_ranges);
+        self.inner.add_copy_range(id, src_path, dst_path, bytes)
+    }
+
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error> {
+        self.inner.set_pids(id, host_pid, namespace_pid)
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error> {
+        count(&self.counters.namespaces);
+        self.inner.add_pid_namespace(id, namespace)
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        count(&self.counters.namespaces);
+        self.inner.add_process_namespace(id, ns_type, inode, entered_by)
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error> {
+        count(&self.counters.signal_handlers);
+        self.inner.add_signal_handler(id, signum, handler, flags)
+    }
+
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error> {
+        self.inner.set_signal_context(id, signum)
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error> {
+        self.inner.set_cpu_time(id, cpu_ns)
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        count(&self.counters.landlock_events);
+        self.inner.add_landlock_event(id, ruleset, event, access, path)
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error> {
+        count(&self.counters.bpf_operations);
+        self.inner.add_bpf_operation(id, command, bpf_type)
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        count(&self.counters.cache_stats);
+        self.inner.add_cache_stat(id, path, stat)
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        count(&self.counters.page_faults);
+        self.inner.add_page_fault_event(id, address, flags, thread_id)
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        count(&self.counters.cross_process_memory);
+        self.inner.add_cross_process_memory_access(id, target_pid, is_write,
+                                                   address, length)
+    }
+
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error> {
+        self.inner.set_wall_time_ns(duration)
+    }
+
+    fn set_time_override(
+        &mut self,
+        time: &TimeOverride,
+    ) -> Result<(), Error> {
+        self.inner.set_time_override(time)
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error> {
+        count(&self.counters.cpu_affinity);
+        self.inner.add_cpu_affinity(id, target_pid, mask)
+    }
+
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        count(&self.counters.jit_regions);
+        self.inner.add_jit_region(id, start, end, prot, path)
+    }
+
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error> {
+        count(&self.counters.quota_checks);
+        self.inner.add_quota_check(id, command, quota_type, device, quota_id)
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error> {
+        count(&self.counters.xattrs);
+        self.inner.add_xattr_read(id, path, name)
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        count(&self.counters.xattrs);
+        self.inner.add_xattr_write(id, path, name, value)
+    }
+
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error> {
+        count(&self.counters.file_hashes);
+        self.inner.add_file_hash(path, hash)
+    }
+
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error> {
+        count(&self.counters.file_labels);
+        self.inner.add_file_label(path, label)
+    }
+
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error> {
+        count(&self.counters.mount_points);
+        self.inner.add_mount_point(device, mount_point, fs_type, options)
+    }
+
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_process_output(id, stdout, stderr)
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        self.inner.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::database::{DatabaseOps, FileOp};
+    use crate::database::mock::MockDatabase;
+    use super::{CountingDatabase, DatabaseStats};
+
+    #[test]
+    fn test_report() {
+        let mut database = CountingDatabase::new(MockDatabase::new());
+        let counters = database.counters();
+        let root = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        let child = database.add_process(Some(root), Path::new("/work"),
+                                         false).unwrap();
+        for _ in 0..3 {
+            database.add_file_open(child, Path::new("/etc/passwd"),
+                                   FileOp::READ, false).unwrap();
+        }
+        database.add_syscall(0).unwrap();
+        database.set_flag("has_sockets", true).unwrap();
+        assert_eq!(database.report(), DatabaseStats {
+            processes: 2,
+            file_opens: 3,
+            syscalls: 1,
+            flags: 1,
+            ..Default::default()
+        });
+        assert_eq!(database.report().total(), 7);
+
+        // The operations went through
+        let inner = database.into_inner();
+        assert_eq!(inner.file_opens.len(), 1);
+        assert_eq!(inner.file_opens[0].access_count, 3);
+        assert!(inner.flag("has_sockets"));
+        inner.commit().unwrap();
+        assert_eq!(counters.report().processes, 2);
+    }
+}
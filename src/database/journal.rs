@@ -0,0 +1,885 @@
+//! A wrapper around a database also writing every event to a journal file.
+//!
+//! The trace is a single SQLite transaction, so nothing is kept if the tracer
+//! crashes before committing. The journal is written one line per event, each
+//! with a single `write()`, so that it survives the tracer process dying, and
+//! can be replayed into a new database with `JournalDatabase::recover()`.
+//! The tracer keeps the journal locked, so it is never recovered while the
+//! trace is still running.
+//!
+//! Replaying gives the processes the same identifiers, since they are
+//! allocated in order, but their start and end times are those of the
+//! recovery.
+
+use std::fs::{File, OpenOptions, remove_file};
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::{FlockArg, flock};
+use nix::sys::signal::Signal;
+
+use crate::{Error, ExitStatus};
+use crate::time_override::TimeOverride;
+use super::{CacheStat, Database, DatabaseOps, FileOp, ProcessId};
+use super::schema;
+
+/// The journal file used for a database.
+pub fn journal_path(database: &Path) -> PathBuf {
+    let mut path = database.as_os_str().to_owned();
+    path.push(".journal");
+    PathBuf::from(path)
+}
+
+/// Lock a journal, failing if a running tracer has it.
+fn lock(journal: &File, path: &Path) -> Result<(), Error> {
+    flock(journal.as_raw_fd(), FlockArg::LockExclusiveNonblock).map_err(|_| {
+        Error::Internal(format!(
+            "Journal {} is in use, is the tracer still running?",
+            path.display(),
+        ))
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn hex_path(path: &Path) -> String {
+    hex(path.as_os_str().as_bytes())
+}
+
+fn hex_list(list: &[Vec<u8>]) -> String {
+    // Can't be empty, a list with an empty element would be ambiguous
+    let mut s = "L".to_owned();
+    for item in list {
+        s.push(',');
+        s.push_str(&hex(item));
+    }
+    s
+}
+
+fn option<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_else(|| "-".to_owned())
+}
+
+/// Reads the fields of a journal line.
+struct Fields<'a>(std::str::Split<'a, char>);
+
+impl<'a> Fields<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        self.0.next()
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self) -> Option<T> {
+        self.next()?.parse().ok()
+    }
+
+    fn option<T: std::str::FromStr>(&mut self) -> Option<Option<T>> {
+        match self.next()? {
+            "-" => Some(None),
+            s => Some(Some(s.parse().ok()?)),
+        }
+    }
+
+    fn id(&mut self) -> Option<ProcessId> {
+        self.parse().map(ProcessId)
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        unhex(self.next()?)
+    }
+
+    fn path(&mut self) -> Option<PathBuf> {
+        Some(super::bytes_path(self.bytes()?))
+    }
+
+    fn option_path(&mut self) -> Option<Option<PathBuf>> {
+        match self.next()? {
+            "-" => Some(None),
+            s => Some(Some(super::bytes_path(unhex(s)?))),
+        }
+    }
+
+    fn list(&mut self) -> Option<Vec<Vec<u8>>> {
+        let mut items = self.next()?.split(',');
+        if items.next()? != "L" {
+            return None;
+        }
+        items.map(unhex).collect()
+    }
+}
+
+/// Database delegating to another, writing each event to a journal first.
+pub struct JournalDatabase<D: DatabaseOps = Database> {
+    inner: D,
+    journal: File,
+    journal_path: PathBuf,
+}
+
+impl<D: DatabaseOps> JournalDatabase<D> {
+    /// Wrap a database, creating its journal next to it.
+    ///
+    /// `schema_version` is that of `inner`, for the trace to be recovered
+    /// with the same schema. The journal stays locked until `commit()`.
+    pub fn new(
+        inner: D,
+        schema_version: u32,
+    ) -> Result<JournalDatabase<D>, Error> {
+        let journal_path = journal_path(inner.path());
+        let journal = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&journal_path)
+            .map_err(|e| {
+                Error::Internal(format!("Can't create journal {}: {}",
+                                        journal_path.display(), e))
+            })?;
+        // Only truncate it once we know no tracer is using it
+        lock(&journal, &journal_path)?;
+        journal.set_len(0)?;
+        let mut database = JournalDatabase { inner, journal, journal_path };
+        database.log(&["schema".into(), schema_version.to_string()])?;
+        Ok(database)
+    }
+
+    fn log(&mut self, fields: &[String]) -> Result<(), Error> {
+        let mut line = fields.join("\t");
+        line.push('\n');
+        // A single write, so a crash can only cut the last line
+        self.journal.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl JournalDatabase {
+    /// Replay a journal left by a crashed trace into a new database.
+    ///
+    /// Any existing database at `db_path` is replaced. A last line cut short
+    /// by the crash is ignored. The journal is removed once the database is
+    /// committed. This fails without changing anything if the journal is
+    /// locked by a tracer that is still running.
+    pub fn recover(journal_path: &Path, db_path: &Path) -> Result<(), Error> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let read_error = |e| {
+            Error::Internal(format!("Can't read journal {}: {}",
+                                    journal_path.display(), e))
+        };
+        let mut file = File::open(journal_path).map_err(read_error)?;
+        lock(&file, journal_path)?;
+        let mut journal = String::new();
+        file.read_to_string(&mut journal).map_err(read_error)?;
+        let complete = match journal.rfind('\n') {
+            Some(end) => &journal[..end + 1],
+            None => "",
+        };
+        let mut lines = complete.lines().peekable();
+        // Journals from before the schema was recorded are of the latest
+        let header = lines.peek().copied()
+            .and_then(|line| line.strip_prefix("schema\t"));
+        let version = match header {
+            Some(version) => {
+                lines.next();
+                version.parse().map_err(|_| {
+                    Error::Internal(format!(
+                        "Invalid journal schema version: {}", version,
+                    ))
+                })?
+            }
+            None => schema::LATEST_VERSION,
+        };
+        let mut database = Database::create(
+            db_path,
+            schema::strategy(version)?.as_ref(),
+            logger,
+        )?;
+        for line in lines {
+            match replay(&mut database, line) {
+                Some(result) => result?,
+                None => {
+                    return Err(Error::Internal(format!(
+                        "Invalid journal line: {}", line,
+                    )))
+                }
+            }
+        }
+        database.commit()?;
+        remove_file(journal_path)?;
+        drop(file);
+        Ok(())
+    }
+}
+
+/// Apply a journal line to the database.
+///
+/// Returns `None` if the line can't be parsed.
+fn replay(
+    database: &mut Database,
+    line: &str,
+) -> Option<Result<(), Error>> {
+    let mut f = Fields(line.split('\t'));
+    let result = match f.next()? {
+        "process" => {
+            let parent = f.option()?.map(ProcessId);
+            let working_dir = f.path()?;
+            let is_thread = f.parse::<u8>()? != 0;
+            database.add_process(parent, &working_dir, is_thread).map(|_| ())
+        }
+        "open" => {
+            let (id, path) = (f.id()?, f.path()?);
+            let mode = FileOp::from_bits_truncate(f.parse()?);
+            let is_directory = f.parse::<u8>()? != 0;
+            database.add_file_open(id, &path, mode, is_directory)
+        }
+        "pattern" => database.set_access_pattern(f.id()?, &f.path()?,
+                                                 f.next()?),
+        "exec" => {
+            let (id, path, argv, envp) = (f.id()?, f.path()?, f.list()?,
+                                          f.list()?);
+            let working_dir = f.path()?;
+            let requires_setuid = f.parse::<u8>()? != 0;
+            database.add_exec(id, &path, &argv, &envp, &working_dir,
+                              requires_setuid)
+        }
+        "exit" => {
+            let id = f.id()?;
+            let status = f.next()?;
+            let status = match status.split_at(1) {
+                ("r", code) => ExitStatus::Return(code.parse().ok()?),
+                ("s", sig) => {
+                    ExitStatus::Signal(Signal::from_c_int(sig.parse().ok()?)
+                        .ok()?)
+                }
+                _ => return None,
+            };
+            database.process_exit(id, status)
+        }
+        "pids" => database.set_pids(f.id()?, f.parse()?, f.parse()?),
+        "pidns" => database.add_pid_namespace(f.id()?, f.parse()?),
+        "procns" => {
+            let (id, ns_type, inode) = (f.id()?, f.next()?, f.parse()?);
+            let entered_by = f.option::<String>()?;
+            database.add_process_namespace(id, ns_type, inode,
+                                           entered_by.as_deref())
+        }
+        "sighandler" => database.add_signal_handler(f.id()?, f.parse()?,
+                                                    f.parse()?, f.parse()?),
+        "sigctx" => database.set_signal_context(f.id()?, f.option()?),
+        "cputime" => database.set_cpu_time(f.id()?, f.parse()?),
+        "syscall" => database.add_syscall(f.parse()?),
+        "flag" => {
+            let name = String::from_utf8(f.bytes()?).ok()?;
+            database.set_flag(&name, f.parse::<u8>()? != 0)
+        }
+        "timerfd_create" => database.add_timerfd_create(
+            f.id()?, f.parse()?, f.parse()?, f.parse()?,
+        ),
+        "timerfd_settime" => database.add_timerfd_settime(
+            f.id()?, f.parse()?, f.parse()?, f.parse()?, f.parse()?,
+        ),
+        "timer_query" => database.add_timer_query(
+            f.id()?, f.parse()?, f.parse()?,
+        ),
+        "sleep" => database.add_sleep(f.id()?, f.parse()?, f.option()?),
+        "poll" => database.add_poll_event(f.id()?, &f.path()?, f.option()?,
+                                          f.parse()?),
+        "fdtransfer" => {
+            let sender = match (f.option()?, f.option()?) {
+                (Some(id), Some(fd)) => Some((ProcessId(id), fd)),
+                _ => None,
+            };
+            let (receiver, receiver_fd) = (f.id()?, f.parse()?);
+            let path = f.option_path()?;
+            database.add_fd_transfer(sender, receiver, receiver_fd,
+                                     path.as_deref())
+        }
+        "copy" => database.add_copy_range(f.id()?, &f.path()?, &f.path()?,
+                                          f.parse()?),
+        "landlock" => {
+            let (id, ruleset) = (f.id()?, (f.id()?, f.parse()?));
+            let (event, access) = (f.next()?, f.option()?);
+            let path = f.option_path()?;
+            database.add_landlock_event(id, ruleset, event, access,
+                                        path.as_deref())
+        }
+        "bpf" => database.add_bpf_operation(f.id()?, f.parse()?, f.parse()?),
+        "cachestat" => {
+            let (id, path) = (f.id()?, f.path()?);
+            let stat = CacheStat {
+                nr_cache: f.parse()?,
+                nr_dirty: f.parse()?,
+                nr_writeback: f.parse()?,
+                nr_evicted: f.parse()?,
+            };
+            database.add_cache_stat(id, &path, &stat)
+        }
+        "pagefault" => database.add_page_fault_event(f.id()?, f.parse()?,
+                                                     f.parse()?, f.option()?),
+        "xpmem" => database.add_cross_process_memory_access(
+            f.id()?, f.parse()?, f.parse::<u8>()? != 0, f.parse()?, f.parse()?,
+        ),
+        "walltime" => database.set_wall_time_ns(f.parse()?),
+        "timeoverride" => {
+            let start_ns = f.parse()?;
+            let speed = f64::from_bits(f.parse()?);
+            database.set_time_override(&TimeOverride::from_start_ns(start_ns,
+                                                                    speed))
+        }
+        "affinity" => database.add_cpu_affinity(f.id()?, f.parse()?,
+                                                &f.bytes()?),
+        "jit" => {
+            let (id, start, end) = (f.id()?, f.parse()?, f.parse()?);
+            let prot = f.parse()?;
+            let path = f.option_path()?;
+            database.add_jit_region(id, start, end, prot, path.as_deref())
+        }
+        "quota" => {
+            let (id, command, quota_type) = (f.id()?, f.parse()?, f.parse()?);
+            let device = f.option_path()?;
+            database.add_quota_check(id, command, quota_type,
+                                     device.as_deref(), f.parse()?)
+        }
+        "xattr_read" => database.add_xattr_read(f.id()?, &f.path()?,
+                                                &f.bytes()?),
+        "xattr_write" => database.add_xattr_write(f.id()?, &f.path()?,
+                                                  &f.bytes()?, &f.bytes()?),
+        "hash" => {
+            let path = f.path()?;
+            let bytes = f.bytes()?;
+            let mut hash = [0u8; 32];
+            if bytes.len() != hash.len() {
+                return None;
+            }
+            hash.copy_from_slice(&bytes);
+            database.add_file_hash(&path, hash)
+        }
+        "label" => database.add_file_label(&f.path()?, &f.bytes()?),
+        "mount" => {
+            let device = String::from_utf8(f.bytes()?).ok()?;
+            let mount_point = f.path()?;
+            let fs_type = String::from_utf8(f.bytes()?).ok()?;
+            let options = String::from_utf8(f.bytes()?).ok()?;
+            database.add_mount_point(&device, &mount_point, &fs_type,
+                                     &options)
+        }
+        "output" => database.add_process_output(f.id()?, &f.bytes()?,
+                                                &f.bytes()?),
+        _ => return None,
+    };
+    Some(result)
+}
+
+impl<D: DatabaseOps> DatabaseOps for JournalDatabase<D> {
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn add_process(
+        &mut self,
+        parent: Option<ProcessId>,
+        working_dir: &Path,
+        is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        self.log(&["process".into(), option(parent.map(|p| p.0)),
+                   hex_path(working_dir), (is_thread as u8).to_string()])?;
+        self.inner.add_process(parent, working_dir, is_thread)
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        self.log(&["open".into(), id.0.to_string(), hex_path(path),
+                   mode.bits().to_string(),
+                   (is_directory as u8).to_string()])?;
+        self.inner.add_file_open(id, path, mode, is_directory)
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        self.log(&["pattern".into(), id.0.to_string(), hex_path(path),
+                   pattern.to_owned()])?;
+        self.inner.set_access_pattern(id, path, pattern)
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        self.log(&["exec".into(), id.0.to_string(), hex_path(path),
+                   hex_list(argv), hex_list(envp), hex_path(working_dir),
+                   (requires_setuid as u8).to_string()])?;
+        self.inner.add_exec(id, path, argv, envp, working_dir,
+                            requires_setuid)
+    }
+
+    fn process_exit(
+        &mut self,
+        id: ProcessId,
+        status: ExitStatus,
+    ) -> Result<(), Error> {
+        let status_field = match status {
+            ExitStatus::Return(code) => format!("r{}", code),
+            ExitStatus::Signal(sig) => format!("s{}", sig as i32),
+        };
+        self.log(&["exit".into(), id.0.to_string(), status_field])?;
+        self.inner.process_exit(id, status)
+    }
+
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error> {
+        self.log(&["pids".into(), id.0.to_string(), host_pid.to_string(),
+                   namespace_pid.to_string()])?;
+        self.inner.set_pids(id, host_pid, namespace_pid)
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error> {
+        self.log(&["pidns".into(), id.0.to_string(), namespace.to_string()])?;
+        self.inner.add_pid_namespace(id, namespace)
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        self.log(&["procns".into(), id.0.to_string(), ns_type.to_owned(),
+                   inode.to_string(), option(entered_by)])?;
+        self.inner.add_process_namespace(id, ns_type, inode, entered_by)
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error> {
+        self.log(&["sighandler".into(), id.0.to_string(), signum.to_string(),
+                   handler.to_string(), flags.to_string()])?;
+        self.inner.add_signal_handler(id, signum, handler, flags)
+    }
+
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error> {
+        self.log(&["sigctx".into(), id.0.to_string(), option(signum)])?;
+        self.inner.set_signal_context(id, signum)
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error> {
+        self.log(&["cputime".into(), id.0.to_string(), cpu_ns.to_string()])?;
+        self.inner.set_cpu_time(id, cpu_ns)
+    }
+
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error> {
+        self.log(&["syscall".into(), number.to_string()])?;
+        self.inner.add_syscall(number)
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        self.log(&["flag".into(), hex(name.as_bytes()),
+                   (value as u8).to_string()])?;
+        self.inner.set_flag(name, value)
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error> {
+        self.log(&["timerfd_create".into(), id.0.to_string(),
+                   timer_fd.to_string(), clockid.to_string(),
+                   flags.to_string()])?;
+        self.inner.add_timerfd_create(id, timer_fd, clockid, flags)
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error> {
+        self.log(&["timerfd_settime".into(), id.0.to_string(),
+                   timer_fd.to_string(), flags.to_string(),
+                   interval_ns.to_string(), value_ns.to_string()])?;
+        self.inner.add_timerfd_settime(id, timer_fd, flags, interval_ns,
+                                       value_ns)
+    }
+
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error> {
+        self.log(&["timer_query".into(), id.0.to_string(),
+                   timer_fd.to_string(), remaining_ns.to_string()])?;
+        self.inner.add_timer_query(id, timer_fd, remaining_ns)
+    }
+
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        self.log(&["sleep".into(), id.0.to_string(), duration_ns.to_string(),
+                   option(clockid)])?;
+        self.inner.add_sleep(id, duration_ns, clockid)
+    }
+
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        self.log(&["poll".into(), id.0.to_string(), hex_path(path),
+                   option(timeout_ns), duration_ns.to_string()])?;
+        self.inner.add_poll_event(id, path, timeout_ns, duration_ns)
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.log(&["fdtransfer".into(), option(sender.map(|(id, _)| id.0)),
+                   option(sender.map(|(_, fd)| fd)), receiver.0.to_string(),
+                   receiver_fd.to_string(),
+                   path.map(hex_path).unwrap_or_else(|| "-".to_owned())])?;
+        self.inner.add_fd_transfer(sender, receiver, receiver_fd, path)
+    }
+
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        self.log(&["copy".into(), id.0.to_string(), hex_path(src_path),
+                   hex_path(dst_path), bytes.to_string()])?;
+        self.inner.add_copy_range(id, src_path, dst_path, bytes)
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.log(&["landlock".into(), id.0.to_string(),
+                   ruleset.0.0.to_string(), ruleset.1.to_string(),
+                   event.to_owned(), option(access),
+                   path.map(hex_path).unwrap_or_else(|| "-".to_owned())])?;
+        self.inner.add_landlock_event(id, ruleset, event, access, path)
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error> {
+        self.log(&["bpf".into(), id.0.to_string(), command.to_string(),
+                   bpf_type.to_string()])?;
+        self.inner.add_bpf_operation(id, command, bpf_type)
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        self.log(&["cachestat".into(), id.0.to_string(), hex_path(path),
+                   stat.nr_cache.to_string(), stat.nr_dirty.to_string(),
+                   stat.nr_writeback.to_string(),
+                   stat.nr_evicted.to_string()])?;
+        self.inner.add_cache_stat(id, path, stat)
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        self.log(&["pagefault".into(), id.0.to_string(), address.to_string(),
+                   flags.to_string(), option(thread_id)])?;
+        self.inner.add_page_fault_event(id, address, flags, thread_id)
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        self.log(&["xpmem".into(), id.0.to_string(), target_pid.to_string(),
+                   (is_write as u8).to_string(), address.to_string(),
+                   length.to_string()])?;
+        self.inner.add_cross_process_memory_access(id, target_pid, is_write,
+                                                   address, length)
+    }
+
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error> {
+        self.log(&["walltime".into(), duration.to_string()])?;
+        self.inner.set_wall_time_ns(duration)
+    }
+
+    fn set_time_override(
+        &mut self,
+        time: &TimeOverride,
+    ) -> Result<(), Error> {
+        self.log(&["timeoverride".into(), time.start_ns().to_string(),
+                   time.speed.to_bits().to_string()])?;
+        self.inner.set_time_override(time)
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error> {
+        self.log(&["affinity".into(), id.0.to_string(),
+                   target_pid.to_string(), hex(mask)])?;
+        self.inner.add_cpu_affinity(id, target_pid, mask)
+    }
+
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.log(&["jit".into(), id.0.to_string(), start.to_string(),
+                   end.to_string(), prot.to_string(),
+                   path.map(hex_path).unwrap_or_else(|| "-".to_owned())])?;
+        self.inner.add_jit_region(id, start, end, prot, path)
+    }
+
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error> {
+        self.log(&["quota".into(), id.0.to_string(), command.to_string(),
+                   quota_type.to_string(),
+                   device.map(hex_path).unwrap_or_else(|| "-".to_owned()),
+                   quota_id.to_string()])?;
+        self.inner.add_quota_check(id, command, quota_type, device, quota_id)
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error> {
+        self.log(&["xattr_read".into(), id.0.to_string(), hex_path(path),
+                   hex(name)])?;
+        self.inner.add_xattr_read(id, path, name)
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.log(&["xattr_write".into(), id.0.to_string(), hex_path(path),
+                   hex(name), hex(value)])?;
+        self.inner.add_xattr_write(id, path, name, value)
+    }
+
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error> {
+        self.log(&["hash".into(), hex_path(path), hex(&hash)])?;
+        self.inner.add_file_hash(path, hash)
+    }
+
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error> {
+        self.log(&["label".into(), hex_path(path), hex(label)])?;
+        self.inner.add_file_label(path, label)
+    }
+
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error> {
+        self.log(&["mount".into(), hex(device.as_bytes()),
+                   hex_path(mount_point), hex(fs_type.as_bytes()),
+                   hex(options.as_bytes())])?;
+        self.inner.add_mount_point(device, mount_point, fs_type, options)
+    }
+
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error> {
+        self.log(&["output".into(), id.0.to_string(), hex(stdout),
+                   hex(stderr)])?;
+        self.inner.add_process_output(id, stdout, stderr)
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        self.inner.commit()?;
+        // The data is safe in the database, the journal is not needed
+        // anymore; it is unlocked once removed
+        remove_file(&self.journal_path)?;
+        drop(self.journal);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::ExitStatus;
+    use crate::testing::TempDatabase;
+    use super::{Database, DatabaseOps, FileOp, JournalDatabase, journal_path,
+                schema};
+
+    /// Record a small trace.
+    fn record<D: DatabaseOps>(database: &mut D) {
+        let root = database.add_process(None, Path::new("/work"), false)
+            .unwrap();
+        let child = database.add_process(Some(root), Path::new("/work"),
+                                         false).unwrap();
+        database.add_exec(child, Path::new("/bin/cat"),
+                          &[b"cat".to_vec(), b"in".to_vec()],
+                          &[b"HOME=/".to_vec()], Path::new("/work"),
+                          false).unwrap();
+        database.add_file_open(child, Path::new("/work/in"), FileOp::READ,
+                               false).unwrap();
+        database.add_file_open(child, Path::new("/work/in"), FileOp::READ,
+                               false).unwrap();
+        database.process_exit(child, ExitStatus::Return(0)).unwrap();
+        database.set_flag("has_sockets", true).unwrap();
+    }
+
+    /// The rows of a trace, apart from the times.
+    fn rows(database: &Database) -> String {
+        format!(
+            "{:?}\n{:?}\n{:?}\n{:?}",
+            database.processes().unwrap(),
+            database.file_opens().unwrap(),
+            database.executions().unwrap(),
+            database.flags().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_recover() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let expected_path = TempDatabase::new("journal-expected");
+        let path = TempDatabase::new("journal");
+        let mut recovered_path = path.path().as_os_str().to_owned();
+        recovered_path.push(".recovered");
+        let recovered_path = TempDatabase::from_path(recovered_path.into());
+
+        let mut expected = Database::with_schema_version(
+            &expected_path, 1, logger.clone(),
+        ).unwrap();
+        record(&mut expected);
+        expected.commit().unwrap();
+
+        let mut database = JournalDatabase::new(
+            Database::with_schema_version(&path, 1, logger.clone()).unwrap(),
+            1,
+        ).unwrap();
+        record(&mut database);
+        // The tracer is still running, its journal is left alone
+        assert!(Database::open(&path, logger.clone()).is_err());
+        assert!(journal_path(path.path()).exists());
+        // The tracer crashes
+        drop(database);
+
+        // Opening the trace recovers it next to it, without changing it
+        let database = Database::open(&path, logger.clone()).unwrap();
+        assert!(!journal_path(path.path()).exists());
+        assert!(database.processes().unwrap().is_empty());
+        drop(database);
+
+        let recovered = Database::open(&recovered_path, logger.clone())
+            .unwrap();
+        let expected = Database::open(&expected_path, logger).unwrap();
+        assert_eq!(rows(&recovered), rows(&expected));
+        assert_eq!(schema::read_version(&recovered.connection).unwrap(), 1);
+    }
+}
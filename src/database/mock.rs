@@ -0,0 +1,430 @@
+//! A database keeping the trace in memory, to test the code recording it.
+//!
+//! Only the events that tests look at are kept, the other operations do
+//! nothing.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{Error, ExitStatus};
+use crate::time_override::TimeOverride;
+use super::{
+    CacheStat, CacheStatQuery, CrossProcessMemoryAccess, DatabaseOps,
+    Execution, FileOp, FileOpen, LandlockPolicies, LandlockPolicy, PageFault,
+    ProcessId,
+};
+
+/// Database recording the trace in memory, as it would be read back from a
+/// `Database`.
+#[derive(Default)]
+pub struct MockDatabase {
+    next_process: u32,
+    /// The file accesses, identical ones counted in the same entry
+    pub file_opens: Vec<FileOpen>,
+    pub executions: Vec<Execution>,
+    pub cache_stat_queries: Vec<CacheStatQuery>,
+    pub page_faults: Vec<PageFault>,
+    pub cross_process_memory_accesses: Vec<CrossProcessMemoryAccess>,
+    pub flags: BTreeMap<String, bool>,
+    landlock: LandlockPolicies,
+}
+
+impl MockDatabase {
+    pub fn new() -> MockDatabase {
+        Default::default()
+    }
+
+    /// Get a boolean flag, false if unset.
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.get(name).copied().unwrap_or(false)
+    }
+
+    /// The Landlock rulesets enforced so far.
+    pub fn landlock_policies(&self) -> &[LandlockPolicy] {
+        &self.landlock.policies
+    }
+}
+
+impl DatabaseOps for MockDatabase {
+    fn path(&self) -> &Path {
+        Path::new(":memory:")
+    }
+
+    fn add_process(
+        &mut self,
+        _parent: Option<ProcessId>,
+        _working_dir: &Path,
+        _is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        let id = ProcessId(self.next_process);
+        self.next_process += 1;
+        Ok(id)
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        let existing = self.file_opens.iter_mut().find(|open| {
+            open.process == id && open.path == path && open.mode == mode
+        });
+        match existing {
+            Some(open) => open.access_count += 1,
+            None => self.file_opens.push(FileOpen {
+                process: id,
+                path: path.to_owned(),
+                mode,
+                is_directory,
+                access_count: 1,
+                access_pattern: None,
+            }),
+        }
+        Ok(())
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        for open in &mut self.file_opens {
+            if open.process == id && open.path == path {
+                open.access_pattern = Some(pattern.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        self.executions.push(Execution {
+            process: id,
+            path: path.to_owned(),
+            argv: argv.to_owned(),
+            envp: envp.to_owned(),
+            working_dir: working_dir.to_owned(),
+            requires_setuid,
+        });
+        Ok(())
+    }
+
+    fn process_exit(
+        &mut self,
+        _id: ProcessId,
+        _status: ExitStatus,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_pids(
+        &mut self,
+        _id: ProcessId,
+        _host_pid: i32,
+        _namespace_pid: i32,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        _id: ProcessId,
+        _namespace: u64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        _id: ProcessId,
+        _ns_type: &str,
+        _inode: u64,
+        _entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        _id: ProcessId,
+        _signum: i32,
+        _handler: u64,
+        _flags: u64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_signal_context(
+        &mut self,
+        _id: ProcessId,
+        _signum: Option<i32>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        _id: ProcessId,
+        _cpu_ns: u64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_syscall(&mut self, _number: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        self.flags.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        _id: ProcessId,
+        _timer_fd: i32,
+        _clockid: i32,
+        _flags: i32,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        _id: ProcessId,
+        _timer_fd: i32,
+        _flags: i32,
+        _interval_ns: i64,
+        _value_ns: i64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_timer_query(
+        &mut self,
+        _id: ProcessId,
+        _timer_fd: i32,
+        _remaining_ns: i64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_sleep(
+        &mut self,
+        _id: ProcessId,
+        _duration_ns: u64,
+        _clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_poll_event(
+        &mut self,
+        _id: ProcessId,
+        _path: &Path,
+        _timeout_ns: Option<u64>,
+        _duration_ns: u64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        _sender: Option<(ProcessId, i32)>,
+        _receiver: ProcessId,
+        _receiver_fd: i32,
+        _path: Option<&Path>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_copy_range(
+        &mut self,
+        _id: ProcessId,
+        _src_path: &Path,
+        _dst_path: &Path,
+        _bytes: u64,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.landlock.add(id, ((ruleset.0).0, ruleset.1), event,
+                          access.unwrap_or(0), path.map(Path::to_owned));
+        Ok(())
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        _id: ProcessId,
+        _command: i32,
+        _bpf_type: u32,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        self.cache_stat_queries.push(CacheStatQuery {
+            process: id,
+            path: path.to_owned(),
+            stat: *stat,
+        });
+        Ok(())
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        self.page_faults.push(PageFault {
+            process: id,
+            address,
+            flags,
+            thread_id,
+        });
+        Ok(())
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        self.cross_process_memory_accesses.push(CrossProcessMemoryAccess {
+            process: id,
+            target_pid,
+            is_write,
+            address,
+            length,
+        });
+        Ok(())
+    }
+
+    fn set_wall_time_ns(&mut self, _duration: u64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn set_time_override(
+        &mut self,
+        _time: &TimeOverride,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        _id: ProcessId,
+        _target_pid: i32,
+        _mask: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_jit_region(
+        &mut self,
+        _id: ProcessId,
+        _start: u64,
+        _end: u64,
+        _prot: i32,
+        _path: Option<&Path>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_quota_check(
+        &mut self,
+        _id: ProcessId,
+        _command: i32,
+        _quota_type: i32,
+        _device: Option<&Path>,
+        _quota_id: u32,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        _id: ProcessId,
+        _path: &Path,
+        _name: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        _id: ProcessId,
+        _path: &Path,
+        _name: &[u8],
+        _value: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_process_output(
+        &mut self,
+        _id: ProcessId,
+        _stdout: &[u8],
+        _stderr: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_file_hash(
+        &mut self,
+        _path: &Path,
+        _hash: [u8; 32],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_file_label(
+        &mut self,
+        _path: &Path,
+        _label: &[u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_mount_point(
+        &mut self,
+        _device: &str,
+        _mount_point: &Path,
+        _fs_type: &str,
+        _options: &str,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+//! A wrapper around a database recording paths relative to a root directory.
+//!
+//! This is used when the traced program sees its files under a prefix, for
+//! example a container's filesystem mounted at `/rootfs`, so the trace can
+//! be used as if it had been made on the host. See
+//! `TraceConfig::root_prefix`.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, ExitStatus};
+use crate::path_utils::normalize_path;
+use crate::time_override::TimeOverride;
+use super::{CacheStat, DatabaseOps, FileOp, ProcessId};
+
+/// Database delegating to another, removing a prefix from the paths.
+///
+/// Without a prefix, everything is passed through unchanged.
+pub struct RemappingDatabase<D: DatabaseOps> {
+    inner: D,
+    root: Option<PathBuf>,
+    logger: slog::Logger,
+    /// The paths outside of the root that were already warned about
+    outside: HashSet<PathBuf>,
+}
+
+impl<D: DatabaseOps> RemappingDatabase<D> {
+    pub fn new(
+        inner: D,
+        root: Option<PathBuf>,
+        logger: slog::Logger,
+    ) -> RemappingDatabase<D> {
+        RemappingDatabase {
+            inner,
+            root,
+            logger,
+            outside: HashSet::new(),
+        }
+    }
+
+    /// Get the wrapped database back.
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// The path to record, warning the first time about paths outside of
+    /// the root.
+    fn remap<'p>(&mut self, path: &'p Path) -> Cow<'p, Path> {
+        let root = match &self.root {
+            Some(root) => root,
+            None => return Cow::Borrowed(path),
+        };
+        if path.starts_with(root) {
+            Cow::Owned(normalize_path(path, root))
+        } else {
+            if self.outside.insert(path.to_owned()) {
+                warn!(self.logger, "Path outside of root directory, recording \
+                                    it as-is";
+                      "path" => %path.display(), "root" => %root.display());
+            }
+            Cow::Borrowed(path)
+        }
+    }
+}
+
+impl<D: DatabaseOps> DatabaseOps for RemappingDatabase<D> {
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn add_process(
+        &mut self,
+        parent: Option<ProcessId>,
+        working_dir: &Path,
+        is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        let working_dir = self.remap(working_dir);
+        self.inner.add_process(parent, &working_dir, is_thread)
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_file_open(id, &path, mode, is_directory)
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.set_access_pattern(id, &path, pattern)
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        let working_dir = self.remap(working_dir);
+        self.inner.add_exec(id, &path, argv, envp, &working_dir,
+                            requires_setuid)
+    }
+
+    fn process_exit(
+        &mut self,
+        id: ProcessId,
+        status: ExitStatus,
+    ) -> Result<(), Error> {
+        self.inner.process_exit(id, status)
+    }
+
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error> {
+        self.inner.add_syscall(number)
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        self.inner.set_flag(name, value)
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error> {
+        self.inner.add_timerfd_create(id, timer_fd, clockid, flags)
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error> {
+        self.inner.add_timerfd_settime(id, timer_fd, flags, interval_ns,
+                                       value_ns)
+    }
+
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error> {
+        self.inner.add_timer_query(id, timer_fd, remaining_ns)
+    }
+
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        self.inner.add_sleep(id, duration_ns, clockid)
+    }
+
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_poll_event(id, &path, timeout_ns, duration_ns)
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        let path = path.map(|path| self.remap(path));
+        self.inner.add_fd_transfer(sender, receiver, receiver_fd,
+                                   path.as_deref())
+    }
+
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        let src_path = self.remap(src_path);
+        let dst_path = self.remap(dst_path);
+        self.inner.add_copy_range(id, &src_path, &dst_path, bytes)
+    }
+
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error> {
+        self.inner.set_pids(id, host_pid, namespace_pid)
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_pid_namespace(id, namespace)
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        self.inner.add_process_namespace(id, ns_type, inode, entered_by)
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_signal_handler(id, signum, handler, flags)
+    }
+
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error> {
+        self.inner.set_signal_context(id, signum)
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error> {
+        self.inner.set_cpu_time(id, cpu_ns)
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        let path = path.map(|path| self.remap(path));
+        self.inner.add_landlock_event(id, ruleset, event, access,
+                                      path.as_deref())
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error> {
+        self.inner.add_bpf_operation(id, command, bpf_type)
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_cache_stat(id, &path, stat)
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        self.inner.add_page_fault_event(id, address, flags, thread_id)
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_cross_process_memory_access(id, target_pid, is_write,
+                                                   address, length)
+    }
+
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error> {
+        self.inner.set_wall_time_ns(duration)
+    }
+
+    fn set_time_override(
+        &mut self,
+        time: &TimeOverride,
+    ) -> Result<(), Error> {
+        self.inner.set_time_override(time)
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_cpu_affinity(id, target_pid, mask)
+    }
+
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        let path = path.map(|path| self.remap(path));
+        self.inner.add_jit_region(id, start, end, prot, path.as_deref())
+    }
+
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error> {
+        let device = device.map(|device| self.remap(device));
+        self.inner.add_quota_check(id, command, quota_type,
+                                   device.as_deref(), quota_id)
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_xattr_read(id, &path, name)
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_xattr_write(id, &path, name, value)
+    }
+
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_file_hash(&path, hash)
+    }
+
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error> {
+        let path = self.remap(path);
+        self.inner.add_file_label(&path, label)
+    }
+
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error> {
+        // Most of the host's mounts are outside of the root, keep those
+        // without warning
+        let mount_point = match &self.root {
+            Some(root) if mount_point.starts_with(root) => {
+                Cow::Owned(normalize_path(mount_point, root))
+            }
+            _ => Cow::Borrowed(mount_point),
+        };
+        self.inner.add_mount_point(device, &mount_point, fs_type, options)
+    }
+
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_process_output(id, stdout, stderr)
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        self.inner.commit()
+    }
+}
@@ -0,0 +1,314 @@
+//! The versions of the database schema, see `TraceConfig::schema_version`.
+//!
+//! Version 1 has the processes, file accesses and executions, which is what
+//! older tools reading traces know about. Version 2 adds the tables for all
+//! the other events, and columns to the first tables for the timing, PIDs,
+//! signals and repeated accesses. When writing an older version, the tables
+//! it doesn't have are created as temporary tables, so that those events are
+//! dropped rather than every recording method having to check; they are
+//! created the same way when reading, so they read as empty. The columns it
+//! doesn't have are left out by `Database`.
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::Error;
+
+/// The version written by default.
+pub const LATEST_VERSION: u32 = 2;
+
+/// The tables of version 1.
+const V1_TABLES: &str = "
+    CREATE TABLE processes(
+        id INTEGER NOT NULL PRIMARY KEY,
+        parent INTEGER,
+        working_dir BLOB NOT NULL,
+        is_thread BOOLEAN NOT NULL
+    );
+    CREATE TABLE file_opens(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        file_op INTEGER NOT NULL,
+        is_directory BOOLEAN NOT NULL
+    );
+    CREATE TABLE executions(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        argv BLOB NOT NULL,
+        envp BLOB NOT NULL,
+        working_dir BLOB NOT NULL
+    );
+    CREATE TABLE process_exits(
+        process_id INTEGER NOT NULL,
+        exit_code INTEGER,
+        signal INTEGER
+    );
+    CREATE TABLE observed_syscalls(
+        number INTEGER NOT NULL PRIMARY KEY
+    );
+    CREATE TABLE metadata(
+        name TEXT NOT NULL PRIMARY KEY,
+        value
+    );
+    CREATE TABLE file_hashes(
+        path BLOB NOT NULL PRIMARY KEY,
+        hash BLOB NOT NULL
+    );
+";
+
+/// The columns added to the tables of version 1 in version 2.
+const V2_COLUMNS: &str = "
+    ALTER TABLE processes ADD COLUMN start_ns INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE processes ADD COLUMN end_ns INTEGER;
+    ALTER TABLE processes ADD COLUMN cpu_time_ns INTEGER;
+    ALTER TABLE processes ADD COLUMN signal INTEGER;
+    ALTER TABLE processes ADD COLUMN host_pid INTEGER;
+    ALTER TABLE processes ADD COLUMN namespace_pid INTEGER;
+    ALTER TABLE file_opens ADD COLUMN signal INTEGER;
+    ALTER TABLE file_opens ADD COLUMN access_count INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE file_opens ADD COLUMN access_pattern TEXT;
+    ALTER TABLE executions ADD COLUMN requires_setuid BOOLEAN NOT NULL
+        DEFAULT 0;
+";
+
+/// The tables added in version 2.
+const V2_TABLES: &str = "
+    CREATE TABLE file_labels(
+        path BLOB NOT NULL PRIMARY KEY,
+        label BLOB NOT NULL
+    );
+    CREATE TABLE timer_events(
+        process_id INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        timer_fd INTEGER NOT NULL,
+        clockid INTEGER,
+        flags INTEGER NOT NULL,
+        interval_ns INTEGER,
+        value_ns INTEGER
+    );
+    CREATE TABLE timer_queries(
+        process_id INTEGER NOT NULL,
+        timer_fd INTEGER NOT NULL,
+        remaining_ns INTEGER NOT NULL
+    );
+    CREATE TABLE sleep_events(
+        process_id INTEGER NOT NULL,
+        duration_ns INTEGER NOT NULL,
+        clockid INTEGER
+    );
+    CREATE TABLE poll_events(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        timeout_ns INTEGER,
+        duration_ns INTEGER NOT NULL
+    );
+    CREATE TABLE fd_transfers(
+        sender INTEGER,
+        sender_fd INTEGER,
+        receiver INTEGER NOT NULL,
+        receiver_fd INTEGER NOT NULL,
+        path BLOB
+    );
+    CREATE TABLE copy_ranges(
+        process_id INTEGER NOT NULL,
+        src_path BLOB NOT NULL,
+        dst_path BLOB NOT NULL,
+        bytes INTEGER NOT NULL,
+        copy_count INTEGER NOT NULL DEFAULT 1
+    );
+    CREATE TABLE cpu_affinity_changes(
+        process_id INTEGER NOT NULL,
+        target_pid INTEGER NOT NULL,
+        mask BLOB NOT NULL
+    );
+    CREATE TABLE jit_regions(
+        process_id INTEGER NOT NULL,
+        start_address INTEGER NOT NULL,
+        end_address INTEGER NOT NULL,
+        prot INTEGER NOT NULL,
+        path BLOB
+    );
+    CREATE TABLE quota_checks(
+        process_id INTEGER NOT NULL,
+        command INTEGER NOT NULL,
+        quota_type INTEGER NOT NULL,
+        device BLOB,
+        quota_id INTEGER NOT NULL
+    );
+    CREATE TABLE xattr_reads(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        name BLOB NOT NULL
+    );
+    CREATE TABLE xattr_writes(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        name BLOB NOT NULL,
+        value BLOB NOT NULL
+    );
+    CREATE TABLE pid_namespaces(
+        process_id INTEGER NOT NULL,
+        namespace INTEGER NOT NULL
+    );
+    CREATE TABLE process_namespaces(
+        process_id INTEGER NOT NULL,
+        ns_type TEXT NOT NULL,
+        ns_inode INTEGER NOT NULL,
+        entered_by TEXT
+    );
+    CREATE TABLE signal_handlers(
+        process_id INTEGER NOT NULL,
+        signum INTEGER NOT NULL,
+        handler INTEGER NOT NULL,
+        flags INTEGER NOT NULL
+    );
+    CREATE TABLE landlock_policies(
+        process_id INTEGER NOT NULL,
+        ruleset_process INTEGER NOT NULL,
+        ruleset_fd INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        access INTEGER,
+        path BLOB
+    );
+    CREATE TABLE bpf_operations(
+        process_id INTEGER NOT NULL,
+        command INTEGER NOT NULL,
+        bpf_type INTEGER NOT NULL
+    );
+    CREATE TABLE cache_stats(
+        process_id INTEGER NOT NULL,
+        path BLOB NOT NULL,
+        nr_cache INTEGER NOT NULL,
+        nr_dirty INTEGER NOT NULL,
+        nr_writeback INTEGER NOT NULL,
+        nr_evicted INTEGER NOT NULL
+    );
+    CREATE TABLE page_fault_events(
+        process_id INTEGER NOT NULL,
+        address INTEGER NOT NULL,
+        flags INTEGER NOT NULL,
+        thread_id INTEGER
+    );
+    CREATE TABLE cross_process_memory_accesses(
+        process_id INTEGER NOT NULL,
+        target_pid INTEGER NOT NULL,
+        is_write INTEGER NOT NULL,
+        address INTEGER NOT NULL,
+        length INTEGER NOT NULL
+    );
+    CREATE TABLE process_outputs(
+        process_id INTEGER NOT NULL,
+        stdout BLOB NOT NULL,
+        stderr BLOB NOT NULL
+    );
+    CREATE TABLE mount_points(
+        device TEXT NOT NULL,
+        mount_point BLOB NOT NULL,
+        filesystem_type TEXT NOT NULL,
+        mount_options TEXT NOT NULL
+    );
+";
+
+/// How to create the tables of one version of the schema.
+pub trait SchemaStrategy {
+    /// The version number, stored as `schema_version` in the metadata.
+    fn version(&self) -> u32;
+
+    /// Create the tables in a new database.
+    fn create_tables(&self, connection: &Connection) -> Result<(), Error>;
+
+    /// Create the tables this version doesn't have as temporary tables.
+    fn create_missing_tables(
+        &self,
+        connection: &Connection,
+    ) -> Result<(), Error>;
+}
+
+/// Create tables as temporary tables, only visible to this connection.
+fn create_temporary(
+    connection: &Connection,
+    tables: &str,
+) -> Result<(), Error> {
+    connection.execute_batch(
+        &tables.replace("CREATE TABLE", "CREATE TEMPORARY TABLE"),
+    )?;
+    Ok(())
+}
+
+/// The first version, with the processes and file accesses.
+pub struct SchemaV1;
+
+impl SchemaStrategy for SchemaV1 {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_tables(&self, connection: &Connection) -> Result<(), Error> {
+        connection.execute_batch(V1_TABLES)?;
+        self.create_missing_tables(connection)
+    }
+
+    fn create_missing_tables(
+        &self,
+        connection: &Connection,
+    ) -> Result<(), Error> {
+        create_temporary(connection, V2_TABLES)
+    }
+}
+
+/// The second version, with all the events.
+pub struct SchemaV2;
+
+impl SchemaStrategy for SchemaV2 {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn create_tables(&self, connection: &Connection) -> Result<(), Error> {
+        connection.execute_batch(V1_TABLES)?;
+        connection.execute_batch(V2_COLUMNS)?;
+        connection.execute_batch(V2_TABLES)?;
+        Ok(())
+    }
+
+    fn create_missing_tables(
+        &self,
+        _connection: &Connection,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Get the strategy for a version of the schema.
+pub fn strategy(version: u32) -> Result<Box<dyn SchemaStrategy>, Error> {
+    match version {
+        1 => Ok(Box::new(SchemaV1)),
+        2 => Ok(Box::new(SchemaV2)),
+        _ => Err(Error::Internal(format!(
+            "Unknown database schema version {}", version,
+        ))),
+    }
+}
+
+/// Get the version of the schema of a database.
+///
+/// Traces from before versions were recorded have the latest one.
+pub fn read_version(connection: &Connection) -> Result<u32, Error> {
+    let version = connection.query_row(
+        "SELECT value FROM metadata WHERE name = 'schema_version'",
+        [],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(version.unwrap_or(LATEST_VERSION))
+}
+
+/// Record the version of the schema of a new database.
+pub fn write_version(
+    connection: &Connection,
+    schema: &dyn SchemaStrategy,
+) -> Result<(), Error> {
+    connection.execute(
+        "INSERT INTO metadata(name, value) VALUES('schema_version', ?1)",
+        params![schema.version()],
+    )?;
+    Ok(())
+}
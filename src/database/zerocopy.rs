@@ -0,0 +1,467 @@
+//! A database staged in memory, copied to its file when the trace is done.
+//!
+//! SQLite normally writes its pages to the database file as its cache fills
+//! up, which for traces with millions of events means many small writes
+//! interleaved with the tracing. `ZeroCopyDatabase` instead has SQLite write
+//! to a file in `/dev/shm`, which lives in memory, and only moves the
+//! finished database to the real file on commit, with `splice()`, without
+//! the data going through userspace.
+//!
+//! A `memfd` would avoid having a name in `/dev/shm` at all, but SQLite
+//! resolves symbolic links, so it can't open one as `/proc/self/fd/<fd>`.
+//!
+//! This is only enabled with the `zerocopy` feature, because of the
+//! tradeoffs:
+//!
+//! * The whole database is kept in memory until the end of the trace, so
+//!   memory use grows with the trace instead of being bounded by SQLite's
+//!   cache.
+//! * Nothing is written to disk before the end, so if the tracer crashes
+//!   there is not even a partial file to look at, and a staging file might
+//!   be left in `/dev/shm`. Wrapping it in a `JournalDatabase` still allows
+//!   recovering the trace.
+//! * The database is written in one go at the end, which is faster if the
+//!   storage is slow, but moves that time to after the program exits.
+//!
+//! Run `cargo bench --features zerocopy` to compare it with `Database` on
+//! your own system and storage.
+
+use std::fs::{File, remove_file};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use nix::errno::Errno;
+use rusqlite::Connection;
+
+use crate::{Error, ExitStatus};
+use crate::time_override::TimeOverride;
+use super::{CacheStat, Database, DatabaseOps, FileOp, ProcessId};
+use super::schema::SchemaV2;
+
+/// Where the databases are staged, a `tmpfs` on most systems.
+const STAGING_DIR: &str = "/dev/shm";
+
+/// How much to move through the pipe at once.
+const SPLICE_SIZE: usize = 1 << 20;
+
+/// Number of databases staged by this process, to name them.
+static STAGED: AtomicU32 = AtomicU32::new(0);
+
+/// A staging file, removed when dropped.
+struct StagingFile(PathBuf);
+
+impl Drop for StagingFile {
+    fn drop(&mut self) {
+        remove_file(&self.0).ok();
+    }
+}
+
+/// A `Database` written to memory, then moved to its path on commit.
+pub struct ZeroCopyDatabase {
+    inner: Database,
+    staging: StagingFile,
+}
+
+impl ZeroCopyDatabase {
+    /// Create a new database, that will be written to `path` on commit.
+    ///
+    /// Any existing file there is replaced at that time.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        logger: slog::Logger,
+    ) -> Result<ZeroCopyDatabase, Error> {
+        let staging = StagingFile(Path::new(STAGING_DIR).join(format!(
+            "reprozip-{}-{}.sqlite",
+            std::process::id(),
+            STAGED.fetch_add(1, Ordering::Relaxed),
+        )));
+        debug!(logger, "Staging database";
+               "path" => %staging.0.display());
+        let connection = Connection::open(&staging.0)?;
+        let inner = Database::with_connection(connection, path.as_ref(),
+                                              &SchemaV2, logger)?;
+        Ok(ZeroCopyDatabase { inner, staging })
+    }
+}
+
+/// Write the whole content of `source` to `target`, going through a pipe
+/// since `splice()` needs one on either side.
+fn splice_all(source: &File, target: &File) -> Result<(), Error> {
+    let mut pipe = [0; 2];
+    Errno::result(unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC) })
+        .map_err(|e| Error::Internal(format!("pipe: {}", e)))?;
+    let (pipe_read, pipe_write) = unsafe {
+        (File::from_raw_fd(pipe[0]), File::from_raw_fd(pipe[1]))
+    };
+    let mut offset: libc::loff_t = 0;
+    loop {
+        let read = unsafe {
+            libc::splice(source.as_raw_fd(), &mut offset,
+                         pipe_write.as_raw_fd(), ptr::null_mut(),
+                         SPLICE_SIZE, libc::SPLICE_F_MOVE)
+        };
+        let mut remaining = Errno::result(read).map_err(|e| {
+            Error::Internal(format!("splice: {}", e))
+        })?;
+        if remaining == 0 {
+            return Ok(());
+        }
+        while remaining > 0 {
+            let written = unsafe {
+                libc::splice(pipe_read.as_raw_fd(), ptr::null_mut(),
+                             target.as_raw_fd(), ptr::null_mut(),
+                             remaining as usize, libc::SPLICE_F_MOVE)
+            };
+            remaining -= Errno::result(written).map_err(|e| {
+                Error::Internal(format!("splice: {}", e))
+            })?;
+        }
+    }
+}
+
+impl DatabaseOps for ZeroCopyDatabase {
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn add_process(
+        &mut self,
+        parent: Option<ProcessId>,
+        working_dir: &Path,
+        is_thread: bool,
+    ) -> Result<ProcessId, Error> {
+        self.inner.add_process(parent, working_dir, is_thread)
+    }
+
+    fn add_file_open(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        mode: FileOp,
+        is_directory: bool,
+    ) -> Result<(), Error> {
+        self.inner.add_file_open(id, path, mode, is_directory)
+    }
+
+    fn set_access_pattern(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        pattern: &str,
+    ) -> Result<(), Error> {
+        self.inner.set_access_pattern(id, path, pattern)
+    }
+
+    fn add_exec(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        argv: &[Vec<u8>],
+        envp: &[Vec<u8>],
+        working_dir: &Path,
+        requires_setuid: bool,
+    ) -> Result<(), Error> {
+        self.inner.add_exec(id, path, argv, envp, working_dir,
+                            requires_setuid)
+    }
+
+    fn process_exit(
+        &mut self,
+        id: ProcessId,
+        status: ExitStatus,
+    ) -> Result<(), Error> {
+        self.inner.process_exit(id, status)
+    }
+
+    fn add_syscall(&mut self, number: u32) -> Result<(), Error> {
+        self.inner.add_syscall(number)
+    }
+
+    fn set_flag(&mut self, name: &str, value: bool) -> Result<(), Error> {
+        self.inner.set_flag(name, value)
+    }
+
+    fn add_timerfd_create(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        clockid: i32,
+        flags: i32,
+    ) -> Result<(), Error> {
+        self.inner.add_timerfd_create(id, timer_fd, clockid, flags)
+    }
+
+    fn add_timerfd_settime(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        flags: i32,
+        interval_ns: i64,
+        value_ns: i64,
+    ) -> Result<(), Error> {
+        self.inner.add_timerfd_settime(id, timer_fd, flags, interval_ns,
+                                       value_ns)
+    }
+
+    fn add_timer_query(
+        &mut self,
+        id: ProcessId,
+        timer_fd: i32,
+        remaining_ns: i64,
+    ) -> Result<(), Error> {
+        self.inner.add_timer_query(id, timer_fd, remaining_ns)
+    }
+
+    fn add_sleep(
+        &mut self,
+        id: ProcessId,
+        duration_ns: u64,
+        clockid: Option<i32>,
+    ) -> Result<(), Error> {
+        self.inner.add_sleep(id, duration_ns, clockid)
+    }
+
+    fn add_poll_event(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        timeout_ns: Option<u64>,
+        duration_ns: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_poll_event(id, path, timeout_ns, duration_ns)
+    }
+
+    fn add_fd_transfer(
+        &mut self,
+        sender: Option<(ProcessId, i32)>,
+        receiver: ProcessId,
+        receiver_fd: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.inner.add_fd_transfer(sender, receiver, receiver_fd, path)
+    }
+
+    fn add_copy_range(
+        &mut self,
+        id: ProcessId,
+        src_path: &Path,
+        dst_path: &Path,
+        bytes: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_copy_range(id, src_path, dst_path, bytes)
+    }
+
+    fn set_pids(
+        &mut self,
+        id: ProcessId,
+        host_pid: i32,
+        namespace_pid: i32,
+    ) -> Result<(), Error> {
+        self.inner.set_pids(id, host_pid, namespace_pid)
+    }
+
+    fn add_pid_namespace(
+        &mut self,
+        id: ProcessId,
+        namespace: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_pid_namespace(id, namespace)
+    }
+
+    fn add_process_namespace(
+        &mut self,
+        id: ProcessId,
+        ns_type: &str,
+        inode: u64,
+        entered_by: Option<&str>,
+    ) -> Result<(), Error> {
+        self.inner.add_process_namespace(id, ns_type, inode, entered_by)
+    }
+
+    fn add_signal_handler(
+        &mut self,
+        id: ProcessId,
+        signum: i32,
+        handler: u64,
+        flags: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_signal_handler(id, signum, handler, flags)
+    }
+
+    fn set_signal_context(
+        &mut self,
+        id: ProcessId,
+        signum: Option<i32>,
+    ) -> Result<(), Error> {
+        self.inner.set_signal_context(id, signum)
+    }
+
+    fn set_cpu_time(
+        &mut self,
+        id: ProcessId,
+        cpu_ns: u64,
+    ) -> Result<(), Error> {
+        self.inner.set_cpu_time(id, cpu_ns)
+    }
+
+    fn add_landlock_event(
+        &mut self,
+        id: ProcessId,
+        ruleset: (ProcessId, i32),
+        event: &str,
+        access: Option<u64>,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.inner.add_landlock_event(id, ruleset, event, access, path)
+    }
+
+    fn add_bpf_operation(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        bpf_type: u32,
+    ) -> Result<(), Error> {
+        self.inner.add_bpf_operation(id, command, bpf_type)
+    }
+
+    fn add_cache_stat(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        stat: &CacheStat,
+    ) -> Result<(), Error> {
+        self.inner.add_cache_stat(id, path, stat)
+    }
+
+    fn add_page_fault_event(
+        &mut self,
+        id: ProcessId,
+        address: u64,
+        flags: u64,
+        thread_id: Option<u32>,
+    ) -> Result<(), Error> {
+        self.inner.add_page_fault_event(id, address, flags, thread_id)
+    }
+
+    fn add_cross_process_memory_access(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        is_write: bool,
+        address: u64,
+        length: u64,
+    ) -> Result<(), Error> {
+        self.inner.add_cross_process_memory_access(id, target_pid, is_write,
+                                                   address, length)
+    }
+
+    fn set_wall_time_ns(&mut self, duration: u64) -> Result<(), Error> {
+        self.inner.set_wall_time_ns(duration)
+    }
+
+    fn set_time_override(
+        &mut self,
+        time: &TimeOverride,
+    ) -> Result<(), Error> {
+        self.inner.set_time_override(time)
+    }
+
+    fn add_cpu_affinity(
+        &mut self,
+        id: ProcessId,
+        target_pid: i32,
+        mask: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_cpu_affinity(id, target_pid, mask)
+    }
+
+    fn add_jit_region(
+        &mut self,
+        id: ProcessId,
+        start: u64,
+        end: u64,
+        prot: i32,
+        path: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.inner.add_jit_region(id, start, end, prot, path)
+    }
+
+    fn add_quota_check(
+        &mut self,
+        id: ProcessId,
+        command: i32,
+        quota_type: i32,
+        device: Option<&Path>,
+        quota_id: u32,
+    ) -> Result<(), Error> {
+        self.inner.add_quota_check(id, command, quota_type, device,
+                                   quota_id)
+    }
+
+    fn add_xattr_read(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_xattr_read(id, path, name)
+    }
+
+    fn add_xattr_write(
+        &mut self,
+        id: ProcessId,
+        path: &Path,
+        name: &[u8],
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_xattr_write(id, path, name, value)
+    }
+
+    fn add_file_hash(
+        &mut self,
+        path: &Path,
+        hash: [u8; 32],
+    ) -> Result<(), Error> {
+        self.inner.add_file_hash(path, hash)
+    }
+
+    fn add_file_label(
+        &mut self,
+        path: &Path,
+        label: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_file_label(path, label)
+    }
+
+    fn add_mount_point(
+        &mut self,
+        device: &str,
+        mount_point: &Path,
+        fs_type: &str,
+        options: &str,
+    ) -> Result<(), Error> {
+        self.inner.add_mount_point(device, mount_point, fs_type, options)
+    }
+
+    fn add_process_output(
+        &mut self,
+        id: ProcessId,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<(), Error> {
+        self.inner.add_process_output(id, stdout, stderr)
+    }
+
+    fn commit(self) -> Result<(), Error> {
+        let ZeroCopyDatabase { inner, staging } = self;
+        let path = inner.path().to_owned();
+        inner.commit()?;
+        let source = File::open(&staging.0)?;
+        let target = File::create(&path)?;
+        splice_all(&source, &target)?;
+        target.sync_all()?;
+        Ok(())
+    }
+}
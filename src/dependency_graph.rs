@@ -0,0 +1,174 @@
+//! Which processes depended on the files written by which others.
+//!
+//! Processes and files form a bipartite graph: a process depends on the
+//! files it reads, and a file depends on the processes that write it. In a
+//! build, following these edges from the final output shows what it was
+//! made from and which steps had to run one after the other.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::Error;
+use crate::database::{Database, FileOp, ProcessId};
+
+/// An edge of the graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edge {
+    /// A process read a file
+    Read(ProcessId, PathBuf),
+    /// A file was written by a process
+    Write(PathBuf, ProcessId),
+}
+
+/// A step of a chain being built, linked to the step before it.
+struct Step {
+    process: ProcessId,
+    path: PathBuf,
+    previous: Option<Rc<Step>>,
+    length: usize,
+}
+
+/// The files read and written by each process, in the order the accesses
+/// were first made.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    edges: Vec<Edge>,
+}
+
+impl DependencyGraph {
+    /// Build the graph from the file accesses of a trace.
+    ///
+    /// Directories and accesses to metadata only are not dependencies. A
+    /// file opened for both reading and writing gives a read then a write.
+    pub fn from_database(db: &Database) -> Result<DependencyGraph, Error> {
+        let mut edges = Vec::new();
+        for open in db.file_opens()? {
+            if open.is_directory {
+                continue;
+            }
+            if open.mode.contains(FileOp::READ) {
+                edges.push(Edge::Read(open.process, open.path.clone()));
+            }
+            if open.mode.contains(FileOp::WRITE) {
+                edges.push(Edge::Write(open.path, open.process));
+            }
+        }
+        Ok(DependencyGraph { edges })
+    }
+
+    /// All the edges, in order.
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// The processes that read a file.
+    pub fn readers<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = ProcessId> + 'a {
+        self.edges.iter().filter_map(move |edge| match edge {
+            Edge::Read(process, p) if p == path => Some(*process),
+            _ => None,
+        })
+    }
+
+    /// The processes that wrote a file.
+    pub fn writers<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = ProcessId> + 'a {
+        self.edges.iter().filter_map(move |edge| match edge {
+            Edge::Write(p, process) if p == path => Some(*process),
+            _ => None,
+        })
+    }
+
+    /// Find the longest chain of processes each reading a file written by
+    /// the one before.
+    ///
+    /// Each entry is a process and the file it wrote, which the process of
+    /// the next entry read. A file only links two processes if it was
+    /// written before being read, so that the chain follows the order in
+    /// which things happened and can't loop.
+    pub fn critical_path(&self) -> Vec<(ProcessId, PathBuf)> {
+        // Longest chain ending with a write of each file, and ending with
+        // each process having read the file written by the last step
+        let mut written: HashMap<&Path, Rc<Step>> = HashMap::new();
+        let mut read: HashMap<ProcessId, Rc<Step>> = HashMap::new();
+        let mut longest: Option<Rc<Step>> = None;
+        for edge in &self.edges {
+            match edge {
+                Edge::Read(process, path) => {
+                    let step = match written.get(path.as_path()) {
+                        Some(step) if step.process != *process => step,
+                        _ => continue,
+                    };
+                    let better = read.get(process)
+                        .is_none_or(|best| best.length < step.length);
+                    if better {
+                        read.insert(*process, step.clone());
+                    }
+                }
+                Edge::Write(path, process) => {
+                    let previous = read.get(process).cloned();
+                    let step = Rc::new(Step {
+                        process: *process,
+                        path: path.clone(),
+                        length: previous.as_ref().map_or(0, |s| s.length) + 1,
+                        previous,
+                    });
+                    let better = written.get(path.as_path())
+                        .is_none_or(|best| best.length < step.length);
+                    if better {
+                        written.insert(path, step.clone());
+                    }
+                    if longest.as_ref()
+                        .is_none_or(|best| best.length < step.length)
+                    {
+                        longest = Some(step);
+                    }
+                }
+            }
+        }
+        let mut chain = Vec::new();
+        let mut step = longest;
+        while let Some(s) = step {
+            chain.push((s.process, s.path.clone()));
+            step = s.previous.clone();
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::database::ProcessId;
+    use super::{DependencyGraph, Edge};
+
+    #[test]
+    fn test_critical_path() {
+        let (compiler, linker) = (ProcessId::from_number(1),
+                                  ProcessId::from_number(2));
+        let path = |p: &str| PathBuf::from(p);
+        let graph = DependencyGraph {
+            edges: vec![
+                // Read before it is written, so not a dependency
+                Edge::Read(compiler, path("/build/app")),
+                Edge::Read(compiler, path("/src/main.c")),
+                Edge::Write(path("/build/main.o"), compiler),
+                Edge::Read(linker, path("/build/main.o")),
+                Edge::Write(path("/build/app"), linker),
+            ],
+        };
+        assert_eq!(graph.critical_path(), vec![
+            (compiler, path("/build/main.o")),
+            (linker, path("/build/app")),
+        ]);
+        assert_eq!(graph.writers(&path("/build/main.o")).collect::<Vec<_>>(),
+                   vec![compiler]);
+    }
+}
@@ -0,0 +1,455 @@
+//! Recording what the tracer gets from the kernel, to replay a trace later
+//! without the traced program.
+//!
+//! With `TraceConfig::diagnostic_recording`, every stop reported by
+//! `waitpid()`, and every register set, memory read and event message
+//! fetched with ptrace, is written to a file. `Tracer::replay_diagnostic()`
+//! then feeds them back in the same order, so the same handlers run on the
+//! same data, which makes a bug from a user's trace reproducible without
+//! their workload.
+//!
+//! What the tracer reads from `/proc` is not recorded; on replay it comes
+//! from whatever process has that pid then, if any.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::ptrace_backend::{NixPtraceBackend, PtraceBackend};
+use crate::syscalls::{Registers, UserRegs};
+
+/// The first bytes of a recording, with its format version.
+const MAGIC: &[u8; 8] = b"RPZDIAG\x01";
+
+#[cfg(target_arch = "x86_64")]
+const ARCH: &[u8] = b"x86_64";
+#[cfg(target_arch = "aarch64")]
+const ARCH: &[u8] = b"aarch64";
+#[cfg(target_arch = "arm")]
+const ARCH: &[u8] = b"arm";
+
+/// The kinds of records, each the result of one call.
+const WAIT: u8 = 1;
+const GETREGS: u8 = 2;
+const READ: u8 = 3;
+const PEEKDATA: u8 = 4;
+const GETEVENT: u8 = 5;
+const GETSIGINFO: u8 = 6;
+
+/// How the first process of a recorded trace was started.
+pub struct RecordingHeader {
+    pub first_proc: Pid,
+    pub working_dir: PathBuf,
+    /// Whether it was attached with `PTRACE_SEIZE`, and so had no first
+    /// stop
+    pub seized: bool,
+}
+
+enum Mode {
+    Live,
+    Recording(RefCell<BufWriter<File>>),
+    Replaying(RefCell<BufReader<File>>),
+}
+
+/// The ptrace backend of the tracer, recording the results of the calls or
+/// replaying them from a recording.
+///
+/// When replaying, calls that change the tracees do nothing.
+pub struct DiagnosticBackend {
+    live: NixPtraceBackend,
+    mode: Mode,
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    writer.write_all(&(bytes.len() as u32).to_ne_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_array<R: Read, const N: usize>(
+    reader: &mut R,
+) -> Result<[u8; N], Error> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            Error::Internal("Diagnostic recording ended before the trace \
+                             did".into())
+        } else {
+            e.into()
+        }
+    })?;
+    Ok(buf)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let len = u32::from_ne_bytes(read_array(reader)?);
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn to_word(data: &[u8]) -> Result<u64, Error> {
+    let mut word = [0u8; 8];
+    if data.len() != word.len() {
+        return Err(Error::Internal("Invalid word in diagnostic \
+                                    recording".into()));
+    }
+    word.copy_from_slice(data);
+    Ok(u64::from_ne_bytes(word))
+}
+
+fn struct_bytes<T>(value: &T) -> Vec<u8> {
+    unsafe {
+        slice::from_raw_parts(value as *const T as *const u8,
+                              mem::size_of::<T>())
+    }.to_owned()
+}
+
+fn regs_to_bytes(regs: &Registers) -> Vec<u8> {
+    match regs {
+        #[cfg(target_arch = "x86_64")]
+        Registers::X86_64(regs) => struct_bytes(regs),
+        #[cfg(target_arch = "aarch64")]
+        Registers::Aarch64(regs) => struct_bytes(regs),
+        #[cfg(target_arch = "arm")]
+        Registers::Arm(regs) => struct_bytes(regs),
+    }
+}
+
+fn regs_from_bytes(bytes: &[u8]) -> Result<Registers, Error> {
+    let mut regs: UserRegs = unsafe { mem::zeroed() };
+    if bytes.len() != mem::size_of_val(&regs) {
+        return Err(Error::Internal("Invalid registers in diagnostic \
+                                    recording".into()));
+    }
+    unsafe {
+        slice::from_raw_parts_mut(&mut regs as *mut _ as *mut u8,
+                                  bytes.len())
+    }.copy_from_slice(bytes);
+    #[cfg(target_arch = "x86_64")]
+    return Ok(Registers::X86_64(regs));
+    #[cfg(target_arch = "aarch64")]
+    return Ok(Registers::Aarch64(regs));
+    #[cfg(target_arch = "arm")]
+    return Ok(Registers::Arm(regs));
+}
+
+impl DiagnosticBackend {
+    pub fn new() -> DiagnosticBackend {
+        DiagnosticBackend { live: NixPtraceBackend, mode: Mode::Live }
+    }
+
+    /// Start recording to a file.
+    pub fn record(
+        &mut self,
+        path: &Path,
+        header: &RecordingHeader,
+    ) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        write_bytes(&mut file, ARCH)?;
+        file.write_all(&p(header.first_proc).to_ne_bytes())?;
+        write_bytes(&mut file, header.working_dir.as_os_str().as_bytes())?;
+        file.write_all(&[header.seized as u8])?;
+        self.mode = Mode::Recording(RefCell::new(file));
+        Ok(())
+    }
+
+    /// Start replaying a recording, returning how the trace started.
+    pub fn replay(&mut self, path: &Path) -> Result<RecordingHeader, Error> {
+        let mut file = BufReader::new(File::open(path)?);
+        if &read_array::<_, 8>(&mut file)? != MAGIC {
+            return Err(Error::Internal(format!(
+                "{} is not a diagnostic recording", path.display(),
+            )));
+        }
+        let arch = read_bytes(&mut file)?;
+        if arch != ARCH {
+            return Err(Error::Internal(format!(
+                "Diagnostic recording is for {}",
+                String::from_utf8_lossy(&arch),
+            )));
+        }
+        let first_proc = Pid::from_raw(i32::from_ne_bytes(
+            read_array(&mut file)?,
+        ));
+        let working_dir = PathBuf::from(std::ffi::OsString::from_vec(
+            read_bytes(&mut file)?,
+        ));
+        let seized = read_array::<_, 1>(&mut file)?[0] != 0;
+        self.mode = Mode::Replaying(RefCell::new(file));
+        Ok(RecordingHeader { first_proc, working_dir, seized })
+    }
+
+    /// Write out what was recorded so far.
+    pub fn flush(&self) -> Result<(), Error> {
+        if let Mode::Recording(file) = &self.mode {
+            file.borrow_mut().flush()?;
+        }
+        Ok(())
+    }
+
+    fn replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replaying(_))
+    }
+
+    /// Make a call, recording its result or getting it from the recording.
+    ///
+    /// Failures are recorded with their message, and replayed as internal
+    /// errors.
+    fn call<F>(
+        &self,
+        kind: u8,
+        pid: Pid,
+        addr: u64,
+        live: F,
+    ) -> Result<Vec<u8>, Error>
+    where
+        F: FnOnce() -> Result<Vec<u8>, Error>,
+    {
+        match &self.mode {
+            Mode::Live => live(),
+            Mode::Recording(file) => {
+                let result = live();
+                let mut file = file.borrow_mut();
+                file.write_all(&[kind])?;
+                file.write_all(&p(pid).to_ne_bytes())?;
+                file.write_all(&addr.to_ne_bytes())?;
+                match &result {
+                    Ok(data) => {
+                        file.write_all(&[1])?;
+                        write_bytes(&mut *file, data)?;
+                    }
+                    Err(e) => {
+                        file.write_all(&[0])?;
+                        write_bytes(&mut *file, e.to_string().as_bytes())?;
+                    }
+                }
+                result
+            }
+            Mode::Replaying(file) => {
+                let mut file = file.borrow_mut();
+                let [rec_kind] = read_array(&mut *file)?;
+                let rec_pid = i32::from_ne_bytes(read_array(&mut *file)?);
+                let rec_addr = u64::from_ne_bytes(read_array(&mut *file)?);
+                // The pid isn't known before waitpid() returns
+                if rec_kind != kind
+                    || (kind != WAIT && rec_pid != p(pid))
+                    || rec_addr != addr
+                {
+                    return Err(Error::Internal(format!(
+                        "Diagnostic replay diverged: expected call {} for \
+                         {} at {:#x}, recorded call {} for {} at {:#x}",
+                        kind, p(pid), addr, rec_kind, rec_pid, rec_addr,
+                    )));
+                }
+                let [ok] = read_array(&mut *file)?;
+                let data = read_bytes(&mut *file)?;
+                if ok != 0 {
+                    Ok(data)
+                } else {
+                    Err(Error::Internal(
+                        String::from_utf8_lossy(&data).into_owned(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Wait for the next stop of any traced thread.
+    pub fn waitpid(&self) -> Result<WaitStatus, Error> {
+        let data = self.call(WAIT, Pid::from_raw(-1), 0, || {
+            let mut status = 0;
+            let pid = Errno::result(unsafe {
+                libc::waitpid(-1, &mut status, libc::__WALL)
+            })?;
+            let mut data = pid.to_ne_bytes().to_vec();
+            data.extend_from_slice(&status.to_ne_bytes());
+            Ok(data)
+        })?;
+        if data.len() != 8 {
+            return Err(Error::Internal("Invalid wait status in diagnostic \
+                                        recording".into()));
+        }
+        let pid = i32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+        let status = i32::from_ne_bytes([data[4], data[5], data[6], data[7]]);
+        Ok(WaitStatus::from_raw(Pid::from_raw(pid), status)?)
+    }
+
+    /// Get the message of the last `PTRACE_EVENT_*` stop.
+    pub fn getevent(&self, pid: Pid) -> Result<u64, Error> {
+        let data = self.call(GETEVENT, pid, 0, || {
+            Ok((ptrace::getevent(pid)? as u64).to_ne_bytes().to_vec())
+        })?;
+        to_word(&data)
+    }
+
+    /// Whether a stop is a signal-delivery-stop, which has a siginfo, rather
+    /// than a group-stop.
+    pub fn has_siginfo(&self, pid: Pid) -> bool {
+        self.call(GETSIGINFO, pid, 0, || {
+            ptrace::getsiginfo(pid)?;
+            Ok(Vec::new())
+        }).is_ok()
+    }
+
+    /// Set the ptrace options of a thread.
+    pub fn setoptions(
+        &self,
+        pid: Pid,
+        options: ptrace::Options,
+    ) -> Result<(), Error> {
+        if !self.replaying() {
+            ptrace::setoptions(pid, options)?;
+        }
+        Ok(())
+    }
+}
+
+impl PtraceBackend for DiagnosticBackend {
+    fn read(&self, pid: Pid, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        if let Mode::Live = self.mode {
+            return self.live.read(pid, addr, buf);
+        }
+        let data = self.call(READ, pid, addr, || {
+            let mut data = vec![0u8; buf.len()];
+            self.live.read(pid, addr, &mut data)?;
+            Ok(data)
+        })?;
+        if data.len() != buf.len() {
+            return Err(Error::Internal(
+                "Diagnostic replay diverged: different read size".into(),
+            ));
+        }
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn write(&self, pid: Pid, addr: u64, buf: &[u8]) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.write(pid, addr, buf)
+    }
+
+    fn peekdata(&self, pid: Pid, addr: u64) -> Result<u64, Error> {
+        let data = self.call(PEEKDATA, pid, addr, || {
+            Ok(self.live.peekdata(pid, addr)?.to_ne_bytes().to_vec())
+        })?;
+        to_word(&data)
+    }
+
+    fn getregs(&self, pid: Pid) -> Result<Registers, Error> {
+        if let Mode::Live = self.mode {
+            return self.live.getregs(pid);
+        }
+        let data = self.call(GETREGS, pid, 0, || {
+            Ok(regs_to_bytes(&self.live.getregs(pid)?))
+        })?;
+        regs_from_bytes(&data)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pokeuser(
+        &self,
+        pid: Pid,
+        offset: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.pokeuser(pid, offset, value)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn setregset(
+        &self,
+        pid: Pid,
+        kind: libc::c_int,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.setregset(pid, kind, data)
+    }
+
+    #[cfg(target_arch = "arm")]
+    fn set_syscall_number(&self, pid: Pid, number: i32) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.set_syscall_number(pid, number)
+    }
+
+    fn syscall(&self, pid: Pid) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.syscall(pid)
+    }
+
+    fn syscall_signal(&self, pid: Pid, signal: Signal) -> Result<(), Error> {
+        if self.replaying() {
+            return Ok(());
+        }
+        self.live.syscall_signal(pid, signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::path::PathBuf;
+
+    use nix::unistd::Pid;
+
+    use crate::ptrace_backend::PtraceBackend;
+    use super::{DiagnosticBackend, RecordingHeader};
+
+    #[test]
+    fn test_record_replay() {
+        let path = temp_dir().join(format!(
+            "reprozip-test-diagnostic-{}", std::process::id(),
+        ));
+        // Our own memory can be read without ptrace
+        let pid = Pid::this();
+        let data = *b"recorded";
+        let addr = data.as_ptr() as u64;
+        let mut backend = DiagnosticBackend::new();
+        backend.record(&path, &RecordingHeader {
+            first_proc: pid,
+            working_dir: PathBuf::from("/work"),
+            seized: true,
+        }).unwrap();
+        let mut buf = [0u8; 8];
+        backend.read(pid, addr, &mut buf).unwrap();
+        assert!(backend.read(pid, 0, &mut buf).is_err());
+        backend.flush().unwrap();
+
+        let mut backend = DiagnosticBackend::new();
+        let header = backend.replay(&path).unwrap();
+        assert_eq!(header.first_proc, pid);
+        assert_eq!(header.working_dir, PathBuf::from("/work"));
+        assert!(header.seized);
+        let mut buf = [0u8; 8];
+        backend.read(pid, addr, &mut buf).unwrap();
+        assert_eq!(&buf, b"recorded");
+        // Not the recorded call
+        assert!(backend.peekdata(pid, 0).is_err());
+        remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+//! Download of packs from object storage.
+//!
+//! This is the other side of `upload`: the pack is checked against the
+//! `.sha256` file uploaded next to it, and an interrupted download is resumed
+//! from where it stopped, using a range request. The ETag of the file is
+//! kept next to the partial download, so it is only resumed if the file
+//! didn't change since. The trace in the pack is then checked for
+//! corruption.
+
+use std::fs::{
+    File, OpenOptions, read_to_string, remove_file, rename, write,
+};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use futures::StreamExt;
+use object_store::{GetOptions, GetRange, ObjectStore};
+use object_store::path::Path as ObjectPath;
+use sha2::{Digest, Sha256};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::Error;
+use crate::archive::extract_database;
+use crate::database::integrity_check;
+use crate::upload::{hex, open_store, parse_url, sidecar_path, store_error};
+
+/// The first bytes of an SQLite database file.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// A finished download.
+#[derive(Debug)]
+pub struct Download {
+    pub path: PathBuf,
+    pub size: u64,
+    /// How much had already been downloaded by a previous attempt
+    pub resumed_from: u64,
+    pub sha256: [u8; 32],
+    /// Whether there was a checksum to compare with
+    pub verified: bool,
+    /// Whether the trace was checked for corruption, which needs the file to
+    /// be a pack or a trace database
+    pub integrity_checked: bool,
+}
+
+/// Downloads a file from object storage.
+pub struct Downloader {
+    store: Box<dyn ObjectStore>,
+    url: Url,
+    /// The path of `url` in the store
+    path: ObjectPath,
+    runtime: Runtime,
+}
+
+impl Downloader {
+    pub fn new(url: &str) -> Result<Downloader, Error> {
+        let url = parse_url(url)?;
+        let (store, path) = open_store(&url)?;
+        let runtime = Runtime::new()?;
+        Ok(Downloader { store, url, path, runtime })
+    }
+
+    /// The name of the downloaded file, the last component of the URL.
+    pub fn file_name(&self) -> Result<String, Error> {
+        match self.path.filename() {
+            Some(name) => Ok(name.to_owned()),
+            None => Err(Error::Internal(format!(
+                "No file name in URL {}", self.url,
+            ))),
+        }
+    }
+
+    /// Get the checksum uploaded next to the file, if any.
+    fn expected_hash(&self) -> Result<Option<[u8; 32]>, Error> {
+        let sidecar = self.runtime.block_on(async {
+            self.store.get(&sidecar_path(&self.path)).await?.bytes().await
+        });
+        let sidecar = match sidecar {
+            Ok(sidecar) => sidecar,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(store_error(e)),
+        };
+        // Same format as sha256sum, the hash then the file name
+        let hash = std::str::from_utf8(&sidecar).ok()
+            .and_then(|s| s.split_whitespace().next())
+            .filter(|hash| hash.len() == 64)
+            .and_then(|hash| {
+                let mut bytes = [0u8; 32];
+                for (i, byte) in bytes.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16)
+                        .ok()?;
+                }
+                Some(bytes)
+            });
+        match hash {
+            Some(hash) => Ok(Some(hash)),
+            None => Err(Error::Internal(format!(
+                "Invalid checksum file for {}", self.url,
+            ))),
+        }
+    }
+
+    /// Download the file into a directory.
+    ///
+    /// The data goes to `<name>.part` first, which is renamed once complete
+    /// and verified. If that file exists, the download continues after it,
+    /// provided the ETag in `<name>.part.etag` shows the file is the same.
+    pub fn download(&self, directory: &Path) -> Result<Download, Error> {
+        let name = self.file_name()?;
+        let path = directory.join(&name);
+        let partial = directory.join(format!("{}.part", name));
+        let etag_path = directory.join(format!("{}.part.etag", name));
+        let expected = self.expected_hash()?;
+
+        let meta = self.runtime.block_on(self.store.head(&self.path))
+            .map_err(store_error)?;
+        let size = meta.size;
+        // Without an ETag, we can't know the partial file is from the same
+        // version, so start over
+        let same_version = match (&meta.e_tag, read_to_string(&etag_path)) {
+            (Some(etag), Ok(previous)) => *etag == previous,
+            _ => false,
+        };
+        let mut resumed_from = match partial.metadata() {
+            Ok(metadata) if same_version && metadata.len() < size => {
+                metadata.len()
+            }
+            _ => 0,
+        };
+        if resumed_from == 0 {
+            match &meta.e_tag {
+                Some(etag) => write(&etag_path, etag)?,
+                None => remove_if_exists(&etag_path)?,
+            }
+        }
+
+        // Hash what we already have, then download the rest
+        let mut hasher = Sha256::new();
+        if resumed_from > 0 {
+            let mut file = File::open(&partial)?;
+            let mut buf = vec![0; 65536];
+            loop {
+                match file.read(&mut buf)? {
+                    0 => break,
+                    n => hasher.update(&buf[..n]),
+                }
+            }
+        }
+        let mut output = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(resumed_from > 0)
+            .truncate(resumed_from == 0)
+            .open(&partial)?;
+        if size > 0 {
+            let options = GetOptions {
+                range: match resumed_from {
+                    0 => None,
+                    offset => Some(GetRange::Offset(offset)),
+                },
+                // Fail if the file changed since we got its size
+                if_match: meta.e_tag.clone(),
+                ..Default::default()
+            };
+            let result = self.runtime.block_on(async {
+                let mut stream = self.store.get_opts(&self.path, options)
+                    .await?
+                    .into_stream();
+                while let Some(bytes) = stream.next().await {
+                    let bytes = bytes?;
+                    hasher.update(&bytes);
+                    output.write_all(&bytes).map_err(|e| {
+                        object_store::Error::Generic {
+                            store: "local",
+                            source: Box::new(e),
+                        }
+                    })?;
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => {}
+                Err(object_store::Error::Precondition { .. }) => {
+                    drop(output);
+                    remove_file(&partial)?;
+                    remove_if_exists(&etag_path)?;
+                    return Err(Error::Internal(format!(
+                        "{} changed during the download, try again",
+                        self.url,
+                    )));
+                }
+                // Keep the partial file, to resume from it next time
+                Err(e) => return Err(store_error(e)),
+            }
+        } else {
+            resumed_from = 0;
+        }
+        output.sync_all()?;
+        drop(output);
+
+        let sha256 = <[u8; 32]>::from(hasher.finalize());
+        if let Some(expected) = expected {
+            if sha256 != expected {
+                remove_file(&partial)?;
+                remove_if_exists(&etag_path)?;
+                return Err(Error::Internal(format!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    self.url, hex(&expected), hex(&sha256),
+                )));
+            }
+        }
+
+        // Check the trace, extracted from the pack unless a database was
+        // downloaded directly
+        let problems = if is_sqlite(&partial)? {
+            Some(integrity_check(&partial)?)
+        } else {
+            let trace = directory.join(format!("{}.trace.tmp", name));
+            let problems = match extract_database(&partial, &trace) {
+                Ok(true) => integrity_check(&trace).map(Some),
+                Ok(false) => Ok(None),
+                Err(e) => Err(e),
+            };
+            remove_if_exists(&trace)?;
+            problems?
+        };
+        if let Some(problems) = &problems {
+            if !problems.is_empty() {
+                remove_file(&partial)?;
+                remove_if_exists(&etag_path)?;
+                return Err(Error::Internal(format!(
+                    "The downloaded trace is corrupt: {}",
+                    problems.join("; "),
+                )));
+            }
+        }
+        rename(&partial, &path)?;
+        remove_if_exists(&etag_path)?;
+        Ok(Download {
+            path,
+            size,
+            resumed_from,
+            sha256,
+            verified: expected.is_some(),
+            integrity_checked: problems.is_some(),
+        })
+    }
+}
+
+/// Remove a file, if it exists.
+fn remove_if_exists(path: &Path) -> Result<(), Error> {
+    match remove_file(path) {
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Whether a file is an SQLite database.
+fn is_sqlite(path: &Path) -> Result<bool, Error> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => Ok(&header == SQLITE_HEADER),
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
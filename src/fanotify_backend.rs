@@ -0,0 +1,310 @@
+//! Tracing backend using `fanotify` instead of ptrace.
+//!
+//! The kernel reports the files opened and closed on the root mount, which
+//! we attribute to the traced processes by walking up their parents in
+//! `/proc`. This is much faster than stopping on every syscall, but requires
+//! `CAP_SYS_ADMIN`, and only sees file accesses: executions, syscalls and
+//! the exit status of processes other than the first one are not recorded.
+
+use std::collections::HashMap;
+use std::env::current_dir;
+use std::ffi::CString;
+use std::fs::{File, read_link, read_to_string};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+use nix::unistd::{ForkResult, Pid, execvp, fork};
+use slog::Drain;
+
+use crate::{Error, ExitStatus, p};
+use crate::database::{Database, DatabaseOps, FileOp, ProcessId};
+
+/// Bit of `CAP_SYS_ADMIN` in the capability sets.
+const CAP_SYS_ADMIN: u32 = 21;
+
+/// How long to wait for events before checking if the program exited, in
+/// milliseconds.
+const POLL_TIMEOUT: i32 = 100;
+
+/// Check whether the current process has `CAP_SYS_ADMIN`.
+fn has_sys_admin() -> Result<bool, Error> {
+    let status = read_to_string("/proc/self/status")?;
+    for line in status.lines() {
+        if let Some(caps) = line.strip_prefix("CapEff:") {
+            let caps = u64::from_str_radix(caps.trim(), 16).map_err(|_| {
+                Error::Internal(format!("Invalid CapEff line: {}", line))
+            })?;
+            return Ok(caps & (1 << CAP_SYS_ADMIN) != 0);
+        }
+    }
+    Ok(false)
+}
+
+/// Get the parent of a process from `/proc`, if it still exists.
+fn parent_pid(pid: i32) -> Option<i32> {
+    let status = read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("PPid:"))
+        .and_then(|ppid| ppid.trim().parse().ok())
+}
+
+/// Tracer using `fanotify` events to find the files a program accesses.
+pub struct FanotifyTracer {
+    logger: slog::Logger,
+    database: Database,
+    fanotify: File,
+    /// The processes we know belong to the traced program
+    processes: HashMap<i32, ProcessId>,
+}
+
+impl FanotifyTracer {
+    pub fn new<D: AsRef<Path>>(database: D) -> Result<FanotifyTracer, Error> {
+        Self::with_logger(database, None)
+    }
+
+    /// Create the tracer, failing if we don't have `CAP_SYS_ADMIN`.
+    pub fn with_logger<D: AsRef<Path>, L: Into<Option<slog::Logger>>>(
+        database: D,
+        logger: L,
+    ) -> Result<FanotifyTracer, Error> {
+        let logger = logger
+            .into()
+            .unwrap_or(slog::Logger::root(slog_stdlog::StdLog.fuse(), o!()));
+        if !has_sys_admin()? {
+            return Err(Error::Internal(
+                "The fanotify backend requires CAP_SYS_ADMIN, run as root or \
+                 use the ptrace backend".into(),
+            ));
+        }
+        let fanotify = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC
+                    | libc::FAN_NONBLOCK,
+                (libc::O_RDONLY | libc::O_LARGEFILE) as libc::c_uint,
+            )
+        };
+        let fanotify = Errno::result(fanotify).map_err(|e| {
+            Error::Internal(format!("fanotify_init: {}", e))
+        })?;
+        let root = CString::new("/").unwrap();
+        let marked = unsafe {
+            libc::fanotify_mark(
+                fanotify,
+                libc::FAN_MARK_ADD | libc::FAN_MARK_MOUNT,
+                libc::FAN_OPEN | libc::FAN_CLOSE_WRITE
+                    | libc::FAN_CLOSE_NOWRITE,
+                libc::AT_FDCWD,
+                root.as_ptr(),
+            )
+        };
+        let fanotify = unsafe { File::from_raw_fd(fanotify) };
+        Errno::result(marked).map_err(|e| {
+            Error::Internal(format!("fanotify_mark: {}", e))
+        })?;
+        Ok(FanotifyTracer {
+            logger: logger.clone(),
+            database: Database::new(database, logger)?,
+            fanotify,
+            processes: HashMap::new(),
+        })
+    }
+
+    /// Run a command and trace it.
+    ///
+    /// Returns the exit status of the command and the identifier of its
+    /// process in the database, always `ProcessId::root()`.
+    pub fn trace<C: AsRef<[u8]>>(
+        self,
+        command: &[C],
+    ) -> Result<(ExitStatus, ProcessId), Error> {
+        self.trace_arg0(command, &command[0])
+    }
+
+    /// Run a command and trace it, replacing `argv[0]`.
+    pub fn trace_arg0<C: AsRef<[u8]>, C2: AsRef<[u8]>>(
+        mut self,
+        command: &[C],
+        arg0: C2,
+    ) -> Result<(ExitStatus, ProcessId), Error> {
+        let args = command
+            .iter()
+            .map(|c| CString::new(c.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::InvalidCommand)?;
+        let arg0 = CString::new(arg0.as_ref())
+            .map_err(|_| Error::InvalidCommand)?;
+        info!(self.logger, "Tracing command with fanotify: {:?}", args);
+
+        match fork() {
+            Ok(ForkResult::Parent { child }) => {
+                info!(self.logger, "Child created, pid={pid}", pid = p(child));
+                let wd = current_dir()?;
+                let identifier = self.database.add_process(None, &wd, false)?;
+                self.processes.insert(p(child), identifier);
+                self.database.add_file_open(identifier, &wd,
+                                            FileOp::WDIR, true)?;
+                let start = Instant::now();
+                let ret = self.watch(child)?;
+                self.database.set_wall_time_ns(
+                    start.elapsed().as_nanos() as u64,
+                )?;
+                self.database.process_exit(identifier, ret)?;
+                info!(self.logger, "Finished, first process exited";
+                      "status" => ?ret);
+                self.database.commit()?;
+                Ok((ret, identifier))
+            }
+            Ok(ForkResult::Child) => match execvp(&arg0, &args) {
+                Ok(_) => unreachable!(),
+                Err(err) => {
+                    eprintln!("Coundn't execute the target command: {}", err);
+                    std::process::exit(127);
+                }
+            },
+            Err(err) => Err(Error::Internal(format!("fork: {}", err))),
+        }
+    }
+
+    /// Read events until the first process exits.
+    fn watch(&mut self, child: Pid) -> Result<ExitStatus, Error> {
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd: self.fanotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, POLL_TIMEOUT) };
+            match Errno::result(ready) {
+                Ok(0) => {}
+                Ok(_) => self.read_events()?,
+                Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(e) => return Err(Error::Internal(format!("poll: {}", e))),
+            }
+            let status = match wait::waitpid(child,
+                                             Some(WaitPidFlag::WNOHANG))? {
+                WaitStatus::Exited(_, status) => ExitStatus::Return(status),
+                WaitStatus::Signaled(_, sig, _) => ExitStatus::Signal(sig),
+                _ => continue,
+            };
+            // Get the events generated while the program was exiting
+            self.read_events()?;
+            return Ok(status);
+        }
+    }
+
+    /// Read the available events and record them.
+    fn read_events(&mut self) -> Result<(), Error> {
+        let header = mem::size_of::<libc::fanotify_event_metadata>();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.fanotify.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            let len = match Errno::result(len) {
+                Ok(0) => return Ok(()),
+                Ok(len) => len as usize,
+                Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(()),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(Error::Internal(format!("read: {}", e))),
+            };
+            let mut pos = 0;
+            while pos + header <= len {
+                let event: libc::fanotify_event_metadata = unsafe {
+                    ptr::read_unaligned(buffer[pos..].as_ptr() as *const _)
+                };
+                if event.vers != libc::FANOTIFY_METADATA_VERSION {
+                    return Err(Error::Internal(
+                        "Unsupported fanotify metadata version".into(),
+                    ));
+                }
+                self.handle_event(&event)?;
+                pos += event.event_len as usize;
+            }
+        }
+    }
+
+    /// Record a single event, closing its file descriptor.
+    fn handle_event(
+        &mut self,
+        event: &libc::fanotify_event_metadata,
+    ) -> Result<(), Error> {
+        if event.mask & libc::FAN_Q_OVERFLOW != 0 {
+            warn!(self.logger, "fanotify queue overflowed, events were lost");
+        }
+        if event.fd < 0 {
+            return Ok(());
+        }
+        // Takes ownership of the descriptor, closing it when dropped
+        let file = unsafe { File::from_raw_fd(event.fd) };
+        let identifier = match self.find_process(event.pid) {
+            Some(identifier) => identifier,
+            None => return Ok(()),
+        };
+        let path = read_link(format!("/proc/self/fd/{}", event.fd))?;
+        let is_directory = file.metadata().map(|m| m.is_dir())
+            .unwrap_or(false);
+        let mut mode = FileOp::empty();
+        if event.mask & libc::FAN_CLOSE_WRITE != 0 {
+            mode |= FileOp::WRITE;
+        }
+        if event.mask & libc::FAN_CLOSE_NOWRITE != 0 {
+            mode |= FileOp::READ;
+        }
+        // Open events tell us which processes exist while /proc is still
+        // there, but not the mode, which we only learn on close
+        if !mode.is_empty() {
+            debug!(self.logger, "fanotify event {path}",
+                   path = path.display();
+                   "tid" => event.pid, "mode" => ?mode);
+            self.database.add_file_open(identifier, &path, mode,
+                                        is_directory)?;
+        }
+        Ok(())
+    }
+
+    /// Get the identifier of a traced process, if this is one.
+    ///
+    /// Processes are added when we first see an event from them, by looking
+    /// for a traced ancestor.
+    fn find_process(&mut self, pid: i32) -> Option<ProcessId> {
+        let mut chain = Vec::new();
+        let mut current = pid;
+        let mut identifier = loop {
+            if let Some(&identifier) = self.processes.get(&current) {
+                break identifier;
+            }
+            chain.push(current);
+            current = match parent_pid(current) {
+                Some(parent) if parent > 1 => parent,
+                _ => return None,
+            };
+        };
+        for &new in chain.iter().rev() {
+            let working_dir = read_link(format!("/proc/{}/cwd", new))
+                .unwrap_or_else(|_| PathBuf::from("/"));
+            identifier = match self.database.add_process(
+                Some(identifier), &working_dir, false,
+            ) {
+                Ok(identifier) => identifier,
+                Err(e) => {
+                    warn!(self.logger, "Can't add process: {}", e;
+                          "tid" => new);
+                    return None;
+                }
+            };
+            info!(self.logger, "Process {tid} appeared", tid = new);
+            self.processes.insert(new, identifier);
+        }
+        Some(identifier)
+    }
+}
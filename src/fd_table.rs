@@ -0,0 +1,209 @@
+//! Tracking of the file descriptors of a process.
+//!
+//! The files opened by the process are kept, so that syscalls taking a file
+//! descriptor can be related to the file. Signal, event and timer
+//! descriptors are kept too, to tell them from files. Pipes, sockets and
+//! descriptors inherited from the tracer are not known.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+use nix::unistd::Pid;
+
+use crate::database::ProcessId;
+
+/// Most descriptors kept in flight for the same file.
+///
+/// Those sent to processes we don't trace are never received, the oldest are
+/// dropped.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// What we know about a file descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FdEntry {
+    /// A file opened by path.
+    File { path: PathBuf },
+    /// An unnamed file created with `O_TMPFILE`, in the given directory.
+    ///
+    /// It is not a dependency unless it gets linked into the filesystem.
+    TempFile { directory: PathBuf },
+    /// A descriptor reading signals, from `signalfd()`.
+    SignalFd,
+    /// A counter used for notifications, from `eventfd()`, with its initial
+    /// value.
+    EventFd(u64),
+    /// A timer from `timerfd_create()`, read when it expires.
+    TimerFd,
+    /// A reference to a process, from `pidfd_open()`.
+    ///
+    /// The pid is as the program sees it, which is only the one we see if
+    /// it is in the same PID namespace as the tracer.
+    PidFd(Pid),
+    /// A Landlock ruleset, from `landlock_create_ruleset()`, identified by
+    /// the process that created it and the descriptor it got.
+    LandlockRuleset(ProcessId, i32),
+    /// A descriptor reporting page faults in some memory, from
+    /// `userfaultfd()`.
+    UserFaultFd,
+    /// A side of a pseudo-terminal, with the number of its `/dev/pts/`
+    /// device.
+    Pty { master: bool, number: u32 },
+}
+
+/// The file descriptors of a thread group.
+#[derive(Debug, Clone, Default)]
+pub struct FdTable {
+    entries: HashMap<i32, FdEntry>,
+    /// The descriptors opened with `O_CLOEXEC`
+    cloexec: HashSet<i32>,
+}
+
+impl FdTable {
+    pub fn insert(&mut self, fd: i32, entry: FdEntry, cloexec: bool) {
+        self.entries.insert(fd, entry);
+        if cloexec {
+            self.cloexec.insert(fd);
+        } else {
+            self.cloexec.remove(&fd);
+        }
+    }
+
+    /// Change what a file descriptor refers to, keeping its flags.
+    pub fn replace(&mut self, fd: i32, entry: FdEntry) {
+        self.entries.insert(fd, entry);
+    }
+
+    pub fn get(&self, fd: i32) -> Option<&FdEntry> {
+        self.entries.get(&fd)
+    }
+
+    /// Whether a known file descriptor is closed on `execve()`.
+    pub fn cloexec(&self, fd: i32) -> bool {
+        self.cloexec.contains(&fd)
+    }
+
+    /// Change the close-on-exec flag, with `fcntl(F_SETFD)`.
+    pub fn set_cloexec(&mut self, fd: i32, cloexec: bool) {
+        if !self.entries.contains_key(&fd) {
+            return;
+        }
+        if cloexec {
+            self.cloexec.insert(fd);
+        } else {
+            self.cloexec.remove(&fd);
+        }
+    }
+
+    /// Make `new` refer to the same file as `old`, like `dup2()` does.
+    ///
+    /// If `old` isn't known, `new` is forgotten, since it no longer refers
+    /// to what it did.
+    pub fn duplicate(&mut self, old: i32, new: i32, cloexec: bool) {
+        match self.entries.get(&old).cloned() {
+            Some(entry) => self.insert(new, entry, cloexec),
+            None => {
+                self.remove(new);
+            }
+        }
+    }
+
+    /// Forget a file descriptor, after it is closed.
+    pub fn remove(&mut self, fd: i32) -> Option<FdEntry> {
+        self.cloexec.remove(&fd);
+        self.entries.remove(&fd)
+    }
+
+    /// Forget the descriptors from `first` to `last` included, or mark them
+    /// close-on-exec, like `close_range()` does.
+    pub fn close_range(&mut self, first: u32, last: u32, cloexec: bool) {
+        let fds: Vec<i32> = self.entries.keys()
+            .copied()
+            .filter(|&fd| (first..=last).contains(&(fd as u32)))
+            .collect();
+        for fd in fds {
+            if cloexec {
+                self.cloexec.insert(fd);
+            } else {
+                self.remove(fd);
+            }
+        }
+    }
+
+    /// Forget the descriptors closed by a successful `execve()`.
+    pub fn exec(&mut self) {
+        for fd in self.cloexec.drain() {
+            self.entries.remove(&fd);
+        }
+    }
+}
+
+/// The fd tables of all the traced threads, by thread id.
+///
+/// This is how `pidfd_getfd()` finds the descriptors of another process. The
+/// tables are owned by the threads, they are only kept here as long as a
+/// thread uses them.
+#[derive(Debug, Default)]
+pub struct FdTables {
+    tables: HashMap<Pid, Weak<RefCell<FdTable>>>,
+}
+
+impl FdTables {
+    /// Set the fd table of a thread, when it is created or stops sharing
+    /// it.
+    pub fn register(&mut self, tid: Pid, fds: &Rc<RefCell<FdTable>>) {
+        self.tables.insert(tid, Rc::downgrade(fds));
+    }
+
+    /// Forget a thread once it is gone.
+    pub fn remove(&mut self, tid: Pid) {
+        self.tables.remove(&tid);
+    }
+
+    pub fn get(&self, tid: Pid) -> Option<Rc<RefCell<FdTable>>> {
+        self.tables.get(&tid).and_then(Weak::upgrade)
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+}
+
+/// A file descriptor sent with `SCM_RIGHTS`.
+#[derive(Debug, Clone)]
+pub struct SentFd {
+    pub sender: ProcessId,
+    pub fd: i32,
+    /// The sender's entry, if the descriptor was known
+    pub entry: Option<FdEntry>,
+}
+
+/// The file descriptors sent over Unix sockets but not received yet.
+///
+/// The receiver gets new descriptor numbers, so they are matched by the
+/// device and inode of the file they refer to.
+#[derive(Debug, Default)]
+pub struct InFlightFds {
+    sent: HashMap<(u64, u64), VecDeque<SentFd>>,
+}
+
+impl InFlightFds {
+    pub fn send(&mut self, file: (u64, u64), sent: SentFd) {
+        let queue = self.sent.entry(file).or_default();
+        if queue.len() >= MAX_IN_FLIGHT {
+            queue.pop_front();
+        }
+        queue.push_back(sent);
+    }
+
+    /// Take the oldest descriptor sent for that file.
+    pub fn receive(&mut self, file: (u64, u64)) -> Option<SentFd> {
+        let queue = self.sent.get_mut(&file)?;
+        let sent = queue.pop_front();
+        if queue.is_empty() {
+            self.sent.remove(&file);
+        }
+        sent
+    }
+}
@@ -0,0 +1,170 @@
+//! Tracing a program once for each input of a fuzzing corpus.
+//!
+//! The command is given as for AFL++ or libFuzzer, with `@@` standing for
+//! the input file. Each run is recorded in its own database, named after the
+//! input, and marked if the program crashed, so the traces of crashing
+//! inputs can be packed and sent along with the bug report.
+
+use std::ffi::OsStr;
+use std::fs::read_dir;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal::Signal;
+
+use crate::{Error, ExitStatus, TraceConfig, Tracer};
+use crate::database::{Database, DatabaseOps};
+
+/// The argument replaced with the path of the input file.
+const INPUT_PLACEHOLDER: &[u8] = b"@@";
+
+/// Whether a program exiting this way crashed.
+pub fn is_crash(status: ExitStatus) -> bool {
+    match status {
+        ExitStatus::Return(code) => code != 0,
+        ExitStatus::Signal(signal) => matches!(
+            signal,
+            Signal::SIGABRT | Signal::SIGSEGV | Signal::SIGBUS
+            | Signal::SIGILL | Signal::SIGFPE
+        ),
+    }
+}
+
+/// The run of the program on one input.
+#[derive(Debug, Clone)]
+pub struct FuzzResult {
+    pub input: PathBuf,
+    pub database: PathBuf,
+    pub status: ExitStatus,
+    pub crashed: bool,
+}
+
+/// Runs the tracer on every file of a corpus.
+///
+/// ```rust,no_run
+/// # use std::path::Path;
+/// # use reprozip::fuzz::FuzzOrchestrator;
+/// let logger = slog::Logger::root(slog::Discard, slog::o!());
+/// let command = vec![b"./parser".to_vec(), b"@@".to_vec()];
+/// let results = FuzzOrchestrator::new("trace.sqlite", command, logger)
+///     .unwrap()
+///     .run(Path::new("corpus"))
+///     .unwrap();
+/// for result in results.iter().filter(|r| r.crashed) {
+///     println!("{} crashed", result.input.display());
+/// }
+/// ```
+pub struct FuzzOrchestrator {
+    logger: slog::Logger,
+    config: TraceConfig,
+    db_template: PathBuf,
+    command: Vec<Vec<u8>>,
+}
+
+impl FuzzOrchestrator {
+    /// Create an orchestrator running `command`, in which an argument `@@`
+    /// is replaced with each input.
+    ///
+    /// The databases are named after `db_template`, with the name of the
+    /// input added before the extension.
+    pub fn new<P: AsRef<Path>>(
+        db_template: P,
+        command: Vec<Vec<u8>>,
+        logger: slog::Logger,
+    ) -> Result<FuzzOrchestrator, Error> {
+        if command.is_empty() {
+            return Err(Error::InvalidCommand);
+        }
+        if !command.iter().any(|arg| arg == INPUT_PLACEHOLDER) {
+            return Err(Error::Internal(
+                "The command doesn't take the input: no @@ argument".into(),
+            ));
+        }
+        Ok(FuzzOrchestrator {
+            logger,
+            config: TraceConfig::default(),
+            db_template: db_template.as_ref().to_owned(),
+            command,
+        })
+    }
+
+    /// Set the options used to trace every run.
+    pub fn config(mut self, config: TraceConfig) -> FuzzOrchestrator {
+        self.config = config;
+        self
+    }
+
+    /// The database recording the run on an input, e.g. `trace.seed1.sqlite`
+    /// for template `trace.sqlite` and input `corpus/seed1`.
+    pub fn database_path(&self, input: &Path) -> PathBuf {
+        let name = input.file_name().unwrap_or_else(|| OsStr::new("input"));
+        let mut file_name = self.db_template.file_stem()
+            .unwrap_or_else(|| OsStr::new("trace"))
+            .to_owned();
+        file_name.push(".");
+        file_name.push(name);
+        if let Some(extension) = self.db_template.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        self.db_template.with_file_name(file_name)
+    }
+
+    /// Trace the program on a single input.
+    pub fn run_input(&self, input: &Path) -> Result<FuzzResult, Error> {
+        let command: Vec<&[u8]> = self.command.iter()
+            .map(|arg| {
+                if arg == INPUT_PLACEHOLDER {
+                    input.as_os_str().as_bytes()
+                } else {
+                    arg.as_slice()
+                }
+            })
+            .collect();
+        let database = self.database_path(input);
+        let tracer = Tracer::with_config(&database, self.logger.clone(),
+                                         self.config.clone())?;
+        let (status, _) = tracer.trace_arg0(&command, command[0])?;
+        let crashed = is_crash(status);
+        if crashed {
+            let mut trace = Database::open_for_update(&database,
+                                                      self.logger.clone())?;
+            trace.set_crash(status)?;
+            trace.commit()?;
+        }
+        Ok(FuzzResult {
+            input: input.to_owned(),
+            database,
+            status,
+            crashed,
+        })
+    }
+
+    /// Trace the program on every file of a directory, in order of their
+    /// names.
+    ///
+    /// A failure to trace one input is logged, and the others are still
+    /// run.
+    pub fn run(&self, input_dir: &Path) -> Result<Vec<FuzzResult>, Error> {
+        let mut inputs = Vec::new();
+        for entry in read_dir(input_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                inputs.push(entry.path());
+            }
+        }
+        inputs.sort();
+        let mut results = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            info!(self.logger, "Running input"; "input" => %input.display());
+            match self.run_input(input) {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    error!(self.logger, "Couldn't trace input: {}", e;
+                           "input" => %input.display());
+                }
+            }
+        }
+        Ok(results)
+    }
+}
@@ -1,39 +1,140 @@
 #[macro_use] extern crate bitflags;
+extern crate csv;
+extern crate flate2;
+#[cfg(feature = "cloud")] extern crate futures;
+extern crate libc;
 extern crate nix;
+extern crate rayon;
+#[cfg(feature = "cloud")] extern crate object_store;
+extern crate rusqlite;
+extern crate serde;
+extern crate sha2;
 #[macro_use] extern crate slog;
 extern crate slog_stdlog;
+extern crate tar;
+#[cfg(feature = "cloud")] extern crate tokio;
+#[cfg(feature = "cloud")] extern crate url;
+extern crate zstd;
 
-mod database;
+pub mod archive;
+pub mod bundle;
+mod checksums;
+pub mod clean;
+pub mod database;
+pub mod dependency_graph;
+mod diagnostic;
+#[cfg(feature = "cloud")]
+pub mod download;
+pub mod fanotify_backend;
+mod fd_table;
+pub mod fuzz;
+mod memory;
+pub mod middleware;
+pub mod output;
+mod output_capture;
+mod path_utils;
+mod pidfd;
+pub mod portability;
+pub mod process_tree;
+mod process_namespace;
+mod procfs_watcher;
+mod ptrace_backend;
+pub mod replay;
+pub mod report;
+pub mod repro_hash;
+pub mod seccomp;
+mod syscalls;
+#[cfg(test)] mod testing;
+pub mod time_override;
+#[cfg(feature = "cloud")]
+pub mod upload;
+mod xattr;
 
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::env::current_dir;
 use std::error::Error as StdError;
-use std::ffi::CString;
+use std::ffi::{CString, OsStr};
 use std::fmt::Display;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::ptr;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use nix::Error as NixError;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
 use nix::sys::ptrace;
 use nix::sys::signal::{Signal, kill};
 use nix::sys::wait;
-use nix::unistd::{ForkResult, Pid, fork, execvp};
+use nix::unistd::{ForkResult, Pid, close, fork, execvp, pipe2, read, write};
 use slog::Drain;
 
-use crate::database::{Database, FileOp, ProcessId};
+use crate::checksums::ChecksumRecorder;
+use crate::database::{Database, DatabaseOps, FileOp, ProcessId};
+use crate::database::remap::RemappingDatabase;
+use crate::diagnostic::{DiagnosticBackend, RecordingHeader};
+use crate::fd_table::{FdTable, FdTables, InFlightFds};
+use crate::middleware::{Action, EventResult, PtraceEvent, TracerMiddleware};
+use crate::output::strace::StraceFormatter;
+use crate::output_capture::OutputCapture;
+use crate::output::syscall_log::SyscallLogger;
+use crate::pidfd::PidFd;
+use crate::process_namespace::ProcessNamespace;
+use crate::procfs_watcher::ProcFsWatcher;
+use crate::ptrace_backend::PtraceBackend;
+use crate::report::ReproducibilityReport;
+use crate::syscalls::{ExecInfo, SyscallContext, SyscallDispatcher};
+use crate::syscalls::jit::JitRegion;
+use crate::syscalls::poll::PollInfo;
+use crate::time_override::{TimeOverride, TimePreload};
 
 /// General error type returned by this crate.
 #[derive(Debug)]
 pub enum Error {
     InvalidCommand,
+    /// The program installed a seccomp filter, which would hide its syscalls
+    /// from the tracer or change their outcome
+    IncompatibleSeccomp,
+    /// A setuid or setgid program was run without its privileges, since the
+    /// kernel doesn't grant them to a traced process
+    SetuidTraceRefused(PathBuf),
+    /// The trace was aborted after `TraceConfig::max_events` ptrace events
+    MaxEventsExceeded(u64),
+    /// The trace was aborted after `TraceConfig::max_processes` processes
+    MaxProcessesExceeded(u32),
     Internal(String),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            &Error::InvalidCommand => write!(f, "Invalid command"),
-            &Error::Internal(ref s) => write!(f, "{}", s),
+            Error::InvalidCommand => write!(f, "Invalid command"),
+            Error::IncompatibleSeccomp => write!(
+                f,
+                "The program installed a seccomp filter, which can't be \
+                 traced reliably",
+            ),
+            Error::SetuidTraceRefused(path) => write!(
+                f,
+                "{} is setuid or setgid, it ran without its privileges \
+                 because it is traced",
+                path.display(),
+            ),
+            Error::MaxEventsExceeded(max) => write!(
+                f,
+                "Trace aborted after {} events",
+                max,
+            ),
+            Error::MaxProcessesExceeded(max) => write!(
+                f,
+                "Trace aborted after {} processes",
+                max,
+            ),
+            Error::Internal(s) => write!(f, "{}", s),
         }
     }
 }
@@ -46,10 +147,191 @@ impl From<NixError> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Internal(format!("{}", err))
+    }
+}
+
 fn p(pid: Pid) -> i32 {
     pid.into()
 }
 
+/// The mechanism used to watch the traced program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Stop the program on every syscall with ptrace, see `Tracer`
+    #[default]
+    Ptrace,
+    /// Get file accesses from fanotify, faster but requires root and records
+    /// less, see `fanotify_backend::FanotifyTracer`
+    Fanotify,
+}
+
+/// Options controlling how a program is traced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceConfig {
+    /// Make `syslog()` fail with `EPERM`, so the program doesn't depend on
+    /// the kernel log
+    pub suppress_syslog: bool,
+    /// Make `perf_event_open()` fail with `EACCES`, as if performance
+    /// counters were not available
+    pub emulate_perf: bool,
+    /// Make `bpf()` fail with `EPERM` when loading a program, as if the
+    /// program lacked the privileges
+    pub block_bpf: bool,
+    /// Don't record files opened only for writing if they are bigger than
+    /// this many bytes, such as large temporary files
+    pub max_file_size: Option<u64>,
+    /// Pretend the machine has a single CPU: `getcpu()` returns 0 and
+    /// `sched_setaffinity()` does nothing
+    pub emulate_single_cpu: bool,
+    /// Make the program see a fake wall-clock time
+    pub time_override: Option<TimeOverride>,
+    /// Give the program random bytes generated from this seed, from
+    /// `getrandom()` and `/dev/urandom`, see `syscalls::random`
+    pub deterministic_random: Option<[u8; 32]>,
+    /// Look for new files mapped in memory every this many syscalls, 0 to
+    /// disable
+    pub maps_scan_interval: u64,
+    /// Which tracer to use; the options above only apply to ptrace
+    pub backend: Backend,
+    /// Also write a strace-like log of the syscalls to this file
+    pub strace_output: Option<PathBuf>,
+    /// Also write the syscalls to this file as CSV, see
+    /// `output::syscall_log`
+    pub syscall_log: Option<PathBuf>,
+    /// Don't record the files accessed by the first process, only those of
+    /// its children, for example to trace the compilers run by `make`
+    pub skip_root: bool,
+    /// Compact the database once the trace is committed, see
+    /// `database::vacuum()`
+    pub auto_vacuum: bool,
+    /// Don't record the files accessed by processes running these programs,
+    /// matched on the file name; their children are still recorded
+    pub ignored_executables: Vec<String>,
+    /// Check the tracked file descriptors against what the kernel returns
+    /// from `fcntl(F_GETFD)` and `dup2()`, to debug the fd table
+    pub verify_fd_table: bool,
+    /// Write the PID of the traced command to this file while it runs, for
+    /// scripts to signal it
+    pub pid_file: Option<PathBuf>,
+    /// Abort the trace after this many ptrace events, in case it never ends
+    pub max_events: Option<u64>,
+    /// Abort the trace once this many processes and threads were created,
+    /// such as with a program forking endlessly
+    pub max_processes: Option<u32>,
+    /// Make `fork()` and `clone()` fail with `EPERM` in processes this many
+    /// levels below the first one, so that a fork bomb can't go deeper;
+    /// creating threads is still allowed
+    pub max_process_depth: Option<u32>,
+    /// Record paths relative to this directory, such as the root of a
+    /// container's filesystem, see `database::remap`
+    pub root_prefix: Option<PathBuf>,
+    /// Store what the program writes to its stdout and stderr in the trace;
+    /// it is still printed as well
+    pub capture_output: bool,
+    /// Most bytes stored of each of stdout and stderr, with
+    /// `capture_output`
+    pub max_output_bytes: usize,
+    /// Attach to the program with `PTRACE_SEIZE` instead of having it call
+    /// `PTRACE_TRACEME` and stop itself; falls back to the latter on kernels
+    /// before 3.4
+    pub use_seize: bool,
+    /// Libraries added to `LD_PRELOAD` for the program, such as sanitizer
+    /// runtimes; they come before any the user set, and are recorded as
+    /// dependencies
+    pub inject_ld_preload: Vec<PathBuf>,
+    /// Hash the files read by the program when they are first opened, in
+    /// the background, rather than later when they might have changed; see
+    /// `repro_hash`
+    pub record_checksums: bool,
+    /// Record the SELinux context of the files opened, so that it can be
+    /// restored on replay; setting it then requires privileges
+    pub record_mac_labels: bool,
+    /// Record everything the tracer gets from the kernel to this file, so
+    /// that the trace can be replayed for debugging with
+    /// `Tracer::replay_diagnostic()`
+    pub diagnostic_recording: Option<PathBuf>,
+    /// The version of the database schema to write; with an older one, the
+    /// events it has no table for are not recorded, see `database::schema`
+    pub schema_version: u32,
+}
+
+impl Default for TraceConfig {
+    fn default() -> TraceConfig {
+        TraceConfig {
+            suppress_syslog: false,
+            emulate_perf: false,
+            block_bpf: false,
+            max_file_size: None,
+            emulate_single_cpu: false,
+            time_override: None,
+            deterministic_random: None,
+            maps_scan_interval: procfs_watcher::DEFAULT_SCAN_INTERVAL,
+            backend: Backend::Ptrace,
+            strace_output: None,
+            syscall_log: None,
+            skip_root: false,
+            auto_vacuum: false,
+            ignored_executables: Vec::new(),
+            verify_fd_table: false,
+            pid_file: None,
+            max_events: None,
+            max_processes: None,
+            max_process_depth: None,
+            root_prefix: None,
+            capture_output: false,
+            max_output_bytes: output_capture::DEFAULT_MAX_OUTPUT_BYTES,
+            use_seize: false,
+            inject_ld_preload: Vec::new(),
+            record_checksums: false,
+            record_mac_labels: false,
+            diagnostic_recording: None,
+            schema_version: database::schema::LATEST_VERSION,
+        }
+    }
+}
+
+/// Sent to the child once it is seized, see `TraceConfig::use_seize`.
+const SEIZED: u8 = b'S';
+/// Sent to the child if it couldn't be seized, so it uses `PTRACE_TRACEME`.
+const TRACE_ME: u8 = b'T';
+
+/// A file holding the PID of the traced command, removed when dropped.
+struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    fn create(path: &Path, pid: Pid) -> Result<PidFile, Error> {
+        std::fs::write(path, format!("{}\n", pid)).map_err(|e| {
+            Error::Internal(format!("Can't write PID file {}: {}",
+                                    path.display(), e))
+        })?;
+        Ok(PidFile { path: path.to_owned() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Counters about the tracer itself, for debugging.
+///
+/// They are shared, so they can still be read once the tracer has been
+/// consumed by `Tracer::trace()`.
+#[derive(Debug, Default)]
+pub struct TracerStats {
+    /// Disagreements between the fd table and the kernel, found with
+    /// `TraceConfig::verify_fd_table`
+    pub fd_table_errors: Cell<u64>,
+    /// Reads from signal and event descriptors, which are not file accesses
+    pub ipc_reads: Cell<u64>,
+}
+
 /// Exit status from a process, either a return code or a signal.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ExitStatus {
@@ -62,38 +344,142 @@ pub enum ExitStatus {
 /// All the threads in a process share some attributes, such as the environment
 /// and the working directory.
 struct ThreadGroup {
-    working_dir: PathBuf,
+    working_dir: RefCell<PathBuf>,
+    watcher: RefCell<ProcFsWatcher>,
+    /// The signals for which a handler function is registered
+    signal_handlers: RefCell<HashSet<i32>>,
+    /// Whether the files accessed are recorded, false for the root process
+    /// with `TraceConfig::skip_root` and after executing one of
+    /// `TraceConfig::ignored_executables`
+    record_files: Cell<bool>,
+    /// Whether the process was reported running generated code, see
+    /// `syscalls::jit`
+    jit_warned: Cell<bool>,
+    /// How many random buffers the process got, with
+    /// `TraceConfig::deterministic_random`
+    random_calls: Cell<u64>,
+    /// How many processes separate it from the first one, 0 for the first
+    depth: u32,
 }
 
 /// A thread that we are tracking.
+///
+/// The parent's fork event and the child's first stop can come in any order.
+/// If the parent's comes first, the child is `Allocated` until it stops. If
+/// the child's comes first, it is `Unknown` and kept stopped until the
+/// parent's event gives it an identifier, then goes straight to `Attached`.
+///
+/// When it exits, the exit is recorded on `PTRACE_EVENT_EXIT` and the thread
+/// is `Exiting` until the tracer reaps it; it can still stop in between.
 enum Thread {
+    /// Seen stopping, but its parent didn't return from `fork()` yet
     Unknown { tid: Pid },
+    /// Created by a known parent, but not seen stopping yet
     Allocated(ThreadInfo),
+    /// Running and traced
     Attached(ThreadInfo),
+    /// Exit recorded, not reaped yet
+    Exiting {
+        tid: Pid,
+        identifier: ProcessId,
+        since: Instant,
+        warned: bool,
+    },
 }
 
+/// How long a thread can stay `Exiting` before we warn about it.
+const EXITING_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 struct ThreadInfo {
     identifier: ProcessId,
     tid: Pid,
     thread_group: Rc<ThreadGroup>,
+    /// The file descriptors, shared with the other threads unless one
+    /// unshares them
+    fds: Rc<RefCell<FdTable>>,
+    /// Whether the thread is between syscall entry and exit
+    in_syscall: bool,
+    /// The current (or last) syscall number
+    syscall: u64,
+    /// The arguments of the current syscall, saved on entry
+    params: [u64; 6],
+    /// Information read on entry to `execve`, used on success
+    pending_exec: Option<ExecInfo>,
+    /// The file descriptors being waited on, read on entry to `poll` and
+    /// similar syscalls
+    pending_poll: Option<PollInfo>,
+    /// The region made executable, read on entry to `mprotect`
+    pending_jit: Option<JitRegion>,
+    /// Value to return from the current syscall, which was denied
+    return_override: Option<i64>,
+    /// The signals being handled, innermost last
+    signals: Vec<i32>,
+    /// The namespaces the thread is in, to notice it changing them
+    namespaces: process_namespace::Namespaces,
 }
 
 impl ThreadInfo {
+    fn new(
+        identifier: ProcessId,
+        tid: Pid,
+        thread_group: Rc<ThreadGroup>,
+        fds: Rc<RefCell<FdTable>>,
+    ) -> ThreadInfo {
+        ThreadInfo {
+            identifier,
+            tid,
+            thread_group,
+            fds,
+            in_syscall: false,
+            syscall: 0,
+            params: [0; 6],
+            pending_exec: None,
+            pending_poll: None,
+            pending_jit: None,
+            return_override: None,
+            signals: Vec::new(),
+            namespaces: Vec::new(),
+        }
+    }
+
     fn exit(
         self,
         exitstatus: ExitStatus,
-        database: &mut Database,
+        database: &mut dyn DatabaseOps,
     ) -> Result<(), Error> {
         database.process_exit(self.identifier, exitstatus)
     }
 }
 
+fn unknown_pid(pid: Pid) -> Error {
+    Error::Internal(format!("unknown pid {}", p(pid)))
+}
+
+/// Put libraries in front of the current value of `LD_PRELOAD`.
+fn prepend_ld_preload(
+    libraries: &[PathBuf],
+    current: Option<&OsStr>,
+) -> Vec<u8> {
+    let mut entries: Vec<&[u8]> = libraries.iter()
+        .map(|library| library.as_os_str().as_bytes())
+        .collect();
+    if let Some(current) = current.filter(|c| !c.is_empty()) {
+        entries.push(current.as_bytes());
+    }
+    entries.join(&b':')
+}
+
 /// Structure holding all the running threads and processes.
 struct Processes {
     logger: slog::Logger,
     pid2process: HashMap<Pid, Thread>,
     identifier2pid: HashMap<ProcessId, Pid>,
+    /// The fd table of each thread, for the syscall handlers to look up
+    /// other processes' descriptors
+    fd_tables: FdTables,
+    /// How many threads were added in total, including those gone
+    created: u32,
 }
 
 impl Processes {
@@ -102,6 +488,8 @@ impl Processes {
             logger,
             pid2process: Default::default(),
             identifier2pid: Default::default(),
+            fd_tables: Default::default(),
+            created: 0,
         }
     }
 
@@ -110,22 +498,96 @@ impl Processes {
         &mut self,
         tid: Pid,
         thread_group: Rc<ThreadGroup>,
-        database: &mut Database,
+        database: &mut dyn DatabaseOps,
     ) -> Result<ProcessId, Error> {
         let identifier =
-            database.add_process(None, &thread_group.working_dir, false)?;
-        self.pid2process.insert(
-            tid,
-            Thread::Allocated(ThreadInfo {
-                identifier,
-                tid,
-                thread_group,
-            }),
-        );
+            database.add_process(None, &thread_group.working_dir.borrow(),
+                                 false)?;
+        process_namespace::record_pids(database, identifier, tid)?;
+        let mut info = ThreadInfo::new(identifier, tid, thread_group,
+                                       Default::default());
+        info.namespaces =
+            process_namespace::record_namespaces(database, identifier, tid)?;
+        self.fd_tables.register(tid, &info.fds);
+        self.pid2process.insert(tid, Thread::Allocated(info));
         self.identifier2pid.insert(identifier, tid);
+        self.created += 1;
         Ok(identifier)
     }
 
+    /// Add a new thread or process, created by a known parent.
+    ///
+    /// Returns true if the thread had already appeared, in which case it is
+    /// stopped and should be resumed.
+    fn add_child(
+        &mut self,
+        parent: Pid,
+        tid: Pid,
+        is_thread: bool,
+        database: &mut dyn DatabaseOps,
+    ) -> Result<bool, Error> {
+        // Borrowing only the threads, the fd tables are updated below
+        let thread = self.pid2process.get(&parent)
+            .ok_or_else(|| unknown_pid(parent))?;
+        let parent = match thread {
+            Thread::Attached(info) => info,
+            _ => {
+                return Err(Error::Internal(format!(
+                    "process {} created by unattached parent {}",
+                    p(tid), p(parent),
+                )))
+            }
+        };
+        let (thread_group, fds) = if is_thread {
+            (parent.thread_group.clone(), parent.fds.clone())
+        } else {
+            let thread_group = Rc::new(ThreadGroup {
+                working_dir: parent.thread_group.working_dir.clone(),
+                // The child starts with the same mappings
+                watcher: parent.thread_group.watcher.clone(),
+                signal_handlers: parent.thread_group.signal_handlers.clone(),
+                record_files: Cell::new(true),
+                jit_warned: Cell::new(false),
+                random_calls: Cell::new(0),
+                depth: parent.thread_group.depth + 1,
+            });
+            let fds = Rc::new(RefCell::new(parent.fds.borrow().clone()));
+            (thread_group, fds)
+        };
+        let identifier = database.add_process(
+            Some(parent.identifier),
+            &thread_group.working_dir.borrow(),
+            is_thread,
+        )?;
+        process_namespace::record_pids(database, identifier, tid)?;
+        let mut info = ThreadInfo::new(identifier, tid, thread_group, fds);
+        info.namespaces =
+            process_namespace::record_namespaces(database, identifier, tid)?;
+        self.fd_tables.register(tid, &info.fds);
+        match self.pid2process.get(&tid) {
+            Some(Thread::Unknown { .. }) => {
+                // Already stopped, waiting for us to know its parent; it gets
+                // its identifier before running anything, so it can be
+                // attached right away
+                debug!(self.logger, "Promoting unknown process";
+                       "tid" => p(tid), "parent" => p(parent.tid));
+                self.pid2process.insert(tid, Thread::Attached(info));
+                self.identifier2pid.insert(identifier, tid);
+                self.created += 1;
+                Ok(true)
+            }
+            Some(_) => Err(Error::Internal(format!(
+                "process {} created twice", p(tid),
+            ))),
+            None => {
+                self.pid2process.insert(tid, Thread::Allocated(info));
+                self.identifier2pid.insert(identifier, tid);
+                self.created += 1;
+                Ok(false)
+            }
+        }
+    }
+
     /// Add a new process, currently unknown.
     ///
     /// This is required because we can see processes appear before we see
@@ -135,19 +597,74 @@ impl Processes {
         Ok(())
     }
 
+    /// Record the exit of a thread, which goes to `Exiting` until reaped.
     fn exit(
         &mut self,
         tid: Pid,
         exitstatus: ExitStatus,
-        database: &mut Database,
+        database: &mut dyn DatabaseOps,
+    ) -> Result<(), Error> {
+        let thread = self.get_pid_mut_checked(tid)?;
+        let info = match thread {
+            Thread::Allocated(info) | Thread::Attached(info) => info.clone(),
+            Thread::Unknown { .. } | Thread::Exiting { .. } => return Ok(()),
+        };
+        *thread = Thread::Exiting {
+            tid,
+            identifier: info.identifier,
+            since: Instant::now(),
+            warned: false,
+        };
+        self.identifier2pid.remove(&info.identifier);
+        info.exit(exitstatus, database)
+    }
+
+    /// Record the exit of all the threads of a process, on entry to
+    /// `exit_group()`.
+    ///
+    /// The kernel kills the other threads, but they can still stop before
+    /// their exit is reported; marking them as exiting right away means
+    /// nothing more is recorded for them.
+    fn exit_group(
+        &mut self,
+        thread_group: &Rc<ThreadGroup>,
+        exitstatus: ExitStatus,
+        database: &mut dyn DatabaseOps,
+    ) -> Result<(), Error> {
+        let mut tids: Vec<Pid> = self.pid2process.iter()
+            .filter_map(|(&tid, thread)| match thread {
+                Thread::Allocated(info) | Thread::Attached(info)
+                    if Rc::ptr_eq(&info.thread_group, thread_group) =>
+                {
+                    Some(tid)
+                }
+                _ => None,
+            })
+            .collect();
+        tids.sort_by_key(|&tid| p(tid));
+        debug!(self.logger, "exit_group() ends {} threads", tids.len());
+        for tid in tids {
+            self.exit(tid, exitstatus, database)?;
+        }
+        Ok(())
+    }
+
+    /// Forget a thread once it's gone, recording its exit if it wasn't.
+    fn reap(
+        &mut self,
+        tid: Pid,
+        exitstatus: ExitStatus,
+        database: &mut dyn DatabaseOps,
     ) -> Result<(), Error> {
-        let thread = self.pid2process.remove(&tid).unwrap();
-        match thread {
-            Thread::Allocated(info) | Thread::Attached(info) => {
-                self.identifier2pid.remove(&info.identifier);
-                info.exit(exitstatus, database)?;
+        self.exit(tid, exitstatus, database)?;
+        self.fd_tables.remove(tid);
+        match self.pid2process.remove(&tid) {
+            Some(Thread::Unknown { tid }) => {
+                debug!(self.logger, "Unknown process exited";
+                       "tid" => p(tid));
             }
-            Thread::Unknown { .. } => {}
+            Some(_) => {}
+            None => return Err(unknown_pid(tid)),
         }
         info!(
             self.logger,
@@ -158,38 +675,95 @@ impl Processes {
         Ok(())
     }
 
+    /// Warn about the threads that have been exiting for too long.
+    fn check_exiting(&mut self) {
+        for thread in self.pid2process.values_mut() {
+            if let Thread::Exiting { tid, identifier, since, warned } = thread {
+                if !*warned && since.elapsed() > EXITING_TIMEOUT {
+                    *warned = true;
+                    warn!(self.logger, "Thread stuck exiting";
+                          "tid" => p(*tid), "process" => identifier.number(),
+                          "seconds" => since.elapsed().as_secs());
+                }
+            }
+        }
+    }
+
     fn is_empty(&self) -> bool {
         self.pid2process.is_empty()
     }
 
-    fn has_pid(&self, pid: Pid) -> bool {
-        self.pid2process.contains_key(&pid)
+    /// Kill all the traced processes and wait for them to go away.
+    fn kill_all(&mut self) {
+        for &tid in self.pid2process.keys() {
+            kill(tid, Signal::SIGKILL).ok();
+        }
+        while let Ok(status) = wait::waitpid(Pid::from_raw(-1),
+                                             Some(wait::WaitPidFlag::__WALL)) {
+            // Killed threads still stop on PTRACE_EVENT_EXIT, and children
+            // we didn't know about yet are stopped too; resume them so they
+            // go away
+            match status {
+                wait::WaitStatus::Stopped(tid, _)
+                | wait::WaitStatus::PtraceEvent(tid, _, _)
+                | wait::WaitStatus::PtraceSyscall(tid) => {
+                    kill(tid, Signal::SIGKILL).ok();
+                    ptrace::cont(tid, None).ok();
+                }
+                _ => {}
+            }
+        }
+        self.pid2process.clear();
+        self.identifier2pid.clear();
+        self.fd_tables.clear();
     }
 
-    fn get_pid(&self, pid: Pid) -> &Thread {
-        self.pid2process.get(&pid).unwrap()
+    fn has_pid(&self, pid: Pid) -> bool {
+        self.pid2process.contains_key(&pid)
     }
 
-    fn get_pid_mut(&mut self, pid: Pid) -> &mut Thread {
-        self.pid2process.get_mut(&pid).unwrap()
+    fn get_pid_checked(&self, pid: Pid) -> Result<&Thread, Error> {
+        self.pid2process.get(&pid).ok_or_else(|| unknown_pid(pid))
     }
 
-    fn get_identifier(&self, id: ProcessId) -> &Thread {
-        let pid = *self.identifier2pid.get(&id).unwrap();
-        self.get_pid(pid)
+    fn get_pid_mut_checked(&mut self, pid: Pid) -> Result<&mut Thread, Error> {
+        self.pid2process.get_mut(&pid).ok_or_else(|| unknown_pid(pid))
     }
 
-    fn get_identifier_mut(&mut self, id: ProcessId) -> &mut Thread {
-        let pid = *self.identifier2pid.get(&id).unwrap();
-        self.get_pid_mut(pid)
+    /// Get a thread, along with the fd tables of all of them.
+    fn get_pid_mut_with_fds(
+        &mut self,
+        pid: Pid,
+    ) -> Result<(&mut Thread, &mut FdTables), Error> {
+        let thread = self.pid2process.get_mut(&pid)
+            .ok_or_else(|| unknown_pid(pid))?;
+        Ok((thread, &mut self.fd_tables))
     }
 }
 
 /// Tracer following processes and logging their execution to a `Database`.
-pub struct Tracer {
+pub struct Tracer<D: DatabaseOps = Database> {
     logger: slog::Logger,
+    config: TraceConfig,
     processes: Processes,
-    database: Database,
+    database: RemappingDatabase<D>,
+    dispatcher: SyscallDispatcher<DiagnosticBackend>,
+    /// The library faking the time, if `config.time_override` is set
+    time_preload: Option<TimePreload>,
+    strace: Option<StraceFormatter>,
+    syscall_log: Option<SyscallLogger>,
+    stats: Rc<TracerStats>,
+    /// File descriptors sent between processes, see `syscalls::sockets`
+    in_flight: InFlightFds,
+    /// How many ptrace events were handled, shared with other threads to
+    /// report progress, see `event_counter()`
+    event_count: Arc<AtomicU64>,
+    /// Called around the handling of each stop, see `add_middleware()`
+    middleware: Vec<Box<dyn TracerMiddleware>>,
+    /// Threads in a syscall whose entry was suppressed by a middleware
+    suppressed_syscalls: HashSet<Pid>,
+    /// Hashes the files read, if `config.record_checksums` is set
+    checksums: Option<ChecksumRecorder>,
 }
 
 impl Tracer {
@@ -201,31 +775,120 @@ impl Tracer {
         database: D,
         logger: L,
     ) -> Result<Tracer, Error> {
+        Self::with_config(database, logger, TraceConfig::default())
+    }
+
+    pub fn with_config<D: AsRef<Path>, L: Into<Option<slog::Logger>>>(
+        database: D,
+        logger: L,
+        config: TraceConfig,
+    ) -> Result<Tracer, Error> {
+        let logger = logger
+            .into()
+            .unwrap_or(slog::Logger::root(slog_stdlog::StdLog.fuse(), o!()));
+        let database = Database::with_schema_version(
+            database,
+            config.schema_version,
+            logger.clone(),
+        )?;
+        Ok(Tracer::with_database(database, logger, config))
+    }
+}
+
+impl<D: DatabaseOps> Tracer<D> {
+    /// Create a tracer recording to an existing database.
+    pub fn with_database<L: Into<Option<slog::Logger>>>(
+        database: D,
+        logger: L,
+        config: TraceConfig,
+    ) -> Tracer<D> {
         let logger = logger
             .into()
             .unwrap_or(slog::Logger::root(slog_stdlog::StdLog.fuse(), o!()));
-        Ok(Tracer {
+        let database = RemappingDatabase::new(database,
+                                              config.root_prefix.clone(),
+                                              logger.clone());
+        Tracer {
             logger: logger.clone(),
-            processes: Processes::new(logger.clone()),
-            database: Database::new(database, logger)?,
-        })
+            config,
+            processes: Processes::new(logger),
+            database,
+            dispatcher: SyscallDispatcher::new(DiagnosticBackend::new()),
+            time_preload: None,
+            strace: None,
+            syscall_log: None,
+            stats: Default::default(),
+            in_flight: Default::default(),
+            event_count: Default::default(),
+            middleware: Vec::new(),
+            suppressed_syscalls: Default::default(),
+            checksums: None,
+        }
+    }
+
+    /// Add a middleware, called around the handling of each ptrace stop
+    /// after those already added.
+    pub fn add_middleware<M: TracerMiddleware + 'static>(
+        &mut self,
+        middleware: M,
+    ) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// The counters about this tracer, updated during the trace.
+    pub fn stats(&self) -> Rc<TracerStats> {
+        self.stats.clone()
+    }
+
+    /// How many ptrace events were handled so far.
+    pub fn event_count(&self) -> u64 {
+        self.event_count.load(Ordering::Relaxed)
+    }
+
+    /// The counter behind `event_count()`, which can be read from another
+    /// thread while `trace()` runs.
+    pub fn event_counter(&self) -> Arc<AtomicU64> {
+        self.event_count.clone()
+    }
+
+    /// The path of the database the trace is written to.
+    pub fn database_path(&self) -> &Path {
+        self.database.path()
     }
 
+    /// Run a command and trace it.
+    ///
+    /// Returns the exit status of the command and the identifier of its
+    /// process in the database, always `ProcessId::root()`.
     pub fn trace<C: AsRef<[u8]>>(
         self,
         command: &[C],
-    ) -> Result<ExitStatus, Error> {
+    ) -> Result<(ExitStatus, ProcessId), Error> {
         self.trace_arg0(command, &command[0])
     }
 
+    /// Run a command and trace it, then summarize the reproducibility risks
+    /// of the trace, see `report::ReproducibilityReport`.
+    pub fn trace_with_report<C: AsRef<[u8]>>(
+        self,
+        command: &[C],
+    ) -> Result<(ExitStatus, ProcessId, ReproducibilityReport), Error> {
+        let path = self.database_path().to_owned();
+        let logger = self.logger.clone();
+        let (status, id) = self.trace(command)?;
+        let database = Database::open(path, logger)?;
+        Ok((status, id, ReproducibilityReport::new(&database)?))
+    }
+
+    /// Run a command and trace it, replacing `argv[0]`.
     pub fn trace_arg0<C: AsRef<[u8]>, C2: AsRef<[u8]>>(
         mut self,
         command: &[C],
         arg0: C2,
-    ) -> Result<ExitStatus, Error> {
+    ) -> Result<(ExitStatus, ProcessId), Error> {
         let args = {
             let mut vec = Vec::new();
-            for c in command.into_iter() {
+            for c in command.iter() {
                 match CString::new(c.as_ref()) {
                     Ok(c) => vec.push(c),
                     Err(_) => return Err(Error::InvalidCommand),
@@ -238,21 +901,102 @@ impl Tracer {
             Err(_) => return Err(Error::InvalidCommand),
         };
         info!(self.logger, "Tracing command: {:?}", args);
+        if let Some(time) = &self.config.time_override {
+            let preload = TimePreload::build(time)?;
+            info!(self.logger, "Faking time with {}",
+                  preload.path().display(); "start" => ?time.start,
+                  "speed" => time.speed);
+            self.time_preload = Some(preload);
+        }
+        if let Some(path) = &self.config.strace_output {
+            self.strace = Some(StraceFormatter::create(path)?);
+        }
+        if let Some(path) = &self.config.syscall_log {
+            self.syscall_log = Some(SyscallLogger::create(path)?);
+        }
+        let capture = if self.config.capture_output {
+            Some(OutputCapture::new(self.config.max_output_bytes)?)
+        } else {
+            None
+        };
+        // With PTRACE_SEIZE, the child waits on this pipe to be attached
+        let seize_pipe = if self.config.use_seize {
+            Some(pipe2(OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
 
         match fork() {
             Ok(ForkResult::Parent { child }) => {
                 info!(self.logger, "Child created, pid={pid}", pid = p(child));
+                let capture = match capture {
+                    Some(capture) => Some(capture.start()?),
+                    None => None,
+                };
+                // Started after the fork, so the child has no such threads
+                if self.config.record_checksums {
+                    self.checksums = Some(ChecksumRecorder::new()?);
+                }
+                // Removed on return, once the trace is over
+                let _pid_file = match &self.config.pid_file {
+                    Some(path) => Some(PidFile::create(path, child)?),
+                    None => None,
+                };
                 let wd = current_dir().unwrap();
-                let identifier = self.processes.add_first(
-                    child,
-                    Rc::new(ThreadGroup {
-                        working_dir: wd.clone(),
-                    }),
-                    &mut self.database,
+                let identifier = self.add_first_process(child, &wd)?;
+                // Only a command we start gets these
+                for library in &self.config.inject_ld_preload {
+                    self.database.add_file_open(identifier,
+                                                &wd.join(library),
+                                                FileOp::READ, false)?;
+                }
+                if let Some(time) = &self.config.time_override {
+                    self.database.set_time_override(time)?;
+                }
+                let mut seized = false;
+                if let Some((pipe_read, pipe_write)) = seize_pipe {
+                    close(pipe_read)?;
+                    seized = self.seize(child)?;
+                    let message = if seized { SEIZED } else { TRACE_ME };
+                    write(pipe_write, &[message])?;
+                    close(pipe_write)?;
+                }
+                if let Some(path) = &self.config.diagnostic_recording {
+                    info!(self.logger, "Recording ptrace events to {}",
+                          path.display());
+                    self.dispatcher.ptrace_mut().record(path, &RecordingHeader {
+                        first_proc: child,
+                        working_dir: wd,
+                        seized,
+                    })?;
+                }
+                let start = Instant::now();
+                let ret = match self.trace_process(child) {
+                    Err(e @ (Error::IncompatibleSeccomp
+                             | Error::MaxEventsExceeded(_)
+                             | Error::MaxProcessesExceeded(_))) => {
+                        // Keep what was recorded, to show what happened
+                        error!(self.logger, "Aborting trace");
+                        self.processes.kill_all();
+                        if let Some(strace) = &mut self.strace {
+                            strace.flush()?;
+                        }
+                        if let Some(log) = &mut self.syscall_log {
+                            log.flush()?;
+                        }
+                        self.dispatcher.ptrace().flush()?;
+                        if let Some(checksums) = self.checksums.take() {
+                            checksums.finish(&mut self.database,
+                                             &self.logger)?;
+                        }
+                        self.database.commit()?;
+                        return Err(e);
+                    }
+                    ret => ret?,
+                };
+                self.database.set_wall_time_ns(
+                    start.elapsed().as_nanos() as u64,
                 )?;
-                self.database.add_file_open(identifier, &wd,
-                                            FileOp::WDIR, true)?;
-                let ret = self.trace_process(child)?;
                 match ret {
                     ExitStatus::Return(i) => {
                         info!(self.logger,
@@ -264,12 +1008,58 @@ impl Tracer {
                               "signal" => ?s);
                     }
                 }
+                if let Some(strace) = &mut self.strace {
+                    strace.flush()?;
+                }
+                if let Some(log) = &mut self.syscall_log {
+                    log.flush()?;
+                }
+                self.dispatcher.ptrace().flush()?;
+                if let Some(capture) = capture {
+                    let (stdout, stderr) = capture.finish()?;
+                    info!(self.logger, "Captured output";
+                          "stdout" => stdout.len(), "stderr" => stderr.len());
+                    self.database.add_process_output(identifier, &stdout,
+                                                     &stderr)?;
+                }
+                match self.stats.ipc_reads.get() {
+                    0 => {}
+                    reads => info!(self.logger, "{} reads from signal and \
+                                                 event descriptors", reads),
+                }
+                if self.config.verify_fd_table {
+                    match self.stats.fd_table_errors.get() {
+                        0 => info!(self.logger, "fd table verified"),
+                        errors => warn!(self.logger,
+                                        "fd table had {} errors", errors),
+                    }
+                }
+                if let Some(checksums) = self.checksums.take() {
+                    checksums.finish(&mut self.database, &self.logger)?;
+                }
+                let path = self.database.path().to_owned();
                 self.database.commit()?;
-                Ok(ret)
+                if self.config.auto_vacuum {
+                    info!(self.logger, "Compacting database");
+                    database::vacuum(&path)?;
+                }
+                Ok((ret, identifier))
             }
             Ok(ForkResult::Child) => {
+                // Wait for the parent to seize us, if it does
+                let seized = match seize_pipe {
+                    Some((pipe_read, pipe_write)) => {
+                        close(pipe_write).ok();
+                        let mut message = [0];
+                        let seized = read(pipe_read, &mut message) == Ok(1)
+                            && message[0] == SEIZED;
+                        close(pipe_read).ok();
+                        seized
+                    }
+                    None => false,
+                };
                 // Trace this process
-                match ptrace::traceme() {
+                match if seized { Ok(()) } else { ptrace::traceme() } {
                     Ok(()) => {}
                     Err(err) => {
                         eprintln!(
@@ -281,8 +1071,29 @@ impl Tracer {
                         std::process::exit(125);
                     }
                 }
+                if let Some(capture) = &capture {
+                    if let Err(err) = capture.redirect() {
+                        eprintln!("Couldn't redirect the output: {}", err);
+                        std::process::exit(125);
+                    }
+                }
+                if let Some(preload) = &self.time_preload {
+                    let current = std::env::var_os("LD_PRELOAD");
+                    let value = preload.ld_preload(current.as_deref());
+                    std::env::set_var("LD_PRELOAD", OsStr::from_bytes(&value));
+                }
+                if !self.config.inject_ld_preload.is_empty() {
+                    let current = std::env::var_os("LD_PRELOAD");
+                    let value = prepend_ld_preload(
+                        &self.config.inject_ld_preload,
+                        current.as_deref(),
+                    );
+                    std::env::set_var("LD_PRELOAD", OsStr::from_bytes(&value));
+                }
                 // Stop this once so tracer can set options
-                kill(Pid::this(), Signal::SIGSTOP).expect("Couldn't stop");
+                if !seized {
+                    kill(Pid::this(), Signal::SIGSTOP).expect("Couldn't stop");
+                }
                 // Execute the target
                 match execvp(&arg0, &args) {
                     Ok(_) => unreachable!(),
@@ -293,112 +1104,697 @@ impl Tracer {
                     }
                 }
             }
-            Err(err) => return Err(Error::Internal(format!("fork: {}", err))),
+            Err(err) => Err(Error::Internal(format!("fork: {}", err))),
+        }
+    }
+
+    /// Attach to a running process and trace it until it exits.
+    ///
+    /// The process is held with a pidfd while it is seized, so that if it
+    /// exits and its PID is reused in the meantime, this fails rather than
+    /// tracing some other program. Kernels before 5.1 don't have pidfds,
+    /// then the PID is attached to with `PTRACE_ATTACH`, which has that
+    /// race.
+    ///
+    /// Only the thread `pid` is traced, with the threads and processes it
+    /// creates from then on, not the other threads it already had.
+    ///
+    /// The options acting on how the command is started, such as
+    /// `TraceConfig::time_override` or `TraceConfig::capture_output`, can't
+    /// apply to a running process, and are an error.
+    pub fn attach_stable(mut self, pid: Pid) -> Result<ExitStatus, Error> {
+        let config = &self.config;
+        if config.time_override.is_some() || config.capture_output
+            || config.pid_file.is_some() || !config.inject_ld_preload.is_empty()
+        {
+            return Err(Error::Internal(
+                "Can't fake the time, capture the output, write a PID file \
+                 or inject libraries when attaching to a process".into(),
+            ));
+        }
+        let pidfd = PidFd::open(pid)?;
+        let wd = std::fs::read_link(format!("/proc/{}/cwd", pid))
+            .map_err(|e| {
+                Error::Internal(format!("Can't get working directory of \
+                                         process {}: {}", pid, e))
+            })?;
+        info!(self.logger, "Attaching to process";
+              "tid" => p(pid), "working_dir" => %wd.display());
+        if let Some(path) = &self.config.strace_output {
+            self.strace = Some(StraceFormatter::create(path)?);
+        }
+        if let Some(path) = &self.config.syscall_log {
+            self.syscall_log = Some(SyscallLogger::create(path)?);
+        }
+        if self.config.record_checksums {
+            self.checksums = Some(ChecksumRecorder::new()?);
+        }
+        // Nothing is recorded until the process is attached, in case it
+        // was replaced
+        match pidfd {
+            Some(pidfd) => {
+                if !self.seize_and_stop(pid)? {
+                    return Err(Error::Internal(
+                        "Can't attach, PTRACE_SEIZE is not available".into(),
+                    ));
+                }
+                // Seizing went through the PID, check it is still the same
+                // process
+                if !pidfd.is_alive()? {
+                    ptrace::detach(pid)?;
+                    return Err(Error::Internal(format!(
+                        "Process {} exited before it could be attached",
+                        pid,
+                    )));
+                }
+                self.add_first_process(pid, &wd)?;
+                self.handle_first_stop(pid)?;
+            }
+            None => {
+                warn!(self.logger, "pidfd_open() is not available, the \
+                                    process might be replaced before it is \
+                                    attached");
+                // It then stops with SIGSTOP, like with PTRACE_TRACEME
+                ptrace::attach(pid)?;
+                self.add_first_process(pid, &wd)?;
+            }
+        }
+        let start = Instant::now();
+        let ret = self.trace_process(pid)?;
+        self.database.set_wall_time_ns(start.elapsed().as_nanos() as u64)?;
+        info!(self.logger, "Finished, attached process exited";
+              "status" => ?ret);
+        if let Some(strace) = &mut self.strace {
+            strace.flush()?;
+        }
+        if let Some(log) = &mut self.syscall_log {
+            log.flush()?;
+        }
+        if let Some(checksums) = self.checksums.take() {
+            checksums.finish(&mut self.database, &self.logger)?;
+        }
+        self.database.commit()?;
+        Ok(ret)
+    }
+
+    /// Replay a recording made with `TraceConfig::diagnostic_recording`.
+    ///
+    /// The same handlers run on the recorded events, without any process,
+    /// and what they find is stored in the database. Replaying with the
+    /// same `TraceConfig` as the trace gives the same results, except for
+    /// what was read from `/proc`.
+    pub fn replay_diagnostic(mut self, recording: &Path) -> Result<(), Error> {
+        let header = self.dispatcher.ptrace_mut().replay(recording)?;
+        info!(self.logger, "Replaying diagnostic recording {}",
+              recording.display(); "tid" => p(header.first_proc));
+        if let Some(path) = &self.config.strace_output {
+            self.strace = Some(StraceFormatter::create(path)?);
+        }
+        if let Some(path) = &self.config.syscall_log {
+            self.syscall_log = Some(SyscallLogger::create(path)?);
+        }
+        self.add_first_process(header.first_proc, &header.working_dir)?;
+        if header.seized {
+            self.handle_first_stop(header.first_proc)?;
+        }
+        let ret = self.trace_process(header.first_proc)?;
+        info!(self.logger, "Replay finished, first process exited";
+              "status" => ?ret);
+        if let Some(strace) = &mut self.strace {
+            strace.flush()?;
+        }
+        if let Some(log) = &mut self.syscall_log {
+            log.flush()?;
+        }
+        self.database.commit()
+    }
+
+    /// Record the first process, with its working directory.
+    fn add_first_process(
+        &mut self,
+        pid: Pid,
+        wd: &Path,
+    ) -> Result<ProcessId, Error> {
+        let identifier = self.processes.add_first(
+            pid,
+            Rc::new(ThreadGroup {
+                working_dir: RefCell::new(wd.to_owned()),
+                watcher: RefCell::new(ProcFsWatcher::new(
+                    self.config.maps_scan_interval,
+                )),
+                signal_handlers: Default::default(),
+                record_files: Cell::new(!self.config.skip_root),
+                jit_warned: Cell::new(false),
+                random_calls: Cell::new(0),
+                depth: 0,
+            }),
+            &mut self.database,
+        )?;
+        if !self.config.skip_root {
+            self.database.add_file_open(identifier, wd, FileOp::WDIR, true)?;
+        }
+        if self.config.deterministic_random.is_some() {
+            self.database.set_flag("deterministic_random", true)?;
+        }
+        self.record_mounts()?;
+        Ok(identifier)
+    }
+
+    /// Record the mount table, to know later which files were on network
+    /// or temporary filesystems.
+    fn record_mounts(&mut self) -> Result<(), Error> {
+        let mounts = match portability::read_mounts() {
+            Ok(mounts) => mounts,
+            Err(e) => {
+                warn!(self.logger, "Can't read the mount table: {}", e);
+                return Ok(());
+            }
+        };
+        for mount in &mounts {
+            self.database.add_mount_point(&mount.device, &mount.mount_point,
+                                          &mount.fs_type, &mount.options)?;
         }
+        Ok(())
     }
 
     /// Main tracing procedure, waits for events.
     fn trace_process(&mut self, first_proc: Pid) -> Result<ExitStatus, Error> {
         let mut first_exit_code = None;
+        let mut last_exiting_check = Instant::now();
         loop {
-            match wait::waitpid(Pid::from_raw(-1),
-                                Some(wait::WaitPidFlag::__WALL))? {
-                // A program exited
-                wait::WaitStatus::Exited(pid, status) => {
-                    let exitstatus = ExitStatus::Return(status);
-                    if pid == first_proc {
-                        first_exit_code = Some(exitstatus);
-                    }
-                    self.processes.exit(pid, exitstatus, &mut self.database)?;
-                    if self.processes.is_empty() {
-                        break;
-                    }
-                    continue;
+            if last_exiting_check.elapsed() > Duration::from_secs(1) {
+                self.processes.check_exiting();
+                last_exiting_check = Instant::now();
+            }
+            let status = self.dispatcher.ptrace().waitpid()?;
+            let events = self.event_count.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some(max) = self.config.max_events {
+                if events > max {
+                    error!(self.logger,
+                           "Too many ptrace events, the trace might be stuck \
+                            in a loop; stopping at the limit of {}", max);
+                    return Err(Error::MaxEventsExceeded(max));
+                }
+            }
+            let event = if self.middleware.is_empty() {
+                None
+            } else {
+                self.ptrace_event(&status)
+            };
+            let suppress = match &event {
+                Some(event) => self.pre_event(event),
+                None => false,
+            };
+            let result = self.handle_stop(status, suppress, first_proc,
+                                          &mut first_exit_code);
+            if let Some(event) = &event {
+                let event_result = match &result {
+                    Ok(_) if suppress => EventResult::Suppressed,
+                    Ok(_) => EventResult::Handled,
+                    Err(e) => EventResult::Failed(e.to_string()),
+                };
+                for middleware in &mut self.middleware {
+                    middleware.post_event(event, &event_result);
+                }
+            }
+            if result? {
+                break;
+            }
+        }
+        Ok(first_exit_code.expect("Trace finished but we never got the first \
+                                   process' exit code"))
+    }
+
+    /// Handle a stop reported by `waitpid()`, and resume the thread.
+    ///
+    /// Returns true once all the processes have exited.
+    fn handle_stop(
+        &mut self,
+        status: wait::WaitStatus,
+        suppress: bool,
+        first_proc: Pid,
+        first_exit_code: &mut Option<ExitStatus>,
+    ) -> Result<bool, Error> {
+        match status {
+            // A program exited
+            wait::WaitStatus::Exited(pid, status) => {
+                self.suppressed_syscalls.remove(&pid);
+                let exitstatus = ExitStatus::Return(status);
+                if pid == first_proc {
+                    *first_exit_code = Some(exitstatus);
+                }
+                let result = self.processes.reap(pid, exitstatus,
+                                                 &mut self.database);
+                self.log_unknown(pid, result)?;
+                return Ok(self.processes.is_empty());
+            }
+            wait::WaitStatus::Signaled(pid, sig, _) => {
+                self.suppressed_syscalls.remove(&pid);
+                let exitstatus = ExitStatus::Signal(sig);
+                if pid == first_proc {
+                    *first_exit_code = Some(exitstatus);
+                }
+                let result = self.processes.reap(pid, exitstatus,
+                                                 &mut self.database);
+                self.log_unknown(pid, result)?;
+                return Ok(self.processes.is_empty());
+            }
+            wait::WaitStatus::PtraceEvent(pid, _, event) => {
+                debug!(self.logger, "ptrace event";
+                       "tid" => p(pid), "event" => event);
+                // Children of a seized process start with this instead
+                // of SIGSTOP
+                if event == libc::PTRACE_EVENT_STOP
+                    && self.handle_first_stop(pid)?
+                {
+                    return Ok(false);
+                }
+                if event == ptrace::Event::PTRACE_EVENT_EXEC as i32 {
+                    let result = self.handle_exec_event(pid);
+                    self.log_unknown(pid, result)?;
+                } else if event == ptrace::Event::PTRACE_EVENT_EXIT as i32
+                {
+                    let result = self.handle_exit_event(pid);
+                    self.log_unknown(pid, result)?;
+                } else if event == ptrace::Event::PTRACE_EVENT_FORK as i32
+                    || event == ptrace::Event::PTRACE_EVENT_VFORK as i32
+                    || event == ptrace::Event::PTRACE_EVENT_CLONE as i32
+                {
+                    let result = self.handle_fork_event(pid);
+                    self.log_unknown(pid, result)?;
+                }
+                // TODO: handle other events, tracer.c:521
+                self.dispatcher.ptrace().syscall(pid)?;
+            }
+            wait::WaitStatus::Stopped(pid, sig) => {
+                if self.handle_first_stop(pid)? {
+                    return Ok(false);
                 }
-                wait::WaitStatus::Signaled(pid, sig, _) => {
-                    let exitstatus = ExitStatus::Signal(sig);
-                    if pid == first_proc {
-                        first_exit_code = Some(exitstatus);
-                    }
-                    self.processes.exit(pid, exitstatus, &mut self.database)?;
-                    if self.processes.is_empty() {
-                        break;
-                    }
-                    continue;
-                }
-                wait::WaitStatus::PtraceEvent(pid, sig, event) => {
-                    debug!(self.logger, "ptrace event");
-                    // TODO: handle events, tracer.c:521
-                    ptrace::syscall(pid)?;
-                }
-                wait::WaitStatus::Stopped(pid, sig) => {
-                    if !self.processes.has_pid(pid) {
-                        info!(self.logger, "process {tid} appeared",
-                              tid=p(pid));
-                        self.processes.add_unknown(pid)?;
-                        Self::set_options(pid)?;
-                        // Don't resume, it will be set to ATTACHED and resumed
-                        // when the parent returns from fork()
-                        continue;
-                    }
-                    let thread = self.processes.get_pid_mut(pid);
-                    if let Some(info) = if let Thread::Allocated(info) = thread
-                    {
-                        // Have to do this in two steps to avoid borrow error
-                        Some(info.clone())
-                    } else {
-                        None
-                    } {
-                        info!(self.logger, "process {tid} attached",
-                              tid=p(pid));
-                        *thread = Thread::Attached(info);
-                        Self::set_options(pid)?;
-                        ptrace::syscall(pid)?;
-                        continue;
-                    }
 
-                    if sig == Signal::SIGTRAP {
-                        warn!(self.logger, "NOT delivering SIGTRAP";
-                              "tid" => p(pid));
-                        ptrace::syscall(pid)?;
+                if sig == Signal::SIGTRAP {
+                    warn!(self.logger, "NOT delivering SIGTRAP";
+                          "tid" => p(pid));
+                    self.dispatcher.ptrace().syscall(pid)?;
+                } else {
+                    warn!(self.logger, "caught signal";
+                          "signal" => ?sig, "tid" => p(pid));
+                    if self.dispatcher.ptrace().has_siginfo(pid) {
+                        if !suppress {
+                            self.handle_signal(pid, sig)?;
+                        }
+                        self.dispatcher.ptrace()
+                            .syscall_signal(pid, sig)?;
                     } else {
-                        warn!(self.logger, "caught signal";
-                              "signal" => ?sig, "tid" => p(pid));
-                        if ptrace::getsiginfo(pid).is_ok() {
-                            ptrace::syscall(pid)?;
-                        } else {
-                            warn!(self.logger, "NOT delivering signal";
-                                  "signal" => ?sig, "tip" => p(pid));
-                            if sig != Signal::SIGSTOP {
-                                ptrace::syscall(pid)?;
-                            }
+                        warn!(self.logger, "NOT delivering signal";
+                              "signal" => ?sig, "tip" => p(pid));
+                        if sig != Signal::SIGSTOP {
+                            self.dispatcher.ptrace().syscall(pid)?;
                         }
                     }
                 }
-                wait::WaitStatus::PtraceSyscall(pid) => {
-                    debug!(self.logger, "ptrace syscall");
-                    // TODO: syscall, tracer.c:423
-                    ptrace::syscall(pid)?;
+            }
+            wait::WaitStatus::PtraceSyscall(pid) => {
+                let result = if suppress {
+                    self.skip_syscall(pid)
+                } else {
+                    self.handle_syscall(pid)
+                };
+                self.log_unknown(pid, result)?;
+                self.dispatcher.ptrace().syscall(pid)?;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Describe a stop for the middleware.
+    fn ptrace_event(&self, status: &wait::WaitStatus) -> Option<PtraceEvent> {
+        Some(match *status {
+            wait::WaitStatus::Exited(pid, status) => {
+                PtraceEvent::Exited(pid, ExitStatus::Return(status))
+            }
+            wait::WaitStatus::Signaled(pid, sig, _) => {
+                PtraceEvent::Exited(pid, ExitStatus::Signal(sig))
+            }
+            wait::WaitStatus::PtraceEvent(pid, _, event) => {
+                PtraceEvent::Event(pid, event)
+            }
+            wait::WaitStatus::Stopped(pid, sig) => {
+                PtraceEvent::Signal(pid, sig)
+            }
+            wait::WaitStatus::PtraceSyscall(pid) => {
+                match self.processes.get_pid_checked(pid) {
+                    Ok(Thread::Attached(info)) if info.in_syscall => {
+                        PtraceEvent::SyscallExit(pid)
+                    }
+                    _ => PtraceEvent::SyscallEntry(pid),
                 }
-                _ => {}
+            }
+            _ => return None,
+        })
+    }
+
+    /// Call the middleware before a stop, and decide whether to suppress it.
+    fn pre_event(&mut self, event: &PtraceEvent) -> bool {
+        let mut suppress = false;
+        for middleware in &mut self.middleware {
+            if middleware.pre_event(event) == Action::Suppress {
+                suppress = true;
             }
         }
-        Ok(first_exit_code.expect("Trace finished but we never got the first \
-                                   process' exit code"))
+        match *event {
+            PtraceEvent::SyscallEntry(pid) => {
+                if suppress {
+                    self.suppressed_syscalls.insert(pid);
+                }
+                suppress
+            }
+            // The entry handler might be expecting the exit, such as to set
+            // the return value, so it is only skipped with the entry
+            PtraceEvent::SyscallExit(pid) => {
+                self.suppressed_syscalls.remove(&pid)
+            }
+            PtraceEvent::Signal(..) => suppress,
+            _ => false,
+        }
     }
 
-    fn set_options(pid: Pid) -> Result<(), Error> {
-        ptrace::setoptions(
-            pid,
-            ptrace::Options::PTRACE_O_TRACESYSGOOD
-                | ptrace::Options::PTRACE_O_EXITKILL
-                | ptrace::Options::PTRACE_O_TRACECLONE
-                | ptrace::Options::PTRACE_O_TRACEFORK
-                | ptrace::Options::PTRACE_O_TRACEVFORK
-                | ptrace::Options::PTRACE_O_TRACEEXEC,
-        )?;
+    /// Keep track of a syscall stop suppressed by a middleware, without
+    /// recording it.
+    fn skip_syscall(&mut self, pid: Pid) -> Result<(), Error> {
+        if let Thread::Attached(info) =
+            self.processes.get_pid_mut_checked(pid)?
+        {
+            info.in_syscall = !info.in_syscall;
+        }
+        Ok(())
+    }
+
+    /// Handle the stop of a new thread, which we might not know yet.
+    ///
+    /// Returns false if the thread was already attached, and this is a
+    /// different stop.
+    fn handle_first_stop(&mut self, pid: Pid) -> Result<bool, Error> {
+        if !self.processes.has_pid(pid) {
+            info!(self.logger, "process {tid} appeared", tid=p(pid));
+            self.processes.add_unknown(pid)?;
+            self.dispatcher.ptrace().setoptions(pid, Self::trace_options())?;
+            // Don't resume, it will be set to ATTACHED and resumed when the
+            // parent returns from fork()
+            return Ok(true);
+        }
+        let thread = self.processes.get_pid_mut_checked(pid)?;
+        if let Some(info) = if let Thread::Allocated(info) = thread {
+            // Have to do this in two steps to avoid borrow error
+            Some(info.clone())
+        } else {
+            None
+        } {
+            info!(self.logger, "process {tid} attached", tid=p(pid));
+            *thread = Thread::Attached(info);
+            self.dispatcher.ptrace().setoptions(pid, Self::trace_options())?;
+            self.dispatcher.ptrace().syscall(pid)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Attach to the first process with `PTRACE_SEIZE`.
+    ///
+    /// It is then interrupted, so that it can be resumed tracing syscalls.
+    /// Returns false if the kernel doesn't support it.
+    fn seize(&mut self, pid: Pid) -> Result<bool, Error> {
+        if !self.seize_and_stop(pid)? {
+            warn!(self.logger, "PTRACE_SEIZE is not available, using \
+                                PTRACE_TRACEME");
+            return Ok(false);
+        }
+        self.handle_first_stop(pid)?;
+        Ok(true)
+    }
+
+    /// Attach to a process with `PTRACE_SEIZE` and wait for it to stop,
+    /// leaving it stopped.
+    ///
+    /// Returns false if the kernel doesn't support it.
+    fn seize_and_stop(&self, pid: Pid) -> Result<bool, Error> {
+        let seized = Errno::result(unsafe {
+            libc::ptrace(
+                libc::PTRACE_SEIZE,
+                p(pid),
+                ptr::null_mut::<libc::c_void>(),
+                Self::trace_options().bits() as usize as *mut libc::c_void,
+            )
+        });
+        match seized {
+            Ok(_) => {}
+            Err(NixError::Sys(Errno::EIO))
+            | Err(NixError::Sys(Errno::EINVAL)) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        Errno::result(unsafe {
+            libc::ptrace(
+                libc::PTRACE_INTERRUPT,
+                p(pid),
+                ptr::null_mut::<libc::c_void>(),
+                ptr::null_mut::<libc::c_void>(),
+            )
+        })?;
+        // It is only resumed with PTRACE_SYSCALL after that stop, so the
+        // first syscall stop we see is an entry
+        loop {
+            use wait::WaitStatus::*;
+            match wait::waitpid(pid, Some(wait::WaitPidFlag::__WALL))? {
+                PtraceEvent(_, _, libc::PTRACE_EVENT_STOP) => break,
+                Exited(..) | Signaled(..) => {
+                    return Err(Error::Internal(
+                        "The program exited before it could be traced".into(),
+                    ));
+                }
+                Stopped(_, sig) => ptrace::cont(pid, sig)?,
+                status => {
+                    debug!(self.logger, "Waiting for seized process to stop";
+                           "status" => ?status);
+                    ptrace::cont(pid, None)?;
+                }
+            }
+        }
+        info!(self.logger, "process {tid} seized", tid = p(pid));
+        Ok(true)
+    }
+
+    /// Log the error from handling an event of a thread we don't know, such
+    /// as one we haven't seen yet, instead of aborting the trace.
+    fn log_unknown(
+        &self,
+        pid: Pid,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        match result {
+            Err(e) if !self.processes.has_pid(pid) => {
+                error!(self.logger, "Ignoring event: {}", e; "tid" => p(pid));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Handle a syscall-stop, dispatching to the syscall's handlers.
+    fn handle_syscall(&mut self, pid: Pid) -> Result<(), Error> {
+        let (thread, fd_tables) =
+            match self.processes.get_pid_mut_with_fds(pid)? {
+                (Thread::Attached(info), fd_tables) => (info, fd_tables),
+                _ => return Ok(()),
+            };
+        let regs = self.dispatcher.ptrace().getregs(pid)?;
+        let entry = !thread.in_syscall;
+        thread.in_syscall = entry;
+        if entry {
+            thread.syscall = regs.syscall();
+            for (i, param) in thread.params.iter_mut().enumerate() {
+                *param = regs.arg(i);
+            }
+            self.database.add_syscall(thread.syscall as u32)?;
+        } else {
+            // Log the value the program will see, which a handler might
+            // have replaced
+            let retvalue =
+                thread.return_override.unwrap_or_else(|| regs.retvalue());
+            if let Some(strace) = &mut self.strace {
+                strace.syscall(pid, thread.syscall, &thread.params,
+                               retvalue)?;
+            }
+            if let Some(log) = &mut self.syscall_log {
+                log.syscall(pid, thread.syscall, &thread.params, retvalue)?;
+            }
+        }
+        let mut context = SyscallContext {
+            logger: &self.logger,
+            config: &self.config,
+            ptrace: self.dispatcher.ptrace(),
+            database: &mut self.database,
+            injected: self.time_preload.as_ref().map(|p| p.path()),
+            stats: &self.stats,
+            in_flight: &mut self.in_flight,
+            fd_tables,
+            thread,
+            regs: &regs,
+            checksums: self.checksums.as_mut(),
+        };
+        self.dispatcher.dispatch(&mut context, entry)?;
+        if !entry {
+            thread.thread_group.watcher.borrow_mut()
+                .syscall(&self.logger, &mut self.database, thread)?;
+        } else if thread.syscall == libc::SYS_exit_group as u64
+            && thread.return_override.is_none()
+        {
+            let status = ExitStatus::Return(thread.params[0] as i32 & 0xff);
+            let thread_group = thread.thread_group.clone();
+            self.processes.exit_group(&thread_group, status,
+                                      &mut self.database)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a signal about to be delivered to a thread.
+    ///
+    /// If the program registered a handler for it, the thread is marked as
+    /// handling that signal until it calls `rt_sigreturn()`.
+    fn handle_signal(&mut self, pid: Pid, sig: Signal) -> Result<(), Error> {
+        let thread = match self.processes.get_pid_mut_checked(pid)? {
+            Thread::Attached(info) => info,
+            _ => return Ok(()),
+        };
+        let signum = sig as i32;
+        if !thread.thread_group.signal_handlers.borrow().contains(&signum) {
+            return Ok(());
+        }
+        debug!(self.logger, "Entering signal handler";
+               "tid" => p(pid), "signal" => ?sig);
+        thread.signals.push(signum);
+        self.database.set_signal_context(thread.identifier, Some(signum))
+    }
+
+    /// Handle `PTRACE_EVENT_EXEC`, when a thread successfully executed a new
+    /// program.
+    fn handle_exec_event(&mut self, pid: Pid) -> Result<(), Error> {
+        // If a non-leader thread called execve, it now has the pid of the
+        // leader, and its old tid is given as the event message
+        let former =
+            Pid::from_raw(self.dispatcher.ptrace().getevent(pid)? as i32);
+        let exec = if former != pid && self.processes.has_pid(former) {
+            match self.processes.get_pid_mut_checked(former)? {
+                Thread::Attached(info) => info.pending_exec.take(),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        let thread = match self.processes.get_pid_mut_checked(pid)? {
+            Thread::Attached(info) => info,
+            _ => return Ok(()),
+        };
+        // The new program starts with the default signal handlers
+        thread.thread_group.signal_handlers.borrow_mut().clear();
+        thread.fds.borrow_mut().exec();
+        if !thread.signals.is_empty() {
+            thread.signals.clear();
+            self.database.set_signal_context(thread.identifier, None)?;
+        }
+        let mut exec = match exec.or_else(|| thread.pending_exec.take()) {
+            Some(exec) => exec,
+            None => {
+                warn!(self.logger, "exec event but no execve() call seen";
+                      "tid" => p(pid));
+                return Ok(());
+            }
+        };
+        if let Some(preload) = &self.time_preload {
+            // Record the environment without our library
+            preload.strip_from(&mut exec.envp);
+        }
+        if let Some(name) = exec.binary.file_name() {
+            if self.config.ignored_executables.iter()
+                .any(|ignored| OsStr::new(ignored) == name)
+            {
+                info!(self.logger, "Ignoring files accessed by {}",
+                      exec.binary.display(); "tid" => p(pid));
+                thread.thread_group.record_files.set(false);
+            }
+        }
+        syscalls::exec_event(&self.logger, &mut self.database, thread, exec)
+    }
+
+    /// Handle `PTRACE_EVENT_EXIT`, when a thread is about to exit.
+    fn handle_exit_event(&mut self, pid: Pid) -> Result<(), Error> {
+        // The event message is the status that waitpid() will return
+        let status = self.dispatcher.ptrace().getevent(pid)? as i32;
+        let exitstatus = if libc::WIFSIGNALED(status) {
+            ExitStatus::Signal(Signal::from_c_int(libc::WTERMSIG(status))?)
+        } else {
+            ExitStatus::Return(libc::WEXITSTATUS(status))
+        };
+        self.processes.exit(pid, exitstatus, &mut self.database)
+    }
+
+    /// Handle a new thread or process being created by `pid`.
+    fn handle_fork_event(&mut self, pid: Pid) -> Result<(), Error> {
+        let child =
+            Pid::from_raw(self.dispatcher.ptrace().getevent(pid)? as i32);
+        let flags = match self.processes.get_pid_checked(pid)? {
+            Thread::Attached(info) => {
+                syscalls::fork::clone_flags(&self.logger,
+                                            self.dispatcher.ptrace(), info)?
+            }
+            _ => return Ok(()),
+        };
+        let is_thread = flags & libc::CLONE_THREAD as u64 != 0;
+        // A child in a different PID namespace than its parent means a new
+        // namespace, from clone(CLONE_NEWPID) or from a previous
+        // unshare(CLONE_NEWPID), which only takes effect for the children
+        let namespace = ProcessNamespace::of(child);
+        if namespace.is_some() && namespace != ProcessNamespace::of(pid) {
+            if let (Thread::Attached(info), Some(namespace)) =
+                (self.processes.get_pid_checked(pid)?, namespace)
+            {
+                info!(self.logger, "Process {parent} created PID namespace \
+                                    {ns}",
+                      parent = p(pid), ns = namespace.inode);
+                self.database.add_pid_namespace(info.identifier,
+                                                namespace.inode)?;
+            }
+        }
+        info!(self.logger, "Process {parent} created {kind} {child}",
+              parent = p(pid), child = p(child),
+              kind = if is_thread { "thread" } else { "process" });
+        let resume = self.processes.add_child(pid, child, is_thread,
+                                              &mut self.database)?;
+        if let Some(max) = self.config.max_processes {
+            if self.processes.created > max {
+                error!(self.logger,
+                       "Too many processes, the program might be forking \
+                        endlessly; stopping at the limit of {}", max;
+                       "tid" => p(child));
+                return Err(Error::MaxProcessesExceeded(max));
+            }
+        }
+        if resume {
+            info!(self.logger, "process {tid} attached", tid = p(child));
+            self.dispatcher.ptrace().syscall(child)?;
+        }
         Ok(())
     }
+
+    /// The ptrace options set on every traced thread.
+    fn trace_options() -> ptrace::Options {
+        ptrace::Options::PTRACE_O_TRACESYSGOOD
+            | ptrace::Options::PTRACE_O_EXITKILL
+            | ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACEEXEC
+            | ptrace::Options::PTRACE_O_TRACEEXIT
+    }
 }
 
 /// Run a command and trace it.
@@ -406,7 +1802,7 @@ pub fn trace<D: AsRef<Path>, C: AsRef<[u8]>>(
     command: &[C],
     database: D,
 ) -> Result<ExitStatus, Error> {
-    Tracer::new(database)?.trace(command)
+    Ok(Tracer::new(database)?.trace(command)?.0)
 }
 
 /// Run a command and trace it, replacing `argv[0]`.
@@ -421,5 +1817,5 @@ pub fn trace_arg0<D: AsRef<Path>, C: AsRef<[u8]>, C2: AsRef<[u8]>>(
     arg0: C2,
     database: D,
 ) -> Result<ExitStatus, Error> {
-    Tracer::new(database)?.trace_arg0(command, arg0)
+    Ok(Tracer::new(database)?.trace_arg0(command, arg0)?.0)
 }
@@ -0,0 +1,183 @@
+//! Reading the memory of traced processes.
+//!
+//! The reads go through a `PtraceBackend`, see `NixPtraceBackend` for how
+//! it is done on real processes. If `/proc/<pid>/mem` can't be read, for
+//! example with Yama's ptrace_scope set to 3, strings and words are read
+//! one word at a time with `PTRACE_PEEKDATA` instead.
+//!
+//! The tracee is of the same architecture as the tracer, so pointers,
+//! `long` and `size_t` in its memory are `WORD_SIZE` bytes like ours.
+
+use nix::unistd::Pid;
+
+use crate::Error;
+use crate::ptrace_backend::PtraceBackend;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Longest string read with `PTRACE_PEEKDATA`, the kernel's limit on the
+/// length of a single argument or environment string (`MAX_ARG_STRLEN`).
+const PEEK_MAX_LEN: usize = 32 * PAGE_SIZE as usize;
+
+/// The size of a pointer in the tracee, and of the words read with
+/// `PTRACE_PEEKDATA`.
+pub const WORD_SIZE: u64 = std::mem::size_of::<usize>() as u64;
+
+/// Reader for the memory of a stopped tracee.
+pub struct MemReader<'a> {
+    pid: Pid,
+    backend: &'a dyn PtraceBackend,
+}
+
+impl<'a> MemReader<'a> {
+    pub fn new(backend: &'a dyn PtraceBackend, pid: Pid) -> MemReader<'a> {
+        MemReader { pid, backend }
+    }
+
+    /// Fill the buffer with the memory at the given address.
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        self.backend.read(self.pid, addr, buf)
+    }
+
+    /// Fill the buffer one word at a time, with `PTRACE_PEEKDATA`.
+    fn peek(&self, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        for (i, chunk) in buf.chunks_mut(WORD_SIZE as usize).enumerate() {
+            let word = self.backend.peekdata(self.pid,
+                                             addr + i as u64 * WORD_SIZE)?;
+            chunk.copy_from_slice(&(word as usize).to_ne_bytes()
+                                      [..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    /// Read a 64-bit value, such as a `__u64` field.
+    pub fn read_u64(&self, addr: u64) -> Result<u64, Error> {
+        let mut buf = [0u8; 8];
+        if let Err(e) = self.read(addr, &mut buf) {
+            self.peek(addr, &mut buf).map_err(|_| e)?;
+        }
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    /// Read a pointer-sized word, such as a pointer, a `long` or a
+    /// `size_t`.
+    pub fn read_word(&self, addr: u64) -> Result<u64, Error> {
+        let mut buf = [0u8; WORD_SIZE as usize];
+        if let Err(e) = self.read(addr, &mut buf) {
+            self.peek(addr, &mut buf).map_err(|_| e)?;
+        }
+        Ok(usize::from_ne_bytes(buf) as u64)
+    }
+
+    /// Read a NUL-terminated string.
+    ///
+    /// Reads are split on page boundaries, so that we don't fail by reading
+    /// past the end of the mapping that contains the string.
+    pub fn read_string(&self, addr: u64) -> Result<Vec<u8>, Error> {
+        match self.read_string_chunks(addr) {
+            Ok(string) => Ok(string),
+            Err(e) => self.peek_string(addr, PEEK_MAX_LEN).map_err(|_| e),
+        }
+    }
+
+    fn read_string_chunks(&self, mut addr: u64) -> Result<Vec<u8>, Error> {
+        let mut result = Vec::new();
+        let mut buf = [0u8; PAGE_SIZE as usize];
+        loop {
+            let size = (PAGE_SIZE - addr % PAGE_SIZE) as usize;
+            let chunk = &mut buf[..size];
+            self.read(addr, chunk)?;
+            if let Some(end) = chunk.iter().position(|&b| b == 0) {
+                result.extend_from_slice(&chunk[..end]);
+                return Ok(result);
+            }
+            result.extend_from_slice(chunk);
+            addr += size as u64;
+        }
+    }
+
+    /// Read a NUL-terminated string one word at a time, with
+    /// `PTRACE_PEEKDATA`.
+    ///
+    /// Only aligned words are read, so that we never cross into the next
+    /// page. The string is truncated to `max_len` bytes if no NUL is found.
+    pub fn peek_string(
+        &self,
+        addr: u64,
+        max_len: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let mut result = Vec::new();
+        let mut word_addr = addr - addr % WORD_SIZE;
+        let mut skip = (addr - word_addr) as usize;
+        while result.len() < max_len {
+            let word = self.backend.peekdata(self.pid, word_addr)?;
+            for &b in &(word as usize).to_ne_bytes()[skip..] {
+                if b == 0 || result.len() == max_len {
+                    return Ok(result);
+                }
+                result.push(b);
+            }
+            word_addr += WORD_SIZE;
+            skip = 0;
+        }
+        Ok(result)
+    }
+
+    /// Read a NULL-terminated array of strings, such as `argv`.
+    pub fn read_string_array(&self, addr: u64) -> Result<Vec<Vec<u8>>, Error> {
+        let mut result = Vec::new();
+        if addr == 0 {
+            return Ok(result);
+        }
+        let mut ptr = addr;
+        loop {
+            let string = self.read_word(ptr)?;
+            if string == 0 {
+                return Ok(result);
+            }
+            result.push(self.read_string(string)?);
+            ptr += WORD_SIZE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::ptrace;
+    use nix::sys::signal::{Signal, kill, raise};
+    use nix::sys::wait::waitpid;
+    use nix::unistd::{ForkResult, fork};
+
+    use crate::ptrace_backend::NixPtraceBackend;
+    use crate::testing::FORK_LOCK;
+    use super::MemReader;
+
+    static STRING: &[u8] = b"..reprozip peekdata test string\0";
+
+    #[test]
+    fn test_peek_string() {
+        let _lock = FORK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // The child is a copy of us, so the string is at the same address
+        let child = match fork().unwrap() {
+            ForkResult::Child => {
+                ptrace::traceme().unwrap();
+                raise(Signal::SIGSTOP).unwrap();
+                unsafe { libc::_exit(0) };
+            }
+            ForkResult::Parent { child } => child,
+        };
+        waitpid(child, None).unwrap();
+
+        let backend = NixPtraceBackend;
+        let reader = MemReader::new(&backend, child);
+        // Start on an unaligned address
+        let addr = STRING.as_ptr() as u64 + 2;
+        let whole = reader.peek_string(addr, 100);
+        let truncated = reader.peek_string(addr, 9);
+        kill(child, Signal::SIGKILL).unwrap();
+        waitpid(child, None).unwrap();
+
+        assert_eq!(whole.unwrap(), b"reprozip peekdata test string");
+        assert_eq!(truncated.unwrap(), b"reprozip ");
+    }
+}
@@ -0,0 +1,112 @@
+//! Hooks run around the handling of each ptrace stop.
+//!
+//! Middleware added with `Tracer::add_middleware()` sees every stop of the
+//! traced threads before and after the tracer handles it, and can have the
+//! tracer skip some. This allows sampling or rate limiting what is recorded,
+//! or logging, without changing the tracer.
+
+use std::time::Instant;
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::{ExitStatus, p};
+
+/// A stop of a traced thread, as reported by `waitpid()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtraceEvent {
+    /// A thread exited
+    Exited(Pid, ExitStatus),
+    /// A thread is entering a syscall
+    SyscallEntry(Pid),
+    /// A thread is returning from a syscall
+    SyscallExit(Pid),
+    /// A thread received a signal, or stopped for the first time
+    Signal(Pid, Signal),
+    /// A `PTRACE_EVENT_*` stop, such as an exec or a fork
+    Event(Pid, i32),
+}
+
+impl PtraceEvent {
+    /// The thread that stopped.
+    pub fn pid(&self) -> Pid {
+        match *self {
+            PtraceEvent::Exited(pid, _)
+            | PtraceEvent::SyscallEntry(pid)
+            | PtraceEvent::SyscallExit(pid)
+            | PtraceEvent::Signal(pid, _)
+            | PtraceEvent::Event(pid, _) => pid,
+        }
+    }
+}
+
+/// What the tracer should do with a stop, returned by
+/// `TracerMiddleware::pre_event()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Handle the stop as usual
+    Continue,
+    /// Don't record anything, only resume the thread
+    ///
+    /// This applies to syscall entries, whose exit is then skipped as well,
+    /// and to signals, which are still delivered. Other stops are needed to
+    /// keep track of the processes, and are always handled.
+    Suppress,
+}
+
+/// How a stop was handled, given to `TracerMiddleware::post_event()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventResult {
+    /// The tracer handled the stop
+    Handled,
+    /// A middleware suppressed the stop
+    Suppressed,
+    /// Handling the stop failed, and the trace is aborted
+    Failed(String),
+}
+
+/// Custom processing of the ptrace stops.
+///
+/// Each middleware is called in the order they were added. A stop is
+/// suppressed if any of them asks for it, but they all see it.
+pub trait TracerMiddleware {
+    /// Called when a thread stops, before the tracer handles it.
+    fn pre_event(&mut self, event: &PtraceEvent) -> Action;
+
+    /// Called once the tracer handled the stop and resumed the thread.
+    fn post_event(&mut self, event: &PtraceEvent, result: &EventResult);
+}
+
+/// Logs every stop, with how long the tracer took to handle it.
+pub struct LoggingMiddleware {
+    logger: slog::Logger,
+    start: Option<Instant>,
+}
+
+impl LoggingMiddleware {
+    pub fn new(logger: slog::Logger) -> LoggingMiddleware {
+        LoggingMiddleware { logger, start: None }
+    }
+}
+
+impl TracerMiddleware for LoggingMiddleware {
+    fn pre_event(&mut self, _event: &PtraceEvent) -> Action {
+        self.start = Some(Instant::now());
+        Action::Continue
+    }
+
+    fn post_event(&mut self, event: &PtraceEvent, result: &EventResult) {
+        let elapsed = self.start.take().map(|start| start.elapsed());
+        match result {
+            EventResult::Failed(err) => {
+                warn!(self.logger, "Handling ptrace stop failed: {}", err;
+                      "tid" => p(event.pid()), "event" => ?event);
+            }
+            result => {
+                debug!(self.logger, "ptrace stop";
+                       "tid" => p(event.pid()), "event" => ?event,
+                       "result" => ?result, "duration" => ?elapsed);
+            }
+        }
+    }
+}
@@ -0,0 +1,4 @@
+//! Additional formats the trace can be written in, besides the database.
+
+pub mod strace;
+pub mod syscall_log;
@@ -0,0 +1,64 @@
+//! A text log of the syscalls, in a format close to strace's.
+//!
+//! This is meant for debugging the tracer: the arguments are printed as raw
+//! numbers, without decoding strings or structures.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::syscalls::syscall_name;
+
+/// Writes one line per syscall, `<pid> <name>(<args>) = <retval>`.
+pub struct StraceFormatter {
+    output: BufWriter<File>,
+}
+
+impl StraceFormatter {
+    /// Create the log file, overwriting it if it exists.
+    pub fn create(path: &Path) -> Result<StraceFormatter, Error> {
+        let file = File::create(path).map_err(|e| {
+            Error::Internal(format!("Can't create strace output {}: {}",
+                                    path.display(), e))
+        })?;
+        Ok(StraceFormatter { output: BufWriter::new(file) })
+    }
+
+    /// Write a syscall that returned.
+    pub fn syscall(
+        &mut self,
+        pid: Pid,
+        number: u64,
+        params: &[u64; 6],
+        retvalue: i64,
+    ) -> Result<(), Error> {
+        match syscall_name(number) {
+            Some(name) => write!(self.output, "{} {}(", p(pid), name)?,
+            None => write!(self.output, "{} syscall_{}(", p(pid), number)?,
+        }
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                write!(self.output, ", ")?;
+            }
+            write!(self.output, "{:#x}", param)?;
+        }
+        // Like strace, show errors as -1 and the errno
+        if (-4095..0).contains(&retvalue) {
+            let errno = Errno::from_i32(-retvalue as i32);
+            writeln!(self.output, ") = -1 {:?} ({})", errno, errno.desc())?;
+        } else {
+            writeln!(self.output, ") = {}", retvalue)?;
+        }
+        Ok(())
+    }
+
+    /// Write out everything buffered so far.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! A CSV log of the syscalls, as an audit trail independent of the database.
+//!
+//! Each line is `timestamp_ns,pid,syscall_name,arg0,...,arg5,retval`, with
+//! the arguments as raw numbers.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::syscalls::syscall_name;
+
+const HEADER: &str =
+    "timestamp_ns,pid,syscall_name,arg0,arg1,arg2,arg3,arg4,arg5,retval";
+
+/// Writes one CSV line per syscall that returned.
+pub struct SyscallLogger {
+    output: BufWriter<File>,
+}
+
+impl SyscallLogger {
+    /// Create the log file, overwriting it if it exists, and write the
+    /// header.
+    pub fn create(path: &Path) -> Result<SyscallLogger, Error> {
+        let file = File::create(path).map_err(|e| {
+            Error::Internal(format!("Can't create syscall log {}: {}",
+                                    path.display(), e))
+        })?;
+        let mut output = BufWriter::new(file);
+        writeln!(output, "{}", HEADER)?;
+        Ok(SyscallLogger { output })
+    }
+
+    /// Write a syscall that returned, timestamped with the current time.
+    pub fn syscall(
+        &mut self,
+        pid: Pid,
+        number: u64,
+        params: &[u64; 6],
+        retvalue: i64,
+    ) -> Result<(), Error> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        write!(self.output, "{},{},", timestamp, p(pid))?;
+        match syscall_name(number) {
+            Some(name) => write!(self.output, "{}", name)?,
+            None => write!(self.output, "syscall_{}", number)?,
+        }
+        for param in params {
+            write!(self.output, ",{}", param)?;
+        }
+        writeln!(self.output, ",{}", retvalue)?;
+        Ok(())
+    }
+
+    /// Write out everything buffered so far.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+//! Capturing what the traced program writes to its stdout and stderr.
+//!
+//! The program's standard output and error are replaced with pipes, read by
+//! threads of the tracer. What they read is passed on to the tracer's own
+//! stdout and stderr, and kept up to a limit to be stored in the trace, see
+//! `TraceConfig::capture_output`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::thread::{self, JoinHandle};
+
+use nix::fcntl::OFlag;
+use nix::unistd::{close, dup2, pipe2};
+
+use crate::Error;
+
+/// Default for `TraceConfig::max_output_bytes`, 10 MiB.
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 10 << 20;
+
+/// The pipes for one stream, before the fork.
+struct Pipe {
+    read: RawFd,
+    write: RawFd,
+}
+
+impl Pipe {
+    fn new() -> Result<Pipe, Error> {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+        Ok(Pipe { read, write })
+    }
+}
+
+/// Pipes replacing the program's stdout and stderr.
+pub struct OutputCapture {
+    stdout: Pipe,
+    stderr: Pipe,
+    max_bytes: usize,
+}
+
+impl OutputCapture {
+    /// Create the pipes, keeping at most `max_bytes` of each stream.
+    pub fn new(max_bytes: usize) -> Result<OutputCapture, Error> {
+        Ok(OutputCapture {
+            stdout: Pipe::new()?,
+            stderr: Pipe::new()?,
+            max_bytes,
+        })
+    }
+
+    /// Make the pipes the standard output and error, in the child.
+    pub fn redirect(&self) -> Result<(), Error> {
+        // dup2() clears the close-on-exec flag of the copies
+        dup2(self.stdout.write, libc::STDOUT_FILENO)?;
+        dup2(self.stderr.write, libc::STDERR_FILENO)?;
+        Ok(())
+    }
+
+    /// Start reading the pipes, in the parent.
+    pub fn start(self) -> Result<CaptureThreads, Error> {
+        // Only the program has the writing ends now, so the reads end when
+        // it and its children are done with them
+        close(self.stdout.write)?;
+        close(self.stderr.write)?;
+        let max_bytes = self.max_bytes;
+        let stdout = unsafe { File::from_raw_fd(self.stdout.read) };
+        let stderr = unsafe { File::from_raw_fd(self.stderr.read) };
+        Ok(CaptureThreads {
+            stdout: thread::spawn(move || {
+                forward(stdout, io::stdout(), max_bytes)
+            }),
+            stderr: thread::spawn(move || {
+                forward(stderr, io::stderr(), max_bytes)
+            }),
+        })
+    }
+}
+
+/// The threads reading the program's output.
+pub struct CaptureThreads {
+    stdout: JoinHandle<io::Result<Vec<u8>>>,
+    stderr: JoinHandle<io::Result<Vec<u8>>>,
+}
+
+impl CaptureThreads {
+    /// Wait for the pipes to be closed, and get the captured stdout and
+    /// stderr.
+    pub fn finish(self) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let join = |handle: JoinHandle<io::Result<Vec<u8>>>| {
+            handle.join()
+                .map_err(|_| {
+                    Error::Internal("Output capture thread panicked".into())
+                })?
+                .map_err(Error::from)
+        };
+        Ok((join(self.stdout)?, join(self.stderr)?))
+    }
+}
+
+/// Copy everything from a pipe to an output, keeping the first `max_bytes`.
+///
+/// Reading goes on past the limit, so that the program doesn't block
+/// writing.
+fn forward<W: Write>(
+    mut pipe: File,
+    mut output: W,
+    max_bytes: usize,
+) -> io::Result<Vec<u8>> {
+    let mut captured = Vec::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let len = match pipe.read(&mut buffer) {
+            Ok(0) => return Ok(captured),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // The tracer's own output might be closed, that's not the program's
+        // problem
+        output.write_all(&buffer[..len]).and_then(|()| output.flush()).ok();
+        let keep = len.min(max_bytes - captured.len());
+        captured.extend_from_slice(&buffer[..keep]);
+    }
+}
@@ -0,0 +1,14 @@
+//! Helpers for the paths recorded in a trace.
+
+use std::path::{Path, PathBuf};
+
+/// Make a path relative to a root directory, as seen from inside it.
+///
+/// For example `/rootfs/usr/lib/libc.so.6` with root `/rootfs` becomes
+/// `/usr/lib/libc.so.6`. Paths outside of the root are returned unchanged.
+pub fn normalize_path(path: &Path, root: &Path) -> PathBuf {
+    match path.strip_prefix(root) {
+        Ok(relative) => Path::new("/").join(relative),
+        Err(_) => path.to_owned(),
+    }
+}
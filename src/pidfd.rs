@@ -0,0 +1,56 @@
+//! Referring to a process through a descriptor, see `Tracer::attach_stable`.
+//!
+//! A PID can be reused as soon as the process it names exits and is reaped,
+//! so a PID checked at one point might be another process by the time it is
+//! attached to. A pidfd from `pidfd_open()` always refers to the process it
+//! was opened for, and signalling it fails once that process is gone.
+
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+use nix::errno::Errno;
+use nix::unistd::{Pid, close};
+
+use crate::Error;
+
+/// A descriptor for a process, closed when dropped.
+pub struct PidFd(RawFd);
+
+impl PidFd {
+    /// Open a descriptor for a process, or `None` if the kernel doesn't
+    /// have pidfds (before 5.1).
+    pub fn open(pid: Pid) -> Result<Option<PidFd>, Error> {
+        let fd = Errno::result(unsafe {
+            libc::syscall(libc::SYS_pidfd_open, libc::pid_t::from(pid), 0)
+        });
+        match fd {
+            Ok(fd) => Ok(Some(PidFd(fd as RawFd))),
+            Err(nix::Error::Sys(Errno::ENOSYS)) => Ok(None),
+            Err(e) => Err(Error::Internal(format!(
+                "Can't open process {}: {}", pid, e,
+            ))),
+        }
+    }
+
+    /// Whether the process is still running, with `pidfd_send_signal()` of
+    /// the null signal.
+    pub fn is_alive(&self) -> Result<bool, Error> {
+        let result = Errno::result(unsafe {
+            libc::syscall(libc::SYS_pidfd_send_signal, self.0, 0,
+                          ptr::null::<libc::siginfo_t>(), 0)
+        });
+        match result {
+            Ok(_) => Ok(true),
+            Err(nix::Error::Sys(Errno::ESRCH)) => Ok(false),
+            Err(e) => Err(Error::Internal(format!(
+                "pidfd_send_signal: {}", e,
+            ))),
+        }
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        close(self.0).ok();
+    }
+}
@@ -0,0 +1,243 @@
+//! Detection of the files that can't be packed as they are.
+//!
+//! Files in temporary filesystems are gone after the run, device and kernel
+//! files depend on the machine, and network mounts are not available
+//! elsewhere. The filesystem of a path is found from the mount table, either
+//! the one recorded with the trace or the current one.
+
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// Locations that are temporary filesystems on usual systems.
+const TMPFS_PATHS: [&str; 4] = ["/tmp", "/run", "/var/run", "/dev/shm"];
+
+/// Devices that exist on every machine and in containers.
+const STANDARD_DEVICES: [&str; 12] = [
+    "/dev/null", "/dev/zero", "/dev/full", "/dev/random", "/dev/urandom",
+    "/dev/tty", "/dev/stdin", "/dev/stdout", "/dev/stderr", "/dev/fd",
+    "/dev/pts", "/dev/ptmx",
+];
+
+/// Filesystem types for remote storage, as listed in `/proc/self/mountinfo`.
+const NETWORK_FILESYSTEMS: [&str; 11] = [
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "9p", "ceph", "glusterfs",
+    "afs", "lustre", "fuse.sshfs",
+];
+
+/// Why a file is not portable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortabilityWarning {
+    /// In a temporary filesystem, so gone after the run
+    TmpfsPath,
+    /// On remote storage, which other machines might not have
+    NetworkMount,
+    /// A device node, which depends on the hardware
+    DevicePath,
+    /// A file generated by the kernel, in `/proc` or `/sys`
+    ProcFsPath,
+}
+
+impl Display for PortabilityWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PortabilityWarning::TmpfsPath => {
+                write!(f, "in a temporary filesystem")
+            }
+            PortabilityWarning::NetworkMount => write!(f, "on a network mount"),
+            PortabilityWarning::DevicePath => write!(f, "a device"),
+            PortabilityWarning::ProcFsPath => {
+                write!(f, "generated by the kernel")
+            }
+        }
+    }
+}
+
+/// A line of the mount table.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Mount {
+    /// The mount source, such as `/dev/sda1` or `server:/export`
+    pub device: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    /// The options of this mount point, e.g. `rw,relatime`
+    pub options: String,
+}
+
+impl Mount {
+    /// Whether this is remote storage, which other machines might not have.
+    pub fn is_network(&self) -> bool {
+        NETWORK_FILESYSTEMS.contains(&self.fs_type.as_str())
+    }
+}
+
+/// Decode the octal escapes used in the mount table, e.g. `\040`.
+fn unescape_mount(field: &str) -> String {
+    let mut result = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let code: String = chars.by_ref().take(3).collect();
+            match u8::from_str_radix(&code, 8) {
+                Ok(byte) => result.push(byte as char),
+                Err(_) => {
+                    result.push('\\');
+                    result.push_str(&code);
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Parse a mount table in the format of `/proc/<pid>/mountinfo`.
+///
+/// Lines that can't be parsed are skipped.
+pub fn parse_mountinfo(table: &str) -> Vec<Mount> {
+    table
+        .lines()
+        .filter_map(|line| {
+            // id, parent, major:minor, root, mount point, options, optional
+            // fields up to "-", then type, source, superblock options
+            let mut fields = line.split_whitespace();
+            let mount_point = unescape_mount(fields.nth(4)?);
+            let options = fields.next()?.to_owned();
+            fields.find(|&field| field == "-")?;
+            let fs_type = fields.next()?.to_owned();
+            let device = unescape_mount(fields.next()?);
+            Some(Mount { device, mount_point: PathBuf::from(mount_point),
+                         fs_type, options })
+        })
+        .collect()
+}
+
+/// Read the current mount table, from `/proc/self/mountinfo`.
+pub fn read_mounts() -> Result<Vec<Mount>, Error> {
+    Ok(parse_mountinfo(&read_to_string("/proc/self/mountinfo")?))
+}
+
+/// Checks paths against the mounts of a machine.
+pub struct PortabilityChecker {
+    mounts: Vec<Mount>,
+}
+
+impl PortabilityChecker {
+    /// Create a checker using the current mount table.
+    pub fn new() -> Result<PortabilityChecker, Error> {
+        Ok(PortabilityChecker::with_mounts(read_mounts()?))
+    }
+
+    pub fn with_mounts(mounts: Vec<Mount>) -> PortabilityChecker {
+        PortabilityChecker { mounts }
+    }
+
+    /// The mount a path is on, the innermost one.
+    pub fn mount(&self, path: &Path) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.mount_point))
+            // Later mounts hide earlier ones on the same point
+            .max_by_key(|mount| mount.mount_point.components().count())
+    }
+
+    /// Check whether a file can be packed, returning the problem if not.
+    pub fn check_path(&self, path: &Path) -> Option<PortabilityWarning> {
+        if TMPFS_PATHS.iter().any(|tmp| path.starts_with(tmp)) {
+            return Some(PortabilityWarning::TmpfsPath);
+        }
+        if path.starts_with("/proc") || path.starts_with("/sys") {
+            return Some(PortabilityWarning::ProcFsPath);
+        }
+        if path.starts_with("/dev") {
+            if STANDARD_DEVICES.iter().any(|dev| path.starts_with(dev)) {
+                return None;
+            }
+            return Some(PortabilityWarning::DevicePath);
+        }
+        let mount = self.mount(path)?;
+        match mount.fs_type.as_str() {
+            "tmpfs" | "ramfs" => Some(PortabilityWarning::TmpfsPath),
+            "proc" | "sysfs" => Some(PortabilityWarning::ProcFsPath),
+            "devtmpfs" => Some(PortabilityWarning::DevicePath),
+            _ if mount.is_network() => Some(PortabilityWarning::NetworkMount),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{Mount, PortabilityChecker, PortabilityWarning,
+                parse_mountinfo};
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let mounts = parse_mountinfo(
+            "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+             40 22 0:35 / /home/my\\040data rw,nosuid shared:20 master:3 - \
+             nfs4 server:/export/home rw,vers=4.2\n\
+             41 22 0:36 / /broken\n",
+        );
+        assert_eq!(mounts, vec![
+            Mount {
+                device: "/dev/sda1".to_owned(),
+                mount_point: PathBuf::from("/"),
+                fs_type: "ext4".to_owned(),
+                options: "rw,relatime".to_owned(),
+            },
+            Mount {
+                device: "server:/export/home".to_owned(),
+                mount_point: PathBuf::from("/home/my data"),
+                fs_type: "nfs4".to_owned(),
+                options: "rw,nosuid".to_owned(),
+            },
+        ]);
+        assert!(!mounts[0].is_network());
+        assert!(mounts[1].is_network());
+    }
+
+    #[test]
+    fn test_check_path() {
+        let mount = |mount_point: &str, fs_type: &str| Mount {
+            device: "none".to_owned(),
+            mount_point: PathBuf::from(mount_point),
+            fs_type: fs_type.to_owned(),
+            options: "rw".to_owned(),
+        };
+        let checker = PortabilityChecker::with_mounts(vec![
+            mount("/", "ext4"),
+            mount("/home", "nfs4"),
+            mount("/home/remram/local", "ext4"),
+            mount("/mnt/scratch", "tmpfs"),
+        ]);
+        let check = |path: &str| checker.check_path(Path::new(path));
+
+        assert_eq!(check("/tmp/output.txt"),
+                   Some(PortabilityWarning::TmpfsPath));
+        assert_eq!(check("/var/run/app.pid"),
+                   Some(PortabilityWarning::TmpfsPath));
+        assert_eq!(check("/dev/shm/buffer"),
+                   Some(PortabilityWarning::TmpfsPath));
+        assert_eq!(check("/mnt/scratch/data"),
+                   Some(PortabilityWarning::TmpfsPath));
+        assert_eq!(check("/home/remram/data.csv"),
+                   Some(PortabilityWarning::NetworkMount));
+        assert_eq!(check("/dev/sda1"), Some(PortabilityWarning::DevicePath));
+        assert_eq!(check("/proc/cpuinfo"),
+                   Some(PortabilityWarning::ProcFsPath));
+        assert_eq!(check("/sys/class/net"),
+                   Some(PortabilityWarning::ProcFsPath));
+
+        assert_eq!(check("/dev/null"), None);
+        assert_eq!(check("/usr/lib/libc.so.6"), None);
+        assert_eq!(check("/home/remram/local/data.csv"), None);
+        // Not a prefix of the path, only of the name
+        assert_eq!(check("/tmpdata/file"), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Tracking of Linux namespaces.
+//!
+//! Processes in a container see different PIDs than the ones we get from
+//! ptrace. The kernel lists the PID of a thread in each nested namespace in
+//! `/proc/<pid>/status`, so both can be recorded.
+//!
+//! The mount, PID, network and user namespaces of each thread are also
+//! recorded, from the `/proc/<pid>/ns/*` links, since replaying a program
+//! that uses several needs them set up.
+
+use std::fs::{read_link, read_to_string};
+
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::database::{DatabaseOps, ProcessId};
+
+/// The PID namespace of a process, identified by the inode of its
+/// `/proc/<pid>/ns/pid` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessNamespace {
+    pub inode: u64,
+}
+
+impl ProcessNamespace {
+    /// Get the namespace a process is in.
+    pub fn of(tid: Pid) -> Option<ProcessNamespace> {
+        Some(ProcessNamespace { inode: namespace_inode(tid, "pid")? })
+    }
+}
+
+/// The kinds of namespaces recorded for each thread, as named in
+/// `/proc/<pid>/ns/`.
+pub const NAMESPACE_TYPES: [&str; 4] = ["mnt", "pid", "net", "user"];
+
+/// The namespaces of a thread, as (type, inode), in the order of
+/// `NAMESPACE_TYPES`.
+pub type Namespaces = Vec<(&'static str, u64)>;
+
+/// Get the inode identifying a namespace of a thread.
+fn namespace_inode(tid: Pid, kind: &str) -> Option<u64> {
+    // The link reads as "<kind>:[<inode>]"
+    let link = read_link(format!("/proc/{}/ns/{}", p(tid), kind)).ok()?;
+    let link = link.to_str()?;
+    let inode = link.strip_prefix(kind)?.strip_prefix(":[")?
+        .strip_suffix(']')?;
+    inode.parse().ok()
+}
+
+/// Get the namespaces a thread is in, leaving out those we can't read.
+pub fn namespaces(tid: Pid) -> Namespaces {
+    NAMESPACE_TYPES.iter()
+        .filter_map(|&kind| Some((kind, namespace_inode(tid, kind)?)))
+        .collect()
+}
+
+/// Record the namespaces of a new thread, and return them.
+pub fn record_namespaces(
+    database: &mut dyn DatabaseOps,
+    id: ProcessId,
+    tid: Pid,
+) -> Result<Namespaces, Error> {
+    let namespaces = namespaces(tid);
+    for &(kind, inode) in &namespaces {
+        database.add_process_namespace(id, kind, inode, None)?;
+    }
+    Ok(namespaces)
+}
+
+/// Get the PID of a thread in its innermost namespace, from the `NSpid`
+/// field of `/proc/<pid>/status`.
+///
+/// This is the same as the host PID if it is not in a nested namespace.
+pub fn namespace_pid(tid: Pid) -> Option<i32> {
+    let status = read_to_string(format!("/proc/{}/status", p(tid))).ok()?;
+    let line = status.lines().find(|l| l.starts_with("NSpid:"))?;
+    // One PID per level, from the host's namespace to the innermost
+    line["NSpid:".len()..].split_whitespace().last()?.parse().ok()
+}
+
+/// Record the PIDs of a new thread, in the host and in its namespace.
+pub fn record_pids(
+    database: &mut dyn DatabaseOps,
+    id: ProcessId,
+    tid: Pid,
+) -> Result<(), Error> {
+    match namespace_pid(tid) {
+        Some(namespace_pid) => database.set_pids(id, p(tid), namespace_pid),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,124 @@
+//! The hierarchy of the traced processes.
+//!
+//! Each thread or process recorded in a trace has the one that created it as
+//! parent, except for the first. Walking the tree shows what ran what.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::Error;
+use crate::database::{Database, Process, ProcessId};
+
+/// A thread or process, with its place in the tree.
+#[derive(Debug, Clone)]
+pub struct ProcessNode {
+    /// The threads and processes it created, in order
+    pub children: Vec<ProcessId>,
+    pub info: Process,
+}
+
+/// The threads and processes of a trace, linked to their parents.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessTree {
+    nodes: Vec<ProcessNode>,
+    index: HashMap<ProcessId, usize>,
+}
+
+impl ProcessTree {
+    /// Build the tree from the processes of a trace.
+    pub fn from_database(db: &Database) -> Result<ProcessTree, Error> {
+        let mut tree = ProcessTree::default();
+        for process in db.processes()? {
+            tree.index.insert(process.id, tree.nodes.len());
+            tree.nodes.push(ProcessNode {
+                children: Vec::new(),
+                info: process,
+            });
+        }
+        for i in 0..tree.nodes.len() {
+            let Process { id, parent, .. } = tree.nodes[i].info;
+            if let Some(&parent) = parent.and_then(|p| tree.index.get(&p)) {
+                tree.nodes[parent].children.push(id);
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Get a thread or process from its identifier.
+    pub fn get(&self, id: ProcessId) -> Option<&ProcessNode> {
+        self.index.get(&id).map(|&i| &self.nodes[i])
+    }
+
+    /// The processes without a known parent, normally only the first one.
+    pub fn roots(&self) -> impl Iterator<Item = &ProcessNode> {
+        self.nodes.iter().filter(move |node| {
+            node.info.parent.is_none_or(|parent| !self.index.contains_key(&parent))
+        })
+    }
+
+    /// Walk the tree depth-first, each process coming before its children.
+    pub fn dfs(&self) -> impl Iterator<Item = &ProcessNode> {
+        let mut stack: Vec<&ProcessNode> = self.roots().collect();
+        stack.reverse();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev().filter_map(|&id| {
+                self.get(id)
+            }));
+            Some(node)
+        })
+    }
+
+    /// Walk the tree breadth-first, level by level.
+    pub fn bfs(&self) -> impl Iterator<Item = &ProcessNode> {
+        let mut queue: VecDeque<&ProcessNode> = self.roots().collect();
+        std::iter::from_fn(move || {
+            let node = queue.pop_front()?;
+            queue.extend(node.children.iter().filter_map(|&id| self.get(id)));
+            Some(node)
+        })
+    }
+
+    /// The parent of a process, its parent's parent, and so on up to the
+    /// first process.
+    pub fn ancestors(&self, id: ProcessId) -> Vec<ProcessId> {
+        let mut ancestors = Vec::new();
+        let mut current = self.get(id).and_then(|node| node.info.parent);
+        while let Some(parent) = current {
+            ancestors.push(parent);
+            current = self.get(parent).and_then(|node| node.info.parent);
+        }
+        ancestors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Tracer;
+    use crate::database::ProcessId;
+    use crate::testing::{TempDatabase, TestProcess};
+    use super::{ProcessNode, ProcessTree};
+
+    #[test]
+    fn test_walk() {
+        let path = TempDatabase::new("process-tree");
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let tracer = Tracer::with_logger(&path, logger).unwrap();
+        // 0 has children 1 and 3, 1 has child 2
+        let database = TestProcess::new()
+            .spawn(TestProcess::new().fork())
+            .spawn(TestProcess::new())
+            .run(tracer);
+        let tree = ProcessTree::from_database(&database).unwrap();
+
+        let numbers = |nodes: Vec<&ProcessNode>| -> Vec<u32> {
+            nodes.iter().map(|node| node.info.id.number()).collect()
+        };
+        assert_eq!(numbers(tree.roots().collect()), vec![0]);
+        assert_eq!(numbers(tree.dfs().collect()), vec![0, 1, 2, 3]);
+        assert_eq!(numbers(tree.bfs().collect()), vec![0, 1, 3, 2]);
+        let id = ProcessId::from_number;
+        assert_eq!(tree.ancestors(id(2)), vec![id(1), id(0)]);
+        assert_eq!(tree.ancestors(id(3)), vec![id(0)]);
+        assert!(tree.ancestors(id(0)).is_empty());
+    }
+}
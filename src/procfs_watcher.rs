@@ -0,0 +1,86 @@
+//! Detection of libraries loaded at runtime, from `/proc/<pid>/maps`.
+//!
+//! Libraries loaded with `dlopen()` are normally seen when they are opened,
+//! but some loaders map files in other ways. Looking at the memory mappings
+//! of the process every few syscalls catches those.
+
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use crate::{Error, ThreadInfo, p};
+use crate::database::{DatabaseOps, FileOp};
+
+/// Default number of syscalls between two reads of the mappings.
+pub const DEFAULT_SCAN_INTERVAL: u64 = 100;
+
+/// Get the files mapped in memory, from the content of `/proc/<pid>/maps`.
+fn mapped_files(maps: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    maps.lines().filter_map(|line| {
+        // Fields: address perms offset dev inode pathname
+        let path = line.splitn(6, ' ').nth(5)?.trim_start();
+        if !path.starts_with('/') || path.ends_with(" (deleted)")
+            || path.starts_with("/dev/")
+        {
+            return None;
+        }
+        Some(PathBuf::from(path))
+    })
+}
+
+/// Watches the mappings of a process, one per thread group.
+#[derive(Clone)]
+pub struct ProcFsWatcher {
+    interval: u64,
+    syscalls: u64,
+    /// The files already seen mapped
+    seen: HashSet<PathBuf>,
+}
+
+impl ProcFsWatcher {
+    /// Create a watcher reading the mappings every `interval` syscalls, 0
+    /// meaning never.
+    pub fn new(interval: u64) -> ProcFsWatcher {
+        ProcFsWatcher {
+            interval,
+            syscalls: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Count a syscall of the process, reading its mappings if it's time.
+    pub fn syscall(
+        &mut self,
+        logger: &slog::Logger,
+        database: &mut dyn DatabaseOps,
+        thread: &ThreadInfo,
+    ) -> Result<(), Error> {
+        if self.interval == 0 || !thread.thread_group.record_files.get() {
+            return Ok(());
+        }
+        self.syscalls += 1;
+        if !self.syscalls.is_multiple_of(self.interval) {
+            return Ok(());
+        }
+        let maps = match read_to_string(format!("/proc/{}/maps",
+                                                p(thread.tid))) {
+            Ok(maps) => maps,
+            Err(e) => {
+                debug!(logger, "Can't read maps: {}", e;
+                       "tid" => p(thread.tid));
+                return Ok(());
+            }
+        };
+        for path in mapped_files(&maps) {
+            if self.seen.contains(&path) {
+                continue;
+            }
+            debug!(logger, "New file mapped: {}", path.display();
+                   "tid" => p(thread.tid));
+            database.add_file_open(thread.identifier, &path, FileOp::READ,
+                                   false)?;
+            self.seen.insert(path);
+        }
+        Ok(())
+    }
+}
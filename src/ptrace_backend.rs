@@ -0,0 +1,435 @@
+//! Access to the stopped tracees.
+//!
+//! The syscall handlers go through `PtraceBackend` rather than calling ptrace
+//! directly, so that they can be tested against `MockPtraceBackend` without
+//! a real process.
+
+use std::fs::{File, OpenOptions};
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::ptr;
+
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::syscalls::Registers;
+
+/// Request changing the syscall number on 32-bit ARM, see
+/// `arch/arm/include/uapi/asm/ptrace.h`.
+#[cfg(target_arch = "arm")]
+const PTRACE_SET_SYSCALL: libc::c_uint = 23;
+
+/// The operations on a tracee used by the tracer.
+pub trait PtraceBackend {
+    /// Fill the buffer with the memory at the given address.
+    fn read(&self, pid: Pid, addr: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Write the buffer to memory at the given address.
+    fn write(&self, pid: Pid, addr: u64, buf: &[u8]) -> Result<(), Error>;
+
+    /// Read a word of memory with `PTRACE_PEEKDATA`.
+    fn peekdata(&self, pid: Pid, addr: u64) -> Result<u64, Error>;
+
+    /// Get the registers.
+    fn getregs(&self, pid: Pid) -> Result<Registers, Error>;
+
+    /// Write a word in the `struct user` area, which holds the registers.
+    #[cfg(target_arch = "x86_64")]
+    fn pokeuser(
+        &self,
+        pid: Pid,
+        offset: u64,
+        value: u64,
+    ) -> Result<(), Error>;
+
+    /// Replace a register set, identified by its `NT_*` type.
+    #[cfg(not(target_arch = "x86_64"))]
+    fn setregset(
+        &self,
+        pid: Pid,
+        kind: libc::c_int,
+        data: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Change the number of the syscall about to be made, with
+    /// `PTRACE_SET_SYSCALL`.
+    #[cfg(target_arch = "arm")]
+    fn set_syscall_number(&self, pid: Pid, number: i32) -> Result<(), Error>;
+
+    /// Resume the tracee, until its next syscall-stop.
+    fn syscall(&self, pid: Pid) -> Result<(), Error>;
+
+    /// Resume the tracee delivering a signal, until its next syscall-stop.
+    fn syscall_signal(&self, pid: Pid, signal: Signal) -> Result<(), Error>;
+}
+
+/// The real backend, using ptrace and `/proc/<pid>/mem`.
+pub struct NixPtraceBackend;
+
+impl PtraceBackend for NixPtraceBackend {
+    fn read(&self, pid: Pid, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        // /proc/<pid>/mem lets us read large buffers in a single call instead
+        // of one word at a time with PTRACE_PEEKDATA
+        let file = File::open(format!("/proc/{}/mem", p(pid))).map_err(|e| {
+            Error::Internal(format!("Can't open memory of {}: {}", p(pid), e))
+        })?;
+        file.read_exact_at(buf, addr).map_err(|e| {
+            Error::Internal(format!(
+                "Can't read memory of {} at {:#x}: {}",
+                p(pid), addr, e,
+            ))
+        })
+    }
+
+    fn write(&self, pid: Pid, addr: u64, buf: &[u8]) -> Result<(), Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/{}/mem", p(pid)))
+            .map_err(|e| {
+                Error::Internal(format!("Can't open memory of {}: {}",
+                                        p(pid), e))
+            })?;
+        file.write_all_at(buf, addr).map_err(|e| {
+            Error::Internal(format!(
+                "Can't write memory of {} at {:#x}: {}",
+                p(pid), addr, e,
+            ))
+        })
+    }
+
+    fn peekdata(&self, pid: Pid, addr: u64) -> Result<u64, Error> {
+        unsafe {
+            // -1 is a valid word, only errno tells if the call failed
+            Errno::clear();
+            let word = libc::ptrace(
+                libc::PTRACE_PEEKDATA,
+                p(pid),
+                addr as *mut libc::c_void,
+                ptr::null_mut::<libc::c_void>(),
+            );
+            if word == -1 && Errno::last() != Errno::UnknownErrno {
+                return Err(Error::Internal(format!(
+                    "Can't peek memory of {} at {:#x}: {}",
+                    p(pid), addr, Errno::last().desc(),
+                )));
+            }
+            Ok(word as u64)
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn getregs(&self, pid: Pid) -> Result<Registers, Error> {
+        unsafe {
+            let mut regs: libc::user_regs_struct = mem::zeroed();
+            Errno::result(libc::ptrace(
+                libc::PTRACE_GETREGS,
+                p(pid),
+                ptr::null_mut::<libc::c_void>(),
+                &mut regs as *mut _ as *mut libc::c_void,
+            ))?;
+            Ok(Registers::X86_64(regs))
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn getregs(&self, pid: Pid) -> Result<Registers, Error> {
+        // There is no PTRACE_GETREGS on newer architectures, only the regset
+        // interface
+        unsafe {
+            let mut regs: crate::syscalls::UserRegs = mem::zeroed();
+            let mut iov = libc::iovec {
+                iov_base: &mut regs as *mut _ as *mut libc::c_void,
+                iov_len: mem::size_of_val(&regs),
+            };
+            Errno::result(libc::ptrace(
+                libc::PTRACE_GETREGSET,
+                p(pid),
+                libc::NT_PRSTATUS as *mut libc::c_void,
+                &mut iov as *mut _ as *mut libc::c_void,
+            ))?;
+            #[cfg(target_arch = "aarch64")]
+            return Ok(Registers::Aarch64(regs));
+            #[cfg(target_arch = "arm")]
+            return Ok(Registers::Arm(regs));
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pokeuser(
+        &self,
+        pid: Pid,
+        offset: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        unsafe {
+            Errno::result(libc::ptrace(
+                libc::PTRACE_POKEUSER,
+                p(pid),
+                offset as *mut libc::c_void,
+                value as *mut libc::c_void,
+            ))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn setregset(
+        &self,
+        pid: Pid,
+        kind: libc::c_int,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        unsafe {
+            // The kernel doesn't write through the iovec on SETREGSET
+            let mut iov = libc::iovec {
+                iov_base: data.as_ptr() as *mut libc::c_void,
+                iov_len: data.len(),
+            };
+            Errno::result(libc::ptrace(
+                libc::PTRACE_SETREGSET,
+                p(pid),
+                kind as libc::c_long as *mut libc::c_void,
+                &mut iov as *mut _ as *mut libc::c_void,
+            ))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_arch = "arm")]
+    fn set_syscall_number(&self, pid: Pid, number: i32) -> Result<(), Error> {
+        unsafe {
+            Errno::result(libc::ptrace(
+                PTRACE_SET_SYSCALL,
+                p(pid),
+                ptr::null_mut::<libc::c_void>(),
+                number as libc::c_long as *mut libc::c_void,
+            ))?;
+        }
+        Ok(())
+    }
+
+    fn syscall(&self, pid: Pid) -> Result<(), Error> {
+        ptrace::syscall(pid)?;
+        Ok(())
+    }
+
+    fn syscall_signal(&self, pid: Pid, signal: Signal) -> Result<(), Error> {
+        unsafe {
+            Errno::result(libc::ptrace(
+                libc::PTRACE_SYSCALL,
+                p(pid),
+                ptr::null_mut::<libc::c_void>(),
+                signal as libc::c_long as *mut libc::c_void,
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// A fake backend for tests, serving memory and registers set up in advance.
+#[cfg(test)]
+pub struct MockPtraceBackend {
+    /// Mapped memory, as (address, content)
+    memory: Vec<(u64, Vec<u8>)>,
+    regs: crate::syscalls::UserRegs,
+    /// Writes to the `struct user` area, as (offset, value)
+    #[cfg(target_arch = "x86_64")]
+    pub pokes: std::cell::RefCell<Vec<(u64, u64)>>,
+    /// Writes to memory, as (address, content)
+    pub writes: std::cell::RefCell<Vec<(u64, Vec<u8>)>>,
+    /// Register sets replaced, as (type, content)
+    #[cfg(not(target_arch = "x86_64"))]
+    pub regsets: std::cell::RefCell<Vec<(libc::c_int, Vec<u8>)>>,
+    /// Syscall numbers set with `PTRACE_SET_SYSCALL`
+    #[cfg(target_arch = "arm")]
+    pub syscall_numbers: std::cell::RefCell<Vec<i32>>,
+}
+
+#[cfg(test)]
+impl MockPtraceBackend {
+    pub fn new() -> MockPtraceBackend {
+        MockPtraceBackend {
+            memory: Vec::new(),
+            regs: unsafe { mem::zeroed() },
+            #[cfg(target_arch = "x86_64")]
+            pokes: Default::default(),
+            writes: Default::default(),
+            #[cfg(not(target_arch = "x86_64"))]
+            regsets: Default::default(),
+            #[cfg(target_arch = "arm")]
+            syscall_numbers: Default::default(),
+        }
+    }
+
+    /// Map some memory at the given address.
+    pub fn map(&mut self, addr: u64, content: &[u8]) {
+        self.memory.push((addr, content.to_owned()));
+    }
+
+    /// Map a NUL-terminated string at the given address.
+    pub fn map_string(&mut self, addr: u64, string: &[u8]) {
+        let mut content = string.to_owned();
+        content.push(0);
+        self.map(addr, &content);
+    }
+
+    /// Map a NULL-terminated array of pointers at the given address.
+    pub fn map_pointers(&mut self, addr: u64, pointers: &[u64]) {
+        let content: Vec<u8> = pointers
+            .iter()
+            .chain(Some(&0))
+            .flat_map(|&ptr| (ptr as usize).to_ne_bytes().to_vec())
+            .collect();
+        self.map(addr, &content);
+    }
+
+    /// Set up the registers for a syscall.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_syscall(
+        &mut self,
+        number: libc::c_long,
+        args: &[u64],
+        retvalue: i64,
+    ) {
+        self.regs.orig_rax = number as u64;
+        self.regs.rax = retvalue as u64;
+        let mut regs = [&mut self.regs.rdi, &mut self.regs.rsi,
+                        &mut self.regs.rdx, &mut self.regs.r10,
+                        &mut self.regs.r8, &mut self.regs.r9];
+        for (reg, &arg) in regs.iter_mut().zip(args) {
+            **reg = arg;
+        }
+    }
+
+    /// Set up the registers for a syscall.
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_syscall(
+        &mut self,
+        number: libc::c_long,
+        args: &[u64],
+        retvalue: i64,
+    ) {
+        self.regs.regs[8] = number as u64;
+        for (reg, &arg) in self.regs.regs.iter_mut().zip(args) {
+            *reg = arg;
+        }
+        self.regs.regs[0] = retvalue as u64;
+    }
+
+    /// Set up the registers for a syscall.
+    #[cfg(target_arch = "arm")]
+    pub fn set_syscall(
+        &mut self,
+        number: libc::c_long,
+        args: &[u64],
+        retvalue: i64,
+    ) {
+        self.regs.arm_r7 = number as libc::c_ulong;
+        let mut regs = [&mut self.regs.arm_r0, &mut self.regs.arm_r1,
+                        &mut self.regs.arm_r2, &mut self.regs.arm_r3,
+                        &mut self.regs.arm_r4, &mut self.regs.arm_r5];
+        for (reg, &arg) in regs.iter_mut().zip(args) {
+            **reg = arg as libc::c_ulong;
+        }
+        self.regs.arm_r0 = retvalue as libc::c_ulong;
+    }
+}
+
+#[cfg(test)]
+impl PtraceBackend for MockPtraceBackend {
+    fn read(&self, pid: Pid, addr: u64, buf: &mut [u8]) -> Result<(), Error> {
+        // Bytes past the end of the mapped content read as zeros, so
+        // reading a whole page around a string works
+        for (start, content) in &self.memory {
+            let end = start + content.len() as u64;
+            if *start <= addr && addr < end {
+                let available = &content[(addr - start) as usize..];
+                let len = available.len().min(buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+                buf[len..].iter_mut().for_each(|b| *b = 0);
+                return Ok(());
+            }
+        }
+        Err(Error::Internal(format!(
+            "Can't read memory of {} at {:#x}: not mapped", p(pid), addr,
+        )))
+    }
+
+    fn write(
+        &self,
+        _pid: Pid,
+        addr: u64,
+        buf: &[u8],
+    ) -> Result<(), Error> {
+        self.writes.borrow_mut().push((addr, buf.to_owned()));
+        Ok(())
+    }
+
+    fn peekdata(&self, pid: Pid, addr: u64) -> Result<u64, Error> {
+        let mut buf = [0u8; std::mem::size_of::<usize>()];
+        self.read(pid, addr, &mut buf)?;
+        Ok(usize::from_ne_bytes(buf) as u64)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn getregs(&self, _pid: Pid) -> Result<Registers, Error> {
+        Ok(Registers::X86_64(self.regs))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn getregs(&self, _pid: Pid) -> Result<Registers, Error> {
+        Ok(Registers::Aarch64(self.regs))
+    }
+
+    #[cfg(target_arch = "arm")]
+    fn getregs(&self, _pid: Pid) -> Result<Registers, Error> {
+        Ok(Registers::Arm(self.regs))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn pokeuser(
+        &self,
+        _pid: Pid,
+        offset: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        self.pokes.borrow_mut().push((offset, value));
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn setregset(
+        &self,
+        _pid: Pid,
+        kind: libc::c_int,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.regsets.borrow_mut().push((kind, data.to_owned()));
+        Ok(())
+    }
+
+    #[cfg(target_arch = "arm")]
+    fn set_syscall_number(
+        &self,
+        _pid: Pid,
+        number: i32,
+    ) -> Result<(), Error> {
+        self.syscall_numbers.borrow_mut().push(number);
+        Ok(())
+    }
+
+    fn syscall(&self, _pid: Pid) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn syscall_signal(
+        &self,
+        _pid: Pid,
+        _signal: Signal,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
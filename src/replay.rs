@@ -0,0 +1,138 @@
+//! Checks run before replaying an experiment from a pack.
+//!
+//! A pack is a directory holding the trace database as `trace.sqlite` and the
+//! packed files under `files/`, at their original absolute paths.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use slog::Drain;
+
+use crate::{Error, xattr};
+use crate::database::{Database, FileOp};
+use crate::repro_hash::hash_file;
+use crate::time_override::TimeOverride;
+
+/// A file whose content differs from when it was packed.
+#[derive(Debug, Clone)]
+pub struct HashMismatch {
+    pub path: PathBuf,
+    pub expected_hash: [u8; 32],
+    /// The hash of the file in the pack, `None` if it is missing
+    pub actual_hash: Option<[u8; 32]>,
+    /// Whether the traced program read this file
+    pub critical: bool,
+}
+
+/// An unpacked experiment, about to be replayed.
+pub struct ReplayEnvironment {
+    root: PathBuf,
+}
+
+impl ReplayEnvironment {
+    pub fn new<P: AsRef<Path>>(pack_path: P) -> ReplayEnvironment {
+        ReplayEnvironment { root: pack_path.as_ref().to_owned() }
+    }
+
+    /// Check the files of a pack against the hashes recorded when packing.
+    pub fn verify_pack(pack_path: &Path) -> Result<Vec<HashMismatch>, Error> {
+        ReplayEnvironment::new(pack_path).verify()
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        self.root.join("trace.sqlite")
+    }
+
+    /// The location of a packed file in the pack.
+    pub fn file_path(&self, path: &Path) -> PathBuf {
+        self.root.join("files").join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    /// The fake time shown to the program when it was traced, to show again
+    /// when replaying.
+    pub fn time_override(&self) -> Result<Option<TimeOverride>, Error> {
+        let logger = slog::Logger::root(slog_stdlog::StdLog.fuse(), o!());
+        Database::open(self.database_path(), logger)?.time_override()
+    }
+
+    /// Check the files of the pack against their recorded hashes.
+    pub fn verify(&self) -> Result<Vec<HashMismatch>, Error> {
+        let logger = slog::Logger::root(slog_stdlog::StdLog.fuse(), o!());
+        let database = Database::open(self.database_path(), logger)?;
+        let read: HashSet<PathBuf> = database
+            .file_opens()?
+            .into_iter()
+            .filter(|f| f.mode.contains(FileOp::READ))
+            .map(|f| f.path)
+            .collect();
+
+        let mut mismatches = Vec::new();
+        for (path, expected_hash) in database.file_hashes()? {
+            let actual_hash = match hash_file(&self.file_path(&path)) {
+                Ok(hash) => Some(hash),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(Error::Internal(format!(
+                        "Can't hash {}: {}", path.display(), e,
+                    )))
+                }
+            };
+            if actual_hash != Some(expected_hash) {
+                mismatches.push(HashMismatch {
+                    critical: read.contains(&path),
+                    path,
+                    expected_hash,
+                    actual_hash,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Find the extended attributes read by the program that are missing
+    /// from the packed files.
+    pub fn missing_xattrs(&self) -> Result<Vec<(PathBuf, Vec<u8>)>, Error> {
+        let logger = slog::Logger::root(slog_stdlog::StdLog.fuse(), o!());
+        let database = Database::open(self.database_path(), logger)?;
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        for read in database.xattr_reads()? {
+            if !seen.insert((read.path.clone(), read.name.clone())) {
+                continue;
+            }
+            let file = self.file_path(&read.path);
+            if !file.exists() {
+                continue;
+            }
+            let names = xattr::list(&file).unwrap_or_default();
+            if !names.contains(&read.name) {
+                missing.push((read.path, read.name));
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Set the SELinux context recorded for each packed file, returning how
+    /// many were set.
+    ///
+    /// This needs `CAP_MAC_ADMIN`, and a filesystem that supports it.
+    pub fn apply_labels(&self) -> Result<usize, Error> {
+        let logger = slog::Logger::root(slog_stdlog::StdLog.fuse(), o!());
+        let database = Database::open(self.database_path(), logger)?;
+        let mut count = 0;
+        for (path, label) in database.file_labels()? {
+            let file = self.file_path(&path);
+            if !file.exists() {
+                continue;
+            }
+            xattr::set(&file, xattr::SELINUX, &label).map_err(|e| {
+                Error::Internal(format!(
+                    "Can't set SELinux context of {}: {}", path.display(), e,
+                ))
+            })?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,178 @@
+//! A summary of what might keep a trace from being reproduced.
+//!
+//! This gathers the counts, the non-determinism flags and the score of a
+//! trace, see `Database::reproducibility_score()`, and rates the overall
+//! risk. It can be shown with `reprozip info --report`, or serialized for
+//! other tools; `Tracer::trace_with_report()` builds it right after a trace.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::Error;
+use crate::database::Database;
+
+/// Scores from which the risk is low or medium, see `RiskLevel`.
+const LOW_RISK_SCORE: f64 = 0.9;
+const MEDIUM_RISK_SCORE: f64 = 0.7;
+
+/// How likely a trace is to give different results when reproduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl RiskLevel {
+    fn from_score(score: f64) -> RiskLevel {
+        if score >= LOW_RISK_SCORE {
+            RiskLevel::Low
+        } else if score >= MEDIUM_RISK_SCORE {
+            RiskLevel::Medium
+        } else {
+            RiskLevel::High
+        }
+    }
+}
+
+impl fmt::Display for RiskLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RiskLevel::Low => "low",
+            RiskLevel::Medium => "medium",
+            RiskLevel::High => "high",
+        })
+    }
+}
+
+/// The reproducibility risks of a trace.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReproducibilityReport {
+    pub risk: RiskLevel,
+    /// See `ReproducibilityScore`
+    pub score: f64,
+    pub reasons: Vec<String>,
+    /// The flags of the metadata, such as `has_bpf`, set or not
+    pub flags: BTreeMap<String, bool>,
+    pub processes: u64,
+    pub executions: u64,
+    pub file_accesses: u64,
+    pub unique_files: u64,
+}
+
+impl ReproducibilityReport {
+    /// Build the report of the trace in a database.
+    pub fn new(database: &Database) -> Result<ReproducibilityReport, Error> {
+        let score = database.reproducibility_score()?;
+        Ok(ReproducibilityReport {
+            risk: RiskLevel::from_score(score.score),
+            score: score.score,
+            reasons: score.reasons,
+            flags: database.flags()?,
+            processes: database.process_count()?,
+            executions: database.executions()?.len() as u64,
+            file_accesses: database.file_open_count()?,
+            unique_files: database.unique_file_count()?,
+        })
+    }
+}
+
+impl fmt::Display for ReproducibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Risk: {} (score {:.2})", self.risk, self.score)?;
+        writeln!(f, "Processes: {}, executions: {}", self.processes,
+                 self.executions)?;
+        writeln!(f, "File accesses: {}, unique files: {}",
+                 self.file_accesses, self.unique_files)?;
+        if !self.reasons.is_empty() {
+            writeln!(f, "Reasons:")?;
+            for reason in &self.reasons {
+                writeln!(f, "    {}", reason)?;
+            }
+        }
+        let set: Vec<&str> = self.flags.iter()
+            .filter(|&(_, &value)| value)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if set.is_empty() {
+            writeln!(f, "Flags: none")
+        } else {
+            writeln!(f, "Flags: {}", set.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{ExitStatus, Tracer};
+    use crate::testing::{FORK_LOCK, TempDatabase};
+    use super::{ReproducibilityReport, RiskLevel};
+
+    #[test]
+    fn test_risk_level() {
+        assert_eq!(RiskLevel::from_score(1.0), RiskLevel::Low);
+        assert_eq!(RiskLevel::from_score(0.9), RiskLevel::Low);
+        assert_eq!(RiskLevel::from_score(0.85), RiskLevel::Medium);
+        assert_eq!(RiskLevel::from_score(0.7), RiskLevel::Medium);
+        assert_eq!(RiskLevel::from_score(0.5), RiskLevel::High);
+        assert_eq!(RiskLevel::from_score(0.0), RiskLevel::High);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut flags = BTreeMap::new();
+        flags.insert("has_bpf".to_owned(), true);
+        flags.insert("has_perf_events".to_owned(), false);
+        flags.insert("has_syslog".to_owned(), true);
+        let report = ReproducibilityReport {
+            risk: RiskLevel::from_score(0.8),
+            score: 0.8,
+            reasons: vec!["reads random numbers".to_owned()],
+            flags,
+            processes: 3,
+            executions: 2,
+            file_accesses: 40,
+            unique_files: 25,
+        };
+        assert_eq!(
+            report.to_string(),
+            "Risk: medium (score 0.80)\n\
+             Processes: 3, executions: 2\n\
+             File accesses: 40, unique files: 25\n\
+             Reasons:\n    \
+             reads random numbers\n\
+             Flags: has_bpf, has_syslog\n",
+        );
+
+        let report = ReproducibilityReport {
+            risk: RiskLevel::Low,
+            score: 1.0,
+            reasons: Vec::new(),
+            flags: BTreeMap::new(),
+            ..report
+        };
+        assert!(report.to_string().ends_with(
+            "unique files: 25\nFlags: none\n",
+        ));
+    }
+
+    #[test]
+    fn test_trace_with_report() {
+        let path = TempDatabase::new("report");
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let tracer = Tracer::with_logger(&path, logger).unwrap();
+        let (status, _, report) = {
+            let _lock = FORK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            tracer.trace_with_report(&["/bin/true"]).unwrap()
+        };
+        assert_eq!(status, ExitStatus::Return(0));
+        assert_eq!(report.processes, 1);
+        assert_eq!(report.executions, 1);
+        assert!(report.file_accesses > 0);
+    }
+}
@@ -0,0 +1,130 @@
+//! Content hashes of the files read by a trace, as an integrity manifest.
+//!
+//! `reprozip repro-hash` stores them in the `file_hashes` table of the trace
+//! and writes them out as JSON; `reprozip validate` then reports the files
+//! that changed since.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+use crate::database::{Database, FileOp};
+
+/// Compute the SHA-256 of a file's content.
+pub fn hash_file(path: &Path) -> Result<[u8; 32], io::Error> {
+    hash_reader(File::open(path)?)
+}
+
+/// Compute the SHA-256 of everything read from a reader.
+pub fn hash_reader<R: Read>(mut reader: R) -> Result<[u8; 32], io::Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 65536];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// The files whose content the traced program read.
+pub fn input_files(database: &Database) -> Result<BTreeSet<PathBuf>, Error> {
+    Ok(database.file_opens()?
+        .into_iter()
+        .filter(|open| open.mode.contains(FileOp::READ) && !open.is_directory)
+        .map(|open| open.path)
+        .collect())
+}
+
+/// Hash the files read by the traced program.
+///
+/// Files that can't be read anymore, such as temporary files, are logged
+/// and left out.
+pub fn hash_inputs(
+    database: &Database,
+    logger: &slog::Logger,
+) -> Result<BTreeMap<PathBuf, [u8; 32]>, Error> {
+    let mut hashes = BTreeMap::new();
+    for path in input_files(database)? {
+        match hash_file(&path) {
+            Ok(hash) => {
+                hashes.insert(path, hash);
+            }
+            Err(e) => {
+                warn!(logger, "Can't hash file: {}", e;
+                      "path" => %path.display());
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Find the files whose content differs from the hashes stored in the
+/// trace, or that are missing.
+pub fn changed_files(database: &Database) -> Result<Vec<PathBuf>, Error> {
+    let mut changed = Vec::new();
+    for (path, expected) in database.file_hashes()? {
+        match hash_file(&path) {
+            Ok(hash) if hash == expected => {}
+            Ok(_) => changed.push(path),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                changed.push(path)
+            }
+            Err(e) => {
+                return Err(Error::Internal(format!(
+                    "Can't hash {}: {}", path.display(), e,
+                )))
+            }
+        }
+    }
+    Ok(changed)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+/// Quote a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                write!(quoted, "\\u{:04x}", c as u32).unwrap();
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write hashes as a JSON object mapping each path to its hexadecimal hash.
+///
+/// Paths that are not valid UTF-8 are written lossily.
+pub fn write_json<W: Write>(
+    hashes: &BTreeMap<PathBuf, [u8; 32]>,
+    mut writer: W,
+) -> io::Result<()> {
+    writeln!(writer, "{{")?;
+    for (i, (path, hash)) in hashes.iter().enumerate() {
+        let sep = if i + 1 < hashes.len() { "," } else { "" };
+        writeln!(writer, "    {}: \"{}\"{}",
+                 json_string(&path.to_string_lossy()), hex(hash), sep)?;
+    }
+    writeln!(writer, "}}")
+}
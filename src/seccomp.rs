@@ -0,0 +1,59 @@
+//! Generation of seccomp profiles from the syscalls seen during a trace.
+//!
+//! The profile uses the JSON format understood by Docker and other OCI
+//! runtimes (`docker run --security-opt seccomp=profile.json`). It denies
+//! every syscall except those the traced program was observed using.
+
+use std::fmt::Write;
+
+use crate::Error;
+use crate::database::Database;
+use crate::syscalls::syscall_name;
+
+/// The libseccomp name of the architecture we trace.
+#[cfg(target_arch = "x86_64")]
+const ARCHITECTURES: &[&str] = &["SCMP_ARCH_X86_64"];
+#[cfg(target_arch = "aarch64")]
+const ARCHITECTURES: &[&str] = &["SCMP_ARCH_AARCH64"];
+#[cfg(target_arch = "arm")]
+const ARCHITECTURES: &[&str] = &["SCMP_ARCH_ARM"];
+
+/// Build a seccomp profile allowing the given syscalls.
+///
+/// Syscalls with unknown numbers are skipped.
+pub fn seccomp_profile(syscalls: &[u32]) -> String {
+    let names: Vec<_> = syscalls
+        .iter()
+        .filter_map(|&n| syscall_name(n as u64))
+        .collect();
+
+    let mut json = String::new();
+    json.push_str("{\n");
+    json.push_str("    \"defaultAction\": \"SCMP_ACT_ERRNO\",\n");
+    json.push_str("    \"architectures\": [");
+    for (i, arch) in ARCHITECTURES.iter().enumerate() {
+        if i > 0 {
+            json.push_str(", ");
+        }
+        write!(json, "\"{}\"", arch).unwrap();
+    }
+    json.push_str("],\n");
+    json.push_str("    \"syscalls\": [\n");
+    json.push_str("        {\n");
+    json.push_str("            \"names\": [\n");
+    for (i, name) in names.iter().enumerate() {
+        let sep = if i + 1 < names.len() { "," } else { "" };
+        writeln!(json, "                \"{}\"{}", name, sep).unwrap();
+    }
+    json.push_str("            ],\n");
+    json.push_str("            \"action\": \"SCMP_ACT_ALLOW\"\n");
+    json.push_str("        }\n");
+    json.push_str("    ]\n");
+    json.push_str("}\n");
+    json
+}
+
+/// Build a seccomp profile from the syscalls recorded in a trace.
+pub fn database_seccomp_profile(database: &Database) -> Result<String, Error> {
+    Ok(seccomp_profile(&database.observed_syscalls()?))
+}
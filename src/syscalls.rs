@@ -0,0 +1,1348 @@
+//! Decoding of the system calls made by the traced processes.
+//!
+//! The tracer stops each thread on entry to and exit from every syscall. The
+//! `SyscallDispatcher` looks up the handlers for that syscall, which read the
+//! arguments from the registers and memory of the tracee and record the
+//! relevant events in the database.
+
+#[cfg(not(feature = "fast-dispatch"))]
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{File, metadata, read_link, read_to_string};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+
+use crate::{Error, ThreadInfo, TraceConfig, TracerStats, p};
+use crate::checksums::ChecksumRecorder;
+use crate::database::{DatabaseOps, FileOp, ld_preload_libraries};
+use crate::fd_table::{FdEntry, FdTables, InFlightFds};
+use crate::memory::MemReader;
+use crate::ptrace_backend::PtraceBackend;
+
+mod files;
+pub mod fork;
+mod ipc;
+pub mod jit;
+mod landlock;
+mod pidfd;
+pub mod poll;
+mod process_vm;
+mod pty;
+mod random;
+mod signals;
+mod sockets;
+mod system;
+#[cfg(target_arch = "aarch64")]
+mod table_aarch64;
+#[cfg(target_arch = "arm")]
+mod table_arm;
+#[cfg(target_arch = "x86_64")]
+mod table_x86_64;
+mod timers;
+mod xattr;
+
+#[cfg(target_arch = "aarch64")]
+use self::table_aarch64::SYSCALL_TABLE;
+#[cfg(target_arch = "arm")]
+use self::table_arm::SYSCALL_TABLE;
+#[cfg(target_arch = "x86_64")]
+use self::table_x86_64::SYSCALL_TABLE;
+
+/// Register set holding the syscall number on AArch64, see `elf.h`.
+#[cfg(target_arch = "aarch64")]
+pub const NT_ARM_SYSTEM_CALL: libc::c_int = 0x404;
+
+/// The general-purpose registers, as read with `PTRACE_GETREGSET`.
+#[cfg(not(target_arch = "arm"))]
+pub type UserRegs = libc::user_regs_struct;
+#[cfg(target_arch = "arm")]
+pub type UserRegs = libc::user_regs;
+
+/// Get the name of a syscall from its number.
+pub fn syscall_name(number: u64) -> Option<&'static str> {
+    SYSCALL_TABLE
+        .binary_search_by_key(&number, |&(n, _)| n)
+        .ok()
+        .map(|i| SYSCALL_TABLE[i].1)
+}
+
+/// The registers of a stopped tracee.
+pub enum Registers {
+    #[cfg(target_arch = "x86_64")]
+    X86_64(libc::user_regs_struct),
+    #[cfg(target_arch = "aarch64")]
+    Aarch64(libc::user_regs_struct),
+    /// 32-bit ARM, with the EABI.
+    ///
+    /// The syscall number is in r7 both in ARM and in Thumb mode, which the
+    /// T bit of CPSR tells apart. With the old ABI it was instead encoded in
+    /// the `swi` instruction, which is not supported.
+    #[cfg(target_arch = "arm")]
+    Arm(libc::user_regs),
+}
+
+impl Registers {
+    /// The number of the syscall being made.
+    pub fn syscall(&self) -> u64 {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Registers::X86_64(regs) => regs.orig_rax,
+            #[cfg(target_arch = "aarch64")]
+            Registers::Aarch64(regs) => regs.regs[8],
+            #[cfg(target_arch = "arm")]
+            Registers::Arm(regs) => regs.arm_r7 as u64,
+        }
+    }
+
+    /// The n-th argument of the syscall, starting at 0.
+    ///
+    /// Only valid on syscall entry, use `SyscallContext::arg()` on exit.
+    pub fn arg(&self, n: usize) -> u64 {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Registers::X86_64(regs) => match n {
+                0 => regs.rdi,
+                1 => regs.rsi,
+                2 => regs.rdx,
+                3 => regs.r10,
+                4 => regs.r8,
+                5 => regs.r9,
+                _ => panic!("Invalid syscall argument {}", n),
+            },
+            // The first argument is overwritten by the return value
+            #[cfg(target_arch = "aarch64")]
+            Registers::Aarch64(regs) => match n {
+                0..=5 => regs.regs[n],
+                _ => panic!("Invalid syscall argument {}", n),
+            },
+            // And r0 here; 64-bit arguments take two registers
+            #[cfg(target_arch = "arm")]
+            Registers::Arm(regs) => match n {
+                0 => regs.arm_r0 as u64,
+                1 => regs.arm_r1 as u64,
+                2 => regs.arm_r2 as u64,
+                3 => regs.arm_r3 as u64,
+                4 => regs.arm_r4 as u64,
+                5 => regs.arm_r5 as u64,
+                _ => panic!("Invalid syscall argument {}", n),
+            },
+        }
+    }
+
+    /// The value returned by the syscall, valid on syscall exit.
+    pub fn retvalue(&self) -> i64 {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Registers::X86_64(regs) => regs.rax as i64,
+            #[cfg(target_arch = "aarch64")]
+            Registers::Aarch64(regs) => regs.regs[0] as i64,
+            #[cfg(target_arch = "arm")]
+            Registers::Arm(regs) => regs.arm_r0 as i32 as i64,
+        }
+    }
+
+    /// Change the number of the syscall about to be made.
+    ///
+    /// Setting it to -1 on entry skips the syscall, which then returns
+    /// `-ENOSYS`.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_syscall(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        number: i64,
+    ) -> Result<(), Error> {
+        // Offset of orig_rax in struct user
+        ptrace.pokeuser(pid, 15 * 8, number as u64)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_syscall(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        number: i64,
+    ) -> Result<(), Error> {
+        // x8 can't be changed directly, it has its own register set
+        ptrace.setregset(pid, NT_ARM_SYSTEM_CALL,
+                         &(number as i32).to_ne_bytes())
+    }
+
+    #[cfg(target_arch = "arm")]
+    pub fn set_syscall(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        number: i64,
+    ) -> Result<(), Error> {
+        // The kernel keeps its own copy of r7, changed with
+        // PTRACE_SET_SYSCALL
+        ptrace.set_syscall_number(pid, number as i32)
+    }
+
+    /// Change the value returned by the syscall, on syscall exit.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_retvalue(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        value: i64,
+    ) -> Result<(), Error> {
+        // Offset of rax in struct user
+        ptrace.pokeuser(pid, 10 * 8, value as u64)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn set_retvalue(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        value: i64,
+    ) -> Result<(), Error> {
+        // There is no POKEUSER, so write back all the registers
+        let Registers::Aarch64(mut regs) = ptrace.getregs(pid)?;
+        regs.regs[0] = value as u64;
+        Registers::write_all(ptrace, pid, &regs)
+    }
+
+    #[cfg(target_arch = "arm")]
+    pub fn set_retvalue(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        value: i64,
+    ) -> Result<(), Error> {
+        let Registers::Arm(mut regs) = ptrace.getregs(pid)?;
+        regs.arm_r0 = value as libc::c_ulong;
+        Registers::write_all(ptrace, pid, &regs)
+    }
+
+    /// Write back all the general-purpose registers.
+    #[cfg(not(target_arch = "x86_64"))]
+    fn write_all(
+        ptrace: &dyn PtraceBackend,
+        pid: Pid,
+        regs: &UserRegs,
+    ) -> Result<(), Error> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                regs as *const _ as *const u8,
+                std::mem::size_of_val(regs),
+            )
+        };
+        ptrace.setregset(pid, libc::NT_PRSTATUS, bytes)
+    }
+}
+
+/// A program about to be executed, read on entry to `execve`.
+#[derive(Clone)]
+pub struct ExecInfo {
+    pub binary: PathBuf,
+    pub argv: Vec<Vec<u8>>,
+    pub envp: Vec<Vec<u8>>,
+}
+
+/// The state available to a syscall handler.
+pub struct SyscallContext<'a> {
+    pub logger: &'a slog::Logger,
+    pub config: &'a TraceConfig,
+    pub ptrace: &'a dyn PtraceBackend,
+    pub database: &'a mut dyn DatabaseOps,
+    /// A file of our own loaded into the program, not to be recorded
+    pub injected: Option<&'a Path>,
+    pub stats: &'a TracerStats,
+    pub in_flight: &'a mut InFlightFds,
+    pub fd_tables: &'a mut FdTables,
+    pub thread: &'a mut ThreadInfo,
+    pub regs: &'a Registers,
+    /// Hashes the files read, with `TraceConfig::record_checksums`
+    pub checksums: Option<&'a mut ChecksumRecorder>,
+}
+
+impl<'a> SyscallContext<'a> {
+    fn memory(&self) -> MemReader<'a> {
+        MemReader::new(self.ptrace, self.thread.tid)
+    }
+
+    /// The n-th argument of the syscall, as saved on entry.
+    fn arg(&self, n: usize) -> u64 {
+        self.thread.params[n]
+    }
+
+    /// The value returned by the syscall, valid on syscall exit.
+    fn retvalue(&self) -> i64 {
+        self.regs.retvalue()
+    }
+
+    /// Write to the memory of the process.
+    fn write_memory(&self, addr: u64, buf: &[u8]) -> Result<(), Error> {
+        self.ptrace.write(self.thread.tid, addr, buf)
+    }
+
+    /// Prevent the syscall from running, making it return `value` instead.
+    ///
+    /// Only valid on syscall entry. The exit handlers won't be called.
+    fn skip(&mut self, value: i64) -> Result<(), Error> {
+        Registers::set_syscall(self.ptrace, self.thread.tid, -1)?;
+        self.thread.return_override = Some(value);
+        Ok(())
+    }
+
+    /// Prevent the syscall from running, making it fail with `errno`.
+    ///
+    /// Only valid on syscall entry. The exit handlers won't be called.
+    fn deny(&mut self, errno: i32) -> Result<(), Error> {
+        self.skip(-errno as i64)
+    }
+
+    /// Make a path absolute, relative to the process's working directory.
+    fn resolve(&self, path: &[u8]) -> PathBuf {
+        resolve(&self.thread.thread_group.working_dir.borrow(), path)
+    }
+
+    /// Make a path absolute, relative to a directory file descriptor.
+    ///
+    /// This is the resolution done by the `*at()` syscalls, `AT_FDCWD`
+    /// meaning the working directory.
+    fn resolve_at(&self, dirfd: i32, path: &[u8]) -> Result<PathBuf, Error> {
+        if path.starts_with(b"/") || dirfd == libc::AT_FDCWD {
+            return Ok(self.resolve(path));
+        }
+        let dir = self.fd_path(dirfd)?;
+        Ok(resolve(&dir, path))
+    }
+
+    /// Get the path of a file descriptor of the process.
+    fn fd_path(&self, fd: i32) -> Result<PathBuf, Error> {
+        Ok(read_link(self.fd_proc_path(fd))?)
+    }
+
+    /// The path of a file descriptor in `/proc`, to access the same file.
+    fn fd_proc_path(&self, fd: i32) -> PathBuf {
+        PathBuf::from(format!("/proc/{}/fd/{}", p(self.thread.tid), fd))
+    }
+}
+
+/// Make a path read from the tracee absolute.
+pub fn resolve(working_dir: &Path, path: &[u8]) -> PathBuf {
+    working_dir.join(OsStr::from_bytes(path))
+}
+
+type HandlerFn = fn(&mut SyscallContext) -> Result<(), Error>;
+
+/// The functions handling a syscall, on entry and exit.
+struct Handler {
+    name: &'static str,
+    entry: Option<HandlerFn>,
+    exit: Option<HandlerFn>,
+}
+
+impl Handler {
+    /// The same functions, for another number of the syscall.
+    fn alias(&self, name: &'static str) -> Handler {
+        Handler { name, entry: self.entry, exit: self.exit }
+    }
+}
+
+/// The handlers, by syscall number.
+#[cfg(not(feature = "fast-dispatch"))]
+type HandlerTable = HashMap<u64, Handler>;
+
+/// The handlers, sorted by syscall number, for a binary search.
+///
+/// There are few of them and the numbers are small, so they fit in a few
+/// cache lines, which makes the lookup cheaper than hashing.
+#[cfg(feature = "fast-dispatch")]
+#[derive(Default)]
+struct HandlerTable(Vec<(u64, Handler)>);
+
+#[cfg(feature = "fast-dispatch")]
+impl HandlerTable {
+    fn new() -> HandlerTable {
+        HandlerTable::default()
+    }
+
+    fn insert(&mut self, number: u64, handler: Handler) {
+        match self.0.binary_search_by_key(&number, |&(n, _)| n) {
+            Ok(i) => self.0[i].1 = handler,
+            Err(i) => self.0.insert(i, (number, handler)),
+        }
+    }
+
+    fn get(&self, number: &u64) -> Option<&Handler> {
+        self.0.binary_search_by_key(number, |&(n, _)| n)
+            .ok()
+            .map(|i| &self.0[i].1)
+    }
+}
+
+#[cfg(feature = "fast-dispatch")]
+impl std::ops::Index<&u64> for HandlerTable {
+    type Output = Handler;
+
+    fn index(&self, number: &u64) -> &Handler {
+        self.get(number).expect("no handler for syscall")
+    }
+}
+
+/// Table of the syscall handlers, indexed by syscall number.
+pub struct SyscallDispatcher<P: PtraceBackend> {
+    handlers: HandlerTable,
+    ptrace: P,
+}
+
+impl<P: PtraceBackend> SyscallDispatcher<P> {
+    pub fn new(ptrace: P) -> SyscallDispatcher<P> {
+        let mut handlers = HandlerTable::new();
+        let mut add = |number: libc::c_long, name, entry, exit| {
+            handlers.insert(number as u64, Handler { name, entry, exit });
+        };
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_open, "open", None, Some(files::open_exit));
+        add(libc::SYS_openat, "openat", None, Some(files::openat_exit));
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_creat, "creat", None, Some(files::creat_exit));
+        add(libc::SYS_execve, "execve", Some(execve_entry), None);
+        add(libc::SYS_execveat, "execveat", Some(execveat_entry), None);
+        add(libc::SYS_exit, "exit", Some(exit_entry), None);
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_fork, "fork", Some(fork::fork_entry), None);
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_vfork, "vfork", Some(fork::fork_entry), None);
+        add(libc::SYS_clone, "clone", Some(fork::fork_entry), None);
+        add(libc::SYS_clone3, "clone3", Some(fork::fork_entry), None);
+        add(libc::SYS_exit_group, "exit_group", Some(exit_entry), None);
+        add(libc::SYS_syslog, "syslog", Some(system::syslog_entry), None);
+        add(libc::SYS_perf_event_open, "perf_event_open",
+            Some(system::perf_event_open_entry), None);
+        add(libc::SYS_bpf, "bpf", Some(system::bpf_entry), None);
+        add(libc::SYS_timerfd_create, "timerfd_create",
+            None, Some(timers::timerfd_create_exit));
+        add(libc::SYS_timerfd_settime, "timerfd_settime",
+            None, Some(timers::timerfd_settime_exit));
+        add(libc::SYS_timerfd_gettime, "timerfd_gettime",
+            None, Some(timers::timer_gettime_exit));
+        add(libc::SYS_timer_gettime, "timer_gettime",
+            None, Some(timers::timer_gettime_exit));
+        add(libc::SYS_nanosleep, "nanosleep",
+            Some(timers::nanosleep_entry), None);
+        add(libc::SYS_clock_nanosleep, "clock_nanosleep",
+            Some(timers::clock_nanosleep_entry), None);
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_poll, "poll",
+            Some(poll::poll_entry), Some(poll::wait_exit));
+        add(libc::SYS_ppoll, "ppoll",
+            Some(poll::ppoll_entry), Some(poll::wait_exit));
+        #[cfg(target_arch = "x86_64")]
+        add(libc::SYS_select, "select",
+            Some(poll::select_entry), Some(poll::wait_exit));
+        #[cfg(target_arch = "arm")]
+        add(libc::SYS__newselect, "_newselect",
+            Some(poll::select_entry), Some(poll::wait_exit));
+        add(libc::SYS_pselect6, "pselect6",
+            Some(poll::pselect6_entry), Some(poll::wait_exit));
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_epoll_wait, "epoll_wait",
+            Some(poll::epoll_wait_entry), Some(poll::wait_exit));
+        add(libc::SYS_epoll_pwait, "epoll_pwait",
+            Some(poll::epoll_wait_entry), Some(poll::wait_exit));
+        add(libc::SYS_getxattr, "getxattr", None, Some(xattr::getxattr_exit));
+        add(libc::SYS_lgetxattr, "lgetxattr",
+            None, Some(xattr::getxattr_exit));
+        add(libc::SYS_fgetxattr, "fgetxattr",
+            None, Some(xattr::fgetxattr_exit));
+        add(libc::SYS_setxattr, "setxattr", None, Some(xattr::setxattr_exit));
+        add(libc::SYS_lsetxattr, "lsetxattr",
+            None, Some(xattr::setxattr_exit));
+        add(libc::SYS_fsetxattr, "fsetxattr",
+            None, Some(xattr::fsetxattr_exit));
+        add(libc::SYS_sched_setaffinity, "sched_setaffinity",
+            Some(system::sched_setaffinity_entry), None);
+        add(libc::SYS_getcpu, "getcpu", None, Some(system::getcpu_exit));
+        add(libc::SYS_mprotect, "mprotect",
+            Some(jit::mprotect_entry), Some(jit::mprotect_exit));
+        add(libc::SYS_pkey_mprotect, "pkey_mprotect",
+            Some(jit::mprotect_entry), Some(jit::mprotect_exit));
+        add(libc::SYS_quotactl, "quotactl",
+            None, Some(system::quotactl_exit));
+        add(libc::SYS_landlock_create_ruleset, "landlock_create_ruleset",
+            None, Some(landlock::landlock_create_ruleset_exit));
+        add(libc::SYS_landlock_add_rule, "landlock_add_rule",
+            None, Some(landlock::landlock_add_rule_exit));
+        add(libc::SYS_landlock_restrict_self, "landlock_restrict_self",
+            None, Some(landlock::landlock_restrict_self_exit));
+        add(libc::SYS_rt_sigaction, "rt_sigaction",
+            None, Some(signals::rt_sigaction_exit));
+        add(libc::SYS_rt_sigreturn, "rt_sigreturn",
+            Some(signals::rt_sigreturn_entry), None);
+        add(libc::SYS_prctl, "prctl", Some(system::prctl_entry), None);
+        add(libc::SYS_unshare, "unshare",
+            None, Some(system::namespace_change_exit));
+        add(libc::SYS_setns, "setns",
+            None, Some(system::namespace_change_exit));
+        add(libc::SYS_seccomp, "seccomp", Some(system::seccomp_entry), None);
+        add(libc::SYS_linkat, "linkat", None, Some(files::linkat_exit));
+        add(libc::SYS_close, "close", None, Some(files::close_exit));
+        add(libc::SYS_close_range, "close_range",
+            None, Some(files::close_range_exit));
+        add(libc::SYS_copy_file_range, "copy_file_range",
+            None, Some(files::copy_file_range_exit));
+        add(libc::SYS_readahead, "readahead",
+            None, Some(files::readahead_exit));
+        #[cfg(not(target_arch = "arm"))]
+        add(libc::SYS_fadvise64, "fadvise64",
+            None, Some(files::fadvise64_exit));
+        #[cfg(target_arch = "arm")]
+        add(libc::SYS_arm_fadvise64_64, "arm_fadvise64_64",
+            None, Some(files::fadvise64_exit));
+        add(files::SYS_CACHESTAT, "cachestat",
+            None, Some(files::cachestat_exit));
+        add(libc::SYS_preadv, "preadv", None, Some(files::vectored_io_exit));
+        add(libc::SYS_pwritev, "pwritev",
+            None, Some(files::vectored_io_exit));
+        add(libc::SYS_preadv2, "preadv2",
+            None, Some(files::vectored_io_exit));
+        add(libc::SYS_pwritev2, "pwritev2",
+            None, Some(files::vectored_io_exit));
+        add(libc::SYS_dup, "dup", None, Some(files::dup_exit));
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_dup2, "dup2", None, Some(files::dup_exit));
+        add(libc::SYS_dup3, "dup3", None, Some(files::dup_exit));
+        add(libc::SYS_fcntl, "fcntl", None, Some(files::fcntl_exit));
+        add(libc::SYS_ioctl, "ioctl", None, Some(files::ioctl_exit));
+        add(libc::SYS_sendmsg, "sendmsg", None, Some(sockets::sendmsg_exit));
+        add(libc::SYS_recvmsg, "recvmsg", None, Some(sockets::recvmsg_exit));
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_signalfd, "signalfd", None, Some(ipc::signalfd_exit));
+        add(libc::SYS_signalfd4, "signalfd4",
+            None, Some(ipc::signalfd_exit));
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        add(libc::SYS_eventfd, "eventfd", None, Some(ipc::eventfd_exit));
+        add(libc::SYS_eventfd2, "eventfd2", None, Some(ipc::eventfd_exit));
+        add(libc::SYS_userfaultfd, "userfaultfd",
+            None, Some(ipc::userfaultfd_exit));
+        add(libc::SYS_read, "read", None, Some(ipc::read_exit));
+        add(libc::SYS_getrandom, "getrandom",
+            Some(random::getrandom_entry), None);
+        add(libc::SYS_pidfd_open, "pidfd_open",
+            None, Some(pidfd::pidfd_open_exit));
+        add(libc::SYS_pidfd_getfd, "pidfd_getfd",
+            None, Some(pidfd::pidfd_getfd_exit));
+        add(libc::SYS_pidfd_send_signal, "pidfd_send_signal",
+            None, Some(pidfd::pidfd_send_signal_exit));
+        add(libc::SYS_process_vm_readv, "process_vm_readv",
+            None, Some(process_vm::process_vm_exit));
+        add(libc::SYS_process_vm_writev, "process_vm_writev",
+            None, Some(process_vm::process_vm_exit));
+        add(libc::SYS_chdir, "chdir", None, Some(files::chdir_exit));
+        add(libc::SYS_fchdir, "fchdir", None, Some(files::fchdir_exit));
+        add(libc::SYS_getdents64, "getdents64",
+            None, Some(files::getdents_exit));
+        // Older numbers of the same syscalls, still used by some programs
+        #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+        {
+            let mut alias = |number: libc::c_long, name, primary| {
+                let handler = handlers[&(primary as u64)].alias(name);
+                handlers.insert(number as u64, handler);
+            };
+            alias(libc::SYS_getdents, "getdents", libc::SYS_getdents64);
+        }
+        SyscallDispatcher { handlers, ptrace }
+    }
+
+    /// The backend used to access the tracees.
+    pub fn ptrace(&self) -> &P {
+        &self.ptrace
+    }
+
+    pub fn ptrace_mut(&mut self) -> &mut P {
+        &mut self.ptrace
+    }
+
+    /// Handle a syscall stop, either entry or exit.
+    pub fn dispatch(
+        &self,
+        context: &mut SyscallContext,
+        entry: bool,
+    ) -> Result<(), Error> {
+        let syscall = context.thread.syscall;
+        if !entry {
+            if let Some(value) = context.thread.return_override.take() {
+                Registers::set_retvalue(context.ptrace, context.thread.tid,
+                                        value)?;
+                return Ok(());
+            }
+        }
+        if let Some(handler) = self.handlers.get(&syscall) {
+            let func = if entry { handler.entry } else { handler.exit };
+            if let Some(func) = func {
+                trace!(context.logger, "syscall {}", handler.name;
+                       "tid" => p(context.thread.tid), "entry" => entry);
+                func(context)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn execve_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let memory = context.memory();
+    let binary = memory.read_string(context.arg(0))?;
+    let argv = memory.read_string_array(context.arg(1))?;
+    let envp = memory.read_string_array(context.arg(2))?;
+    context.thread.pending_exec = Some(ExecInfo {
+        binary: context.resolve(&binary),
+        argv,
+        envp,
+    });
+    Ok(())
+}
+
+/// Entry of `execveat()`, like `execve()` but relative to a directory
+/// descriptor.
+///
+/// The directory is taken from the fd table if it is known there. With
+/// `AT_EMPTY_PATH` and an empty path, the descriptor is the program itself,
+/// as with `fexecve()`.
+fn execveat_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let memory = context.memory();
+    let dirfd = context.arg(0) as i32;
+    let path = memory.read_string(context.arg(1))?;
+    let argv = memory.read_string_array(context.arg(2))?;
+    let envp = memory.read_string_array(context.arg(3))?;
+    let flags = context.arg(4) as i32;
+    let known = match context.thread.fds.borrow().get(dirfd) {
+        Some(FdEntry::File { path }) => Some(path.clone()),
+        _ => None,
+    };
+    let binary = if path.is_empty() && flags & libc::AT_EMPTY_PATH != 0 {
+        match known {
+            Some(binary) => binary,
+            None => context.fd_path(dirfd)?,
+        }
+    } else {
+        match known {
+            Some(dir) if !path.starts_with(b"/") => resolve(&dir, &path),
+            _ => context.resolve_at(dirfd, &path)?,
+        }
+    };
+    context.thread.pending_exec = Some(ExecInfo { binary, argv, envp });
+    Ok(())
+}
+
+/// Record the CPU time of a thread about to exit, from `/proc`.
+///
+/// The threads killed by another thread's `exit_group()` don't get here, so
+/// their CPU time is not known.
+fn exit_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let tid = p(context.thread.tid);
+    let stat = match read_to_string(format!("/proc/{}/task/{}/stat",
+                                            tid, tid)) {
+        Ok(stat) => stat,
+        Err(_) => return Ok(()),
+    };
+    // Fields after the command name, which is in parentheses, start with
+    // the state; utime and stime are the 12th and 13th, in clock ticks
+    let fields: Vec<&str> = match stat.rfind(')') {
+        Some(pos) => stat[pos + 1..].split_whitespace().collect(),
+        None => return Ok(()),
+    };
+    let ticks = match (fields.get(11).and_then(|f| f.parse::<u64>().ok()),
+                       fields.get(12).and_then(|f| f.parse::<u64>().ok())) {
+        (Some(utime), Some(stime)) => utime + stime,
+        _ => return Ok(()),
+    };
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    context.database.set_cpu_time(context.thread.identifier,
+                                  ticks * 1_000_000_000 / ticks_per_sec)
+}
+
+/// Check that a program didn't lose its setuid or setgid privileges.
+///
+/// The kernel doesn't change the credentials of a traced process on
+/// `execve()`, unless the tracer is privileged, so the process keeps running
+/// as its user. That is seen from the effective IDs after the exec.
+fn check_setuid(binary: &Path, tid: Pid) -> Result<(), Error> {
+    let stat = match metadata(binary) {
+        Ok(stat) => stat,
+        Err(_) => return Ok(()),
+    };
+    let mode = stat.mode();
+    if mode & (libc::S_ISUID | libc::S_ISGID) == 0 {
+        return Ok(());
+    }
+    let status = match read_to_string(format!("/proc/{}/status", p(tid))) {
+        Ok(status) => status,
+        Err(_) => return Ok(()),
+    };
+    // The lines list the real, effective, saved and filesystem IDs
+    let effective = |name: &str| {
+        status.lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|ids| ids.split_whitespace().nth(1)?.parse::<u32>().ok())
+    };
+    let uid_refused = mode & libc::S_ISUID != 0
+        && effective("Uid:").is_some_and(|uid| uid != stat.uid());
+    // Setgid without group execute means mandatory locking, not a privilege
+    let gid_refused = mode & libc::S_ISGID != 0
+        && mode & libc::S_IXGRP != 0
+        && effective("Gid:").is_some_and(|gid| gid != stat.gid());
+    if uid_refused || gid_refused {
+        Err(Error::SetuidTraceRefused(binary.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Maximum number of nested interpreters, same as Linux's limit.
+const MAX_INTERPRETER_DEPTH: usize = 5;
+
+/// Read the interpreter from the `#!` line of a script.
+///
+/// Returns `None` if the file doesn't start with `#!`. Like the kernel, only
+/// the first 256 bytes are considered.
+fn read_shebang(path: &Path) -> io::Result<Option<PathBuf>> {
+    let mut buf = [0u8; 256];
+    let mut file = File::open(path)?;
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let line = match buf[..len].strip_prefix(b"#!") {
+        Some(rest) => rest.split(|&b| b == b'\n').next().unwrap(),
+        None => return Ok(None),
+    };
+    let interpreter = line
+        .split(|&b| b == b' ' || b == b'\t')
+        .find(|w| !w.is_empty());
+    Ok(interpreter.map(|i| PathBuf::from(OsStr::from_bytes(i))))
+}
+
+/// Record a successful execution, on `PTRACE_EVENT_EXEC`.
+pub fn exec_event(
+    logger: &slog::Logger,
+    database: &mut dyn DatabaseOps,
+    thread: &ThreadInfo,
+    exec: ExecInfo,
+) -> Result<(), Error> {
+    let working_dir = &*thread.thread_group.working_dir.borrow();
+    info!(logger, "Process {tid} executed {binary}",
+          tid = p(thread.tid), binary = exec.binary.display());
+    let requires_setuid = match check_setuid(&exec.binary, thread.tid) {
+        Ok(()) => false,
+        Err(err) => {
+            warn!(logger, "{}", err; "tid" => p(thread.tid));
+            true
+        }
+    };
+    database.add_exec(
+        thread.identifier,
+        &exec.binary,
+        &exec.argv,
+        &exec.envp,
+        working_dir,
+        requires_setuid,
+    )?;
+    if !thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    database.add_file_open(thread.identifier, &exec.binary,
+                           FileOp::READ, false)?;
+
+    // If the kernel ended up running a different program, we executed a
+    // script, and need its interpreter(s) too
+    let exe = read_link(format!("/proc/{}/exe", p(thread.tid)));
+    if let Ok(exe) = exe {
+        if exe.canonicalize().ok() != exec.binary.canonicalize().ok() {
+            let mut script = exec.binary.clone();
+            for _ in 0..MAX_INTERPRETER_DEPTH {
+                let interpreter = match read_shebang(&script) {
+                    Ok(Some(interpreter)) => working_dir.join(interpreter),
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!(logger, "Can't read script {}: {}",
+                              script.display(), e);
+                        break;
+                    }
+                };
+                info!(logger, "Script {script} uses interpreter {interp}",
+                      script = script.display(),
+                      interp = interpreter.display();
+                      "tid" => p(thread.tid));
+                database.add_file_open(thread.identifier, &interpreter,
+                                       FileOp::READ, false)?;
+                script = interpreter;
+            }
+        }
+    }
+
+    // Libraries from LD_PRELOAD are loaded into the program, and will be
+    // needed to run it again
+    for library in ld_preload_libraries(&exec.envp) {
+        if library.components().count() == 1 {
+            // No slash, the loader looks it up in its search path
+            info!(logger, "LD_PRELOAD library {lib} from search path",
+                  lib = library.display(); "tid" => p(thread.tid));
+            continue;
+        }
+        let library = working_dir.join(library);
+        info!(logger, "LD_PRELOAD library {lib}",
+              lib = library.display(); "tid" => p(thread.tid));
+        database.add_file_open(thread.identifier, &library,
+                               FileOp::READ, false)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use nix::fcntl::AtFlags;
+    use nix::unistd::Pid;
+
+    use crate::{ThreadGroup, ThreadInfo, TraceConfig};
+    use crate::database::{CacheStat, DatabaseOps, FileOp};
+    use crate::database::mock::MockDatabase;
+    use crate::fd_table::{FdEntry, FdTable, FdTables};
+    use crate::procfs_watcher::ProcFsWatcher;
+    use crate::ptrace_backend::{MockPtraceBackend, PtraceBackend};
+    use super::{SyscallContext, SyscallDispatcher};
+
+    /// A pid that doesn't exist, so nothing is read from `/proc`.
+    const TID: i32 = i32::MAX;
+
+    /// Set up a database and a thread in `/work` to run handlers on.
+    fn setup() -> (MockDatabase, ThreadInfo) {
+        let mut database = MockDatabase::new();
+        let working_dir = Path::new("/work");
+        let identifier = database.add_process(None, working_dir, false)
+            .unwrap();
+        let thread_group = Rc::new(ThreadGroup {
+            working_dir: RefCell::new(working_dir.to_owned()),
+            watcher: RefCell::new(ProcFsWatcher::new(0)),
+            signal_handlers: Default::default(),
+            record_files: Cell::new(true),
+            jit_warned: Cell::new(false),
+            random_calls: Cell::new(0),
+            depth: 0,
+        });
+        let thread = ThreadInfo::new(identifier, Pid::from_raw(TID),
+                                     thread_group, Default::default());
+        (database, thread)
+    }
+
+    /// Run a syscall through the dispatcher, on entry and exit, like the
+    /// `Tracer` does.
+    fn run_syscall(
+        dispatcher: &SyscallDispatcher<MockPtraceBackend>,
+        database: &mut MockDatabase,
+        thread: &mut ThreadInfo,
+    ) {
+        run_syscall_with_fds(dispatcher, database, thread,
+                             &mut Default::default());
+    }
+
+    /// Run a syscall, with the fd tables of other threads.
+    fn run_syscall_with_fds(
+        dispatcher: &SyscallDispatcher<MockPtraceBackend>,
+        database: &mut MockDatabase,
+        thread: &mut ThreadInfo,
+        fd_tables: &mut FdTables,
+    ) {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let config = TraceConfig::default();
+        let regs = dispatcher.ptrace().getregs(thread.tid).unwrap();
+        thread.syscall = regs.syscall();
+        for (i, param) in thread.params.iter_mut().enumerate() {
+            *param = regs.arg(i);
+        }
+        for &entry in &[true, false] {
+            let mut context = SyscallContext {
+                logger: &logger,
+                config: &config,
+                ptrace: dispatcher.ptrace(),
+                database: &mut *database,
+                injected: None,
+                stats: &Default::default(),
+                in_flight: &mut Default::default(),
+                fd_tables: &mut *fd_tables,
+                thread: &mut *thread,
+                regs: &regs,
+                checksums: None,
+            };
+            dispatcher.dispatch(&mut context, entry).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_openat() {
+        let (mut database, mut thread) = setup();
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map_string(0x1000, b"data/input.txt");
+        ptrace.set_syscall(
+            libc::SYS_openat,
+            &[libc::AT_FDCWD as u64, 0x1000, libc::O_RDONLY as u64],
+            3,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].path, Path::new("/work/data/input.txt"));
+        assert_eq!(opens[0].mode, FileOp::READ);
+    }
+
+    #[test]
+    fn test_openat_failed() {
+        let (mut database, mut thread) = setup();
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map_string(0x1000, b"missing");
+        ptrace.set_syscall(
+            libc::SYS_openat,
+            &[libc::AT_FDCWD as u64, 0x1000, libc::O_RDONLY as u64],
+            -libc::ENOENT as i64,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        assert!(database.file_opens.is_empty());
+    }
+
+    #[test]
+    fn test_execve() {
+        let (mut database, mut thread) = setup();
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map_string(0x1000, b"bin/prog");
+        ptrace.map_string(0x1100, b"prog");
+        ptrace.map_string(0x1200, b"--flag");
+        ptrace.map_pointers(0x2000, &[0x1100, 0x1200]);
+        ptrace.map_string(0x1300, b"HOME=/home/user");
+        ptrace.map_pointers(0x3000, &[0x1300]);
+        ptrace.set_syscall(libc::SYS_execve, &[0x1000, 0x2000, 0x3000], 0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        let exec = thread.pending_exec.take().unwrap();
+        assert_eq!(exec.binary, Path::new("/work/bin/prog"));
+        assert_eq!(exec.argv, vec![b"prog".to_vec(), b"--flag".to_vec()]);
+        assert_eq!(exec.envp, vec![b"HOME=/home/user".to_vec()]);
+    }
+
+    #[test]
+    fn test_close_range() {
+        let (mut database, mut thread) = setup();
+        // Real descriptors, numbered like the kernel does
+        let files: Vec<File> = (0..3)
+            .map(|_| File::open("/dev/null").unwrap())
+            .collect();
+        let fds: Vec<i32> = files.iter().map(AsRawFd::as_raw_fd).collect();
+        for &fd in &fds {
+            thread.fds.borrow_mut().insert(
+                fd,
+                FdEntry::File { path: PathBuf::from("/dev/null") },
+                false,
+            );
+        }
+        let first = *fds.iter().min().unwrap();
+        let last = *fds.iter().max().unwrap();
+        // Another thread of the same process
+        let shared = thread.fds.clone();
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(
+            libc::SYS_close_range,
+            &[first as u64, last as u64, libc::CLOSE_RANGE_CLOEXEC as u64],
+            0,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        for &fd in &fds {
+            assert!(shared.borrow().get(fd).is_some());
+            assert!(shared.borrow().cloexec(fd));
+        }
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(
+            libc::SYS_close_range,
+            &[first as u64, first as u64, libc::CLOSE_RANGE_UNSHARE as u64],
+            0,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert!(thread.fds.borrow().get(first).is_none());
+        assert!(thread.fds.borrow().get(last).is_some());
+        // The other thread still has it
+        assert!(shared.borrow().get(first).is_some());
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(
+            libc::SYS_close_range,
+            &[0, u32::MAX as u64, 0],
+            0,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        for &fd in &fds {
+            assert!(thread.fds.borrow().get(fd).is_none());
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "arm"))]
+    fn test_fadvise() {
+        let (mut database, mut thread) = setup();
+        thread.fds.borrow_mut().insert(
+            3,
+            FdEntry::File { path: PathBuf::from("/data/input") },
+            false,
+        );
+        for &advice in &[libc::POSIX_FADV_WILLNEED,
+                         libc::POSIX_FADV_SEQUENTIAL,
+                         libc::POSIX_FADV_DONTNEED]
+        {
+            let mut ptrace = MockPtraceBackend::new();
+            ptrace.set_syscall(libc::SYS_fadvise64,
+                               &[3, 0, 0, advice as u64], 0);
+            let dispatcher = SyscallDispatcher::new(ptrace);
+            run_syscall(&dispatcher, &mut database, &mut thread);
+        }
+
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 2);
+        assert_eq!(opens[0].mode, FileOp::READ);
+        assert_eq!(opens[0].access_pattern.as_deref(), Some("sequential"));
+        assert_eq!(opens[1].mode, FileOp::CACHE_DROP);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_getdents_alias() {
+        let (mut database, mut thread) = setup();
+        thread.fds.borrow_mut().insert(
+            3,
+            FdEntry::File { path: PathBuf::from("/data") },
+            false,
+        );
+        for &syscall in &[libc::SYS_getdents, libc::SYS_getdents64] {
+            let mut ptrace = MockPtraceBackend::new();
+            ptrace.set_syscall(syscall, &[3, 0x1000, 4096], 48);
+            let dispatcher = SyscallDispatcher::new(ptrace);
+            run_syscall(&dispatcher, &mut database, &mut thread);
+        }
+
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].path, Path::new("/data"));
+        assert_eq!(opens[0].mode, FileOp::READ);
+        assert!(opens[0].is_directory);
+    }
+
+    #[test]
+    fn test_cachestat() {
+        let (mut database, mut thread) = setup();
+        thread.fds.borrow_mut().insert(
+            3,
+            FdEntry::File { path: PathBuf::from("/data/input") },
+            false,
+        );
+        let mut ptrace = MockPtraceBackend::new();
+        let stat: Vec<u8> = [10u64, 2, 1, 4, 0].iter()
+            .flat_map(|n| n.to_ne_bytes().to_vec())
+            .collect();
+        ptrace.map(0x2000, &stat);
+        ptrace.set_syscall(super::files::SYS_CACHESTAT, &[3, 0x1000, 0x2000],
+                           0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].mode, FileOp::STAT);
+        let queries = &database.cache_stat_queries;
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].path, Path::new("/data/input"));
+        assert_eq!(queries[0].stat, CacheStat {
+            nr_cache: 10,
+            nr_dirty: 2,
+            nr_writeback: 1,
+            nr_evicted: 4,
+        });
+    }
+
+    #[test]
+    fn test_userfaultfd() {
+        let (mut database, mut thread) = setup();
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(libc::SYS_userfaultfd,
+                           &[libc::O_CLOEXEC as u64], 5);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        // struct uffd_msg for a write fault by thread 42
+        let mut msg = vec![0x12u8, 0, 0, 0, 0, 0, 0, 0];
+        for field in &[1u64, 0x7f00_0000_1000, 42] {
+            msg.extend_from_slice(&field.to_ne_bytes());
+        }
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map(0x3000, &msg);
+        ptrace.set_syscall(libc::SYS_read, &[5, 0x3000, 64], 32);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        assert!(database.file_opens.is_empty());
+        let faults = &database.page_faults;
+        assert_eq!(faults.len(), 1);
+        assert_eq!(faults[0].address, 0x7f00_0000_1000);
+        assert_eq!(faults[0].flags, 1);
+        assert_eq!(faults[0].thread_id, Some(42));
+    }
+
+    #[test]
+    fn test_process_vm_readv() {
+        let (mut database, mut thread) = setup();
+        // Two remote ranges, of which only 24 bytes were read
+        let mut iov = Vec::new();
+        for &field in &[0x7f00_1000usize, 16, 0x7f00_2000, 16] {
+            iov.extend_from_slice(&field.to_ne_bytes());
+        }
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map(0x3000, &iov);
+        ptrace.set_syscall(libc::SYS_process_vm_readv,
+                           &[1234, 0x4000, 1, 0x3000, 2, 0], 24);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        let accesses = &database.cross_process_memory_accesses;
+        assert_eq!(accesses.len(), 2);
+        assert_eq!(accesses[0].target_pid, 1234);
+        assert!(!accesses[0].is_write);
+        assert_eq!(accesses[0].address, 0x7f00_1000);
+        assert_eq!(accesses[0].length, 16);
+        assert_eq!(accesses[1].address, 0x7f00_2000);
+        assert_eq!(accesses[1].length, 8);
+        assert!(database.flag("has_cross_process_memory"));
+    }
+
+    /// An ioctl request as a syscall argument, they are 32-bit on ARM.
+    fn ioctl<T: Into<u64>>(request: T) -> u64 {
+        request.into()
+    }
+
+    #[test]
+    fn test_pty() {
+        let (mut database, mut thread) = setup();
+        // ptsname() on a master opened before the trace
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map(0x3000, &3u32.to_ne_bytes());
+        ptrace.set_syscall(libc::SYS_ioctl,
+                           &[4, ioctl(libc::TIOCGPTN), 0x3000], 0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert_eq!(
+            thread.fds.borrow().get(4),
+            Some(&FdEntry::Pty { master: true, number: 3 }),
+        );
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(
+            libc::SYS_ioctl,
+            &[4, ioctl(libc::TIOCGPTPEER),
+              (libc::O_RDWR | libc::O_CLOEXEC) as u64],
+            5,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert_eq!(
+            thread.fds.borrow().get(5),
+            Some(&FdEntry::Pty { master: false, number: 3 }),
+        );
+        assert!(thread.fds.borrow().cloexec(5));
+
+        // Opening the slave by path
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map_string(0x4000, b"/dev/pts/3");
+        ptrace.set_syscall(
+            libc::SYS_openat,
+            &[libc::AT_FDCWD as u64, 0x4000, libc::O_WRONLY as u64],
+            6,
+        );
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert_eq!(
+            thread.fds.borrow().get(6),
+            Some(&FdEntry::Pty { master: false, number: 3 }),
+        );
+
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 2);
+        assert!(opens.iter().all(|o| o.path == Path::new("/dev/pts/3")));
+        assert_eq!(opens[0].mode, FileOp::READ | FileOp::WRITE);
+        assert_eq!(opens[1].mode, FileOp::WRITE);
+    }
+
+    #[test]
+    fn test_pidfd_getfd() {
+        let (mut database, mut thread) = setup();
+        let other_tid = TID - 1;
+        let other = Pid::from_raw(other_tid);
+        let other_fds = Rc::new(RefCell::new(FdTable::default()));
+        other_fds.borrow_mut().insert(
+            5,
+            FdEntry::File { path: PathBuf::from("/data/input") },
+            false,
+        );
+        let mut fd_tables = FdTables::default();
+        fd_tables.register(thread.tid, &thread.fds);
+        fd_tables.register(other, &other_fds);
+        // A stale entry, for a descriptor that was closed
+        thread.fds.borrow_mut().insert(
+            9,
+            FdEntry::File { path: PathBuf::from("/old") },
+            false,
+        );
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(libc::SYS_pidfd_open, &[other_tid as u64, 0], 7);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall_with_fds(&dispatcher, &mut database, &mut thread,
+                             &mut fd_tables);
+        assert_eq!(thread.fds.borrow().get(7), Some(&FdEntry::PidFd(other)));
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(libc::SYS_pidfd_getfd, &[7, 5, 0], 8);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall_with_fds(&dispatcher, &mut database, &mut thread,
+                             &mut fd_tables);
+        assert_eq!(
+            thread.fds.borrow().get(8),
+            Some(&FdEntry::File { path: PathBuf::from("/data/input") }),
+        );
+        assert!(thread.fds.borrow().cloexec(8));
+
+        // Not known in the other process
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(libc::SYS_pidfd_getfd, &[7, 6, 0], 9);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall_with_fds(&dispatcher, &mut database, &mut thread,
+                             &mut fd_tables);
+        assert!(thread.fds.borrow().get(9).is_none());
+    }
+
+    #[test]
+    fn test_landlock() {
+        let (mut database, mut thread) = setup();
+        thread.fds.borrow_mut().insert(
+            4,
+            FdEntry::File { path: PathBuf::from("/usr") },
+            false,
+        );
+
+        // Handles execute, write_file and read_file
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map(0x1000, &0b111u64.to_ne_bytes());
+        ptrace.set_syscall(libc::SYS_landlock_create_ruleset,
+                           &[0x1000, 8, 0], 5);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert_eq!(thread.fds.borrow().get(5),
+                   Some(&FdEntry::LandlockRuleset(thread.identifier, 5)));
+
+        // Allows execute and read_file beneath /usr
+        let mut ptrace = MockPtraceBackend::new();
+        let mut attr = 0b101u64.to_ne_bytes().to_vec();
+        attr.extend_from_slice(&4i32.to_ne_bytes());
+        ptrace.map(0x2000, &attr);
+        ptrace.set_syscall(libc::SYS_landlock_add_rule, &[5, 1, 0x2000, 0], 0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+        assert!(database.landlock_policies().is_empty());
+
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.set_syscall(libc::SYS_landlock_restrict_self, &[5, 0], 0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        let policies = database.landlock_policies();
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].handled_access, 0b111);
+        assert_eq!(policies[0].rules, vec![(PathBuf::from("/usr"), 0b101)]);
+        assert!(database.flag("has_landlock"));
+    }
+
+    #[test]
+    fn test_execveat() {
+        let (mut database, mut thread) = setup();
+        thread.fds.borrow_mut().insert(
+            5,
+            FdEntry::File { path: PathBuf::from("/opt/tools") },
+            false,
+        );
+        thread.fds.borrow_mut().insert(
+            6,
+            FdEntry::File { path: PathBuf::from("/opt/tools/run") },
+            true,
+        );
+        let execveat = |dirfd: u64, flags: AtFlags| {
+            let mut ptrace = MockPtraceBackend::new();
+            ptrace.map_string(0x1000, b"bin/prog");
+            ptrace.map_string(0x1100, b"prog");
+            ptrace.map_string(0x1200, b"");
+            ptrace.map_pointers(0x2000, &[0x1100]);
+            ptrace.map_pointers(0x3000, &[]);
+            let pathname = if flags.contains(AtFlags::AT_EMPTY_PATH) {
+                0x1200
+            } else {
+                0x1000
+            };
+            ptrace.set_syscall(
+                libc::SYS_execveat,
+                &[dirfd, pathname, 0x2000, 0x3000, flags.bits() as u64],
+                0,
+            );
+            SyscallDispatcher::new(ptrace)
+        };
+
+        run_syscall(&execveat(libc::AT_FDCWD as u64, AtFlags::empty()),
+                    &mut database, &mut thread);
+        let exec = thread.pending_exec.take().unwrap();
+        assert_eq!(exec.binary, Path::new("/work/bin/prog"));
+        assert_eq!(exec.argv, vec![b"prog".to_vec()]);
+        assert!(exec.envp.is_empty());
+
+        run_syscall(&execveat(5, AtFlags::empty()),
+                    &mut database, &mut thread);
+        let exec = thread.pending_exec.take().unwrap();
+        assert_eq!(exec.binary, Path::new("/opt/tools/bin/prog"));
+
+        // Like fexecve()
+        run_syscall(&execveat(6, AtFlags::AT_EMPTY_PATH),
+                    &mut database, &mut thread);
+        let exec = thread.pending_exec.take().unwrap();
+        assert_eq!(exec.binary, Path::new("/opt/tools/run"));
+    }
+
+    #[test]
+    fn test_chdir() {
+        let (mut database, mut thread) = setup();
+        let mut ptrace = MockPtraceBackend::new();
+        ptrace.map_string(0x1000, b"subdir");
+        ptrace.set_syscall(libc::SYS_chdir, &[0x1000], 0);
+        let dispatcher = SyscallDispatcher::new(ptrace);
+        run_syscall(&dispatcher, &mut database, &mut thread);
+
+        assert_eq!(*thread.thread_group.working_dir.borrow(),
+                   Path::new("/work/subdir"));
+        let opens = &database.file_opens;
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].path, Path::new("/work/subdir"));
+        assert_eq!(opens[0].mode, FileOp::WDIR);
+        assert!(opens[0].is_directory);
+    }
+}
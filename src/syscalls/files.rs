@@ -0,0 +1,565 @@
+//! Handlers for the syscalls opening files.
+
+use std::cell::RefCell;
+use std::fs::{File, metadata, read_link};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::{Error, p, xattr};
+use crate::database::{CacheStat, FileOp};
+use crate::fd_table::FdEntry;
+use crate::memory::{MemReader, WORD_SIZE};
+use super::{SyscallContext, pty};
+
+/// Most entries in an array of `struct iovec`, like the kernel's `IOV_MAX`.
+const IOV_MAX: u64 = 1024;
+
+/// Number of `cachestat()`, which libc doesn't have for every architecture;
+/// it is the same on all of them.
+pub const SYS_CACHESTAT: libc::c_long = 451;
+
+/// Get the access mode from the flags passed to `open()`.
+fn flags_to_mode(flags: i32) -> FileOp {
+    let mut mode = match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => FileOp::READ,
+        libc::O_WRONLY => FileOp::WRITE,
+        _ => FileOp::READ | FileOp::WRITE,
+    };
+    if flags & libc::O_TRUNC != 0 {
+        // The previous content is discarded, not read
+        mode.remove(FileOp::READ);
+    }
+    mode
+}
+
+/// Record a file opened by a successful syscall.
+fn record_open(
+    context: &mut SyscallContext,
+    path: &Path,
+    flags: i32,
+) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 || context.injected == Some(path) {
+        return Ok(());
+    }
+    if flags & libc::O_TMPFILE == libc::O_TMPFILE {
+        // An unnamed file in that directory, not a dependency unless it gets
+        // linked, see linkat_exit()
+        debug!(context.logger, "Temporary file in {}", path.display();
+               "tid" => p(context.thread.tid), "fd" => fd);
+        context.thread.fds.borrow_mut().insert(
+            fd as i32,
+            FdEntry::TempFile { directory: path.to_owned() },
+            flags & libc::O_CLOEXEC != 0,
+        );
+        return Ok(());
+    }
+    let entry = pty::opened_entry(context, path, fd as i32)
+        .unwrap_or_else(|| FdEntry::File { path: path.to_owned() });
+    context.thread.fds.borrow_mut().insert(
+        fd as i32,
+        entry,
+        flags & libc::O_CLOEXEC != 0,
+    );
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let mode = flags_to_mode(flags);
+    let stat = metadata(context.fd_proc_path(fd as i32)).ok();
+    let is_directory = stat.as_ref().map(|s| s.is_dir()).unwrap_or(false);
+
+    if let (Some(max), Some(stat)) = (context.config.max_file_size, &stat) {
+        if mode == FileOp::WRITE && stat.len() > max {
+            debug!(context.logger, "Not recording big file {}",
+                   path.display(); "tid" => p(context.thread.tid),
+                   "size" => stat.len());
+            return Ok(());
+        }
+    }
+
+    if mode.contains(FileOp::READ) && !is_directory {
+        let proc_path = context.fd_proc_path(fd as i32);
+        if let Some(checksums) = context.checksums.as_deref_mut() {
+            if !checksums.has(path) {
+                match File::open(proc_path) {
+                    Ok(file) => checksums.record(path, file),
+                    Err(e) => {
+                        debug!(context.logger, "Can't open file to hash: {}",
+                               e; "path" => %path.display());
+                    }
+                }
+            }
+        }
+    }
+
+    context.database.add_file_open(context.thread.identifier, path, mode,
+                                   is_directory)?;
+    if context.config.record_mac_labels {
+        record_label(context, path)?;
+    }
+    Ok(())
+}
+
+/// Record the SELinux context of a file, if it has one.
+fn record_label(
+    context: &mut SyscallContext,
+    path: &Path,
+) -> Result<(), Error> {
+    match xattr::get(path, xattr::SELINUX) {
+        Ok(label) => context.database.add_file_label(path, &label),
+        Err(ref e) if e.raw_os_error() == Some(libc::ENODATA)
+            || e.raw_os_error() == Some(libc::ENOTSUP) => Ok(()),
+        Err(e) => {
+            debug!(context.logger, "Can't get SELinux context: {}", e;
+                   "path" => %path.display());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+pub fn open_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let path = context.memory().read_string(context.arg(0))?;
+    let path = context.resolve(&path);
+    record_open(context, &path, context.arg(1) as i32)
+}
+
+pub fn openat_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let path = context.memory().read_string(context.arg(1))?;
+    let path = context.resolve_at(context.arg(0) as i32, &path)?;
+    record_open(context, &path, context.arg(2) as i32)
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+pub fn creat_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let path = context.memory().read_string(context.arg(0))?;
+    let path = context.resolve(&path);
+    record_open(context, &path,
+                libc::O_CREAT | libc::O_WRONLY | libc::O_TRUNC)
+}
+
+/// The file descriptor a `linkat()` links, if it links one.
+///
+/// This is either the file descriptor itself with `AT_EMPTY_PATH`, or its
+/// `/proc/self/fd/<fd>` link with `AT_SYMLINK_FOLLOW`.
+fn linked_fd(context: &SyscallContext, old_path: &[u8]) -> Option<i32> {
+    let flags = context.arg(4) as i32;
+    if old_path.is_empty() && flags & libc::AT_EMPTY_PATH != 0 {
+        return Some(context.arg(0) as i32);
+    }
+    if flags & libc::AT_SYMLINK_FOLLOW == 0 {
+        return None;
+    }
+    let own = format!("/proc/{}/fd/", p(context.thread.tid));
+    let fd = old_path.strip_prefix(b"/proc/self/fd/")
+        .or_else(|| old_path.strip_prefix(own.as_bytes()))?;
+    std::str::from_utf8(fd).ok()?.parse().ok()
+}
+
+/// Record an `O_TMPFILE` file getting a name, making it a real file.
+pub fn linkat_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let old_path = context.memory().read_string(context.arg(1))?;
+    let fd = match linked_fd(context, &old_path) {
+        Some(fd) => fd,
+        None => return Ok(()),
+    };
+    match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::TempFile { .. }) => {}
+        _ => return Ok(()),
+    }
+    let new_path = context.memory().read_string(context.arg(3))?;
+    let new_path = context.resolve_at(context.arg(2) as i32, &new_path)?;
+    context.thread.fds.borrow_mut()
+        .replace(fd, FdEntry::File { path: new_path.clone() });
+    info!(context.logger, "Temporary file linked to {}", new_path.display();
+          "tid" => p(context.thread.tid), "fd" => fd);
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    context.database.add_file_open(context.thread.identifier, &new_path,
+                                   FileOp::WRITE, false)
+}
+
+pub fn close_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() == 0 {
+        context.thread.fds.borrow_mut()
+            .remove(context.arg(0) as i32);
+    }
+    Ok(())
+}
+
+/// Exit of `close_range()`, closing the descriptors in a range, or marking
+/// them close-on-exec.
+///
+/// With `CLOSE_RANGE_UNSHARE`, the thread stops sharing its descriptors with
+/// the other threads first, so only its own copy changes.
+pub fn close_range_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let first = context.arg(0) as u32;
+    let last = context.arg(1) as u32;
+    let flags = context.arg(2) as u32;
+    debug!(context.logger, "close_range({}, {})", first, last;
+           "tid" => p(context.thread.tid), "flags" => flags);
+    if flags & libc::CLOSE_RANGE_UNSHARE != 0 {
+        let fds = context.thread.fds.borrow().clone();
+        context.thread.fds = Rc::new(RefCell::new(fds));
+        context.fd_tables.register(context.thread.tid, &context.thread.fds);
+    }
+    context.thread.fds.borrow_mut().close_range(
+        first,
+        last,
+        flags & libc::CLOSE_RANGE_CLOEXEC != 0,
+    );
+    Ok(())
+}
+
+/// Exit of `readahead()`, preloading a file, which means it will be read.
+///
+/// The file might not be read through that descriptor afterwards, e.g. if
+/// it is mapped, so its content at this time is needed.
+pub fn readahead_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0
+        || !context.thread.thread_group.record_files.get()
+    {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let path = match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::File { path }) => path.clone(),
+        _ => return Ok(()),
+    };
+    debug!(context.logger, "readahead({})", path.display();
+           "tid" => p(context.thread.tid), "fd" => fd);
+    context.database.add_file_open(context.thread.identifier, &path,
+                                   FileOp::READ, false)
+}
+
+/// Exit of `getdents64()` and `getdents()`, listing a directory.
+///
+/// Opening the directory is usually recorded already, but not when the
+/// descriptor was inherited from outside the trace.
+pub fn getdents_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() <= 0
+        || !context.thread.thread_group.record_files.get()
+    {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let path = match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::File { path }) => Some(path.clone()),
+        _ => None,
+    };
+    let path = match path {
+        Some(path) => path,
+        None => context.fd_path(fd)?,
+    };
+    debug!(context.logger, "getdents({})", path.display();
+           "tid" => p(context.thread.tid), "fd" => fd);
+    context.database.add_file_open(context.thread.identifier, &path,
+                                   FileOp::READ, true)
+}
+
+/// Exit of `cachestat()`, querying how much of a file is in the page cache.
+///
+/// Only the file's metadata is read, but a program looking at this probably
+/// adapts to the memory available, so the answer is recorded as well.
+pub fn cachestat_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let path = match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::File { path }) => Some(path.clone()),
+        _ => None,
+    };
+    let path = match path {
+        Some(path) => path,
+        None => context.fd_path(fd)?,
+    };
+    // struct cachestat: nr_cache, nr_dirty, nr_writeback, nr_evicted, then
+    // nr_recently_evicted
+    let memory = context.memory();
+    let addr = context.arg(2);
+    let stat = CacheStat {
+        nr_cache: memory.read_u64(addr)?,
+        nr_dirty: memory.read_u64(addr + 8)?,
+        nr_writeback: memory.read_u64(addr + 16)?,
+        nr_evicted: memory.read_u64(addr + 24)?,
+    };
+    debug!(context.logger, "cachestat({})", path.display();
+           "tid" => p(context.thread.tid), "fd" => fd, "stat" => ?stat);
+    let id = context.thread.identifier;
+    if context.thread.thread_group.record_files.get() {
+        context.database.add_file_open(id, &path, FileOp::STAT, false)?;
+        context.database.add_cache_stat(id, &path, &stat)?;
+    }
+    Ok(())
+}
+
+/// The argument of `fadvise64()` holding the advice. 32-bit ARM has it
+/// second, so that the 64-bit offset and length fall in register pairs.
+#[cfg(not(target_arch = "arm"))]
+const FADVISE_ADVICE_ARG: usize = 3;
+#[cfg(target_arch = "arm")]
+const FADVISE_ADVICE_ARG: usize = 1;
+
+/// Exit of `fadvise64()`, from `posix_fadvise()`.
+///
+/// `POSIX_FADV_WILLNEED` has the kernel read the file ahead, like
+/// `readahead()`, and `POSIX_FADV_DONTNEED` drops it from the cache.
+/// `POSIX_FADV_SEQUENTIAL` and `POSIX_FADV_RANDOM` tell how the file will be
+/// read, which a replay could use to prefetch it.
+pub fn fadvise64_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0
+        || !context.thread.thread_group.record_files.get()
+    {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let path = match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::File { path }) => path.clone(),
+        _ => return Ok(()),
+    };
+    let advice = context.arg(FADVISE_ADVICE_ARG) as i32;
+    debug!(context.logger, "fadvise64({})", path.display();
+           "tid" => p(context.thread.tid), "fd" => fd, "advice" => advice);
+    let id = context.thread.identifier;
+    match advice {
+        libc::POSIX_FADV_WILLNEED => {
+            context.database.add_file_open(id, &path, FileOp::READ, false)
+        }
+        libc::POSIX_FADV_DONTNEED => {
+            context.database.add_file_open(id, &path, FileOp::CACHE_DROP,
+                                           false)
+        }
+        libc::POSIX_FADV_SEQUENTIAL => {
+            context.database.set_access_pattern(id, &path, "sequential")
+        }
+        libc::POSIX_FADV_RANDOM => {
+            context.database.set_access_pattern(id, &path, "random")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get the total length of the buffers in an array of `struct iovec`.
+fn iovec_len(memory: &MemReader, addr: u64, count: u64) -> Result<u64, Error> {
+    let mut total = 0u64;
+    for i in 0..count.min(IOV_MAX) {
+        // struct iovec { void *iov_base; size_t iov_len; }
+        let len = memory.read_word(addr + (2 * i + 1) * WORD_SIZE)?;
+        total = total.saturating_add(len);
+    }
+    Ok(total)
+}
+
+/// Exit of `preadv()`, `pwritev()`, `preadv2()` and `pwritev2()`, reading
+/// or writing a file at an offset, into or from several buffers.
+pub fn vectored_io_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let transferred = context.retvalue();
+    if transferred < 0 {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let path = match context.thread.fds.borrow().get(fd) {
+        Some(FdEntry::File { path }) => path.clone(),
+        _ => return Ok(()),
+    };
+    let syscall = context.thread.syscall;
+    let (name, mode) = match syscall as libc::c_long {
+        libc::SYS_preadv => ("preadv", FileOp::READ),
+        libc::SYS_pwritev => ("pwritev", FileOp::WRITE),
+        libc::SYS_preadv2 => ("preadv2", FileOp::READ),
+        _ => ("pwritev2", FileOp::WRITE),
+    };
+    let flags = match syscall as libc::c_long {
+        libc::SYS_preadv2 | libc::SYS_pwritev2 => context.arg(5) as i32,
+        _ => 0,
+    };
+    let requested = iovec_len(&context.memory(), context.arg(1),
+                              context.arg(2))?;
+    debug!(context.logger, "{}({}) = {} of {} bytes", name, path.display(),
+           transferred, requested;
+           "tid" => p(context.thread.tid), "fd" => fd,
+           "nowait" => flags & libc::RWF_NOWAIT != 0,
+           "hipri" => flags & libc::RWF_HIPRI != 0);
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    context.database.add_file_open(context.thread.identifier, &path, mode,
+                                   false)
+}
+
+/// Exit of `copy_file_range()`, which copies data between two files in the
+/// kernel, without it going through the program.
+pub fn copy_file_range_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    let copied = context.retvalue();
+    if copied < 0 {
+        return Ok(());
+    }
+    let path = |fd: u64| match context.thread.fds.borrow().get(fd as i32) {
+        Some(FdEntry::File { path }) => Some(path.clone()),
+        _ => None,
+    };
+    let src_path = path(context.arg(0));
+    let dst_path = path(context.arg(2));
+    debug!(context.logger, "copy_file_range() = {} bytes", copied;
+           "tid" => p(context.thread.tid), "from" => ?src_path,
+           "to" => ?dst_path);
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let id = context.thread.identifier;
+    if let Some(src_path) = &src_path {
+        context.database.add_file_open(id, src_path, FileOp::READ, false)?;
+    }
+    if let Some(dst_path) = &dst_path {
+        context.database.add_file_open(id, dst_path, FileOp::WRITE, false)?;
+    }
+    if let (Some(src_path), Some(dst_path)) = (src_path, dst_path) {
+        context.database.add_copy_range(id, &src_path, &dst_path,
+                                        copied as u64)?;
+    }
+    Ok(())
+}
+
+/// Count and log a disagreement between the fd table and the kernel.
+fn fd_table_error(context: &SyscallContext, fd: i32, message: &str) {
+    error!(context.logger, "fd table is wrong: {}", message;
+           "tid" => p(context.thread.tid), "fd" => fd);
+    let errors = &context.stats.fd_table_errors;
+    errors.set(errors.get() + 1);
+}
+
+/// Check that the fd table has the right file for a descriptor, with
+/// `TraceConfig::verify_fd_table`.
+fn verify_fd_path(context: &SyscallContext, fd: i32) {
+    let fds = context.thread.fds.borrow();
+    let path = match fds.get(fd) {
+        Some(FdEntry::File { path }) => path,
+        _ => return,
+    };
+    // The kernel gives the resolved path, so compare the resolved files;
+    // those that were deleted can't be compared
+    if let (Ok(ours), Ok(kernel)) = (
+        path.canonicalize(),
+        context.fd_path(fd).and_then(|p| Ok(p.canonicalize()?)),
+    ) {
+        if ours != kernel {
+            fd_table_error(context, fd, &format!(
+                "has {}, kernel has {}", ours.display(), kernel.display(),
+            ));
+        }
+    }
+}
+
+/// Exit of `dup()`, `dup2()` and `dup3()`.
+pub fn dup_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let new = context.retvalue();
+    if new < 0 {
+        return Ok(());
+    }
+    let old = context.arg(0) as i32;
+    let cloexec = context.thread.syscall == libc::SYS_dup3 as u64
+        && context.arg(2) as i32 & libc::O_CLOEXEC != 0;
+    context.thread.fds.borrow_mut()
+        .duplicate(old, new as i32, cloexec);
+    if context.config.verify_fd_table && old != new as i32 {
+        verify_fd_path(context, old);
+    }
+    Ok(())
+}
+
+pub fn fcntl_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let ret = context.retvalue();
+    if ret < 0 {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    match context.arg(1) as i32 {
+        libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => {
+            let cloexec = context.arg(1) as i32 == libc::F_DUPFD_CLOEXEC;
+            context.thread.fds.borrow_mut()
+                .duplicate(fd, ret as i32, cloexec);
+        }
+        libc::F_SETFD => {
+            let cloexec = context.arg(2) as i32 & libc::FD_CLOEXEC != 0;
+            context.thread.fds.borrow_mut()
+                .set_cloexec(fd, cloexec);
+        }
+        libc::F_GETFD if context.config.verify_fd_table => {
+            let fds = context.thread.fds.borrow();
+            let kernel = ret as i32 & libc::FD_CLOEXEC != 0;
+            if fds.get(fd).is_some() && fds.cloexec(fd) != kernel {
+                fd_table_error(context, fd, &format!(
+                    "close-on-exec is {}, kernel has {}",
+                    fds.cloexec(fd), kernel,
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Exit of `ioctl()`, which can also change the close-on-exec flag, or
+/// give the slave side of a pty.
+pub fn ioctl_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let request = context.arg(1) as libc::Ioctl;
+    if let libc::TIOCGPTN | libc::TIOCGPTPEER = request {
+        return pty::pty_ioctl_exit(context);
+    }
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let cloexec = match request {
+        libc::FIOCLEX => true,
+        libc::FIONCLEX => false,
+        _ => return Ok(()),
+    };
+    context.thread.fds.borrow_mut()
+        .set_cloexec(context.arg(0) as i32, cloexec);
+    Ok(())
+}
+
+/// Update the working directory after a successful `chdir()` or `fchdir()`.
+fn change_dir(
+    context: &mut SyscallContext,
+    path: PathBuf,
+) -> Result<(), Error> {
+    // Ask the kernel, which resolved "." and ".." and symlinks
+    let cwd = format!("/proc/{}/cwd", p(context.thread.tid));
+    let path = read_link(cwd).unwrap_or(path);
+    info!(context.logger, "Process {tid} changed directory to {path}",
+          tid = p(context.thread.tid), path = path.display());
+    if context.thread.thread_group.record_files.get() {
+        context.database.add_file_open(context.thread.identifier, &path,
+                                       FileOp::WDIR, true)?;
+    }
+    *context.thread.thread_group.working_dir.borrow_mut() = path;
+    Ok(())
+}
+
+pub fn chdir_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let path = context.memory().read_string(context.arg(0))?;
+    let path = context.resolve(&path);
+    change_dir(context, path)
+}
+
+pub fn fchdir_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let path = context.fd_path(context.arg(0) as i32)?;
+    change_dir(context, path)
+}
@@ -0,0 +1,85 @@
+//! Decoding of the syscalls creating processes and threads.
+//!
+//! The new thread is reported by a `PTRACE_EVENT_FORK`, `_VFORK` or `_CLONE`
+//! stop of its parent, still inside the syscall, so its arguments can be read
+//! to know what was shared with the child.
+
+use crate::{Error, ThreadInfo, p};
+use crate::memory::MemReader;
+use crate::ptrace_backend::PtraceBackend;
+use super::SyscallContext;
+
+/// The arguments of `clone3()` we use, from the kernel's `struct clone_args`.
+#[derive(Debug, Clone, Copy)]
+pub struct CloneArgs {
+    pub flags: u64,
+    pub exit_signal: u64,
+}
+
+/// Read the `struct clone_args` passed to `clone3()`.
+pub fn read_clone3_args(
+    reader: &MemReader,
+    addr: u64,
+) -> Result<CloneArgs, Error> {
+    // Layout: flags, pidfd, child_tid, parent_tid, exit_signal, ...
+    Ok(CloneArgs {
+        flags: reader.read_u64(addr)?,
+        exit_signal: reader.read_u64(addr + 32)?,
+    })
+}
+
+/// Get the clone flags of the syscall that created a new thread.
+///
+/// `thread` is the parent, currently stopped in the syscall.
+pub fn clone_flags(
+    logger: &slog::Logger,
+    ptrace: &dyn PtraceBackend,
+    thread: &ThreadInfo,
+) -> Result<u64, Error> {
+    let syscall = thread.syscall as libc::c_long;
+    if syscall == libc::SYS_clone3 {
+        let reader = MemReader::new(ptrace, thread.tid);
+        match read_clone3_args(&reader, thread.params[0]) {
+            Ok(args) => {
+                debug!(logger, "clone3() flags={:#x} exit_signal={}",
+                       args.flags, args.exit_signal);
+                return Ok(args.flags);
+            }
+            Err(e) => {
+                warn!(logger, "Can't read clone3() arguments: {}", e);
+            }
+        }
+    }
+    #[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+    {
+        if syscall == libc::SYS_fork {
+            return Ok(libc::SIGCHLD as u64);
+        } else if syscall == libc::SYS_vfork {
+            return Ok((libc::CLONE_VM | libc::CLONE_VFORK | libc::SIGCHLD)
+                      as u64);
+        }
+    }
+    // Legacy clone(), flags are the first argument
+    Ok(thread.params[0])
+}
+
+/// Entry of the syscalls creating processes, denying them below
+/// `TraceConfig::max_process_depth`.
+pub fn fork_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let max = match context.config.max_process_depth {
+        Some(max) => max,
+        None => return Ok(()),
+    };
+    let depth = context.thread.thread_group.depth;
+    if depth < max {
+        return Ok(());
+    }
+    let flags = clone_flags(context.logger, context.ptrace, context.thread)?;
+    if flags & libc::CLONE_THREAD as u64 != 0 {
+        return Ok(());
+    }
+    warn!(context.logger, "Denying new process, too deep in the process \
+                           tree; the program might be forking endlessly";
+          "tid" => p(context.thread.tid), "depth" => depth);
+    context.deny(libc::EPERM)
+}
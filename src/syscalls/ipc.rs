@@ -0,0 +1,142 @@
+//! Handlers for the syscalls creating descriptors used for notifications.
+//!
+//! Signal and event descriptors have no path, but are read like files. They
+//! are kept in the fd table so that those reads are not taken for file
+//! accesses. The page faults read from a `userfaultfd()` descriptor are
+//! recorded, as they show how the program handles its memory.
+
+use crate::{Error, p};
+use crate::fd_table::FdEntry;
+use super::{SyscallContext, random};
+
+/// Exit of `signalfd()` and `signalfd4()`.
+///
+/// Given an existing signalfd, this changes its mask and returns it.
+pub fn signalfd_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let flags = if context.thread.syscall == libc::SYS_signalfd4 as u64 {
+        context.arg(3) as i32
+    } else {
+        0
+    };
+    debug!(context.logger, "signalfd() = {}", fd;
+           "tid" => p(context.thread.tid));
+    context.thread.fds.borrow_mut().insert(
+        fd as i32,
+        FdEntry::SignalFd,
+        flags & libc::SFD_CLOEXEC != 0,
+    );
+    Ok(())
+}
+
+/// Exit of `eventfd()` and `eventfd2()`.
+pub fn eventfd_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let initval = context.arg(0) as u32;
+    let flags = if context.thread.syscall == libc::SYS_eventfd2 as u64 {
+        context.arg(1) as i32
+    } else {
+        0
+    };
+    debug!(context.logger, "eventfd({}) = {}", initval, fd;
+           "tid" => p(context.thread.tid));
+    context.thread.fds.borrow_mut().insert(
+        fd as i32,
+        FdEntry::EventFd(initval as u64),
+        flags & libc::EFD_CLOEXEC != 0,
+    );
+    Ok(())
+}
+
+/// Size of `struct uffd_msg`.
+const UFFD_MSG_SIZE: usize = 32;
+/// Type of the `struct uffd_msg` reporting a page fault.
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+
+/// Exit of `userfaultfd()`.
+pub fn userfaultfd_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let flags = context.arg(0) as i32;
+    debug!(context.logger, "userfaultfd() = {}", fd;
+           "tid" => p(context.thread.tid));
+    context.thread.fds.borrow_mut().insert(
+        fd as i32,
+        FdEntry::UserFaultFd,
+        flags & libc::O_CLOEXEC != 0,
+    );
+    Ok(())
+}
+
+/// Record the page faults read from a `userfaultfd()` descriptor, as
+/// `struct uffd_msg` in the buffer of `read()`.
+fn read_page_faults(
+    context: &mut SyscallContext,
+    size: usize,
+) -> Result<(), Error> {
+    let mut buffer = vec![0u8; size - size % UFFD_MSG_SIZE];
+    context.memory().read(context.arg(1), &mut buffer)?;
+    for msg in buffer.chunks(UFFD_MSG_SIZE) {
+        if msg[0] != UFFD_EVENT_PAGEFAULT {
+            continue;
+        }
+        // The fault follows the 8-byte header: flags, address, then the
+        // thread's ID with UFFD_FEATURE_THREAD_ID
+        let field = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&msg[offset..offset + 8]);
+            u64::from_ne_bytes(bytes)
+        };
+        let (flags, address) = (field(8), field(16));
+        let thread_id = match field(24) as u32 {
+            0 => None,
+            ptid => Some(ptid),
+        };
+        debug!(context.logger, "Page fault at {:#x}", address;
+               "tid" => p(context.thread.tid), "flags" => flags,
+               "faulting_tid" => ?thread_id);
+        context.database.add_page_fault_event(context.thread.identifier,
+                                              address, flags, thread_id)?;
+    }
+    Ok(())
+}
+
+/// Exit of `read()`, counting the reads from signal and event descriptors,
+/// noting timers that expired, and recording page faults.
+///
+/// Reads from `/dev/urandom` are also replaced here, see `random`.
+pub fn read_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let entry = context.thread.fds.borrow().get(context.arg(0) as i32)
+        .cloned();
+    match entry {
+        Some(FdEntry::SignalFd) | Some(FdEntry::EventFd(_)) => {}
+        Some(FdEntry::UserFaultFd) => {
+            let size = context.retvalue() as usize;
+            read_page_faults(context, size)?;
+        }
+        Some(FdEntry::TimerFd) => {
+            // Reads give the number of expirations, blocking until one
+            debug!(context.logger, "Timer fired";
+                   "tid" => p(context.thread.tid));
+            return context.database.set_flag("timer_fired", true);
+        }
+        Some(ref entry) if random::is_random_device(entry) => {
+            return random::random_device_read(context);
+        }
+        _ => return Ok(()),
+    }
+    let reads = &context.stats.ipc_reads;
+    reads.set(reads.get() + 1);
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Handlers for changes of memory protection, detecting generated code.
+//!
+//! Memory that is written and then made executable holds code generated at
+//! runtime, by a JIT compiler or self-modifying code. If it was mapped from a
+//! file, that file is needed to run the program again.
+
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use crate::{Error, p};
+use crate::database::FileOp;
+use super::SyscallContext;
+
+/// A region being made executable, read on entry to `mprotect()` and
+/// recorded on exit.
+#[derive(Clone)]
+pub struct JitRegion {
+    start: u64,
+    end: u64,
+    prot: i32,
+    /// The file the region was mapped from, if any
+    path: Option<PathBuf>,
+}
+
+/// A line of `/proc/<pid>/maps`.
+struct Mapping {
+    writable: bool,
+    path: Option<PathBuf>,
+}
+
+/// Find the mapping containing an address.
+fn find_mapping(maps: &str, addr: u64) -> Option<Mapping> {
+    maps.lines().find_map(|line| {
+        // Fields: address perms offset dev inode pathname
+        let mut fields = line.splitn(6, ' ');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let start = u64::from_str_radix(start, 16).ok()?;
+        let end = u64::from_str_radix(end, 16).ok()?;
+        if addr < start || addr >= end {
+            return None;
+        }
+        let writable = fields.next()?.contains('w');
+        let path = fields.nth(3)
+            .map(str::trim_start)
+            .filter(|path| {
+                path.starts_with('/') && !path.ends_with(" (deleted)")
+            })
+            .map(PathBuf::from);
+        Some(Mapping { writable, path })
+    })
+}
+
+/// Entry of `mprotect()` and `pkey_mprotect()`, noting the regions that
+/// become executable while writable, or after having been writable.
+pub fn mprotect_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let prot = context.arg(2) as i32;
+    if prot & libc::PROT_EXEC == 0 {
+        return Ok(());
+    }
+    let start = context.arg(0);
+    // The protection can only be read before the change
+    let mapping = read_to_string(format!("/proc/{}/maps",
+                                         p(context.thread.tid)))
+        .ok()
+        .and_then(|maps| find_mapping(&maps, start));
+    let was_writable = mapping.as_ref().map(|m| m.writable).unwrap_or(false);
+    if prot & libc::PROT_WRITE == 0 && !was_writable {
+        return Ok(());
+    }
+    context.thread.pending_jit = Some(JitRegion {
+        start,
+        end: start.saturating_add(context.arg(1)),
+        prot,
+        path: mapping.and_then(|m| m.path),
+    });
+    Ok(())
+}
+
+pub fn mprotect_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let region = match context.thread.pending_jit.take() {
+        Some(region) => region,
+        None => return Ok(()),
+    };
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let thread_group = &context.thread.thread_group;
+    if !thread_group.jit_warned.replace(true) {
+        warn!(context.logger, "Process runs JIT-compiled or self-modifying \
+                               code";
+              "tid" => p(context.thread.tid));
+    }
+    debug!(context.logger, "Executable region {:#x}-{:#x}", region.start,
+           region.end; "tid" => p(context.thread.tid), "prot" => region.prot,
+           "file" => ?region.path);
+    context.database.add_jit_region(context.thread.identifier, region.start,
+                                    region.end, region.prot,
+                                    region.path.as_deref())?;
+    context.database.set_flag("has_jit_code", true)?;
+    if let Some(path) = &region.path {
+        if thread_group.record_files.get() {
+            context.database.add_file_open(context.thread.identifier, path,
+                                           FileOp::READ, false)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+//! Handlers for the Landlock syscalls, which a process uses to sandbox
+//! itself.
+//!
+//! A ruleset is created with the filesystem access rights it restricts,
+//! rules allow some of those rights beneath given files and directories, and
+//! the process then enforces it on itself and its future children. The
+//! program might behave differently where Landlock is missing or the files
+//! are in different places, so the policy is recorded.
+
+use crate::{Error, p};
+use crate::database::ProcessId;
+use crate::fd_table::FdEntry;
+use super::SyscallContext;
+
+/// Flag of `landlock_create_ruleset()` asking for the ABI version.
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+/// Flag of `landlock_create_ruleset()` asking for the fixed errata.
+const LANDLOCK_CREATE_RULESET_ERRATA: u32 = 1 << 1;
+/// Rule type for `struct landlock_path_beneath_attr`.
+const LANDLOCK_RULE_PATH_BENEATH: u64 = 1;
+
+/// The ruleset a descriptor refers to.
+///
+/// Rulesets created before the trace are identified by the current process
+/// and the descriptor.
+fn ruleset(context: &SyscallContext, fd: i32) -> (ProcessId, i32) {
+    match context.thread.fds.borrow().get(fd) {
+        Some(&FdEntry::LandlockRuleset(process, fd)) => (process, fd),
+        _ => (context.thread.identifier, fd),
+    }
+}
+
+/// Exit of `landlock_create_ruleset()`.
+pub fn landlock_create_ruleset_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    let fd = context.retvalue();
+    let flags = context.arg(2) as u32;
+    if fd < 0 || flags & (LANDLOCK_CREATE_RULESET_VERSION
+                          | LANDLOCK_CREATE_RULESET_ERRATA) != 0
+    {
+        return Ok(());
+    }
+    let fd = fd as i32;
+    // struct landlock_ruleset_attr starts with handled_access_fs, later
+    // versions added fields after it
+    let handled_access = if context.arg(1) >= 8 {
+        context.memory().read_u64(context.arg(0))?
+    } else {
+        0
+    };
+    debug!(context.logger, "landlock_create_ruleset() = {}", fd;
+           "tid" => p(context.thread.tid),
+           "handled_access" => handled_access);
+    let id = context.thread.identifier;
+    // Ruleset descriptors are always close-on-exec
+    context.thread.fds.borrow_mut().insert(
+        fd,
+        FdEntry::LandlockRuleset(id, fd),
+        true,
+    );
+    context.database.add_landlock_event(id, (id, fd), "create",
+                                        Some(handled_access), None)
+}
+
+/// Exit of `landlock_add_rule()`, recording the files a ruleset allows.
+pub fn landlock_add_rule_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    if context.arg(1) != LANDLOCK_RULE_PATH_BENEATH {
+        // Network rules restrict ports, not files
+        debug!(context.logger, "landlock_add_rule()";
+               "tid" => p(context.thread.tid), "type" => context.arg(1));
+        return Ok(());
+    }
+    // struct landlock_path_beneath_attr is packed: allowed_access, then
+    // parent_fd
+    let memory = context.memory();
+    let allowed_access = memory.read_u64(context.arg(2))?;
+    let mut parent_fd = [0u8; 4];
+    memory.read(context.arg(2) + 8, &mut parent_fd)?;
+    let parent_fd = i32::from_ne_bytes(parent_fd);
+    let path = match context.thread.fds.borrow().get(parent_fd) {
+        Some(FdEntry::File { path }) => Some(path.clone()),
+        _ => None,
+    };
+    let path = match path {
+        Some(path) => path,
+        None => context.fd_path(parent_fd)?,
+    };
+    debug!(context.logger, "landlock_add_rule({})", path.display();
+           "tid" => p(context.thread.tid),
+           "allowed_access" => allowed_access);
+    let ruleset = ruleset(context, context.arg(0) as i32);
+    context.database.add_landlock_event(context.thread.identifier, ruleset,
+                                        "rule", Some(allowed_access),
+                                        Some(&path))
+}
+
+/// Exit of `landlock_restrict_self()`, enforcing a ruleset.
+pub fn landlock_restrict_self_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    warn!(context.logger, "Process uses Landlock for sandboxing";
+          "tid" => p(context.thread.tid));
+    let ruleset = ruleset(context, context.arg(0) as i32);
+    context.database.add_landlock_event(context.thread.identifier, ruleset,
+                                        "restrict", None, None)?;
+    context.database.set_flag("has_landlock", true)
+}
@@ -0,0 +1,71 @@
+//! Handlers for the syscalls referring to processes through descriptors.
+//!
+//! `pidfd_open()` gives a descriptor for a process, which `pidfd_getfd()`
+//! then uses to copy one of that process's descriptors. The copy refers to
+//! the same file, so its entry is taken from the other process's fd table.
+
+use nix::unistd::Pid;
+
+use crate::{Error, p};
+use crate::fd_table::FdEntry;
+use super::SyscallContext;
+
+/// Exit of `pidfd_open()`.
+pub fn pidfd_open_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let pid = Pid::from_raw(context.arg(0) as i32);
+    debug!(context.logger, "pidfd_open({}) = {}", p(pid), fd;
+           "tid" => p(context.thread.tid));
+    // Process descriptors are always close-on-exec
+    context.thread.fds.borrow_mut().insert(fd as i32, FdEntry::PidFd(pid),
+                                           true);
+    Ok(())
+}
+
+/// Exit of `pidfd_getfd()`, copying a descriptor from another process.
+pub fn pidfd_getfd_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let pidfd = context.arg(0) as i32;
+    let target_fd = context.arg(1) as i32;
+    let pid = match context.thread.fds.borrow().get(pidfd) {
+        Some(&FdEntry::PidFd(pid)) => Some(pid),
+        _ => None,
+    };
+    let entry = pid
+        .and_then(|pid| context.fd_tables.get(pid))
+        .and_then(|fds| fds.borrow().get(target_fd).cloned());
+    debug!(context.logger, "pidfd_getfd({}, {}) = {}", pidfd, target_fd, fd;
+           "tid" => p(context.thread.tid), "pid" => ?pid.map(p),
+           "entry" => ?entry);
+    let mut fds = context.thread.fds.borrow_mut();
+    match entry {
+        // The new descriptor is always close-on-exec
+        Some(entry) => fds.insert(fd as i32, entry, true),
+        None => {
+            fds.remove(fd as i32);
+        }
+    }
+    Ok(())
+}
+
+/// Exit of `pidfd_send_signal()`.
+pub fn pidfd_send_signal_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let pid = match context.thread.fds.borrow().get(context.arg(0) as i32) {
+        Some(&FdEntry::PidFd(pid)) => Some(pid),
+        _ => None,
+    };
+    debug!(context.logger, "pidfd_send_signal({})", context.arg(1) as i32;
+           "tid" => p(context.thread.tid), "pid" => ?pid.map(p));
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Handlers for the syscalls waiting on file descriptors.
+//!
+//! Waiting on a file is not an access, but which files a process stays
+//! blocked on, and for how long, helps understand its latency. Only the
+//! files in the `FdTable` are recorded, which leaves out pipes and sockets.
+
+use std::fs::read_to_string;
+use std::time::Instant;
+
+use crate::{Error, p};
+use crate::fd_table::FdEntry;
+use crate::memory::{MemReader, WORD_SIZE};
+use super::SyscallContext;
+use super::timers::read_timespec;
+
+/// Most file descriptors read from a `poll()` array, larger arrays are cut.
+const MAX_POLL_FDS: u64 = 65536;
+
+/// A wait read on syscall entry, recorded on exit.
+#[derive(Clone)]
+pub struct PollInfo {
+    fds: Vec<i32>,
+    timeout_ns: Option<u64>,
+    start: Instant,
+}
+
+/// Read a `struct timeval` as nanoseconds.
+fn read_timeval(memory: &MemReader, addr: u64) -> Result<i64, Error> {
+    // struct timeval { time_t tv_sec; suseconds_t tv_usec; }, two longs
+    let secs = memory.read_word(addr)? as i64;
+    let usecs = memory.read_word(addr + WORD_SIZE)? as i64;
+    Ok(secs.saturating_mul(1_000_000_000)
+        .saturating_add(usecs.saturating_mul(1000)))
+}
+
+/// Convert a timeout in milliseconds, negative meaning infinite.
+fn timeout_ms(timeout: i32) -> Option<u64> {
+    if timeout < 0 {
+        None
+    } else {
+        Some(timeout as u64 * 1_000_000)
+    }
+}
+
+/// Read the file descriptors from an array of `struct pollfd`.
+fn read_pollfds(
+    memory: &MemReader,
+    addr: u64,
+    nfds: u64,
+) -> Result<Vec<i32>, Error> {
+    let mut fds = Vec::new();
+    let mut buf = [0u8; 8];
+    for i in 0..nfds.min(MAX_POLL_FDS) {
+        // struct pollfd { int fd; short events; short revents; }
+        memory.read(addr + i * 8, &mut buf)?;
+        let fd = i32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if fd >= 0 {
+            fds.push(fd);
+        }
+    }
+    Ok(fds)
+}
+
+/// Read the file descriptors set in the `fd_set`s passed to `select()`.
+fn read_fd_sets(
+    memory: &MemReader,
+    nfds: u64,
+    sets: &[u64],
+) -> Result<Vec<i32>, Error> {
+    // An fd_set is an array of longs
+    let bits_per_word = WORD_SIZE * 8;
+    let nfds = nfds.min(libc::FD_SETSIZE as u64);
+    let mut fds = Vec::new();
+    for &addr in sets.iter().filter(|&&addr| addr != 0) {
+        for word in 0..nfds.div_ceil(bits_per_word) {
+            let bits = memory.read_word(addr + word * WORD_SIZE)?;
+            for bit in 0..bits_per_word {
+                let fd = word * bits_per_word + bit;
+                if fd < nfds && bits & (1 << bit) != 0
+                    && !fds.contains(&(fd as i32))
+                {
+                    fds.push(fd as i32);
+                }
+            }
+        }
+    }
+    Ok(fds)
+}
+
+/// Remember the wait until the syscall returns.
+fn start_wait(
+    context: &mut SyscallContext,
+    fds: Vec<i32>,
+    timeout_ns: Option<u64>,
+) {
+    context.thread.pending_poll = Some(PollInfo {
+        fds,
+        timeout_ns,
+        start: Instant::now(),
+    });
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+pub fn poll_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let fds = read_pollfds(&context.memory(), context.arg(0),
+                           context.arg(1))?;
+    let timeout = timeout_ms(context.arg(2) as i32);
+    start_wait(context, fds, timeout);
+    Ok(())
+}
+
+pub fn ppoll_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let memory = context.memory();
+    let fds = read_pollfds(&memory, context.arg(0), context.arg(1))?;
+    let timeout = match context.arg(2) {
+        0 => None,
+        addr => Some(read_timespec(&memory, addr)?.max(0) as u64),
+    };
+    start_wait(context, fds, timeout);
+    Ok(())
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "arm"))]
+pub fn select_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let memory = context.memory();
+    let fds = read_fd_sets(&memory, context.arg(0),
+                           &[context.arg(1), context.arg(2), context.arg(3)])?;
+    let timeout = match context.arg(4) {
+        0 => None,
+        addr => Some(read_timeval(&memory, addr)?.max(0) as u64),
+    };
+    start_wait(context, fds, timeout);
+    Ok(())
+}
+
+pub fn pselect6_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let memory = context.memory();
+    let fds = read_fd_sets(&memory, context.arg(0),
+                           &[context.arg(1), context.arg(2), context.arg(3)])?;
+    let timeout = match context.arg(4) {
+        0 => None,
+        addr => Some(read_timespec(&memory, addr)?.max(0) as u64),
+    };
+    start_wait(context, fds, timeout);
+    Ok(())
+}
+
+/// Entry of `epoll_wait()` and `epoll_pwait()`.
+///
+/// The file descriptors are those registered with the epoll instance, which
+/// the kernel lists in `/proc/<pid>/fdinfo/<epfd>`.
+pub fn epoll_wait_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let epfd = context.arg(0) as i32;
+    let fdinfo = match read_to_string(format!("/proc/{}/fdinfo/{}",
+                                              p(context.thread.tid), epfd)) {
+        Ok(fdinfo) => fdinfo,
+        Err(_) => return Ok(()),
+    };
+    // Lines look like "tfd:        5 events:       19 data: ..."
+    let fds = fdinfo
+        .lines()
+        .filter_map(|line| line.strip_prefix("tfd:"))
+        .filter_map(|rest| rest.split_whitespace().next()?.parse().ok())
+        .collect();
+    let timeout = timeout_ms(context.arg(3) as i32);
+    start_wait(context, fds, timeout);
+    Ok(())
+}
+
+/// Record the files that were waited on, on exit of any of the syscalls.
+pub fn wait_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let wait = match context.thread.pending_poll.take() {
+        Some(wait) => wait,
+        None => return Ok(()),
+    };
+    let duration = wait.start.elapsed().as_nanos() as u64;
+    let mut paths = Vec::new();
+    {
+        let fds = context.thread.fds.borrow();
+        for fd in wait.fds {
+            if let Some(FdEntry::File { path }) = fds.get(fd) {
+                if !paths.contains(path) {
+                    paths.push(path.clone());
+                }
+            }
+        }
+    }
+    for path in paths {
+        debug!(context.logger, "Waited on {} for {}ns", path.display(),
+               duration; "tid" => p(context.thread.tid));
+        context.database.add_poll_event(context.thread.identifier, &path,
+                                        wait.timeout_ns, duration)?;
+    }
+    Ok(())
+}
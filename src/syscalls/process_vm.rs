@@ -0,0 +1,52 @@
+//! Handlers for the syscalls accessing the memory of another process.
+//!
+//! `process_vm_readv()` and `process_vm_writev()` copy memory between two
+//! processes directly, as debuggers and profilers do. What the program does
+//! then depends on the state of the other process, which might not be the
+//! same on replay, so the ranges are recorded and the trace is flagged.
+
+use crate::{Error, p};
+use crate::memory::WORD_SIZE;
+use super::SyscallContext;
+
+/// Most entries in the array of remote `struct iovec`, the kernel's
+/// `IOV_MAX`.
+const IOV_MAX: u64 = 1024;
+
+/// Exit of `process_vm_readv()` and `process_vm_writev()`.
+pub fn process_vm_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let transferred = context.retvalue();
+    if transferred < 0 {
+        return Ok(());
+    }
+    let target_pid = context.arg(0) as i32;
+    let is_write =
+        context.thread.syscall == libc::SYS_process_vm_writev as u64;
+    debug!(context.logger, "Process accessed memory of {}", target_pid;
+           "tid" => p(context.thread.tid), "write" => is_write,
+           "bytes" => transferred);
+    // Only the ranges that were transferred, the copy stops at the first
+    // one that fails
+    let mut remaining = transferred as u64;
+    let memory = context.memory();
+    let mut ranges = Vec::new();
+    for i in 0..context.arg(4).min(IOV_MAX) {
+        if remaining == 0 {
+            break;
+        }
+        // struct iovec { void *iov_base; size_t iov_len; }
+        let iov = context.arg(3) + 2 * i * WORD_SIZE;
+        let base = memory.read_word(iov)?;
+        let len = memory.read_word(iov + WORD_SIZE)?.min(remaining);
+        remaining -= len;
+        if len > 0 {
+            ranges.push((base, len));
+        }
+    }
+    for (address, length) in ranges {
+        context.database.add_cross_process_memory_access(
+            context.thread.identifier, target_pid, is_write, address, length,
+        )?;
+    }
+    context.database.set_flag("has_cross_process_memory", true)
+}
@@ -0,0 +1,90 @@
+//! Tracking of pseudo-terminals, used by interactive programs such as `ssh`,
+//! `screen` and `tmux`.
+//!
+//! Opening `/dev/ptmx`, as `posix_openpt()` and `openpty()` do, creates a
+//! new pty and gives its master side. The slave side is `/dev/pts/<number>`,
+//! opened by path once `ptsname()` got the number with the `TIOCGPTN` ioctl,
+//! or directly with the `TIOCGPTPEER` ioctl on the master.
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, p};
+use crate::database::FileOp;
+use crate::fd_table::FdEntry;
+use super::SyscallContext;
+
+/// The path of the slave side of a pty.
+fn slave_path(number: u32) -> PathBuf {
+    PathBuf::from(format!("/dev/pts/{}", number))
+}
+
+/// The entry for a file just opened, if it is a side of a pty.
+///
+/// The number of a new pty is read from `/proc`, where kernels before 4.9
+/// don't show it; `TIOCGPTN` gives it then.
+pub fn opened_entry(
+    context: &SyscallContext,
+    path: &Path,
+    fd: i32,
+) -> Option<FdEntry> {
+    if path == Path::new("/dev/ptmx") || path == Path::new("/dev/pts/ptmx") {
+        let fdinfo = read_to_string(format!("/proc/{}/fdinfo/{}",
+                                            p(context.thread.tid), fd)).ok()?;
+        let number = fdinfo.lines()
+            .find_map(|line| line.strip_prefix("tty-index:"))?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(FdEntry::Pty { master: true, number })
+    } else if path.parent() == Some(Path::new("/dev/pts")) {
+        let number = path.file_name()?.to_str()?.parse().ok()?;
+        Some(FdEntry::Pty { master: false, number })
+    } else {
+        None
+    }
+}
+
+/// Exit of `ioctl()` with `TIOCGPTN` or `TIOCGPTPEER` on the master side of
+/// a pty.
+pub fn pty_ioctl_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let ret = context.retvalue();
+    if ret < 0 {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    if context.arg(1) as libc::Ioctl == libc::TIOCGPTN {
+        let mut number = [0u8; 4];
+        context.memory().read(context.arg(2), &mut number)?;
+        let number = u32::from_ne_bytes(number);
+        debug!(context.logger, "Pty {} created", number;
+               "tid" => p(context.thread.tid), "fd" => fd);
+        context.thread.fds.borrow_mut()
+            .replace(fd, FdEntry::Pty { master: true, number });
+        return Ok(());
+    }
+    // TIOCGPTPEER, opening the slave side
+    let number = match context.thread.fds.borrow().get(fd) {
+        Some(&FdEntry::Pty { master: true, number }) => number,
+        _ => return Ok(()),
+    };
+    let flags = context.arg(2) as i32;
+    let path = slave_path(number);
+    debug!(context.logger, "Opened {} from its master", path.display();
+           "tid" => p(context.thread.tid), "fd" => ret);
+    context.thread.fds.borrow_mut().insert(
+        ret as i32,
+        FdEntry::Pty { master: false, number },
+        flags & libc::O_CLOEXEC != 0,
+    );
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let mode = match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => FileOp::READ,
+        libc::O_WRONLY => FileOp::WRITE,
+        _ => FileOp::READ | FileOp::WRITE,
+    };
+    context.database.add_file_open(context.thread.identifier, &path, mode,
+                                   false)
+}
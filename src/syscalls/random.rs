@@ -0,0 +1,84 @@
+//! Handlers giving the program deterministic random bytes.
+//!
+//! With `TraceConfig::deterministic_random`, what the program gets from
+//! `getrandom()` and from reading `/dev/urandom` is generated from the seed,
+//! so a program seeding its PRNG that way does the same thing on every run.
+//! Each process numbers the buffers it gets, and the bytes depend on the
+//! seed, the process and that number, so the output doesn't depend on how
+//! the processes are scheduled.
+
+use sha2::{Digest, Sha256};
+
+use crate::{Error, p};
+use crate::fd_table::FdEntry;
+use super::SyscallContext;
+
+/// Most bytes returned by one `getrandom()`, which is allowed to return
+/// less than asked.
+const MAX_GETRANDOM: usize = 1 << 20;
+
+/// The devices whose reads are replaced.
+const RANDOM_DEVICES: [&str; 2] = ["/dev/urandom", "/dev/random"];
+
+/// Generate the next buffer of random bytes for the process.
+fn generate(
+    context: &SyscallContext,
+    seed: &[u8; 32],
+    buf: &mut [u8],
+) {
+    let calls = &context.thread.thread_group.random_calls;
+    let call = calls.get();
+    calls.set(call + 1);
+    let process = context.thread.identifier.number();
+    for (block, chunk) in buf.chunks_mut(32).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(seed);
+        hasher.update(process.to_le_bytes());
+        hasher.update(call.to_le_bytes());
+        hasher.update((block as u64).to_le_bytes());
+        chunk.copy_from_slice(&hasher.finalize()[..chunk.len()]);
+    }
+}
+
+/// Entry of `getrandom()`, filling the buffer ourselves and skipping the
+/// syscall.
+pub fn getrandom_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let seed = match &context.config.deterministic_random {
+        Some(seed) => *seed,
+        None => return Ok(()),
+    };
+    let len = (context.arg(1) as usize).min(MAX_GETRANDOM);
+    let mut buf = vec![0u8; len];
+    generate(context, &seed, &mut buf);
+    if context.write_memory(context.arg(0), &buf).is_err() {
+        // Let the kernel fail it with EFAULT
+        return Ok(());
+    }
+    debug!(context.logger, "Replacing getrandom()";
+           "tid" => p(context.thread.tid), "len" => len);
+    context.skip(len as i64)
+}
+
+/// Exit of `read()` on a random device, replacing the bytes read.
+pub fn random_device_read(context: &mut SyscallContext) -> Result<(), Error> {
+    let seed = match &context.config.deterministic_random {
+        Some(seed) => *seed,
+        None => return Ok(()),
+    };
+    let mut buf = vec![0u8; context.retvalue() as usize];
+    generate(context, &seed, &mut buf);
+    debug!(context.logger, "Replacing read from random device";
+           "tid" => p(context.thread.tid), "len" => buf.len());
+    context.write_memory(context.arg(1), &buf)
+}
+
+/// Whether a descriptor is a random device, which `random_device_read()`
+/// handles.
+pub fn is_random_device(entry: &FdEntry) -> bool {
+    match entry {
+        FdEntry::File { path } => {
+            RANDOM_DEVICES.iter().any(|device| path.as_os_str() == *device)
+        }
+        _ => false,
+    }
+}
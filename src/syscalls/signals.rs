@@ -0,0 +1,46 @@
+//! Handlers tracking signal handlers, to know which accesses they make.
+//!
+//! The thread is marked as handling a signal when it is delivered, see
+//! `Tracer::handle_signal()`, until the handler returns through
+//! `rt_sigreturn()`.
+
+use crate::{Error, p};
+use crate::memory::WORD_SIZE;
+use super::SyscallContext;
+
+pub fn rt_sigaction_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let act = context.arg(1);
+    if context.retvalue() != 0 || act == 0 {
+        return Ok(());
+    }
+    let signum = context.arg(0) as i32;
+    // struct kernel_sigaction starts with the handler then the flags
+    let memory = context.memory();
+    let handler = memory.read_word(act)?;
+    let flags = memory.read_word(act + WORD_SIZE)?;
+    debug!(context.logger, "sigaction({signum}, {handler:#x})",
+           signum = signum, handler = handler;
+           "tid" => p(context.thread.tid));
+    context.database.add_signal_handler(context.thread.identifier, signum,
+                                        handler, flags)?;
+    let mut handlers = context.thread.thread_group.signal_handlers
+        .borrow_mut();
+    if handler == libc::SIG_DFL as u64 || handler == libc::SIG_IGN as u64 {
+        handlers.remove(&signum);
+    } else {
+        handlers.insert(signum);
+    }
+    Ok(())
+}
+
+pub fn rt_sigreturn_entry(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.thread.signals.pop().is_none() {
+        return Ok(());
+    }
+    debug!(context.logger, "Leaving signal handler";
+           "tid" => p(context.thread.tid));
+    let current = context.thread.signals.last().cloned();
+    context.database.set_signal_context(context.thread.identifier, current)
+}
@@ -0,0 +1,125 @@
+//! Handlers for the syscalls passing file descriptors over Unix sockets.
+//!
+//! A process can get a file descriptor from another one in an `SCM_RIGHTS`
+//! control message, instead of opening the file itself. Sent descriptors are
+//! kept until received, so the receiver's fd table gets the sender's files.
+
+use std::fs::metadata;
+use std::os::unix::fs::MetadataExt;
+
+use crate::{Error, p};
+use crate::fd_table::{FdEntry, SentFd};
+use crate::memory::{MemReader, WORD_SIZE};
+use super::SyscallContext;
+
+/// The size of a `struct cmsghdr`, after which the data starts: a `size_t`
+/// and two ints, aligned like a `size_t`.
+const CMSG_HEADER_LEN: u64 = (WORD_SIZE + 8).next_multiple_of(WORD_SIZE);
+
+/// Most bytes of control messages read, longer buffers are cut.
+const MAX_CONTROL_LEN: u64 = 65536;
+
+/// Read the descriptors in the `SCM_RIGHTS` messages of a `struct msghdr`.
+fn read_rights(memory: &MemReader, msg: u64) -> Result<Vec<i32>, Error> {
+    // struct msghdr has msg_control and msg_controllen as 5th and 6th
+    // words, msg_namelen being padded
+    let control = memory.read_word(msg + 4 * WORD_SIZE)?;
+    let control_len = memory.read_word(msg + 5 * WORD_SIZE)?
+        .min(MAX_CONTROL_LEN);
+    let mut fds = Vec::new();
+    let mut offset = 0;
+    while control != 0 && offset + CMSG_HEADER_LEN <= control_len {
+        // struct cmsghdr { size_t cmsg_len; int cmsg_level; int cmsg_type; }
+        let len = memory.read_word(control + offset)?;
+        if len < CMSG_HEADER_LEN || offset + len > control_len {
+            break;
+        }
+        let mut header = [0u8; 8];
+        memory.read(control + offset + WORD_SIZE, &mut header)?;
+        let level = i32::from_ne_bytes([header[0], header[1], header[2],
+                                        header[3]]);
+        let kind = i32::from_ne_bytes([header[4], header[5], header[6],
+                                       header[7]]);
+        if level == libc::SOL_SOCKET && kind == libc::SCM_RIGHTS {
+            let mut data = vec![0u8; (len - CMSG_HEADER_LEN) as usize / 4 * 4];
+            memory.read(control + offset + CMSG_HEADER_LEN, &mut data)?;
+            fds.extend(data.chunks_exact(4).map(|b| {
+                i32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+            }));
+        }
+        offset += len.next_multiple_of(WORD_SIZE);
+    }
+    Ok(fds)
+}
+
+/// The device and inode of the file a descriptor refers to.
+fn file_id(context: &SyscallContext, fd: i32) -> Option<(u64, u64)> {
+    let stat = metadata(context.fd_proc_path(fd)).ok()?;
+    Some((stat.dev(), stat.ino()))
+}
+
+pub fn sendmsg_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    for fd in read_rights(&context.memory(), context.arg(1))? {
+        let file = match file_id(context, fd) {
+            Some(file) => file,
+            None => continue,
+        };
+        let entry = context.thread.fds.borrow().get(fd).cloned();
+        debug!(context.logger, "Sending fd {}", fd;
+               "tid" => p(context.thread.tid), "entry" => ?entry);
+        context.in_flight.send(file, SentFd {
+            sender: context.thread.identifier,
+            fd,
+            entry,
+        });
+    }
+    Ok(())
+}
+
+pub fn recvmsg_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let cloexec = context.arg(2) as i32 & libc::MSG_CMSG_CLOEXEC != 0;
+    for fd in read_rights(&context.memory(), context.arg(1))? {
+        let sent = file_id(context, fd)
+            .and_then(|file| context.in_flight.receive(file));
+        let entry = match sent.as_ref().and_then(|s| s.entry.clone()) {
+            Some(entry) => Some(entry),
+            // Not known to the sender, but the kernel knows whether it's a
+            // file; others are "pipe:[...]", "socket:[...]", etc.
+            None => context.fd_path(fd).ok()
+                .filter(|path| path.is_absolute())
+                .map(|path| FdEntry::File { path }),
+        };
+        info!(context.logger, "Received fd {}", fd;
+              "tid" => p(context.thread.tid), "entry" => ?entry,
+              "sender" => sent.as_ref().map(|s| s.sender.number()));
+        {
+            let mut fds = context.thread.fds.borrow_mut();
+            match &entry {
+                Some(entry) => fds.insert(fd, entry.clone(), cloexec),
+                None => {
+                    fds.remove(fd);
+                }
+            }
+        }
+        if !context.thread.thread_group.record_files.get() {
+            continue;
+        }
+        let path = match &entry {
+            Some(FdEntry::File { path }) => Some(path.as_path()),
+            _ => None,
+        };
+        context.database.add_fd_transfer(
+            sent.as_ref().map(|s| (s.sender, s.fd)),
+            context.thread.identifier,
+            fd,
+            path,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+//! Handlers for syscalls that depend on features of the host system.
+//!
+//! These access kernel facilities whose state or availability differs between
+//! machines, so their use is flagged in the trace's metadata. Some can be
+//! denied, letting the program fall back to a more portable behavior.
+
+use crate::{Error, p};
+use crate::process_namespace::namespaces;
+use super::SyscallContext;
+
+pub fn syslog_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let action = context.arg(0) as i32;
+    warn!(context.logger, "Process uses the kernel log";
+          "tid" => p(context.thread.tid), "action" => action);
+    context.database.set_flag("has_kernel_log", true)?;
+    if context.config.suppress_syslog {
+        info!(context.logger, "Denying syslog()";
+              "tid" => p(context.thread.tid));
+        context.deny(libc::EPERM)?;
+    }
+    Ok(())
+}
+
+pub fn perf_event_open_entry(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    error!(context.logger, "Process uses hardware performance counters, \
+                            which are not reproducible across machines";
+           "tid" => p(context.thread.tid));
+    context.database.set_flag("has_perf_events", true)?;
+    if context.config.emulate_perf {
+        info!(context.logger, "Denying perf_event_open()";
+              "tid" => p(context.thread.tid));
+        context.deny(libc::EACCES)?;
+    }
+    Ok(())
+}
+
+/// `bpf()` command creating a map.
+const BPF_MAP_CREATE: i32 = 0;
+/// `bpf()` command loading a program.
+const BPF_PROG_LOAD: i32 = 5;
+
+/// Entry of `bpf()`, recording the eBPF programs and maps created.
+///
+/// Other commands operate on existing objects and are not recorded.
+pub fn bpf_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let command = context.arg(0) as i32;
+    if command != BPF_MAP_CREATE && command != BPF_PROG_LOAD {
+        return Ok(());
+    }
+    // union bpf_attr starts with the map type or the program type
+    let mut bpf_type = [0u8; 4];
+    context.memory().read(context.arg(1), &mut bpf_type)?;
+    let bpf_type = u32::from_ne_bytes(bpf_type);
+    warn!(context.logger, "Process uses eBPF, which requires kernel support \
+                           at the same version";
+          "tid" => p(context.thread.tid), "command" => command,
+          "type" => bpf_type);
+    context.database.add_bpf_operation(context.thread.identifier, command,
+                                       bpf_type)?;
+    context.database.set_flag("has_bpf", true)?;
+    if command == BPF_PROG_LOAD && context.config.block_bpf {
+        info!(context.logger, "Denying bpf(BPF_PROG_LOAD)";
+              "tid" => p(context.thread.tid));
+        context.deny(libc::EPERM)?;
+    }
+    Ok(())
+}
+
+pub fn sched_setaffinity_entry(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    let target = context.arg(0) as i32;
+    let mut mask = vec![0u8; context.arg(1) as usize];
+    context.memory().read(context.arg(2), &mut mask)?;
+    warn!(context.logger, "Process sets its CPU affinity";
+          "tid" => p(context.thread.tid), "target" => target);
+    context.database.add_cpu_affinity(context.thread.identifier, target,
+                                      &mask)?;
+    context.database.set_flag("has_cpu_affinity", true)?;
+    if context.config.emulate_single_cpu {
+        info!(context.logger, "Ignoring sched_setaffinity()";
+              "tid" => p(context.thread.tid));
+        context.skip(0)?;
+    }
+    Ok(())
+}
+
+/// Exit of `quotactl()`, which makes the program depend on the disk quotas
+/// configured on this machine.
+pub fn quotactl_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    // QCMD(command, type)
+    let cmd = context.arg(0) as u32;
+    let (command, quota_type) = ((cmd >> 8) as i32, (cmd & 0xff) as i32);
+    let device = match context.arg(1) {
+        0 => None,
+        addr => {
+            let device = context.memory().read_string(addr)?;
+            Some(context.resolve(&device))
+        }
+    };
+    warn!(context.logger, "This trace uses disk quotas; replay on a system \
+                           without matching quotas may fail";
+          "tid" => p(context.thread.tid), "command" => command,
+          "result" => context.retvalue());
+    context.database.add_quota_check(context.thread.identifier, command,
+                                     quota_type, device.as_deref(),
+                                     context.arg(2) as u32)?;
+    context.database.set_flag("has_quota_dependency", true)
+}
+
+/// Make `getcpu()` report CPU 0 and NUMA node 0.
+///
+/// Note that glibc's `sched_getcpu()` usually gets this from the vDSO or
+/// from rseq, without making the syscall.
+pub fn getcpu_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if !context.config.emulate_single_cpu || context.retvalue() != 0 {
+        return Ok(());
+    }
+    for &addr in &[context.arg(0), context.arg(1)] {
+        if addr != 0 {
+            context.write_memory(addr, &0u32.to_ne_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Stop the trace when the program installs a seccomp filter.
+///
+/// A filter returning `SECCOMP_RET_TRACE` would have its syscalls fail
+/// since we don't handle seccomp stops, and the filter can deny or fake
+/// syscalls in ways we wouldn't see. Since the tracer doesn't install a
+/// filter of its own, there is nothing to hand over, so the trace is aborted.
+fn seccomp_filter(context: &mut SyscallContext) -> Result<(), Error> {
+    error!(context.logger, "Process installs a seccomp filter, which is not \
+                            compatible with tracing";
+           "tid" => p(context.thread.tid));
+    context.database.set_flag("has_seccomp_filter", true)?;
+    Err(Error::IncompatibleSeccomp)
+}
+
+pub fn prctl_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.arg(0) as i32 == libc::PR_SET_SECCOMP
+        && context.arg(1) as u32 == libc::SECCOMP_MODE_FILTER
+    {
+        seccomp_filter(context)?;
+    }
+    Ok(())
+}
+
+pub fn seccomp_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.arg(0) as u32 == libc::SECCOMP_SET_MODE_FILTER {
+        seccomp_filter(context)?;
+    }
+    Ok(())
+}
+
+/// Exit of `unshare()` and `setns()`, recording the namespaces the thread
+/// entered.
+///
+/// A new PID namespace only applies to the children, it is recorded when
+/// they are created.
+pub fn namespace_change_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let syscall = if context.thread.syscall == libc::SYS_setns as u64 {
+        "setns"
+    } else {
+        "unshare"
+    };
+    let current = namespaces(context.thread.tid);
+    for &(kind, inode) in &current {
+        if context.thread.namespaces.contains(&(kind, inode)) {
+            continue;
+        }
+        warn!(context.logger, "Process entered a new namespace";
+              "tid" => p(context.thread.tid), "type" => kind,
+              "inode" => inode, "syscall" => syscall);
+        context.database.add_process_namespace(context.thread.identifier,
+                                               kind, inode, Some(syscall))?;
+    }
+    context.thread.namespaces = current;
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Handlers for timer syscalls.
+//!
+//! Timers make the program depend on the passing of real time, which is not
+//! reproduced on replay. They are recorded as markers of time dependency,
+//! and so are sleeps, which show that the program expected time to pass.
+
+use std::mem;
+
+use crate::{Error, p};
+use crate::fd_table::FdEntry;
+use crate::memory::{MemReader, WORD_SIZE};
+use super::SyscallContext;
+
+/// Read a `struct timespec` as nanoseconds.
+///
+/// This is the one with a `long` `time_t`, not the 64-bit one that 32-bit
+/// architectures pass to the `_time64` syscalls.
+pub fn read_timespec(memory: &MemReader, addr: u64) -> Result<i64, Error> {
+    let secs = memory.read_word(addr)? as i64;
+    let nsecs = memory.read_word(addr + WORD_SIZE)? as i64;
+    Ok(secs.saturating_mul(1_000_000_000).saturating_add(nsecs))
+}
+
+/// Read a `struct itimerspec` as (interval, value) nanoseconds.
+fn read_itimerspec(
+    memory: &MemReader,
+    addr: u64,
+) -> Result<(i64, i64), Error> {
+    Ok((read_timespec(memory, addr)?,
+        read_timespec(memory, addr + 2 * WORD_SIZE)?))
+}
+
+pub fn timerfd_create_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    let fd = context.retvalue();
+    if fd < 0 {
+        return Ok(());
+    }
+    let clockid = context.arg(0) as i32;
+    let flags = context.arg(1) as i32;
+    info!(context.logger, "timerfd_create() = {fd}", fd = fd;
+          "clockid" => clockid);
+    context.thread.fds.borrow_mut().insert(
+        fd as i32,
+        FdEntry::TimerFd,
+        flags & libc::TFD_CLOEXEC != 0,
+    );
+    context.database.add_timerfd_create(context.thread.identifier, fd as i32,
+                                        clockid, flags)?;
+    context.database.set_flag("has_timerfd", true)
+}
+
+pub fn timerfd_settime_exit(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let fd = context.arg(0) as i32;
+    let flags = context.arg(1) as i32;
+    let (interval, value) =
+        read_itimerspec(&context.memory(), context.arg(2))?;
+    debug!(context.logger, "timerfd_settime({fd})", fd = fd;
+           "interval_ns" => interval, "value_ns" => value);
+    context.database.add_timerfd_settime(context.thread.identifier, fd, flags,
+                                         interval, value)?;
+    context.database.set_flag("has_timerfd", true)
+}
+
+/// Exit of `timerfd_gettime()` and `timer_gettime()`, recording the time
+/// left before the timer expires.
+///
+/// The program is checking how much time passed, and might act on it.
+pub fn timer_gettime_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() != 0 {
+        return Ok(());
+    }
+    let timer = context.arg(0) as i32;
+    let (_, remaining) = read_itimerspec(&context.memory(), context.arg(1))?;
+    debug!(context.logger, "Timer {} queried, {}ns left", timer, remaining;
+           "tid" => p(context.thread.tid));
+    context.database.add_timer_query(context.thread.identifier, timer,
+                                     remaining)
+}
+
+pub fn nanosleep_entry(context: &mut SyscallContext) -> Result<(), Error> {
+    let duration = read_timespec(&context.memory(), context.arg(0))?;
+    debug!(context.logger, "nanosleep({ns}ns)", ns = duration;
+           "tid" => p(context.thread.tid));
+    context.database.add_sleep(context.thread.identifier,
+                               duration.max(0) as u64, None)
+}
+
+pub fn clock_nanosleep_entry(
+    context: &mut SyscallContext,
+) -> Result<(), Error> {
+    let clockid = context.arg(0) as i32;
+    let flags = context.arg(1) as i32;
+    let mut duration = read_timespec(&context.memory(), context.arg(2))?;
+    if flags & libc::TIMER_ABSTIME != 0 {
+        // Sleeping until a deadline, compare with the current time on that
+        // clock
+        let mut now: libc::timespec = unsafe { mem::zeroed() };
+        if unsafe { libc::clock_gettime(clockid, &mut now) } != 0 {
+            return Ok(());
+        }
+        duration -= (now.tv_sec as i64).saturating_mul(1_000_000_000)
+            .saturating_add(now.tv_nsec as i64);
+    }
+    debug!(context.logger, "clock_nanosleep({ns}ns)", ns = duration;
+           "tid" => p(context.thread.tid), "clockid" => clockid);
+    context.database.add_sleep(context.thread.identifier,
+                               duration.max(0) as u64, Some(clockid))
+}
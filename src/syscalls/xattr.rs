@@ -0,0 +1,81 @@
+//! Handlers for the extended attribute syscalls.
+//!
+//! Extended attributes hold metadata such as SELinux labels, ACLs and file
+//! capabilities, which the program might depend on.
+
+use std::path::PathBuf;
+
+use crate::{Error, p};
+use super::SyscallContext;
+
+/// Record a `*getxattr()` on the given file.
+fn record_read(
+    context: &mut SyscallContext,
+    path: PathBuf,
+) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let name = context.memory().read_string(context.arg(1))?;
+    debug!(context.logger, "getxattr({path}, {name})",
+           path = path.display(), name = String::from_utf8_lossy(&name);
+           "tid" => p(context.thread.tid));
+    context.database.add_xattr_read(context.thread.identifier, &path, &name)
+}
+
+/// Record a `*setxattr()` on the given file.
+fn record_write(
+    context: &mut SyscallContext,
+    path: PathBuf,
+) -> Result<(), Error> {
+    if !context.thread.thread_group.record_files.get() {
+        return Ok(());
+    }
+    let memory = context.memory();
+    let name = memory.read_string(context.arg(1))?;
+    let mut value = vec![0u8; context.arg(3) as usize];
+    memory.read(context.arg(2), &mut value)?;
+    debug!(context.logger, "setxattr({path}, {name})",
+           path = path.display(), name = String::from_utf8_lossy(&name);
+           "tid" => p(context.thread.tid));
+    context.database.add_xattr_write(context.thread.identifier, &path, &name,
+                                     &value)
+}
+
+/// The file argument of the path-based variants.
+fn path_arg(context: &SyscallContext) -> Result<PathBuf, Error> {
+    let path = context.memory().read_string(context.arg(0))?;
+    Ok(context.resolve(&path))
+}
+
+pub fn getxattr_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let path = path_arg(context)?;
+    record_read(context, path)
+}
+
+pub fn fgetxattr_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let path = context.fd_path(context.arg(0) as i32)?;
+    record_read(context, path)
+}
+
+pub fn setxattr_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let path = path_arg(context)?;
+    record_write(context, path)
+}
+
+pub fn fsetxattr_exit(context: &mut SyscallContext) -> Result<(), Error> {
+    if context.retvalue() < 0 {
+        return Ok(());
+    }
+    let path = context.fd_path(context.arg(0) as i32)?;
+    record_write(context, path)
+}
@@ -0,0 +1,359 @@
+//! A small program with a known sequence of syscalls, to test the tracer.
+//!
+//! The program is written in C from the steps given to `TestProcess` and
+//! compiled with `cc`, then traced for real, so the tests go through ptrace
+//! and the kernel rather than `MockPtraceBackend`.
+
+use std::env::temp_dir;
+use std::fs::{create_dir_all, remove_dir_all, remove_file, write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{ExitStatus, Tracer};
+use crate::database::Database;
+
+/// Held by the tests that have children, as the tracer waits for any
+/// child and would get those of other tests running at the same time.
+pub static FORK_LOCK: Mutex<()> = Mutex::new(());
+
+/// Numbers the build directories of the tests running in this process.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The path of a database in the temporary directory, removed when dropped.
+pub struct TempDatabase {
+    path: PathBuf,
+}
+
+impl TempDatabase {
+    /// `name` must be different in each test, as they run in parallel.
+    pub fn new(name: &str) -> TempDatabase {
+        TempDatabase::from_path(temp_dir().join(format!(
+            "reprozip-test-{}-{}.sqlite", name, std::process::id(),
+        )))
+    }
+
+    /// Remove a database created somewhere else, such as next to another.
+    pub fn from_path(path: PathBuf) -> TempDatabase {
+        TempDatabase { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDatabase {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        remove_file(&self.path).ok();
+    }
+}
+
+const HEADER: &str = r#"#define _GNU_SOURCE
+#include <fcntl.h>
+#include <stdlib.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static void wait_child(pid_t child)
+{
+    int status;
+    if(waitpid(child, &status, 0) < 0 || !WIFEXITED(status))
+        exit(1);
+    exit(WEXITSTATUS(status));
+}
+
+static void join_child(pid_t child)
+{
+    int status;
+    if(waitpid(child, &status, 0) < 0 || !WIFEXITED(status)
+            || WEXITSTATUS(status) != 0)
+        exit(1);
+}
+
+static void exec_at(int dirfd, const char *path, int flags)
+{
+    char *argv[] = {(char*)path, NULL};
+    syscall(SYS_execveat, dirfd, path, argv, environ, flags);
+}
+
+int main(void)
+{
+    int fd;
+    pid_t child;
+    (void)fd;
+    (void)child;
+"#;
+
+enum Step {
+    OpenFile(PathBuf),
+    Fork,
+    Spawn(Vec<Step>),
+    Chdir(PathBuf),
+    Exec(PathBuf),
+    ExecAt(PathBuf),
+    Fexecve(PathBuf),
+}
+
+/// Quote a path as a C string literal.
+fn c_string(path: &Path) -> String {
+    let mut literal = "\"".to_owned();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            0x20..=0x7e => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Write the C code doing some steps.
+fn steps_source(source: &mut String, steps: &[Step]) {
+    for step in steps {
+        match step {
+            Step::OpenFile(path) => {
+                source.push_str(&format!(
+                    "    fd = open({}, O_RDONLY);\n    \
+                     if(fd >= 0)\n        close(fd);\n",
+                    c_string(path),
+                ));
+            }
+            Step::Fork => {
+                source.push_str(
+                    "    child = fork();\n    if(child < 0)\n        \
+                     return 1;\n    if(child > 0)\n        \
+                     wait_child(child);\n",
+                );
+            }
+            Step::Spawn(steps) => {
+                source.push_str(
+                    "    child = fork();\n    if(child < 0)\n        \
+                     return 1;\n    if(child == 0) {\n",
+                );
+                steps_source(source, steps);
+                source.push_str(
+                    "    return 0;\n    }\n    join_child(child);\n",
+                );
+            }
+            Step::Chdir(path) => {
+                source.push_str(&format!(
+                    "    if(chdir({}) < 0)\n        return 1;\n",
+                    c_string(path),
+                ));
+            }
+            Step::Exec(path) => {
+                let path = c_string(path);
+                source.push_str(&format!(
+                    "    execl({0}, {0}, (char*)NULL);\n    \
+                     return 127;\n",
+                    path,
+                ));
+            }
+            Step::ExecAt(path) => {
+                source.push_str(&format!(
+                    "    exec_at(AT_FDCWD, {}, 0);\n    return 127;\n",
+                    c_string(path),
+                ));
+            }
+            Step::Fexecve(path) => {
+                source.push_str(&format!(
+                    "    fd = open({}, O_RDONLY);\n    \
+                     exec_at(fd, \"\", AT_EMPTY_PATH);\n    \
+                     return 127;\n",
+                    c_string(path),
+                ));
+            }
+        }
+    }
+}
+
+/// Builds a program doing a sequence of syscalls, and traces it.
+///
+/// ```rust,ignore
+/// let database = TestProcess::new()
+///     .open_file("/etc/passwd")
+///     .fork()
+///     .exec("/bin/true")
+///     .run(Tracer::new(&path).unwrap());
+/// ```
+#[derive(Default)]
+pub struct TestProcess {
+    steps: Vec<Step>,
+}
+
+impl TestProcess {
+    pub fn new() -> TestProcess {
+        Default::default()
+    }
+
+    /// Open a file for reading, then close it.
+    pub fn open_file<P: AsRef<Path>>(mut self, path: P) -> TestProcess {
+        self.steps.push(Step::OpenFile(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Fork, doing the next steps in the child.
+    ///
+    /// The parent waits for the child and exits with its status.
+    pub fn fork(mut self) -> TestProcess {
+        self.steps.push(Step::Fork);
+        self
+    }
+
+    /// Fork, doing the steps of `child` in the child.
+    ///
+    /// The parent waits for the child, and goes on with the next steps if it
+    /// exited successfully.
+    pub fn spawn(mut self, child: TestProcess) -> TestProcess {
+        self.steps.push(Step::Spawn(child.steps));
+        self
+    }
+
+    /// Change the working directory.
+    pub fn chdir<P: AsRef<Path>>(mut self, path: P) -> TestProcess {
+        self.steps.push(Step::Chdir(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Execute a program, without arguments. The steps after this are not
+    /// done, unless it fails, in which case the process exits with 127.
+    pub fn exec<P: AsRef<Path>>(mut self, path: P) -> TestProcess {
+        self.steps.push(Step::Exec(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Execute a program with `execveat()` and `AT_FDCWD`, so a relative
+    /// path is from the working directory. Like `exec()` otherwise.
+    pub fn exec_at<P: AsRef<Path>>(mut self, path: P) -> TestProcess {
+        self.steps.push(Step::ExecAt(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Open a program and execute it from its descriptor, with
+    /// `execveat()` and `AT_EMPTY_PATH` like `fexecve()`. Like `exec()`
+    /// otherwise.
+    pub fn fexecve<P: AsRef<Path>>(mut self, path: P) -> TestProcess {
+        self.steps.push(Step::Fexecve(path.as_ref().to_owned()));
+        self
+    }
+
+    /// The C source of the program.
+    fn source(&self) -> String {
+        let mut source = HEADER.to_owned();
+        steps_source(&mut source, &self.steps);
+        source.push_str("    return 0;\n}\n");
+        source
+    }
+
+    /// Compile the program and trace it, returning the database.
+    ///
+    /// This panics if the program can't be built or traced, or doesn't exit
+    /// successfully.
+    pub fn run(&self, tracer: Tracer) -> Database {
+        let directory = temp_dir().join(format!(
+            "reprozip-test-process-{}-{}", std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        ));
+        create_dir_all(&directory).unwrap();
+        let source_path = directory.join("program.c");
+        let program = directory.join("program");
+        write(&source_path, self.source()).unwrap();
+        let status = Command::new("cc")
+            .arg("-o")
+            .arg(&program)
+            .arg(&source_path)
+            .status()
+            .expect("Can't run C compiler");
+        assert!(status.success(), "Compiling test program failed");
+
+        let database_path = tracer.database_path().to_owned();
+        let result = {
+            let _lock = FORK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            tracer.trace(&[program.as_os_str().as_bytes()])
+        };
+        remove_dir_all(&directory).unwrap();
+        let (status, _) = result.unwrap();
+        assert_eq!(status, ExitStatus::Return(0));
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Database::open(&database_path, logger).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::Tracer;
+    use crate::database::{FileOp, ProcessId};
+    use super::{TempDatabase, TestProcess};
+
+    #[test]
+    fn test_open_fork_exec() {
+        let path = TempDatabase::new("process");
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let tracer = Tracer::with_logger(&path, logger).unwrap();
+        let database = TestProcess::new()
+            .open_file("/dev/null")
+            .fork()
+            .exec("/bin/true")
+            .run(tracer);
+
+        let processes = database.processes().unwrap();
+        assert_eq!(processes.len(), 2);
+        let child = processes[1].id;
+        assert_eq!(processes[1].parent, Some(ProcessId::root()));
+
+        assert!(database.file_opens().unwrap().iter().any(|open| {
+            open.process == ProcessId::root()
+                && open.path == Path::new("/dev/null")
+                && open.mode.contains(FileOp::READ)
+        }));
+        let executions = database.executions().unwrap();
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[1].process, child);
+        assert_eq!(executions[1].path, Path::new("/bin/true"));
+    }
+
+    #[test]
+    fn test_execveat() {
+        let path = TempDatabase::new("execveat");
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let trace = |program: TestProcess| {
+            let tracer = Tracer::with_logger(&path, logger.clone()).unwrap();
+            program.run(tracer).executions().unwrap()
+        };
+
+        // Relative to the working directory, with AT_FDCWD. The tracer gets
+        // that directory with its symlinks resolved
+        let binary = Path::new("/bin/true").canonicalize().unwrap();
+        let executions = trace(
+            TestProcess::new()
+                .chdir(binary.parent().unwrap())
+                .exec_at("true"),
+        );
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[1].process, ProcessId::root());
+        assert_eq!(executions[1].path, binary);
+        assert_eq!(executions[1].argv, vec![b"true".to_vec()]);
+
+        // From a descriptor, with AT_EMPTY_PATH
+        let executions = trace(TestProcess::new().fexecve("/bin/true"));
+        assert_eq!(executions.len(), 2);
+        assert_eq!(executions[1].path, Path::new("/bin/true"));
+        assert_eq!(executions[1].argv, vec![b"".to_vec()]);
+    }
+}
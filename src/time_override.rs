@@ -0,0 +1,223 @@
+//! Making the traced program see a fake wall-clock time.
+//!
+//! A small library overriding `clock_gettime()`, `gettimeofday()` and
+//! `time()` is compiled when the trace starts, and loaded into the program
+//! with `LD_PRELOAD`. Only dynamically-linked programs getting the time
+//! through libc are affected.
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Write;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// A fake clock, starting at a given time and running at a given speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeOverride {
+    /// The time the program sees when the trace starts
+    pub start: SystemTime,
+    /// How fast the fake clock runs, relative to the real one
+    pub speed: f64,
+}
+
+impl TimeOverride {
+    pub fn new(start: SystemTime) -> TimeOverride {
+        TimeOverride { start, speed: 1.0 }
+    }
+
+    /// Set how fast time passes, 2.0 meaning twice as fast.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The start time, in nanoseconds since the Unix epoch.
+    pub fn start_ns(&self) -> i64 {
+        match self.start.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos() as i64,
+            Err(e) => -(e.duration().as_nanos() as i64),
+        }
+    }
+
+    /// Build from a start time in nanoseconds since the Unix epoch.
+    pub fn from_start_ns(start_ns: i64, speed: f64) -> TimeOverride {
+        let offset = Duration::from_nanos(start_ns.unsigned_abs());
+        let start = if start_ns >= 0 {
+            UNIX_EPOCH + offset
+        } else {
+            UNIX_EPOCH - offset
+        };
+        TimeOverride { start, speed }
+    }
+}
+
+const SOURCE: &str = r#"
+#define _GNU_SOURCE
+#include <dlfcn.h>
+#include <stddef.h>
+#include <time.h>
+
+static const long long START_NS = @START@LL;
+static const long long REFERENCE_NS = @REFERENCE@LL;
+static const double SPEED = @SPEED@;
+
+/* Not including sys/time.h, whose prototype of gettimeofday() differs
+ * between glibc versions */
+struct fake_timeval {
+    long tv_sec;
+    long tv_usec;
+};
+
+static int (*real_clock_gettime)(clockid_t, struct timespec *);
+
+static void init(void)
+{
+    if(!real_clock_gettime)
+        real_clock_gettime = dlsym(RTLD_NEXT, "clock_gettime");
+}
+
+/* Fake time, from the system-wide monotonic clock so that all the processes
+ * of the trace agree */
+static long long fake_now(void)
+{
+    struct timespec ts;
+    long long elapsed;
+    init();
+    real_clock_gettime(CLOCK_MONOTONIC, &ts);
+    elapsed = ts.tv_sec * 1000000000LL + ts.tv_nsec - REFERENCE_NS;
+    return START_NS + (long long)(elapsed * SPEED);
+}
+
+int clock_gettime(clockid_t clock, struct timespec *tp)
+{
+    if(clock == CLOCK_REALTIME || clock == CLOCK_REALTIME_COARSE)
+    {
+        long long now = fake_now();
+        tp->tv_sec = now / 1000000000LL;
+        tp->tv_nsec = now % 1000000000LL;
+        return 0;
+    }
+    init();
+    return real_clock_gettime(clock, tp);
+}
+
+int gettimeofday(struct fake_timeval *tv, void *tz)
+{
+    (void)tz;
+    if(tv)
+    {
+        long long now = fake_now();
+        tv->tv_sec = now / 1000000000LL;
+        tv->tv_usec = (now % 1000000000LL) / 1000;
+    }
+    return 0;
+}
+
+time_t time(time_t *t)
+{
+    time_t now = fake_now() / 1000000000LL;
+    if(t)
+        *t = now;
+    return now;
+}
+"#;
+
+/// Read the system-wide monotonic clock, in nanoseconds.
+pub(crate) fn monotonic_ns() -> i64 {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as i64 * 1_000_000_000 + ts.tv_nsec as i64
+}
+
+/// The compiled library, removed when this is dropped.
+pub struct TimePreload {
+    directory: PathBuf,
+    library: PathBuf,
+}
+
+impl TimePreload {
+    /// Compile the library, with the fake clock starting now.
+    ///
+    /// This requires a C compiler, `cc`.
+    pub fn build(time: &TimeOverride) -> Result<TimePreload, Error> {
+        let directory = std::env::temp_dir().join(format!(
+            "reprozip-time-{}", std::process::id(),
+        ));
+        fs::create_dir_all(&directory)?;
+        let preload = TimePreload {
+            library: directory.join("libfaketime.so"),
+            directory,
+        };
+        let source_path = preload.directory.join("faketime.c");
+        let source = SOURCE
+            .replace("@START@", &time.start_ns().to_string())
+            .replace("@REFERENCE@", &monotonic_ns().to_string())
+            .replace("@SPEED@", &format!("{:?}", time.speed));
+        File::create(&source_path)?.write_all(source.as_bytes())?;
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-O2", "-o"])
+            .arg(&preload.library)
+            .arg(&source_path)
+            .arg("-ldl")
+            .status()
+            .map_err(|e| {
+                Error::Internal(format!(
+                    "Can't run C compiler for the time override: {}", e,
+                ))
+            })?;
+        if !status.success() {
+            return Err(Error::Internal(format!(
+                "Compiling the time override library failed: {}", status,
+            )));
+        }
+        Ok(preload)
+    }
+
+    /// The path of the library to put in `LD_PRELOAD`.
+    pub fn path(&self) -> &Path {
+        &self.library
+    }
+
+    /// Remove the library from `LD_PRELOAD` in an environment.
+    ///
+    /// This gets back the environment the user set, to be recorded.
+    pub fn strip_from(&self, envp: &mut Vec<Vec<u8>>) {
+        let library = self.library.as_os_str().as_bytes();
+        envp.retain_mut(|var| {
+            let value = match var.strip_prefix(b"LD_PRELOAD=") {
+                Some(value) => value,
+                None => return true,
+            };
+            let others: Vec<&[u8]> = value
+                .split(|&b| b == b':')
+                .filter(|&lib| lib != library)
+                .collect();
+            let keep = !others.is_empty();
+            let mut stripped = b"LD_PRELOAD=".to_vec();
+            stripped.extend_from_slice(&others.join(&b':'));
+            *var = stripped;
+            keep
+        });
+    }
+
+    /// The value to give to `LD_PRELOAD`, adding to the existing one.
+    pub fn ld_preload(&self, current: Option<&OsStr>) -> Vec<u8> {
+        let mut value = self.library.as_os_str().as_bytes().to_vec();
+        if let Some(current) = current.filter(|c| !c.is_empty()) {
+            value.push(b':');
+            value.extend_from_slice(current.as_bytes());
+        }
+        value
+    }
+}
+
+impl Drop for TimePreload {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.directory).ok();
+    }
+}
@@ -0,0 +1,184 @@
+//! Upload of packs to object storage.
+//!
+//! The destination is any URL supported by the `object_store` crate, such as
+//! `s3://`, `gs://`, `az://`, `https://` or `file://`. Credentials are read
+//! from the usual environment variables, e.g. `AWS_ACCESS_KEY_ID`.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use futures::StreamExt;
+use object_store::{ObjectStore, WriteMultipart};
+use object_store::path::Path as ObjectPath;
+use sha2::{Digest, Sha256};
+use tokio::runtime::Runtime;
+use url::Url;
+
+use crate::Error;
+
+/// Size of the parts uploaded, S3 needs at least 5 MiB.
+const CHUNK_SIZE: usize = 8 << 20;
+
+/// Most parts uploaded at the same time.
+const MAX_CONCURRENCY: usize = 4;
+
+/// How to verify an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// Download the object again and compare its SHA-256
+    Sha256,
+}
+
+impl FromStr for Checksum {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Checksum, Error> {
+        match s {
+            "sha256" => Ok(Checksum::Sha256),
+            _ => Err(Error::Internal(format!("Unknown checksum {}", s))),
+        }
+    }
+}
+
+/// A finished upload.
+#[derive(Debug)]
+pub struct Upload {
+    /// Where the pack is now
+    pub url: Url,
+    pub size: u64,
+    /// The SHA-256 of the pack, if verified
+    pub sha256: Option<[u8; 32]>,
+}
+
+pub(crate) fn store_error(err: object_store::Error) -> Error {
+    Error::Internal(format!("Object storage error: {}", err))
+}
+
+/// Write a hash in hexadecimal.
+pub fn hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The checksum file kept next to an object.
+pub(crate) fn sidecar_path(path: &ObjectPath) -> ObjectPath {
+    ObjectPath::from(format!("{}.sha256", path).as_str())
+}
+
+/// Set up the store for a URL, and get the path of the URL in that store.
+///
+/// The configuration keys are the environment variables in lowercase, as
+/// the builders' `from_env()` would read them.
+pub(crate) fn open_store(
+    url: &Url,
+) -> Result<(Box<dyn ObjectStore>, ObjectPath), Error> {
+    let options = std::env::vars()
+        .map(|(key, value)| (key.to_ascii_lowercase(), value));
+    object_store::parse_url_opts(url, options).map_err(store_error)
+}
+
+/// Parse a URL given by the user.
+pub(crate) fn parse_url(url: &str) -> Result<Url, Error> {
+    Url::parse(url).map_err(|e| {
+        Error::Internal(format!("Invalid URL {}: {}", url, e))
+    })
+}
+
+/// Uploads files to a location in object storage.
+pub struct Uploader {
+    store: Box<dyn ObjectStore>,
+    url: Url,
+    /// The path of `url` in the store
+    prefix: ObjectPath,
+    runtime: Runtime,
+}
+
+impl Uploader {
+    /// Set up the store for a URL.
+    ///
+    /// If the URL ends with `/`, files are uploaded under it with their own
+    /// name, otherwise it is the name of the object.
+    pub fn new(url: &str) -> Result<Uploader, Error> {
+        let url = parse_url(url)?;
+        let (store, prefix) = open_store(&url)?;
+        let runtime = Runtime::new()?;
+        Ok(Uploader { store, url, prefix, runtime })
+    }
+
+    /// Upload a file, verifying it afterwards if `checksum` is given.
+    ///
+    /// Verified uploads also get a `.sha256` file next to them, in the
+    /// format of `sha256sum`, which `Downloader` checks.
+    pub fn upload(
+        &self,
+        file: &Path,
+        checksum: Option<Checksum>,
+    ) -> Result<Upload, Error> {
+        let (path, url) = if self.url.path().ends_with('/') {
+            let name = file.file_name().and_then(|n| n.to_str())
+                .ok_or_else(|| Error::Internal(format!(
+                    "Invalid file name {}", file.display(),
+                )))?;
+            let url = self.url.join(name).map_err(|e| {
+                Error::Internal(format!("Invalid URL: {}", e))
+            })?;
+            (self.prefix.child(name), url)
+        } else {
+            (self.prefix.clone(), self.url.clone())
+        };
+
+        let (size, local_hash) = self.runtime.block_on(async {
+            let mut input = File::open(file)?;
+            let upload = self.store.put_multipart(&path).await
+                .map_err(store_error)?;
+            let mut writer = WriteMultipart::new_with_chunk_size(upload,
+                                                                 CHUNK_SIZE);
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let len = input.read(&mut buf)?;
+                if len == 0 {
+                    break;
+                }
+                writer.wait_for_capacity(MAX_CONCURRENCY).await
+                    .map_err(store_error)?;
+                writer.write(&buf[..len]);
+                hasher.update(&buf[..len]);
+                size += len as u64;
+            }
+            writer.finish().await.map_err(store_error)?;
+            Ok::<_, Error>((size, <[u8; 32]>::from(hasher.finalize())))
+        })?;
+
+        let sha256 = match checksum {
+            None => None,
+            Some(Checksum::Sha256) => {
+                let remote_hash = self.runtime.block_on(async {
+                    let mut stream = self.store.get(&path).await
+                        .map_err(store_error)?
+                        .into_stream();
+                    let mut hasher = Sha256::new();
+                    while let Some(bytes) = stream.next().await {
+                        hasher.update(&bytes.map_err(store_error)?);
+                    }
+                    Ok::<_, Error>(<[u8; 32]>::from(hasher.finalize()))
+                })?;
+                if remote_hash != local_hash {
+                    return Err(Error::Internal(format!(
+                        "Checksum mismatch after uploading to {}", url,
+                    )));
+                }
+                let name = file.file_name().unwrap_or_default()
+                    .to_string_lossy();
+                let sidecar = format!("{}  {}\n", hex(&local_hash), name);
+                self.runtime.block_on(
+                    self.store.put(&sidecar_path(&path), sidecar.into()),
+                ).map_err(store_error)?;
+                Some(local_hash)
+            }
+        };
+        Ok(Upload { url, size, sha256 })
+    }
+}
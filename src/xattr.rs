@@ -0,0 +1,108 @@
+//! Access to the extended attributes of files.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+/// The attribute holding the SELinux context of a file.
+pub const SELINUX: &[u8] = b"security.selinux";
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Call a libc function twice, first to get the size then to fill a buffer.
+fn sized_call<F>(mut f: F) -> io::Result<Vec<u8>>
+where
+    F: FnMut(*mut libc::c_void, usize) -> libc::ssize_t,
+{
+    loop {
+        let size = f(ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let len = f(buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+        if len >= 0 {
+            buf.truncate(len as usize);
+            return Ok(buf);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+        // The value grew in between, try again
+    }
+}
+
+/// List the names of the extended attributes of a file, not following
+/// symlinks.
+pub fn list(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let path = c_path(path)?;
+    let names = sized_call(|buf, size| unsafe {
+        libc::llistxattr(path.as_ptr(), buf as *mut libc::c_char, size)
+    })?;
+    Ok(names
+        .split(|&b| b == 0)
+        .filter(|n| !n.is_empty())
+        .map(|n| n.to_owned())
+        .collect())
+}
+
+/// Get the value of an extended attribute, not following symlinks.
+pub fn get(path: &Path, name: &[u8]) -> io::Result<Vec<u8>> {
+    let path = c_path(path)?;
+    let name = CString::new(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    sized_call(|buf, size| unsafe {
+        libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size)
+    })
+}
+
+/// Set the value of an extended attribute, not following symlinks.
+pub fn set(path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+    let path = c_path(path)?;
+    let name = CString::new(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let ret = unsafe {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Copy the extended attributes of a file to another.
+///
+/// Attributes we are not allowed to set, such as `security.*` ones when not
+/// root, are skipped, as are all attributes if the destination filesystem
+/// doesn't support them.
+pub fn copy(source: &Path, dest: &Path) -> io::Result<()> {
+    let names = match list(source) {
+        Ok(names) => names,
+        Err(ref e) if e.raw_os_error() == Some(libc::ENOTSUP) => {
+            return Ok(())
+        }
+        Err(e) => return Err(e),
+    };
+    for name in names {
+        let value = get(source, &name)?;
+        match set(dest, &name, &value) {
+            Ok(()) => {}
+            Err(ref e) if e.raw_os_error() == Some(libc::EPERM)
+                || e.raw_os_error() == Some(libc::ENOTSUP) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}